@@ -0,0 +1,163 @@
+//! # Builtins
+//!
+//! `builtins` contains the native builtins provided by the shell core (echo, printf)
+
+//
+//   Shell-Core
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::{NativeBuiltin, ShellCore};
+use crate::format;
+
+use std::io::{Read, Write};
+use std::rc::Rc;
+
+/// ## Echo
+/// 
+/// The echo builtin
+pub(crate) struct Echo;
+
+/// ## Printf
+/// 
+/// The printf builtin. With `-v var`, the output is assigned to var instead of being written
+pub(crate) struct Printf;
+
+/// ### defaults
+/// 
+/// Returns the native builtins which are registered by default
+pub(crate) fn defaults() -> Vec<Rc<dyn NativeBuiltin>> {
+    vec![Rc::new(Echo), Rc::new(Printf)]
+}
+
+impl NativeBuiltin for Echo {
+
+    fn name(&self) -> &str {
+        "echo"
+    }
+
+    fn run(&self, _core: &mut ShellCore, argv: &[String], _stdin: &mut dyn Read, stdout: &mut dyn Write, _stderr: &mut dyn Write) -> u8 {
+        match stdout.write_all(format::echo(&argv[1..]).as_bytes()) {
+            Ok(_) => 0,
+            Err(_) => 1
+        }
+    }
+}
+
+impl NativeBuiltin for Printf {
+
+    fn name(&self) -> &str {
+        "printf"
+    }
+
+    fn run(&self, core: &mut ShellCore, argv: &[String], _stdin: &mut dyn Read, stdout: &mut dyn Write, stderr: &mut dyn Write) -> u8 {
+        let mut args: &[String] = &argv[1..];
+        let mut var: Option<String> = None;
+        if args.first().map(|x| x.as_str()) == Some("-v") {
+            var = args.get(1).cloned();
+            args = match args.len() > 2 {
+                true => &args[2..],
+                false => &[]
+            };
+        }
+        if args.first().map(|x| x.as_str()) == Some("--") {
+            args = &args[1..];
+        }
+        let format: &String = match args.first() {
+            Some(format) => format,
+            None => {
+                let _ = stderr.write_all(b"printf: usage: printf [-v var] format [arguments]\n");
+                return 2
+            }
+        };
+        let (output, errors): (String, Vec<String>) = format::printf(format.as_str(), &args[1..]);
+        let mut rc: u8 = 0;
+        for err in errors.into_iter() {
+            rc = 1;
+            let _ = writeln!(stderr, "{}", err);
+        }
+        match var {
+            Some(var) => {
+                if ! core.storage_set(var.clone(), output) {
+                    let _ = writeln!(stderr, "printf: `{}': not a valid identifier", var);
+                    rc = 2;
+                }
+            },
+            None => {
+                if stdout.write_all(output.as_bytes()).is_err() {
+                    rc = 1;
+                }
+            }
+        }
+        rc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::parsers::bash::Bash;
+    use crate::UserStream;
+
+    #[test]
+    fn test_builtins_echo() {
+        let (mut core, _ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        let mut stdout: Vec<u8> = Vec::new();
+        let mut stderr: Vec<u8> = Vec::new();
+        let argv: Vec<String> = vec![String::from("echo"), String::from("-e"), String::from("foo\\tbar")];
+        assert_eq!(Echo.run(&mut core, &argv, &mut "".as_bytes(), &mut stdout, &mut stderr), 0);
+        assert_eq!(String::from_utf8(stdout).unwrap(), String::from("foo\tbar\n"));
+        assert!(stderr.is_empty());
+    }
+
+    #[test]
+    fn test_builtins_printf() {
+        let (mut core, _ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        let mut stdout: Vec<u8> = Vec::new();
+        let mut stderr: Vec<u8> = Vec::new();
+        let argv: Vec<String> = vec![String::from("printf"), String::from("%s-%d\\n"), String::from("a"), String::from("1")];
+        assert_eq!(Printf.run(&mut core, &argv, &mut "".as_bytes(), &mut stdout, &mut stderr), 0);
+        assert_eq!(String::from_utf8(stdout).unwrap(), String::from("a-1\n"));
+        //Into variable
+        let mut stdout: Vec<u8> = Vec::new();
+        let argv: Vec<String> = vec![String::from("printf"), String::from("-v"), String::from("FOO"), String::from("%s"), String::from("bar")];
+        assert_eq!(Printf.run(&mut core, &argv, &mut "".as_bytes(), &mut stdout, &mut stderr), 0);
+        assert!(stdout.is_empty());
+        assert_eq!(core.value_get(&String::from("FOO")).unwrap(), String::from("bar"));
+        //Usage
+        let argv: Vec<String> = vec![String::from("printf")];
+        assert_eq!(Printf.run(&mut core, &argv, &mut "".as_bytes(), &mut stdout, &mut stderr), 2);
+        assert_eq!(String::from_utf8(stderr).unwrap(), String::from("printf: usage: printf [-v var] format [arguments]\n"));
+        //Errors are written to stderr
+        let mut stderr: Vec<u8> = Vec::new();
+        let argv: Vec<String> = vec![String::from("printf"), String::from("%d%y"), String::from("foo")];
+        assert_eq!(Printf.run(&mut core, &argv, &mut "".as_bytes(), &mut stdout, &mut stderr), 1);
+        assert_eq!(String::from_utf8(stdout).unwrap(), String::from("0"));
+        assert_eq!(String::from_utf8(stderr).unwrap(), String::from("printf: foo: invalid number\nprintf: `y': invalid format character\n"));
+        let mut stdout: Vec<u8> = Vec::new();
+        let mut stderr: Vec<u8> = Vec::new();
+        let argv: Vec<String> = vec![String::from("printf"), String::from("-v"), String::from("1FOO"), String::from("bar")];
+        assert_eq!(Printf.run(&mut core, &argv, &mut "".as_bytes(), &mut stdout, &mut stderr), 2);
+        assert!(stdout.is_empty());
+        assert_eq!(String::from_utf8(stderr).unwrap(), String::from("printf: `1FOO': not a valid identifier\n"));
+    }
+}
@@ -26,18 +26,29 @@
 //
 
 extern crate dirs;
+extern crate nix;
+extern crate regex;
 extern crate whoami;
 
-use crate::{ParserErrorCode, ParseStatement, ShellCore, ShellError, ShellExpression, ShellState, ShellRunner, UserStream};
+use crate::{CommandNotFoundHandler, CommandType, Completion, CompletionKind, CompletionWord, GlobOptions, HistoryEntry, HistoryMatch, HistorySearch, HistorySearchMode, NativeBuiltin, ParserError, ParserErrorCode, ParseStatement, Redirection, ShellCore, ShellCoreSnapshot, ShellError, ShellExpression, ShellFunction, ShellState, ShellRunner, ShellStatement, ShellStreamMessage, StartupMode, TaskRelation, TrapSignal, UnixSignal, UserStream};
+use crate::builtins;
+use crate::signals::Signals;
 use crate::streams;
+use crate::wildcard;
 
 use std::collections::{HashMap, VecDeque};
 use dirs::home_dir;
+use nix::fcntl::{flock, FlockArg};
+use nix::unistd::{access, AccessFlags, User};
+use regex::{Regex, RegexBuilder};
 use std::env;
 use std::io::ErrorKind;
-use std::fs::{DirEntry, read_dir};
-use std::path::PathBuf;
-use std::time::{Duration, Instant};
+use std::fs::{DirEntry, File, OpenOptions, read_dir};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 //Data types
 
@@ -60,6 +71,7 @@ impl ShellCore {
             None => home.clone()
         };
         let _ = env::set_current_dir(wrkdir.as_path());
+        env::set_var("PWD", wrkdir.as_os_str());
         //Get streams
         let (sstream, ustream) = streams::new_streams();
         //Instantiate and return new core
@@ -78,13 +90,34 @@ impl ShellCore {
             alias: HashMap::new(),
             functions: HashMap::new(),
             dirs: VecDeque::with_capacity(255),
+            glob_options: GlobOptions::new(),
             history: VecDeque::with_capacity(history_size),
+            history_size,
+            history_unsaved: 0,
+            history_files: HashMap::new(),
+            traps: HashMap::new(),
+            signals: Signals::new(),
+            hash: HashMap::new(),
+            hash_path: None,
+            command_not_found: None,
+            builtins: HashMap::new(),
+            redirection: Redirection::Stdout,
+            stderr_redirection: Redirection::Stderr,
+            input_redirection: None,
+            environ_overrides: Vec::new(),
+            startup_files: HashMap::new(),
             parser: parser,
             buf_in: String::new(),
             sstream: sstream
         };
         //Push home to dirs
         core.pushd(core.home_dir.clone());
+        //Register default native builtins
+        for builtin in builtins::defaults().into_iter() {
+            core.builtins.insert(builtin.name().to_string(), builtin);
+        }
+        //Import the functions exported by the parent process
+        core.function_import();
         //Return core and ustream
         (core, ustream)
     }
@@ -132,22 +165,390 @@ impl ShellCore {
 
     /// ### change_directory
     /// 
-    /// Change current directory, the previous directory is stored as previous directory
-    pub(crate) fn change_directory(&mut self, directory: PathBuf) -> Result<(), ShellError> {
+    /// Change current directory, the previous directory is stored as previous directory.
+    /// If physical is false, the directory is resolved logically (like `cd -L`): relative paths are joined to the current working directory
+    /// and `..` removes the previous path component, preserving symlinks. Otherwise symlinks are resolved (like `cd -P`).
+    /// PWD and OLDPWD are updated in the environment
+    pub(crate) fn change_directory(&mut self, directory: PathBuf, physical: bool) -> Result<(), ShellError> {
         let current_dir: PathBuf = self.wrk_dir.clone();
-        match env::set_current_dir(directory.as_path()) {
+        //Resolve directory
+        let mut target: PathBuf = self.normalize_path(&directory);
+        if target.exists() && ! target.is_dir() {
+            return Err(ShellError::NotADirectory(directory))
+        }
+        if physical {
+            target = match target.canonicalize() {
+                Ok(path) => path,
+                Err(err) => return Err(self.change_directory_error(directory, err.kind()))
+            };
+        }
+        match env::set_current_dir(target.as_path()) {
             Ok(()) => {
                 self.prev_dir = current_dir;
-                self.wrk_dir = directory;
+                self.wrk_dir = target;
+                let _ = self.environ_set(String::from("OLDPWD"), String::from(self.prev_dir.to_string_lossy()));
+                let _ = self.environ_set(String::from("PWD"), String::from(self.wrk_dir.to_string_lossy()));
                 Ok(())
             },
-            Err(err) => match err.kind() {
-                ErrorKind::PermissionDenied => Err(ShellError::PermissionDenied(directory.clone())),
-                ErrorKind::Other => Err(ShellError::NotADirectory(directory.clone())),
-                ErrorKind::NotFound => Err(ShellError::NoSuchFileOrDirectory(directory.clone())),
-                _ => Err(ShellError::Other)
+            Err(err) => Err(self.change_directory_error(directory, err.kind()))
+        }
+    }
+
+    /// ### cdpath_resolve
+    /// 
+    /// Look for the provided directory in the directories listed in CDPATH.
+    /// Returns the first matching directory and whether it has been found through a non-empty entry (empty entries stand for the current directory);
+    /// None if the directory is not relative, it starts with `.` or `..`, or if it couldn't be found
+    pub(crate) fn cdpath_resolve(&self, directory: &PathBuf) -> Option<(PathBuf, bool)> {
+        if directory.is_absolute() || directory.starts_with(".") || directory.starts_with("..") {
+            return None
+        }
+        let cdpath: String = self.value_get(&String::from("CDPATH"))?;
+        for entry in cdpath.split(':') {
+            //Empty entries stand for the current directory
+            let mut candidate: PathBuf = match entry.len() {
+                0 => self.wrk_dir.clone(),
+                _ => self.resolve_path(String::from(entry))
+            };
+            candidate.push(directory);
+            let candidate: PathBuf = self.normalize_path(&candidate);
+            if candidate.is_dir() {
+                return Some((candidate, ! entry.is_empty()))
+            }
+        }
+        None
+    }
+
+    /// ### normalize_path
+    /// 
+    /// Make a path absolute (relative to the logical working directory) and lexically remove `.` and `..` components
+    fn normalize_path(&self, path: &PathBuf) -> PathBuf {
+        let abs_path: PathBuf = match path.is_relative() {
+            true => {
+                let mut abs_path: PathBuf = self.wrk_dir.clone();
+                abs_path.push(path);
+                abs_path
+            },
+            false => path.clone()
+        };
+        let mut normalized: PathBuf = PathBuf::new();
+        for component in abs_path.components() {
+            match component {
+                Component::CurDir => {},
+                Component::ParentDir => {
+                    let _ = normalized.pop();
+                },
+                _ => normalized.push(component.as_os_str())
+            }
+        }
+        normalized
+    }
+
+    /// ### change_directory_error
+    /// 
+    /// Convert an IO error kind into a ShellError for change directory
+    fn change_directory_error(&self, directory: PathBuf, kind: ErrorKind) -> ShellError {
+        match kind {
+            ErrorKind::PermissionDenied => ShellError::PermissionDenied(directory),
+            ErrorKind::Other => ShellError::NotADirectory(directory),
+            ErrorKind::NotFound => ShellError::NoSuchFileOrDirectory(directory),
+            _ => ShellError::Other
+        }
+    }
+
+    //@! Builtins
+
+    /// ### builtin_register
+    /// 
+    /// Register a native builtin, replacing the one with the same name if any.
+    /// Returns false if the name is not valid or is a builtin of the parser
+    pub fn builtin_register(&mut self, builtin: Box<dyn NativeBuiltin>) -> bool {
+        let name: String = builtin.name().to_string();
+        if name.is_empty() || name.contains(|c: char| c == '/' || c.is_whitespace()) || self.parser.has_builtin(&name) {
+            return false
+        }
+        self.builtins.insert(name, Rc::from(builtin));
+        true
+    }
+
+    /// ### builtin_unregister
+    /// 
+    /// Remove a native builtin. Returns whether it was registered
+    pub fn builtin_unregister(&mut self, name: &str) -> bool {
+        self.builtins.remove(name).is_some()
+    }
+
+    /// ### builtin_get
+    /// 
+    /// Get a native builtin by its name
+    pub(crate) fn builtin_get(&self, name: &str) -> Option<Rc<dyn NativeBuiltin>> {
+        self.builtins.get(name).cloned()
+    }
+
+    //@! Commands
+
+    /// ### resolve_command
+    /// 
+    /// Returns what the command name resolves to, in the same order used to execute it:
+    /// aliases, functions, builtins and then executable files (from the hash table or PATH)
+    pub fn resolve_command(&mut self, name: &str) -> CommandType {
+        if let Some(alias) = self.alias_get(&String::from(name)) {
+            CommandType::Alias(alias)
+        } else if self.functions.contains_key(name) {
+            CommandType::Function
+        } else if self.is_builtin(name) {
+            CommandType::Builtin
+        } else {
+            self.hash_check_path();
+            match self.hash.get(name).map(|(path, _)| path.clone()).or_else(|| self.path_lookup(name)) {
+                Some(path) => CommandType::File(path),
+                None => CommandType::NotFound
+            }
+        }
+    }
+
+    /// ### resolve_command_all
+    /// 
+    /// Returns all the resolutions of the command name, in order of precedence; each executable file in PATH is reported
+    pub fn resolve_command_all(&mut self, name: &str) -> Vec<CommandType> {
+        let mut resolved: Vec<CommandType> = Vec::new();
+        if let Some(alias) = self.alias_get(&String::from(name)) {
+            resolved.push(CommandType::Alias(alias));
+        }
+        if self.functions.contains_key(name) {
+            resolved.push(CommandType::Function);
+        }
+        if self.is_builtin(name) {
+            resolved.push(CommandType::Builtin);
+        }
+        for path in self.path_lookup_all(name).into_iter() {
+            resolved.push(CommandType::File(path));
+        }
+        resolved
+    }
+
+    /// ### set_command_not_found_handler
+    /// 
+    /// Set the handler run when a command can't be found (None removes it)
+    pub fn set_command_not_found_handler(&mut self, handler: Option<CommandNotFoundHandler>) {
+        self.command_not_found = handler;
+    }
+
+    /// ### command_not_found
+    /// 
+    /// Run the command not found handler, if any. Returns the exit status if the handler took care of the command
+    pub(crate) fn command_not_found(&mut self, argv: &[String]) -> Option<u8> {
+        //The handler is taken out while it runs, so that it can use the core
+        let mut handler: CommandNotFoundHandler = self.command_not_found.take()?;
+        let rc: Option<u8> = handler(self, argv);
+        if self.command_not_found.is_none() {
+            self.command_not_found = Some(handler);
+        }
+        rc
+    }
+
+    /// ### similar_commands
+    /// 
+    /// Returns the names of the aliases, functions and executables in PATH which are similar to the provided one
+    /// (at most `max_distance` edits away), sorted by similarity
+    pub fn similar_commands(&self, name: &str, max_distance: usize) -> Vec<String> {
+        let mut names: Vec<String> = self.alias.keys().chain(self.functions.keys()).cloned().collect();
+        let paths: String = self.value_get(&String::from("PATH")).unwrap_or_default();
+        for dir in paths.split(':').filter(|dir| ! dir.is_empty()) {
+            if let Ok(entries) = read_dir(dir) {
+                names.extend(entries.filter_map(|entry| entry.ok()).map(|entry| entry.file_name().to_string_lossy().to_string()));
+            }
+        }
+        let mut similar: Vec<(usize, String)> = names.into_iter().map(|x| (edit_distance(name, x.as_str()), x)).filter(|(distance, x)| *distance <= max_distance && x != name).collect();
+        similar.sort();
+        similar.dedup();
+        similar.into_iter().map(|(_, x)| x).collect()
+    }
+
+    /// ### is_builtin
+    /// 
+    /// Returns whether the command is a shell builtin (either handled by the parser or by the runner)
+    pub(crate) fn is_builtin(&self, name: &str) -> bool {
+        self.parser.has_builtin(name) || self.is_native_builtin(name)
+    }
+
+    /// ### is_native_builtin
+    /// 
+    /// Returns whether the command is a native builtin (e.g. echo or a builtin registered by the host), which is executed as part of a task chain
+    pub(crate) fn is_native_builtin(&self, name: &str) -> bool {
+        self.builtins.contains_key(name)
+    }
+
+    /// ### path_lookup
+    /// 
+    /// Look for an executable file in PATH. If the name contains a slash, it's checked as it is
+    pub(crate) fn path_lookup(&self, name: &str) -> Option<PathBuf> {
+        self.path_lookup_all(name).into_iter().next()
+    }
+
+    /// ### path_lookup_all
+    /// 
+    /// Look for all the executable files with this name in PATH. If the name contains a slash, it's checked as it is
+    fn path_lookup_all(&self, name: &str) -> Vec<PathBuf> {
+        let is_executable = |path: &PathBuf| -> bool { path.is_file() && access(path.as_path(), AccessFlags::X_OK).is_ok() };
+        if name.contains('/') {
+            let path: PathBuf = PathBuf::from(name);
+            return match is_executable(&path) {
+                true => vec![path],
+                false => vec![]
+            }
+        }
+        let paths: String = self.value_get(&String::from("PATH")).unwrap_or_default();
+        paths.split(':').filter(|dir| ! dir.is_empty()).map(|dir| PathBuf::from(dir).join(name)).filter(|path| is_executable(path)).collect()
+    }
+
+    //@! Completion
+
+    /// ### complete
+    /// 
+    /// Returns the candidates to complete the word which ends at cursor (a byte offset of buffer), sorted by value; the word is found by the parser.
+    /// Commands (aliases, functions, builtins and executables in PATH) are completed in command position, files and directories otherwise.
+    /// Words starting with `$` are completed with variable names and words starting with `~` with users and entries of the directory stack.
+    /// The arguments of cd and pushd are completed with directories, including the ones in CDPATH, and the ones of pushd and popd with `+N`/`-N` too
+    pub fn complete(&self, buffer: &str, cursor: usize) -> Vec<Completion> {
+        let word: CompletionWord = match self.parser.completion_word(buffer, cursor) {
+            Some(word) => word,
+            None => return Vec::new()
+        };
+        let raw: &str = word.word.as_str();
+        let command: &str = match word.command_position {
+            true => "",
+            false => word.command.as_deref().unwrap_or_default()
+        };
+        let mut candidates: Vec<(String, String, CompletionKind)> = Vec::new();
+        if let Some(variable) = raw.strip_prefix('"').unwrap_or(raw).strip_prefix('$').filter(|x| x.strip_prefix('{').unwrap_or(x).chars().all(|c| c.is_ascii_alphanumeric() || c == '_')) {
+            //Variables
+            let quote: &str = &raw[..raw.len() - variable.len() - 1];
+            let (prefix, braces): (&str, bool) = match variable.strip_prefix('{') {
+                Some(prefix) => (prefix, true),
+                None => (variable, false)
+            };
+            let mut names: Vec<String> = self.storage.keys().cloned().chain(env::vars().map(|(key, _)| key)).filter(|x| x.starts_with(prefix) && self.is_variable_name_valid(x)).collect();
+            names.sort();
+            names.dedup();
+            for name in names.into_iter() {
+                let replacement: String = match braces {
+                    true => format!("{}${{{}}}", quote, name),
+                    false => format!("{}${}", quote, name)
+                };
+                candidates.push((name, replacement, CompletionKind::Variable));
+            }
+        } else if raw.starts_with('~') && ! raw.contains('/') {
+            //Directory stack and users
+            let prefix: &str = &raw[1..];
+            if prefix.starts_with(|c: char| c == '+' || c == '-' || c.is_ascii_digit()) {
+                let sign: &str = prefix.get(..1).filter(|x| *x == "+" || *x == "-").unwrap_or_default();
+                for index in 0..self.dirs.len() {
+                    let value: String = format!("~{}{}", sign, index);
+                    if value.starts_with(raw) {
+                        candidates.push((value.clone(), value, CompletionKind::DirStack));
+                    }
+                }
+            }
+            for user in completion_users().into_iter().filter(|x| x.starts_with(prefix)) {
+                let value: String = format!("~{}/", user);
+                candidates.push((value.clone(), value, CompletionKind::User));
             }
+        } else if (command == "pushd" || command == "popd") && raw.starts_with(['+', '-']) {
+            //Directory stack
+            for index in 0..self.dirs.len() {
+                let value: String = format!("{}{}", &raw[..1], index);
+                if value.starts_with(raw) {
+                    candidates.push((value.clone(), value, CompletionKind::DirStack));
+                }
+            }
+        } else if word.command_position && ! raw.contains('/') {
+            //Commands
+            let (prefix, quote): (String, Option<char>) = completion_unquote(raw, None);
+            let mut names: Vec<(String, CompletionKind)> = Vec::new();
+            names.extend(self.alias.keys().map(|x| (x.clone(), CompletionKind::Alias)));
+            names.extend(self.functions.keys().map(|x| (x.clone(), CompletionKind::Function)));
+            names.extend(self.parser.builtin_names().into_iter().chain(self.builtins.keys().cloned()).map(|x| (x, CompletionKind::Builtin)));
+            let paths: String = self.value_get(&String::from("PATH")).unwrap_or_default();
+            for dir in paths.split(':').filter(|dir| ! dir.is_empty()) {
+                if let Ok(entries) = read_dir(dir) {
+                    for entry in entries.filter_map(|entry| entry.ok()) {
+                        let name: String = entry.file_name().to_string_lossy().to_string();
+                        let path: PathBuf = entry.path();
+                        if name.starts_with(prefix.as_str()) && path.is_file() && access(path.as_path(), AccessFlags::X_OK).is_ok() {
+                            names.push((name, CompletionKind::Command));
+                        }
+                    }
+                }
+            }
+            for (name, kind) in names.into_iter().filter(|(x, _)| x.starts_with(prefix.as_str())) {
+                let replacement: String = completion_quote("", name.as_str(), quote, true);
+                candidates.push((name, replacement, kind));
+            }
+        } else {
+            //Files
+            candidates = self.complete_files(raw, command == "cd" || command == "pushd");
         }
+        let mut completions: Vec<Completion> = candidates.into_iter().map(|(value, replacement, kind)| Completion {
+            value,
+            replacement,
+            kind,
+            start: word.start,
+            end: cursor
+        }).collect();
+        //Stable sort: the first kind found is kept among the candidates with the same replacement (e.g. an alias shadowing a command)
+        completions.sort_by(|a, b| a.value.cmp(&b.value));
+        completions.dedup_by(|a, b| a.replacement == b.replacement);
+        completions
+    }
+
+    /// ### complete_files
+    /// 
+    /// Returns the files (or only the directories) which complete the path written in raw, as (value, replacement, kind).
+    /// Hidden files are completed only if the file name starts with `.`; relative directories are looked up in CDPATH too if dirs_only
+    fn complete_files(&self, raw: &str, dirs_only: bool) -> Vec<(String, String, CompletionKind)> {
+        //Split the word into directory and file name
+        let (dir_raw, name_raw): (&str, &str) = match raw.rfind('/') {
+            Some(index) => raw.split_at(index + 1),
+            None => ("", raw)
+        };
+        let (mut dir, quote): (String, Option<char>) = completion_unquote(dir_raw, None);
+        let (name, quote): (String, Option<char>) = completion_unquote(name_raw, quote);
+        if dir_raw.starts_with('~') {
+            if let Some((prefix, rest)) = dir.split_once('/') {
+                if let Some(home) = self.tilde_prefix(&String::from(&prefix[1..])) {
+                    dir = format!("{}/{}", home.to_string_lossy().trim_end_matches('/'), rest);
+                }
+            }
+        }
+        let mut directories: Vec<PathBuf> = vec![self.wrk_dir.join(dir.as_str())];
+        if dirs_only && ! dir.starts_with('/') && ! dir.starts_with("./") && ! dir.starts_with("../") && ! name.starts_with('.') {
+            let cdpath: String = self.value_get(&String::from("CDPATH")).unwrap_or_default();
+            directories.extend(cdpath.split(':').filter(|x| ! x.is_empty()).map(|x| self.resolve_path(String::from(x)).join(dir.as_str())));
+        }
+        let mut candidates: Vec<(String, String, CompletionKind)> = Vec::new();
+        for directory in directories.iter() {
+            let entries: Vec<DirEntry> = match self.get_files_in(directory.clone()) {
+                Ok(entries) => entries,
+                Err(_) => continue
+            };
+            for entry in entries.into_iter() {
+                let file_name: String = entry.file_name().to_string_lossy().to_string();
+                if ! file_name.starts_with(name.as_str()) || (file_name.starts_with('.') && ! name.starts_with('.')) {
+                    continue;
+                }
+                let is_dir: bool = entry.path().is_dir();
+                if dirs_only && ! is_dir {
+                    continue;
+                }
+                let candidate: (String, String, CompletionKind) = match is_dir {
+                    true => (format!("{}/", file_name), format!("{}/", completion_quote(dir_raw, file_name.as_str(), quote, false)), CompletionKind::Directory),
+                    false => (file_name.clone(), completion_quote(dir_raw, file_name.as_str(), quote, true), CompletionKind::File)
+                };
+                candidates.push(candidate);
+            }
+        }
+        candidates
     }
 
     //@! Directories
@@ -196,8 +597,13 @@ impl ShellCore {
     
     /// ### exit
     /// 
-    /// Terminate shell and exit
+    /// Terminate shell and exit. The EXIT trap is run before and the other traps are reset
     pub fn exit(&mut self) {
+        self.trap_exit();
+        let traps: Vec<TrapSignal> = self.traps.keys().copied().collect();
+        for trap in traps.iter() {
+            self.trap_unset(trap);
+        }
         self.state = ShellState::Terminated;
         self.exit_code = 0;
         self.execution_time = Duration::from_secs(0);
@@ -213,6 +619,11 @@ impl ShellCore {
         self.dirs.clear();
         self.history.clear();
         self.buf_in.clear();
+        self.hash.clear();
+        self.hash_path = None;
+        self.redirection = Redirection::Stdout;
+        self.stderr_redirection = Redirection::Stderr;
+        self.input_redirection = None;
     }
 
     //@! Files
@@ -246,6 +657,28 @@ impl ShellCore {
         Ok(files)
     }
 
+    /// ### get_glob_options
+    /// 
+    /// Returns the options used by the filename expansion
+    pub fn get_glob_options(&self) -> GlobOptions {
+        self.glob_options
+    }
+
+    /// ### set_glob_options
+    /// 
+    /// Set the options used by the filename expansion (nullglob, failglob, dotglob)
+    pub fn set_glob_options(&mut self, options: GlobOptions) {
+        self.glob_options = options;
+    }
+
+    /// ### glob
+    /// 
+    /// Returns the sorted list of paths which match the pattern, according to the glob options.
+    /// Relative patterns are resolved from the working directory and the paths are returned relative
+    pub fn glob(&self, pattern: &str) -> Vec<String> {
+        wildcard::glob(pattern, self.wrk_dir.as_path(), &self.glob_options)
+    }
+
     //@! Functions
 
     /// ### function_get
@@ -254,20 +687,103 @@ impl ShellCore {
     pub(crate) fn function_get(&self, name: &String) -> Option<ShellExpression> {
         match self.functions.get(name) {
             None => None,
-            Some(f) => Some(f.clone())
+            Some(f) => Some(f.expression.clone())
         }
     }
 
     /// ### function_set
     /// 
-    /// Set a new Shell Function
+    /// Set a new Shell Function, whose source is not available
     /// Returns false if the function name is not valid
-    pub(crate) fn function_set(&mut self, name: String, expression: ShellExpression) -> bool {
-        if self.is_function_name_valid(&name) {
-            self.functions.insert(name, expression);
-            true
-        } else {
-            false
+    pub fn function_set(&mut self, name: String, expression: ShellExpression) -> bool {
+        self.function_define(name, expression, String::new())
+    }
+
+    /// ### function_define
+    /// 
+    /// Define a Shell Function with its source (the compound command which makes up its body).
+    /// A function which is redefined keeps being exported. Returns false if the function name is not valid
+    pub(crate) fn function_define(&mut self, name: String, expression: ShellExpression, source: String) -> bool {
+        if ! self.is_function_name_valid(&name) {
+            return false
+        }
+        let exported: bool = self.functions.get(&name).map(|f| f.exported).unwrap_or(false);
+        if exported {
+            env::set_var(function_environ_key(&name), format!("() {}", source));
+        }
+        self.functions.insert(name, ShellFunction { expression, source, exported });
+        true
+    }
+
+    /// ### function_unset
+    /// 
+    /// Remove a Shell Function. Returns false if the function doesn't exist
+    pub(crate) fn function_unset(&mut self, name: &str) -> bool {
+        match self.functions.remove(name) {
+            None => false,
+            Some(f) => {
+                if f.exported {
+                    env::remove_var(function_environ_key(name));
+                }
+                true
+            }
+        }
+    }
+
+    /// ### function_export
+    /// 
+    /// Export a Shell Function to the child processes, or stop exporting it.
+    /// As in bash, exported functions are passed through the environment (`BASH_FUNC_name%%=() body`).
+    /// Returns false if the function doesn't exist
+    pub(crate) fn function_export(&mut self, name: &str, export: bool) -> bool {
+        let source: String = match self.functions.get_mut(name) {
+            None => return false,
+            Some(f) => {
+                f.exported = export;
+                f.source.clone()
+            }
+        };
+        match export {
+            true => env::set_var(function_environ_key(name), format!("() {}", source)),
+            false => env::remove_var(function_environ_key(name))
+        }
+        true
+    }
+
+    /// ### function_names
+    /// 
+    /// Returns the sorted names of the Shell Functions, along with whether they're exported
+    pub(crate) fn function_names(&self) -> Vec<(String, bool)> {
+        let mut names: Vec<(String, bool)> = self.functions.iter().map(|(name, f)| (name.clone(), f.exported)).collect();
+        names.sort();
+        names
+    }
+
+    /// ### function_source
+    /// 
+    /// Returns the source of a Shell Function body
+    pub(crate) fn function_source(&self, name: &str) -> Option<String> {
+        self.functions.get(name).map(|f| f.source.clone())
+    }
+
+    /// ### function_import
+    /// 
+    /// Define the functions exported by the parent process
+    fn function_import(&mut self) {
+        for (key, value) in env::vars() {
+            let name: &str = match key.strip_prefix("BASH_FUNC_").and_then(|x| x.strip_suffix("%%")) {
+                Some(name) => name,
+                None => continue
+            };
+            if let Some(source) = value.strip_prefix("() ") {
+                if let Ok(expression) = self.parse(&String::from(source)) {
+                    //The body must be a single compound command; anything following it (e.g. `() { :; }; cmd`) is rejected
+                    let compound: bool = matches!(expression.statements.as_slice(), [(ShellStatement::Group(..), TaskRelation::Unrelated)] | [(ShellStatement::Subshell(..), TaskRelation::Unrelated)]);
+                    if compound && self.is_function_name_valid(&String::from(name)) {
+                        self.functions.insert(String::from(name), ShellFunction { expression, source: String::from(source), exported: true });
+                    }
+                }
+            }
         }
     }
 
@@ -306,6 +822,121 @@ impl ShellCore {
         wrkdir
     }
 
+    /// ### get_redirection
+    /// 
+    /// Returns the shell stdout redirection (set with `exec > file`)
+    pub fn get_redirection(&self) -> Redirection {
+        self.redirection.clone()
+    }
+
+    /// ### set_redirection
+    /// 
+    /// Set the shell stdout redirection; commands writing to stdout will be redirected there
+    pub fn set_redirection(&mut self, redirection: Redirection) {
+        self.redirection = redirection;
+    }
+
+    /// ### get_stderr_redirection
+    /// 
+    /// Returns the shell stderr redirection (set with `exec 2> file`)
+    pub fn get_stderr_redirection(&self) -> Redirection {
+        self.stderr_redirection.clone()
+    }
+
+    /// ### set_stderr_redirection
+    /// 
+    /// Set the shell stderr redirection; commands writing to stderr will be redirected there
+    pub fn set_stderr_redirection(&mut self, redirection: Redirection) {
+        self.stderr_redirection = redirection;
+    }
+
+    /// ### get_input_redirection
+    /// 
+    /// Returns the file the shell input is read from (set with `exec < file`).
+    /// The file is shared by the commands, which read it from the current offset
+    pub(crate) fn get_input_redirection(&self) -> Option<Rc<File>> {
+        self.input_redirection.clone()
+    }
+
+    /// ### set_input_redirection
+    /// 
+    /// Set the file the shell input is read from; commands which don't read from a pipe will read from it
+    pub(crate) fn set_input_redirection(&mut self, file: File) {
+        self.input_redirection = Some(Rc::new(file));
+    }
+
+    //@! Hash
+
+    /// ### hash_get_all
+    /// 
+    /// Returns the commands in the hash table with their path and hits, sorted by name
+    pub fn hash_get_all(&mut self) -> Vec<(String, PathBuf, usize)> {
+        self.hash_check_path();
+        let mut commands: Vec<(String, PathBuf, usize)> = self.hash.iter().map(|(name, (path, hits))| (name.clone(), path.clone(), *hits)).collect();
+        commands.sort_by(|a, b| a.0.cmp(&b.0));
+        commands
+    }
+
+    /// ### hash_get
+    /// 
+    /// Returns the path of a command in the hash table
+    pub(crate) fn hash_get(&mut self, name: &str) -> Option<PathBuf> {
+        self.hash_check_path();
+        self.hash.get(name).map(|(path, _)| path.clone())
+    }
+
+    /// ### hash_lookup
+    /// 
+    /// Returns the path of the command to execute. The command is looked up in PATH only the first time, then it's taken from the hash table.
+    /// Names containing a slash are not hashed
+    pub(crate) fn hash_lookup(&mut self, name: &str) -> Option<PathBuf> {
+        if name.contains('/') {
+            return None
+        }
+        self.hash_check_path();
+        if let Some((path, hits)) = self.hash.get_mut(name) {
+            *hits += 1;
+            return Some(path.clone())
+        }
+        let path: PathBuf = self.path_lookup(name)?;
+        self.hash.insert(String::from(name), (path.clone(), 1));
+        Some(path)
+    }
+
+    /// ### hash_set
+    /// 
+    /// Set the path of a command in the hash table
+    pub(crate) fn hash_set(&mut self, name: String, path: PathBuf) {
+        self.hash_check_path();
+        self.hash.insert(name, (path, 0));
+    }
+
+    /// ### hash_remove
+    /// 
+    /// Remove a command from the hash table. Returns false if the command wasn't hashed
+    pub(crate) fn hash_remove(&mut self, name: &str) -> bool {
+        self.hash_check_path();
+        self.hash.remove(name).is_some()
+    }
+
+    /// ### hash_clear
+    /// 
+    /// Forget all the hashed commands
+    pub fn hash_clear(&mut self) {
+        self.hash.clear();
+    }
+
+    /// ### hash_check_path
+    /// 
+    /// Clear the hash table if PATH has changed since the table was filled
+    fn hash_check_path(&mut self) {
+        let path: Option<String> = self.value_get(&String::from("PATH"));
+        if path != self.hash_path {
+            self.hash.clear();
+            self.hash_path = path;
+        }
+    }
+
     //@! History
 
     /// ### history_at
@@ -313,10 +944,15 @@ impl ShellCore {
     /// Get the command at a certain index of the history
     /// None is returned in case index is out of range
     pub fn history_at(&self, index: usize) -> Option<String> {
-        match self.history.get(index) {
-            Some(s) => Some(s.clone()),
-            None => None
-        }
+        self.history.get(index).map(|x| x.command.clone())
+    }
+
+    /// ### history_entry_at
+    /// 
+    /// Get the history entry at a certain index of the history
+    /// None is returned in case index is out of range
+    pub fn history_entry_at(&self, index: usize) -> Option<HistoryEntry> {
+        self.history.get(index).cloned()
     }
 
     /// ### history_clear
@@ -324,6 +960,7 @@ impl ShellCore {
     /// Clear history
     pub fn history_clear(&mut self) {
         self.history.clear();
+        self.history_unsaved = 0;
     }
 
     /// ### history_del
@@ -331,62 +968,369 @@ impl ShellCore {
     /// Delete history from a certain index
     pub fn history_del(&mut self, index: usize) {
         self.history.truncate(index + 1); //Length is index + 1
+        self.history_unsaved = self.history_unsaved.min(self.history.len());
     }
 
     /// ### history_get
     /// 
     /// Returns the entire history copy
     pub fn history_get(&self) -> VecDeque<String> {
+        self.history.iter().map(|x| x.command.clone()).collect()
+    }
+
+    /// ### history_get_entries
+    /// 
+    /// Returns a copy of the entire history, including the entries metadata
+    pub fn history_get_entries(&self) -> VecDeque<HistoryEntry> {
         self.history.clone()
     }
 
     /// ### history_load
     /// 
-    /// Load history
-    /// NOTE: the maximum history size will still be the size provided at constructor
+    /// Load history from the lines of a history file, from the oldest to the newest entry; the entries exceeding the history size are discarded.
+    /// Lines can be either plain commands or entries in the extended format (see history_write_file)
     pub fn history_load(&mut self, history: VecDeque<String>) {
         //Clear current history
-        self.history.clear();
-        //Iterate over provided history NOTE: VecDeque allocates capacity / 2 - 1
-        let history_size: usize = (self.history.capacity() + 1) / 2;
-        for (index, entry) in history.iter().enumerate() {
-            if index >= history_size {
+        self.history_clear();
+        for entry in history_parse(history.into_iter()).into_iter() {
+            self.history.push_front(entry);
+        }
+        self.history_truncate();
+    }
+
+    /// ### history_read_file
+    /// 
+    /// Read the entries of a history file and merge them into the history list, by time; the entries which haven't been saved yet are kept the newest ones.
+    /// If only_new is true, only the entries appended to the file since it was last read or written by this shell are read,
+    /// skipping those already in the history (e.g. written by this shell). Returns the amount of entries read
+    pub fn history_read_file(&mut self, path: &Path, only_new: bool) -> Result<usize, ShellError> {
+        let mut file: File = File::open(path).map_err(|err| io_error(err, path))?;
+        //Wait for the shells which are writing the file
+        let _ = flock(file.as_raw_fd(), FlockArg::LockShared);
+        let length: u64 = file.metadata().map(|x| x.len()).unwrap_or(0);
+        //If the file has been truncated, it's read from the beginning
+        let offset: u64 = match only_new {
+            true => self.history_files.get(path).copied().filter(|x| *x <= length).unwrap_or(0),
+            false => 0
+        };
+        let mut content: Vec<u8> = Vec::with_capacity((length - offset) as usize);
+        if file.seek(SeekFrom::Start(offset)).and_then(|_| file.read_to_end(&mut content)).is_err() {
+            return Err(ShellError::Other)
+        }
+        drop(file);
+        self.history_files.insert(path.to_path_buf(), offset + content.len() as u64);
+        let content: String = String::from_utf8_lossy(&content).into_owned();
+        let mut read: usize = 0;
+        //Merge from the newest entry, keeping the file order and the entries which haven't been saved yet as the newest ones
+        let mut position: usize = self.history_unsaved;
+        for entry in history_parse(content.lines().map(String::from)).into_iter().rev() {
+            if only_new && self.history.iter().any(|x| x.timestamp == entry.timestamp && x.command == entry.command) {
+                continue;
+            }
+            while position < self.history.len() && self.history[position].timestamp >= entry.timestamp {
+                position += 1;
+            }
+            self.history.insert(position, entry);
+            position += 1;
+            read += 1;
+        }
+        self.history_truncate();
+        Ok(read)
+    }
+
+    /// ### history_write_file
+    /// 
+    /// Write the history to file, from the oldest to the newest entry, in the extended format: `: <start time>:<duration>;<command>`,
+    /// where the backslashes and the newlines of the command are escaped by a backslash.
+    /// If append is true, only the entries which haven't been saved yet are appended to the file. The file is locked while writing, so that many shells can share it
+    pub fn history_write_file(&mut self, path: &Path, append: bool) -> Result<(), ShellError> {
+        let mut file: File = OpenOptions::new().create(true).append(append).write(true).open(path).map_err(|err| io_error(err, path))?;
+        let _ = flock(file.as_raw_fd(), FlockArg::LockExclusive);
+        let length: u64 = file.metadata().map(|x| x.len()).unwrap_or(0);
+        let entries: usize = match append {
+            true => self.history_unsaved,
+            false => self.history.len()
+        };
+        let mut content: String = String::new();
+        for entry in self.history.iter().take(entries).rev() {
+            content.push_str(history_format(entry).as_str());
+            content.push('\n');
+        }
+        if ! append {
+            //Truncate after locking
+            let _ = file.set_len(0);
+        }
+        if file.write_all(content.as_bytes()).is_err() {
+            return Err(ShellError::Other)
+        }
+        //The offset is moved only if no other shell has written the file since it was last read, otherwise their entries would be skipped by `history -n`
+        let previous: u64 = self.history_files.get(path).copied().unwrap_or(0);
+        if ! append || previous == length {
+            let length: u64 = match append {
+                true => length + content.len() as u64,
+                false => content.len() as u64
+            };
+            self.history_files.insert(path.to_path_buf(), length);
+        }
+        self.history_unsaved = 0;
+        Ok(())
+    }
+
+    /// ### history_search
+    /// 
+    /// Search the history for the entries matching the search pattern and filters, from the newest to the oldest entry.
+    /// Fuzzy matches are sorted by score, keeping the newest entry first among the ones with the same score.
+    /// Returns BadValue if the pattern is not a valid regex
+    pub fn history_search(&self, search: &HistorySearch) -> Result<Vec<HistoryMatch>, ShellError> {
+        //Substring and prefix searches are literal regexes
+        let regex: Option<Regex> = match search.mode {
+            HistorySearchMode::Fuzzy => None,
+            HistorySearchMode::Prefix => Some(format!("^{}", regex::escape(search.pattern.as_str()))),
+            HistorySearchMode::Regex => Some(search.pattern.clone()),
+            HistorySearchMode::Substring => Some(regex::escape(search.pattern.as_str()))
+        }.map(|x| RegexBuilder::new(x.as_str()).case_insensitive(! search.case_sensitive).build()).transpose().map_err(|_| ShellError::BadValue(search.pattern.clone()))?;
+        let pattern: Vec<char> = search.pattern.chars().collect();
+        let mut matches: Vec<HistoryMatch> = Vec::new();
+        for (index, entry) in self.history.iter().enumerate() {
+            //Fuzzy matches must be sorted before being limited
+            if search.mode != HistorySearchMode::Fuzzy && Some(matches.len()) == search.max_entries {
                 break;
             }
-            self.history.push_front(entry.clone());
+            if search.cwd.is_some() && entry.cwd != search.cwd {
+                continue;
+            }
+            if search.exit_code.is_some() && entry.exit_code != search.exit_code {
+                continue;
+            }
+            let result: Option<(i64, Vec<(usize, usize)>)> = match &regex {
+                Some(regex) => match regex.is_match(entry.command.as_str()) {
+                    true => Some((0, regex.find_iter(entry.command.as_str()).filter(|x| x.start() < x.end()).map(|x| (x.start(), x.end())).collect())),
+                    false => None
+                },
+                None => fuzzy_match(&pattern, entry.command.as_str(), search.case_sensitive)
+            };
+            if let Some((score, spans)) = result {
+                matches.push(HistoryMatch {
+                    index,
+                    command: entry.command.clone(),
+                    score,
+                    spans
+                });
+            }
+        }
+        if search.mode == HistorySearchMode::Fuzzy {
+            //Stable sort, so the newest entries come first among the ones with the same score
+            matches.sort_by_key(|x| std::cmp::Reverse(x.score));
+            if let Some(max_entries) = search.max_entries {
+                matches.truncate(max_entries);
+            }
         }
+        Ok(matches)
+    }
+
+    /// ### history_size
+    /// 
+    /// Returns the maximum amount of entries in the history. `HISTSIZE` takes precedence over the size provided at constructor;
+    /// if it's negative, the history has no limit
+    pub fn history_size(&self) -> usize {
+        match self.value_get(&String::from("HISTSIZE")).map(|x| x.trim().parse::<isize>()) {
+            Some(Ok(size)) if size < 0 => usize::MAX,
+            Some(Ok(size)) => size as usize,
+            _ => self.history_size
+        }
+    }
+
+    /// ### history_set_size
+    /// 
+    /// Set the history size used when `HISTSIZE` is not set; the oldest entries exceeding the size are removed
+    pub fn history_set_size(&mut self, size: usize) {
+        self.history_size = size;
+        self.history_truncate();
     }
 
     /// ### history_push
     /// 
-    /// Push a new entry to the history.
+    /// Push a new entry to the history, unless it's excluded by `HISTCONTROL` or `HISTIGNORE`.
     /// The entry is stored at the front of the history. The first the newest
-    pub(crate) fn history_push(&mut self, expression: String) {
-        //Check if history overflows the size
-        let history_size: usize = (self.history.capacity() + 1) / 2;
-        if self.history.len() + 1 > history_size {
-            self.history.pop_back();
+    /// Returns whether the entry has been stored
+    pub(crate) fn history_push(&mut self, expression: String) -> bool {
+        let command: &str = expression.trim_end_matches('\n');
+        let previous: Option<String> = self.history_at(0);
+        //HISTCONTROL: colon separated list of ignorespace, ignoredups, ignoreboth and erasedups
+        let control: String = self.value_get(&String::from("HISTCONTROL")).unwrap_or_default();
+        let mut erase_dups: bool = false;
+        for option in control.split(':') {
+            match option {
+                "ignorespace" | "ignoreboth" if command.starts_with(' ') => return false,
+                "ignoredups" | "ignoreboth" if previous.as_deref() == Some(command) => return false,
+                "erasedups" => erase_dups = true,
+                _ => {}
+            }
         }
-        self.history.push_front(expression);
+        //HISTIGNORE: colon separated list of patterns which must match the whole line; `&` matches the previous entry
+        let ignore: String = self.value_get(&String::from("HISTIGNORE")).unwrap_or_default();
+        for pattern in ignore.split(':').filter(|x| ! x.is_empty()) {
+            let mut expanded: String = String::with_capacity(pattern.len());
+            let mut escaped: bool = false;
+            for c in pattern.chars() {
+                match c {
+                    '&' if ! escaped => expanded.push_str(wildcard::escape(previous.as_deref().unwrap_or_default()).as_str()),
+                    c => expanded.push(c)
+                }
+                escaped = c == '\\' && ! escaped;
+            }
+            if wildcard::matches(expanded.as_str(), command) {
+                return false
+            }
+        }
+        if erase_dups {
+            self.history_unsaved -= self.history.iter().take(self.history_unsaved).filter(|x| x.command == command).count();
+            self.history.retain(|x| x.command != command);
+        }
+        self.history_unsaved += 1;
+        let mut entry: HistoryEntry = history_entry(String::from(command));
+        entry.cwd = Some(self.wrk_dir.clone());
+        self.history_append(entry);
+        true
+    }
+
+    /// ### history_append
+    /// 
+    /// Push an entry to the front of the history, as it is, removing the oldest entries exceeding the history size
+    pub(crate) fn history_append(&mut self, entry: HistoryEntry) {
+        self.history.push_front(entry);
+        self.history_truncate();
+    }
+
+    /// ### history_complete
+    /// 
+    /// Set the duration and the exit code of the last history entry, once its command has terminated
+    pub(crate) fn history_complete(&mut self, command: &str, duration: Duration, exit_code: u8) {
+        if let Some(entry) = self.history.front_mut().filter(|x| x.exit_code.is_none() && x.command == command.trim_end_matches('\n')) {
+            entry.duration = Some(duration);
+            entry.exit_code = Some(exit_code);
+        }
+    }
+
+    /// ### history_truncate
+    /// 
+    /// Remove the oldest entries exceeding the history size
+    fn history_truncate(&mut self) {
+        let history_size: usize = self.history_size();
+        self.history.truncate(history_size);
+        self.history_unsaved = self.history_unsaved.min(self.history.len());
     }
 
     //@! Misc
 
     /// ### resolve_path
     /// 
-    /// Resolve path
+    /// Resolve path, expanding a leading tilde. `-` is resolved to the previous directory
     pub fn resolve_path(&self, path: String) -> PathBuf {
-        let mut resolved_path: String = path.clone();
-        //If path starts with '~', replace ~ with home dir
-        if resolved_path.starts_with("~") {
-            let orig_path: String = resolved_path.clone();
-            let path_after_tilde = &orig_path.as_str()[1..];
-            resolved_path = String::from(self.home_dir.as_path().to_str().unwrap());
-            resolved_path.push_str(path_after_tilde);
-        } else if resolved_path == "-" {
-            resolved_path = String::from(self.prev_dir.as_path().to_str().unwrap());
+        match path.as_str() {
+            "-" => self.prev_dir.clone(),
+            _ => PathBuf::from(self.expand_tilde(&path, false))
+        }
+    }
+
+    /// ### expand_tilde
+    /// 
+    /// Perform tilde expansion on a word. The supported prefixes are:
+    /// - `~`: home directory
+    /// - `~user`: home directory of user (from the passwd database)
+    /// - `~+`: current working directory
+    /// - `~-`: previous working directory
+    /// - `~N`, `~+N`, `~-N`: Nth element of the directory stack, starting from the front (or from the back with `-`)
+    /// 
+    /// The prefix lasts until the first `/`. If assignment is true, tildes after `=` and `:` are expanded too, and the prefix lasts until `:` as well.
+    /// Quoted and escaped (`\~`) tildes are never expanded; the escape is removed.
+    /// Prefixes which can't be resolved are left unchanged
+    pub fn expand_tilde(&self, word: &str, assignment: bool) -> String {
+        let chars: Vec<char> = word.chars().collect();
+        let mut output: String = String::with_capacity(word.len());
+        let mut index: usize = 0;
+        let mut expandable: bool = true; //Whether a tilde in this position can be expanded
+        let mut assigned: bool = false; //Whether the first '=' has already been found
+        while index < chars.len() {
+            let c: char = chars[index];
+            if c == '\\' && chars.get(index + 1) == Some(&'~') {
+                //Escaped tilde
+                output.push('~');
+                index += 2;
+                expandable = false;
+                continue;
+            }
+            if c == '\'' || c == '"' {
+                //Quoted text is never expanded
+                let end: usize = match chars[index + 1..].iter().position(|x| *x == c) {
+                    Some(position) => index + position + 2,
+                    None => chars.len()
+                };
+                output.extend(chars[index..end].iter());
+                index = end;
+                expandable = false;
+                continue;
+            }
+            if c == '~' && expandable {
+                //Get prefix
+                let mut end: usize = index + 1;
+                while end < chars.len() && chars[end] != '/' && ! (assignment && chars[end] == ':') {
+                    end += 1;
+                }
+                let prefix: String = chars[index + 1..end].iter().collect();
+                match self.tilde_prefix(&prefix) {
+                    Some(dir) => output.push_str(dir.to_string_lossy().as_ref()),
+                    None => {
+                        output.push('~');
+                        output.push_str(prefix.as_str());
+                    }
+                }
+                index = end;
+                expandable = false;
+                continue;
+            }
+            output.push(c);
+            expandable = assignment && (c == ':' || (c == '=' && ! assigned));
+            if c == '=' {
+                assigned = true;
+            }
+            index += 1;
+        }
+        output
+    }
+
+    /// ### tilde_prefix
+    /// 
+    /// Resolve a tilde prefix (the characters between `~` and `/`) into a directory
+    fn tilde_prefix(&self, prefix: &str) -> Option<PathBuf> {
+        match prefix {
+            "" => Some(self.home_dir.clone()),
+            "+" => Some(self.wrk_dir.clone()),
+            "-" => Some(self.prev_dir.clone()),
+            _ => {
+                //Directory stack
+                let (from_back, digits): (bool, &str) = match prefix.chars().next() {
+                    Some('+') => (false, &prefix[1..]),
+                    Some('-') => (true, &prefix[1..]),
+                    _ => (false, prefix)
+                };
+                if ! digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+                    let index: usize = digits.parse::<usize>().ok()?;
+                    return match from_back {
+                        true => match index < self.dirs.len() {
+                            true => self.dirs.get(self.dirs.len() - 1 - index).cloned(),
+                            false => None
+                        },
+                        false => self.dirs.get(index).cloned()
+                    }
+                }
+                //User home
+                match User::from_name(prefix) {
+                    Ok(Some(user)) => Some(user.dir),
+                    _ => None
+                }
+            }
         }
-        PathBuf::from(resolved_path)
     }
 
     /// ### reverse_search
@@ -397,7 +1341,7 @@ impl ShellCore {
     pub fn reverse_search(&self, needle: &String, max_entries: Option<usize>) -> Option<Vec<String>> {
         let mut matches: Vec<String> = Vec::new();
         //Iterate over history
-        for entry in self.history.iter() {
+        for entry in self.history.iter().map(|x| &x.command) {
             if entry.as_str().contains(needle.as_str()) {
                 matches.push(entry.clone());
             }
@@ -423,7 +1367,7 @@ impl ShellCore {
     pub fn reverse_search_hits(&self, needle: &String, hit: usize) -> Option<String> {
         let mut matchnth: usize = 0;
         //Iterate over history
-        for entry in self.history.iter() {
+        for entry in self.history.iter().map(|x| &x.command) {
             if entry.as_str().contains(needle.as_str()) {
                 //If matchnth == hit, return entry
                 if matchnth == hit {
@@ -450,6 +1394,19 @@ impl ShellCore {
         if self.state != ShellState::Idle && self.state != ShellState::Waiting {
             return Err(ShellError::ShellNotInIdle)
         }
+        //History expansion; the expanded line is echoed, then it's run and stored in the history in place of the input
+        let stdin: String = match self.parser.expand_history(self, stdin.as_str()) {
+            Ok(Some((expanded, execute))) => {
+                let _ = self.sstream.send(ShellStreamMessage::HistoryExpansion(expanded.clone()));
+                if ! execute {
+                    let _ = self.history_push(expanded);
+                    return Ok(0)
+                }
+                expanded
+            },
+            Ok(None) => stdin,
+            Err(err) => return Err(ShellError::Parser(err))
+        };
         //If state is Waiting, concatenate bufin and stdin
         let stdin: String = match self.state {
             ShellState::Waiting => {
@@ -461,7 +1418,7 @@ impl ShellCore {
         match self.parser.parse(&self, &stdin) {
             Ok(expression) => {
                 //Push stdin to history
-                self.history_push(stdin.clone());
+                let in_history: bool = self.history_push(stdin.clone());
                 //Set state to Running
                 self.state = ShellState::Running;
                 //Instantiate runner
@@ -474,6 +1431,9 @@ impl ShellCore {
                 self.storage_set(String::from("status"), rc.to_string());
                 self.storage_set(String::from("?"), rc.to_string());
                 self.execution_time = self.execution_started.elapsed();
+                if in_history {
+                    self.history_complete(stdin.as_str(), self.execution_time, rc);
+                }
                 //Set state back to Idle
                 self.state = ShellState::Idle;
                 Ok(rc)
@@ -487,7 +1447,7 @@ impl ShellCore {
                     },
                     _ => {
                         //Push stdin to history
-                        self.history_push(stdin.clone());
+                        let _ = self.history_push(stdin.clone());
                     }
                 }
                 //Return error
@@ -517,6 +1477,13 @@ impl ShellCore {
         rc
     }
 
+    /// ### parse
+    /// 
+    /// Parse a command with the shell parser, without executing it
+    pub(crate) fn parse(&self, command: &String) -> Result<ShellExpression, ParserError> {
+        self.parser.parse(self, command)
+    }
+
     /// ### source
     /// 
     /// Source file
@@ -539,6 +1506,159 @@ impl ShellCore {
         }
     }
 
+    //@! Snapshot
+
+    /// ### snapshot
+    /// 
+    /// Take a snapshot of the shell state (working directory, variables, environment, aliases, functions, options and traps), which is restored when a subshell terminates
+    pub(crate) fn snapshot(&self) -> ShellCoreSnapshot {
+        ShellCoreSnapshot {
+            wrk_dir: self.wrk_dir.clone(),
+            prev_dir: self.prev_dir.clone(),
+            storage: self.storage.clone(),
+            alias: self.alias.clone(),
+            functions: self.functions.clone(),
+            dirs: self.dirs.clone(),
+            glob_options: self.glob_options,
+            traps: self.traps.clone(),
+            hash: self.hash.clone(),
+            hash_path: self.hash_path.clone(),
+            redirection: self.redirection.clone(),
+            stderr_redirection: self.stderr_redirection.clone(),
+            input_redirection: self.input_redirection.clone(),
+            environ: env::vars().collect(),
+            environ_overrides: self.environ_overrides.clone()
+        }
+    }
+
+    /// ### restore
+    /// 
+    /// Restore the shell state from a snapshot
+    pub(crate) fn restore(&mut self, snapshot: ShellCoreSnapshot) {
+        if self.wrk_dir != snapshot.wrk_dir {
+            let _ = env::set_current_dir(snapshot.wrk_dir.as_path());
+        }
+        self.wrk_dir = snapshot.wrk_dir;
+        self.prev_dir = snapshot.prev_dir;
+        self.storage = snapshot.storage;
+        self.alias = snapshot.alias;
+        self.functions = snapshot.functions;
+        self.dirs = snapshot.dirs;
+        self.glob_options = snapshot.glob_options;
+        //Reinstall the signal handlers of the traps which have changed
+        let mut trapped: Vec<UnixSignal> = Vec::new();
+        for signal in self.traps.keys().chain(snapshot.traps.keys()) {
+            if let TrapSignal::Signal(sig) = signal {
+                if ! trapped.contains(sig) {
+                    trapped.push(*sig);
+                }
+            }
+        }
+        for sig in trapped.into_iter() {
+            let signal: TrapSignal = TrapSignal::Signal(sig);
+            match snapshot.traps.get(&signal) {
+                current if current == self.traps.get(&signal) => {},
+                None => {
+                    self.signals.restore(sig);
+                },
+                Some(action) if action.is_empty() => {
+                    self.signals.ignore(sig);
+                },
+                Some(_) => {
+                    self.signals.catch(sig);
+                }
+            }
+        }
+        self.traps = snapshot.traps;
+        self.hash = snapshot.hash;
+        self.hash_path = snapshot.hash_path;
+        self.redirection = snapshot.redirection;
+        self.stderr_redirection = snapshot.stderr_redirection;
+        self.input_redirection = snapshot.input_redirection;
+        //Restore environment
+        self.environ_overrides = snapshot.environ_overrides;
+        for (key, value) in env::vars() {
+            match snapshot.environ.get(&key) {
+                None => env::remove_var(key),
+                Some(prev_value) if *prev_value != value => env::set_var(key, prev_value),
+                Some(_) => {}
+            }
+        }
+        for (key, value) in snapshot.environ.iter() {
+            if env::var_os(key).is_none() {
+                env::set_var(key, value);
+            }
+        }
+    }
+
+    //@! Startup
+
+    /// ### startup
+    /// 
+    /// Source the startup files for the provided mode, in order.
+    /// Errors are reported to the user stream and don't stop the startup; returns the exit code of the last file
+    pub fn startup(&mut self, mode: StartupMode) -> u8 {
+        let mut rc: u8 = 0;
+        for file in self.startup_files(mode).into_iter() {
+            rc = match self.source(file) {
+                Ok(rc) => rc,
+                Err(err) => {
+                    let _ = self.sstream.send(ShellStreamMessage::Error(err));
+                    1
+                }
+            };
+        }
+        rc
+    }
+
+    /// ### startup_files
+    /// 
+    /// Returns the files which are sourced at startup for the provided mode.
+    /// Default files which don't exist are skipped, while files named by `ENV` and `BASH_ENV` are always returned
+    pub fn startup_files(&self, mode: StartupMode) -> Vec<PathBuf> {
+        if let Some(files) = self.startup_files.get(&mode) {
+            return files.clone()
+        }
+        let mut files: Vec<PathBuf> = Vec::new();
+        let environ: Option<&str> = match mode {
+            StartupMode::Login => {
+                let profile: PathBuf = PathBuf::from("/etc/profile");
+                if profile.is_file() {
+                    files.push(profile);
+                }
+                let user_profile: Option<PathBuf> = [".bash_profile", ".bash_login", ".profile"].iter().map(|x| self.home_dir.join(x)).find(|x| x.is_file());
+                files.extend(user_profile);
+                None
+            },
+            StartupMode::Interactive => {
+                let bashrc: PathBuf = self.home_dir.join(".bashrc");
+                if bashrc.is_file() {
+                    files.push(bashrc);
+                }
+                Some("ENV")
+            },
+            StartupMode::NonInteractive => Some("BASH_ENV")
+        };
+        //Files named by the environment
+        if let Some(file) = environ.and_then(|x| self.value_get(&String::from(x))).filter(|x| ! x.is_empty()) {
+            let file: PathBuf = self.resolve_path(file);
+            if ! files.contains(&file) {
+                files.push(file);
+            }
+        }
+        files
+    }
+
+    /// ### set_startup_files
+    /// 
+    /// Override the files sourced at startup for the provided mode. An empty list disables the startup files, while None restores the defaults
+    pub fn set_startup_files(&mut self, mode: StartupMode, files: Option<Vec<PathBuf>>) {
+        match files {
+            Some(files) => self.startup_files.insert(mode, files),
+            None => self.startup_files.remove(&mode)
+        };
+    }
+
     //@! Storage
 
     /// ### value_get
@@ -563,8 +1683,11 @@ impl ShellCore {
 
     /// ### environ_get
     /// 
-    /// Get a variable from the environment
+    /// Get a variable from the environment; the overrides of the running commands come first
     fn environ_get(&self, key: &String) -> Option<String> {
+        if let Some(value) = self.environ_overrides.iter().rev().find_map(|x| x.get(key)) {
+            return Some(value.clone())
+        }
         match env::var_os(key.as_str()) {
             None => None,
             Some(val) => {
@@ -578,35 +1701,72 @@ impl ShellCore {
 
     /// ### environ_getall
     /// 
-    /// Get all variables stored in the environment
+    /// Get the entire environment, including the overrides of the running commands
     pub fn environ_getall(&self) -> HashMap<String, String> {
         let mut environ: HashMap<String, String> = HashMap::new();
         for (key, value) in env::vars() {
             environ.insert(key.clone(), value.clone());
         }
+        environ.extend(self.environ_overridden());
         environ
     }
 
     /// ### environ_set
     /// 
-    /// Set a value in the environment
+    /// Set a value in the environment; if the variable is overridden by a running command, only the override is changed.
     /// Returns false if the variable name is invalid
-    pub(crate) fn environ_set(&self, key: String, value: String) -> bool {
+    pub(crate) fn environ_set(&mut self, key: String, value: String) -> bool {
         if ! self.is_variable_name_valid(&key) {
             false
         } else {
-            env::set_var(key, value);
+            match self.environ_overrides.iter_mut().rev().find(|x| x.contains_key(&key)) {
+                Some(overrides) => {
+                    overrides.insert(key, value);
+                },
+                None => env::set_var(key, value)
+            }
             true
         }
     }
 
     /// ### environ_unset
     /// 
-    /// Remove a variable from the environment
-    fn environ_unset(&self, key: &String) {
+    /// Remove a variable from the environment and from the overrides
+    fn environ_unset(&mut self, key: &String) {
+        for overrides in self.environ_overrides.iter_mut() {
+            overrides.remove(key);
+        }
         env::remove_var(key.clone());
     }
 
+    /// ### environ_override
+    ///
+    /// Set the provided variables in the environment for the duration of a command (e.g. for `FOO=bar func`).
+    /// The overrides are kept by the core (the process environment is left untouched) and must be removed with `environ_restore` once the command has terminated.
+    /// Variables with an invalid name are ignored
+    pub(crate) fn environ_override(&mut self, overrides: &HashMap<String, String>) {
+        let overrides: HashMap<String, String> = overrides.iter().filter(|(key, _)| self.is_variable_name_valid(key)).map(|(key, value)| (key.clone(), value.clone())).collect();
+        self.environ_overrides.push(overrides);
+    }
+
+    /// ### environ_restore
+    ///
+    /// Remove the innermost overrides set by `environ_override`
+    pub(crate) fn environ_restore(&mut self) {
+        self.environ_overrides.pop();
+    }
+
+    /// ### environ_overridden
+    ///
+    /// Get the variables overridden by the running commands, which must be passed to the processes they spawn
+    pub(crate) fn environ_overridden(&self) -> HashMap<String, String> {
+        let mut environ: HashMap<String, String> = HashMap::new();
+        for overrides in self.environ_overrides.iter() {
+            environ.extend(overrides.iter().map(|(key, value)| (key.clone(), value.clone())));
+        }
+        environ
+    }
+
     /// ### storage_get
     /// 
     /// Get a value from the storage
@@ -636,7 +1796,12 @@ impl ShellCore {
         if ! self.is_variable_name_valid(&key) {
             false
         } else {
+            let histsize: bool = key == "HISTSIZE";
             self.storage.insert(key, value);
+            //The history is truncated as soon as HISTSIZE changes
+            if histsize {
+                self.history_truncate();
+            }
             true
         }
     }
@@ -653,11 +1818,98 @@ impl ShellCore {
         }
     }
 
+    /// ### storage_array_set
+    /// 
+    /// Set an array into the storage. Elements are stored as `NAME[index]`, while `NAME` holds the first element.
+    /// The previous elements of the array are removed.
+    /// Returns false if the variable name is invalid
+    pub(crate) fn storage_array_set(&mut self, key: String, values: Vec<String>) -> bool {
+        if ! self.is_variable_name_valid(&key) {
+            return false
+        }
+        self.storage_unset(&key);
+        self.storage.insert(key.clone(), values.first().cloned().unwrap_or_default());
+        for (index, value) in values.into_iter().enumerate() {
+            self.storage.insert(format!("{}[{}]", key, index), value);
+        }
+        true
+    }
+
     /// ### storage_unset
     /// 
-    /// Unset a value from the storage
+    /// Unset a value from the storage; array elements are removed too
     fn storage_unset(&mut self, key: &String) {
         let _ = self.storage.remove(key);
+        let prefix: String = format!("{}[", key);
+        self.storage.retain(|name, _| ! (name.starts_with(prefix.as_str()) && name.ends_with(']')));
+    }
+
+    //@! Traps
+
+    /// ### trap_get_all
+    /// 
+    /// Returns all the traps
+    pub(crate) fn trap_get_all(&self) -> HashMap<TrapSignal, String> {
+        self.traps.clone()
+    }
+
+    /// ### trap_get
+    /// 
+    /// Returns the action of a trap; an empty action means the signal is ignored
+    pub(crate) fn trap_get(&self, signal: &TrapSignal) -> Option<String> {
+        self.traps.get(signal).cloned()
+    }
+
+    /// ### trap_set
+    /// 
+    /// Set the action of a trap. Signals are caught when delivered to the shell process, or ignored if the action is empty.
+    /// Returns false if the signal handler couldn't be set (e.g. SIGKILL)
+    pub(crate) fn trap_set(&mut self, signal: TrapSignal, action: String) -> bool {
+        if let TrapSignal::Signal(sig) = signal {
+            let handled: bool = match action.is_empty() {
+                true => self.signals.ignore(sig),
+                false => self.signals.catch(sig)
+            };
+            if ! handled {
+                return false
+            }
+        }
+        self.traps.insert(signal, action);
+        true
+    }
+
+    /// ### trap_unset
+    /// 
+    /// Remove a trap; the signal gets back the disposition it had before being trapped
+    pub(crate) fn trap_unset(&mut self, signal: &TrapSignal) {
+        if self.traps.remove(signal).is_some() {
+            if let TrapSignal::Signal(sig) = signal {
+                self.signals.restore(*sig);
+            }
+        }
+    }
+
+    /// ### trap_pending
+    /// 
+    /// Returns the trapped signals which have been delivered to the shell process since the last call
+    pub(crate) fn trap_pending(&mut self) -> Vec<TrapSignal> {
+        let trapped: Vec<UnixSignal> = self.traps.iter().filter_map(|(signal, action)| match signal {
+            TrapSignal::Signal(sig) if ! action.is_empty() => Some(*sig),
+            _ => None
+        }).collect();
+        self.signals.take_pending(&trapped).into_iter().map(TrapSignal::Signal).collect()
+    }
+
+    /// ### trap_exit
+    /// 
+    /// Run the EXIT trap (only once)
+    fn trap_exit(&mut self) {
+        if let Some(action) = self.traps.remove(&TrapSignal::Exit) {
+            if let Ok(expression) = self.parse(&action) {
+                let mut runner: ShellRunner = ShellRunner::new();
+                let _ = runner.run(self, expression);
+            }
+        }
     }
 
     //@! Validators
@@ -692,6 +1944,309 @@ impl ShellCore {
  
 }
 
+/// ### function_environ_key
+/// 
+/// Returns the name of the environment variable which contains an exported function
+fn function_environ_key(name: &str) -> String {
+    format!("BASH_FUNC_{}%%", name)
+}
+
+/// ### history_entry
+/// 
+/// Returns a new history entry for command, run now
+fn history_entry(command: String) -> HistoryEntry {
+    HistoryEntry {
+        command,
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0),
+        duration: None,
+        exit_code: None,
+        cwd: None
+    }
+}
+
+/// ### history_parse
+/// 
+/// Parse the lines of a history file into history entries. Lines in the extended format (`: <start time>:<duration>;<command>`) continue
+/// on the next line if they end with a backslash; plain lines are preceded by their timestamp (`#<start time>`) in bash files
+fn history_parse<I: Iterator<Item = String>>(lines: I) -> Vec<HistoryEntry> {
+    let mut entries: Vec<HistoryEntry> = Vec::new();
+    let mut timestamp: Option<u64> = None;
+    let mut continued: bool = false;
+    for line in lines {
+        //Continuation of the previous entry
+        if continued {
+            if let Some(entry) = entries.last_mut() {
+                let (command, next): (String, bool) = history_unescape(line.as_str());
+                continued = next;
+                entry.command.push('\n');
+                entry.command.push_str(command.as_str());
+                continue;
+            }
+        }
+        //Extended format
+        if let Some((metadata, command)) = line.strip_prefix(": ").and_then(|x| x.split_once(';')) {
+            let metadata: Option<(u64, u64)> = metadata.split_once(':').and_then(|(start, duration)| Some((start.parse::<u64>().ok()?, duration.parse::<u64>().ok()?)));
+            if let Some((start, duration)) = metadata {
+                let (command, next): (String, bool) = history_unescape(command);
+                continued = next;
+                entries.push(HistoryEntry {
+                    command,
+                    timestamp: start,
+                    duration: Some(Duration::from_secs(duration)),
+                    exit_code: None,
+                    cwd: None
+                });
+                continue;
+            }
+        }
+        //Bash timestamps
+        if let Some(start) = line.strip_prefix('#').and_then(|x| x.parse::<u64>().ok()) {
+            timestamp = Some(start);
+            continue;
+        }
+        let mut entry: HistoryEntry = history_entry(line);
+        if let Some(start) = timestamp.take() {
+            entry.timestamp = start;
+        }
+        entries.push(entry);
+    }
+    entries
+}
+
+/// ### history_format
+/// 
+/// Format a history entry in the extended format of the history file; backslashes and newlines are escaped by a backslash
+fn history_format(entry: &HistoryEntry) -> String {
+    format!(": {}:{};{}", entry.timestamp, entry.duration.map(|x| x.as_secs()).unwrap_or(0), entry.command.replace('\\', "\\\\").replace('\n', "\\\n"))
+}
+
+/// ### history_unescape
+/// 
+/// Remove the escapes from a line of a command in the extended format (see history_format).
+/// Returns the line and whether the command continues on the next line (the newline is escaped)
+fn history_unescape(line: &str) -> (String, bool) {
+    let mut output: String = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            None => return (output, true),
+            Some('\\') => output.push('\\'),
+            Some(c) => {
+                //Other escapes are kept as they are
+                output.push('\\');
+                output.push(c);
+            }
+        }
+    }
+    (output, false)
+}
+
+/// ### fuzzy_match
+/// 
+/// Match pattern as a subsequence of text, choosing the positions with the best score.
+/// Each matched character scores, with a bonus if it starts a word or follows the previous match; gaps between the matches are penalized.
+/// Returns the score and the byte ranges of text which have been matched, or None if text doesn't contain the pattern
+fn fuzzy_match(pattern: &[char], text: &str, case_sensitive: bool) -> Option<(i64, Vec<(usize, usize)>)> {
+    const SCORE_MATCH: i64 = 16;
+    const BONUS_BOUNDARY: i64 = 8;
+    const BONUS_CAMEL_CASE: i64 = 7;
+    const BONUS_CONSECUTIVE: i64 = 8;
+    const PENALTY_GAP_START: i64 = 3;
+    const PENALTY_GAP_EXTENSION: i64 = 1;
+    if pattern.is_empty() {
+        return Some((0, Vec::new()))
+    }
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let equals = |a: char, b: char| -> bool {
+        match case_sensitive {
+            true => a == b,
+            false => a == b || a.to_lowercase().eq(b.to_lowercase())
+        }
+    };
+    //Quick check: the pattern must be a subsequence of text
+    let mut position: usize = 0;
+    for p in pattern.iter() {
+        position += chars.iter().skip(position).position(|(_, c)| equals(*p, *c))? + 1;
+    }
+    //Bonus of each position of text
+    let bonus: Vec<i64> = chars.iter().enumerate().map(|(i, (_, c))| {
+        match i.checked_sub(1).map(|x| chars[x].1) {
+            None => BONUS_BOUNDARY,
+            Some(prev) if ! prev.is_alphanumeric() && c.is_alphanumeric() => BONUS_BOUNDARY,
+            Some(prev) if prev.is_lowercase() && c.is_uppercase() => BONUS_CAMEL_CASE,
+            Some(_) => 0
+        }
+    }).collect();
+    //scores[i][j] is the best score of pattern[..=i] with pattern[i] matched at text[j]; parents[i][j] is where pattern[i - 1] was matched
+    let mut scores: Vec<Vec<Option<i64>>> = vec![vec![None; chars.len()]; pattern.len()];
+    let mut parents: Vec<Vec<usize>> = vec![vec![0; chars.len()]; pattern.len()];
+    for (i, p) in pattern.iter().enumerate() {
+        //Best score of the previous row followed by a gap, before j
+        let mut gapped: Option<(i64, usize)> = None;
+        for j in i..chars.len() {
+            if i > 0 && j >= 2 {
+                gapped = gapped.map(|(score, parent)| (score - PENALTY_GAP_EXTENSION, parent));
+                if let Some(score) = scores[i - 1][j - 2] {
+                    if gapped.map(|(best, _)| score - PENALTY_GAP_START > best).unwrap_or(true) {
+                        gapped = Some((score - PENALTY_GAP_START, j - 2));
+                    }
+                }
+            }
+            if ! equals(*p, chars[j].1) {
+                continue;
+            }
+            let base: i64 = SCORE_MATCH + bonus[j];
+            if i == 0 {
+                scores[i][j] = Some(base);
+                continue;
+            }
+            let consecutive: Option<i64> = scores[i - 1][j - 1].map(|x| x + base + BONUS_CONSECUTIVE);
+            let gap: Option<i64> = gapped.map(|(score, _)| score + base);
+            match (consecutive, gap) {
+                (Some(consecutive), Some(gap)) if gap > consecutive => {
+                    scores[i][j] = Some(gap);
+                    parents[i][j] = gapped.map(|(_, parent)| parent).unwrap_or(0);
+                }
+                (Some(consecutive), _) => {
+                    scores[i][j] = Some(consecutive);
+                    parents[i][j] = j - 1;
+                }
+                (None, Some(gap)) => {
+                    scores[i][j] = Some(gap);
+                    parents[i][j] = gapped.map(|(_, parent)| parent).unwrap_or(0);
+                }
+                (None, None) => {}
+            }
+        }
+    }
+    //Take the best score of the last row (the leftmost one on draw) and walk back to the matched positions
+    let last: usize = pattern.len() - 1;
+    let mut best: Option<(usize, i64)> = None;
+    for (j, score) in scores[last].iter().enumerate() {
+        if let Some(score) = score {
+            if best.map(|(_, best)| *score > best).unwrap_or(true) {
+                best = Some((j, *score));
+            }
+        }
+    }
+    let (mut j, score): (usize, i64) = best?;
+    let mut positions: Vec<usize> = vec![j];
+    for i in (1..pattern.len()).rev() {
+        j = parents[i][j];
+        positions.push(j);
+    }
+    positions.reverse();
+    //Merge the adjacent positions into byte ranges
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    for j in positions {
+        let (start, c): (usize, char) = chars[j];
+        let end: usize = start + c.len_utf8();
+        match spans.last_mut() {
+            Some(span) if span.1 == start => span.1 = end,
+            _ => spans.push((start, end))
+        }
+    }
+    Some((score, spans))
+}
+
+/// ### completion_unquote
+/// 
+/// Remove quotes and escapes from a word being completed, starting with quote open (if any).
+/// Returns the unquoted text and the quote which is still open at the end of the word
+fn completion_unquote(word: &str, quote: Option<char>) -> (String, Option<char>) {
+    let mut output: String = String::with_capacity(word.len());
+    let mut quote: Option<char> = quote;
+    let mut chars = word.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '\\') => output.extend(chars.next()),
+            (Some('"'), '\\') if matches!(chars.peek(), Some('"') | Some('\\') | Some('$') | Some('`')) => output.extend(chars.next()),
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (_, c) => output.push(c)
+        }
+    }
+    (output, quote)
+}
+
+/// ### completion_quote
+/// 
+/// Returns the replacement of the word being completed, made up of prefix (kept as it's been written) and value, which is quoted like the rest of the word:
+/// inside the open quote, if any, or escaping the special characters. If terminated, the open quote is closed
+fn completion_quote(prefix: &str, value: &str, quote: Option<char>, terminated: bool) -> String {
+    let mut replacement: String = String::from(prefix);
+    //Reopen the quote if it was opened in the file name
+    if let Some(quote) = quote {
+        if completion_unquote(prefix, None).1.is_none() {
+            replacement.push(quote);
+        }
+    }
+    for c in value.chars() {
+        match (quote, c) {
+            (Some('\''), '\'') => replacement.push_str("'\\''"),
+            (Some('"'), '"') | (Some('"'), '\\') | (Some('"'), '$') | (Some('"'), '`') => {
+                replacement.push('\\');
+                replacement.push(c);
+            },
+            (None, c) if c.is_whitespace() || "'\"\\$`&|;()<>*?[]{}!#".contains(c) => {
+                replacement.push('\\');
+                replacement.push(c);
+            },
+            (_, c) => replacement.push(c)
+        }
+    }
+    if let (Some(quote), true) = (quote, terminated) {
+        replacement.push(quote);
+    }
+    replacement
+}
+
+/// ### completion_users
+/// 
+/// Returns the names of the users in the passwd database
+fn completion_users() -> Vec<String> {
+    match std::fs::read_to_string("/etc/passwd") {
+        Ok(passwd) => passwd.lines().filter(|x| ! x.starts_with('#')).filter_map(|x| x.split(':').next()).filter(|x| ! x.is_empty()).map(String::from).collect(),
+        Err(_) => Vec::new()
+    }
+}
+
+/// ### io_error
+/// 
+/// Convert an IO error on path into a ShellError
+fn io_error(err: std::io::Error, path: &Path) -> ShellError {
+    match err.kind() {
+        ErrorKind::NotFound => ShellError::NoSuchFileOrDirectory(path.to_path_buf()),
+        ErrorKind::PermissionDenied => ShellError::PermissionDenied(path.to_path_buf()),
+        _ => ShellError::Other
+    }
+}
+
+/// ### edit_distance
+/// 
+/// Returns the Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ch_a) in a.chars().enumerate() {
+        let mut prev: usize = row[0];
+        row[0] = i + 1;
+        for (j, ch_b) in b.iter().enumerate() {
+            let current: usize = row[j + 1];
+            row[j + 1] = match ch_a == *ch_b {
+                true => prev,
+                false => 1 + prev.min(row[j]).min(row[j + 1])
+            };
+            prev = current;
+        }
+    }
+    row[b.len()]
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -700,6 +2255,9 @@ mod tests {
     use crate::ShellStatement;
     use crate::TaskRelation;
 
+    use nix::libc;
+    use nix::unistd::getuid;
+    use std::io::Write;
     use std::process::Command;
 
     #[test]
@@ -761,24 +2319,198 @@ mod tests {
     fn test_core_change_dir() {
         let (mut core, _): (ShellCore, UserStream) = ShellCore::new(Some(PathBuf::from("/tmp/")), 128, Box::new(Bash::new()));
         //Change directory
-        assert!(core.change_directory(PathBuf::from("/var/")).is_ok());
+        assert!(core.change_directory(PathBuf::from("/var/"), false).is_ok());
         //Verify current directory/previous directory
         assert_eq!(core.get_wrkdir(), PathBuf::from("/var/"));
         assert_eq!(core.get_prev_dir(), PathBuf::from("/tmp/"));
         //Change directory to unexisting directory
-        assert_eq!(core.change_directory(PathBuf::from("/pippoland/")).err().unwrap(), ShellError::NoSuchFileOrDirectory(PathBuf::from("/pippoland/")));
+        assert_eq!(core.change_directory(PathBuf::from("/pippoland/"), false).err().unwrap(), ShellError::NoSuchFileOrDirectory(PathBuf::from("/pippoland/")));
         //Verify directories didn't change
         assert_eq!(core.get_wrkdir(), PathBuf::from("/var/"));
         assert_eq!(core.get_prev_dir(), PathBuf::from("/tmp/"));
         //Try to change directory to file
         let tmpfile: tempfile::NamedTempFile = create_tmpfile();
-        assert_eq!(core.change_directory(PathBuf::from(tmpfile.path())).err().unwrap(), ShellError::NotADirectory(PathBuf::from(tmpfile.path())));
+        assert_eq!(core.change_directory(PathBuf::from(tmpfile.path()), false).err().unwrap(), ShellError::NotADirectory(PathBuf::from(tmpfile.path())));
         //Try to change directory to a directory where you can't enter
         let tmpdir: tempfile::TempDir = create_tmpdir();
         //Use chmod instead of set_mode because it just doesn't work...
         assert!(Command::new("chmod").args(&["000", tmpdir.path().to_str().unwrap()]).status().is_ok());
         //Okay, now try to change directory inside that directory
-        assert_eq!(core.change_directory(PathBuf::from(tmpdir.path())).err().unwrap(), ShellError::PermissionDenied(PathBuf::from(tmpdir.path())));
+        assert_eq!(core.change_directory(PathBuf::from(tmpdir.path()), false).err().unwrap(), ShellError::PermissionDenied(PathBuf::from(tmpdir.path())));
+    }
+
+    #[test]
+    fn test_core_change_dir_logical_physical() {
+        let tmpdir: tempfile::TempDir = create_tmpdir();
+        let root: PathBuf = tmpdir.path().canonicalize().unwrap();
+        //Make root/a/real and symlink root/link => root/a/real
+        let mut real: PathBuf = root.clone();
+        real.push("a/real");
+        assert!(std::fs::create_dir_all(real.as_path()).is_ok());
+        let mut link: PathBuf = root.clone();
+        link.push("link");
+        assert!(std::os::unix::fs::symlink(real.as_path(), link.as_path()).is_ok());
+        let (mut core, _): (ShellCore, UserStream) = ShellCore::new(Some(root.clone()), 128, Box::new(Bash::new()));
+        //Logical change directory keeps symlink
+        assert!(core.change_directory(PathBuf::from("link"), false).is_ok());
+        assert_eq!(core.get_wrkdir(), link);
+        assert_eq!(core.get_prev_dir(), root);
+        //.. is relative to the logical path
+        assert!(core.change_directory(PathBuf::from(".."), false).is_ok());
+        assert_eq!(core.get_wrkdir(), root);
+        assert!(core.change_directory(PathBuf::from("./link/../a/./real"), false).is_ok());
+        assert_eq!(core.get_wrkdir(), real);
+        //Physical change directory resolves symlinks
+        assert!(core.change_directory(link.clone(), true).is_ok());
+        assert_eq!(core.get_wrkdir(), real);
+        assert!(core.change_directory(PathBuf::from(".."), true).is_ok());
+        let mut parent: PathBuf = root.clone();
+        parent.push("a");
+        assert_eq!(core.get_wrkdir(), parent);
+        //Not existing directory
+        assert!(core.change_directory(PathBuf::from("pippoland"), true).is_err());
+        assert_eq!(core.get_wrkdir(), parent);
+    }
+
+    #[test]
+    fn test_core_cdpath_resolve() {
+        let tmpdir: tempfile::TempDir = create_tmpdir();
+        let root: PathBuf = tmpdir.path().canonicalize().unwrap();
+        let mut projects: PathBuf = root.clone();
+        projects.push("projects/shell");
+        assert!(std::fs::create_dir_all(projects.as_path()).is_ok());
+        let (mut core, _): (ShellCore, UserStream) = ShellCore::new(Some(PathBuf::from("/")), 128, Box::new(Bash::new()));
+        //No CDPATH
+        assert!(core.cdpath_resolve(&PathBuf::from("shell")).is_none());
+        assert!(core.storage_set(String::from("CDPATH"), format!(":/pippoland:{}/projects", root.display())));
+        assert_eq!(core.cdpath_resolve(&PathBuf::from("shell")).unwrap(), (projects, true));
+        //Current directory has priority
+        assert_eq!(core.cdpath_resolve(&PathBuf::from("tmp")).unwrap(), (PathBuf::from("/tmp"), false));
+        //Absolute and dot paths are never searched
+        assert!(core.cdpath_resolve(&PathBuf::from("/shell")).is_none());
+        assert!(core.cdpath_resolve(&PathBuf::from("./shell")).is_none());
+        assert!(core.cdpath_resolve(&PathBuf::from("../shell")).is_none());
+        assert!(core.cdpath_resolve(&PathBuf::from("pippoland")).is_none());
+    }
+
+    #[test]
+    fn test_core_commands() {
+        let (mut core, _): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash::new()));
+        //Prepare a PATH with an executable
+        let tmpdir: tempfile::TempDir = create_tmpdir();
+        let executable: PathBuf = tmpdir.path().join("mycommand");
+        std::fs::write(executable.as_path(), "#!/bin/sh\n").unwrap();
+        assert!(Command::new("chmod").args(["755", executable.to_str().unwrap()]).status().unwrap().success());
+        std::fs::write(tmpdir.path().join("notexecutable"), "").unwrap();
+        assert!(core.storage_set(String::from("PATH"), String::from(tmpdir.path().to_str().unwrap())));
+        //Resolve
+        assert_eq!(core.resolve_command("mycommand"), CommandType::File(executable.clone()));
+        assert_eq!(core.resolve_command("notexecutable"), CommandType::NotFound);
+        assert_eq!(core.resolve_command(executable.to_str().unwrap()), CommandType::File(executable.clone()));
+        assert_eq!(core.resolve_command("cd"), CommandType::Builtin);
+        assert_eq!(core.resolve_command("echo"), CommandType::Builtin);
+        assert!(core.function_set(String::from("mycommand"), ShellExpression { statements: vec![] }));
+        assert_eq!(core.resolve_command("mycommand"), CommandType::Function);
+        assert!(core.alias_set(String::from("mycommand"), String::from("ls -l")));
+        assert_eq!(core.resolve_command("mycommand"), CommandType::Alias(String::from("ls -l")));
+        assert_eq!(core.resolve_command_all("mycommand"), vec![CommandType::Alias(String::from("ls -l")), CommandType::Function, CommandType::File(executable.clone())]);
+        //Similar commands
+        assert!(core.alias_set(String::from("mycmd"), String::from("ls")));
+        assert_eq!(core.similar_commands("mycomand", 3), vec![String::from("mycommand"), String::from("mycmd")]);
+        assert_eq!(core.similar_commands("mycomand", 1), vec![String::from("mycommand")]);
+        assert!(core.similar_commands("mycommand", 0).is_empty());
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", "abc"), 0);
+        //Hash table
+        assert!(core.hash_get_all().is_empty());
+        assert_eq!(core.hash_lookup("mycommand"), Some(executable.clone()));
+        assert_eq!(core.hash_lookup("mycommand"), Some(executable.clone()));
+        assert_eq!(core.hash_lookup("notexecutable"), None);
+        assert_eq!(core.hash_lookup(executable.to_str().unwrap()), None);
+        assert_eq!(core.hash_get_all(), vec![(String::from("mycommand"), executable.clone(), 2)]);
+        //Hashed path is used even if the file is moved
+        std::fs::rename(executable.as_path(), tmpdir.path().join("moved")).unwrap();
+        assert_eq!(core.hash_lookup("mycommand"), Some(executable.clone()));
+        assert!(core.hash_remove("mycommand"));
+        assert!(! core.hash_remove("mycommand"));
+        assert_eq!(core.hash_lookup("mycommand"), None);
+        core.hash_set(String::from("ls"), PathBuf::from("/bin/ls"));
+        assert_eq!(core.hash_get("ls"), Some(PathBuf::from("/bin/ls")));
+        //PATH changes invalidate the table
+        assert!(core.storage_set(String::from("PATH"), String::from("/usr/bin:/bin")));
+        assert!(core.hash_get_all().is_empty());
+        core.hash_set(String::from("ls"), PathBuf::from("/bin/ls"));
+        core.hash_clear();
+        assert!(core.hash_get_all().is_empty());
+    }
+
+    #[test]
+    fn test_core_complete() {
+        let tmpdir: tempfile::TempDir = create_tmpdir();
+        let root: PathBuf = tmpdir.path().canonicalize().unwrap();
+        std::fs::write(root.join("alpha.txt"), "").unwrap();
+        std::fs::write(root.join(".hidden"), "").unwrap();
+        assert!(std::fs::create_dir_all(root.join("alpine")).is_ok());
+        assert!(std::fs::create_dir_all(root.join("My Dir")).is_ok());
+        std::fs::write(root.join("My Dir/sub file"), "").unwrap();
+        assert!(std::fs::create_dir_all(root.join("cdpath/project")).is_ok());
+        assert!(std::fs::create_dir_all(root.join("bin")).is_ok());
+        let executable: PathBuf = root.join("bin/mycmdexec");
+        std::fs::write(executable.as_path(), "#!/bin/sh\n").unwrap();
+        assert!(Command::new("chmod").args(["755", executable.to_str().unwrap()]).status().unwrap().success());
+        let (mut core, _): (ShellCore, UserStream) = ShellCore::new(Some(root.clone()), 128, Box::new(Bash::new()));
+        let values = |completions: Vec<Completion>| -> Vec<String> { completions.into_iter().map(|x| x.value).collect() };
+        //Files and directories
+        let completions: Vec<Completion> = core.complete("cat al", 6);
+        assert_eq!(completions, vec![
+            Completion { value: String::from("alpha.txt"), replacement: String::from("alpha.txt"), kind: CompletionKind::File, start: 4, end: 6 },
+            Completion { value: String::from("alpine/"), replacement: String::from("alpine/"), kind: CompletionKind::Directory, start: 4, end: 6 }
+        ]);
+        assert_eq!(values(core.complete("cat ", 4)), vec![String::from("My Dir/"), String::from("alpha.txt"), String::from("alpine/"), String::from("bin/"), String::from("cdpath/")]);
+        assert_eq!(values(core.complete("cat .h", 6)), vec![String::from(".hidden")]);
+        assert_eq!(values(core.complete("echo al > al", 12)), vec![String::from("alpha.txt"), String::from("alpine/")]);
+        //Only the word before the cursor is completed
+        assert_eq!(core.complete("cat al | wc", 6).len(), 2);
+        //Special characters are quoted like the word
+        assert_eq!(core.complete("cat My", 6)[0].replacement, String::from("My\\ Dir/"));
+        assert_eq!(core.complete("cat My\\ Dir/", 12)[0].replacement, String::from("My\\ Dir/sub\\ file"));
+        assert_eq!(core.complete("cat \"My Dir/s", 13)[0].replacement, String::from("\"My Dir/sub file\""));
+        assert_eq!(core.complete("cat My\\ Dir/'s", 14)[0].replacement, String::from("My\\ Dir/'sub file'"));
+        //Commands
+        assert!(core.storage_set(String::from("PATH"), format!("{}/bin", root.display())));
+        assert!(core.alias_set(String::from("mycmdalias"), String::from("ls")));
+        assert!(core.function_set(String::from("mycmdfunction"), ShellExpression { statements: vec![] }));
+        let completions: Vec<Completion> = core.complete("mycmd", 5);
+        assert_eq!(values(completions.clone()), vec![String::from("mycmdalias"), String::from("mycmdexec"), String::from("mycmdfunction")]);
+        assert_eq!(completions.iter().map(|x| x.kind).collect::<Vec<CompletionKind>>(), vec![CompletionKind::Alias, CompletionKind::Command, CompletionKind::Function]);
+        assert_eq!(core.complete("ls; FOO=bar pus", 15), vec![Completion { value: String::from("pushd"), replacement: String::from("pushd"), kind: CompletionKind::Builtin, start: 12, end: 15 }]);
+        assert!(core.alias_set(String::from("mycmdexec"), String::from("ls")));
+        assert_eq!(core.complete("mycmde", 6)[0].kind, CompletionKind::Alias);
+        assert_eq!(values(core.complete("./al", 4)), vec![String::from("alpha.txt"), String::from("alpine/")]);
+        //Variables
+        assert!(core.storage_set(String::from("COMPLETION_VAR"), String::from("1")));
+        assert_eq!(core.complete("echo $COMPLETION_V", 18), vec![Completion { value: String::from("COMPLETION_VAR"), replacement: String::from("$COMPLETION_VAR"), kind: CompletionKind::Variable, start: 5, end: 18 }]);
+        assert_eq!(core.complete("echo \"${COMPLETION_", 19)[0].replacement, String::from("\"${COMPLETION_VAR}"));
+        //cd: directories and CDPATH
+        assert_eq!(values(core.complete("cd al", 5)), vec![String::from("alpine/")]);
+        assert!(core.complete("cd pro", 6).is_empty());
+        assert!(core.storage_set(String::from("CDPATH"), format!("{}/cdpath", root.display())));
+        assert_eq!(values(core.complete("cd pro", 6)), vec![String::from("project/")]);
+        assert!(core.complete("cd ./pro", 8).is_empty());
+        //Directory stack
+        core.pushd(root.join("alpine"));
+        core.pushd(root.join("My Dir"));
+        assert_eq!(values(core.complete("popd +", 6)), vec![String::from("+0"), String::from("+1"), String::from("+2")]);
+        assert_eq!(values(core.complete("cd ~-", 5)), vec![String::from("~-0"), String::from("~-1"), String::from("~-2")]);
+        assert_eq!(core.complete("cd ~1", 5)[0].kind, CompletionKind::DirStack);
+        //Users and home
+        let user: String = User::from_uid(getuid()).unwrap().unwrap().name;
+        let prefix: String = format!("ls ~{}", &user[..user.len() - 1]);
+        assert!(values(core.complete(prefix.as_str(), prefix.len())).contains(&format!("~{}/", user)));
+        assert!(core.complete("ls ~/", 5).iter().all(|x| x.replacement.starts_with("~/")));
+        //Bad cursor
+        assert!(core.complete("cat al", 7).is_empty());
     }
 
     #[test]
@@ -810,7 +2542,7 @@ mod tests {
     fn test_core_get_files() {
         let (mut core, _): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash::new()));
         //CD to tmp
-        assert!(core.change_directory(PathBuf::from("/tmp/")).is_ok());
+        assert!(core.change_directory(PathBuf::from("/tmp/"), false).is_ok());
         //Create tmp files
         let tmpfile1: tempfile::NamedTempFile = create_tmpfile();
         let tmpfile2: tempfile::NamedTempFile = create_tmpfile();
@@ -860,6 +2592,24 @@ mod tests {
         assert!(! core.function_set(String::from("5loops"), test_function));
     }
 
+    #[test]
+    fn test_core_function_import() {
+        //NOTE: the environment is shared by the tests, so the names must be unique
+        env::set_var("BASH_FUNC_core_imported_group%%", "() { echo group; }");
+        env::set_var("BASH_FUNC_core_imported_subshell%%", "() ( echo subshell )");
+        env::set_var("BASH_FUNC_core_imported_injected%%", "() { :; }; echo injected");
+        env::set_var("BASH_FUNC_core_imported_command%%", "() echo command");
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash::new()));
+        for name in ["group", "subshell", "injected", "command"].iter() {
+            env::remove_var(format!("BASH_FUNC_core_imported_{}%%", name));
+        }
+        assert_eq!(core.function_source("core_imported_group").unwrap(), String::from("{ echo group; }"));
+        assert_eq!(core.function_source("core_imported_subshell").unwrap(), String::from("( echo subshell )"));
+        //Only a single compound command is accepted as body
+        assert!(core.function_get(&String::from("core_imported_injected")).is_none());
+        assert!(core.function_get(&String::from("core_imported_command")).is_none());
+    }
+
     #[test]
     fn test_core_getters() {
         let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash::new()));
@@ -871,7 +2621,7 @@ mod tests {
 
     #[test]
     fn test_core_history() {
-        let (mut core, _): (ShellCore, UserStream) = ShellCore::new(None, 2, Box::new(Bash::new())); //@! History size => 2
+        let (mut core, _ustream): (ShellCore, UserStream) = ShellCore::new(None, 2, Box::new(Bash::new())); //@! History size => 2
         //Verify history is empty
         assert_eq!(core.history_get().len(), 0);
         //Try to get from history
@@ -899,16 +2649,141 @@ mod tests {
         history.push_back(String::from("command 2"));
         history.push_back(String::from("command 3"));
         core.history_load(history);
-        //Length must be 2, since history size is 2
+        //Length must be 2, since history size is 2; the newest entries are kept
         assert_eq!(core.history.len(), 2);
-        assert_eq!(core.history_at(0).unwrap(), String::from("command 2"));
-        assert_eq!(core.history_at(1).unwrap(), String::from("command 1"));
+        assert_eq!(core.history_at(0).unwrap(), String::from("command 3"));
+        assert_eq!(core.history_at(1).unwrap(), String::from("command 2"));
         //Cut history
         core.history_del(0);
         assert_eq!(core.history.len(), 1);
-        assert_eq!(core.history_at(0).unwrap(), String::from("command 2"));
+        assert_eq!(core.history_at(0).unwrap(), String::from("command 3"));
+        //HISTSIZE overrides the size and truncates the history as soon as it changes
+        assert_eq!(core.history_size(), 2);
+        assert!(core.storage_set(String::from("HISTSIZE"), String::from("-1")));
+        assert_eq!(core.history_size(), usize::MAX);
+        for index in 0..5 {
+            assert!(core.history_push(format!("command {}", index)));
+        }
+        assert_eq!(core.history.len(), 6);
+        assert!(core.storage_set(String::from("HISTSIZE"), String::from("3")));
+        assert_eq!(core.history_get(), vec![String::from("command 4"), String::from("command 3"), String::from("command 2")]);
+        assert!(core.storage_set(String::from("HISTSIZE"), String::from("foo")));
+        core.history_set_size(1);
+        assert_eq!(core.history_get(), vec![String::from("command 4")]);
+        core.storage_unset(&String::from("HISTSIZE"));
+        core.history_set_size(16);
+        core.history_clear();
+        //HISTCONTROL
+        assert!(core.storage_set(String::from("HISTCONTROL"), String::from("ignoreboth")));
+        assert!(core.history_push(String::from("ls")));
+        assert!(! core.history_push(String::from("ls\n")));
+        assert!(! core.history_push(String::from(" secret")));
+        assert!(core.history_push(String::from("pwd")));
+        assert!(core.history_push(String::from("ls")));
+        assert!(core.storage_set(String::from("HISTCONTROL"), String::from("erasedups")));
+        assert!(core.history_push(String::from("pwd")));
+        assert_eq!(core.history_get(), vec![String::from("pwd"), String::from("ls"), String::from("ls")]);
+        core.storage_unset(&String::from("HISTCONTROL"));
+        //HISTIGNORE
+        assert!(core.storage_set(String::from("HISTIGNORE"), String::from("&:cd *:\\&")));
+        assert!(! core.history_push(String::from("pwd")));
+        assert!(! core.history_push(String::from("cd /tmp")));
+        assert!(! core.history_push(String::from("&")));
+        assert!(core.history_push(String::from("cd")));
+        assert_eq!(core.history_at(0).unwrap(), String::from("cd"));
+        core.storage_unset(&String::from("HISTIGNORE"));
+        //Metadata
+        let entry: HistoryEntry = core.history_entry_at(0).unwrap();
+        assert!(entry.timestamp > 0);
+        assert!(entry.duration.is_none());
+        assert!(entry.exit_code.is_none());
+        core.history_complete("cd", Duration::from_millis(10), 0);
+        core.history_complete("cd", Duration::from_millis(20), 1);
+        let entry: HistoryEntry = core.history_get_entries().pop_front().unwrap();
+        assert_eq!(entry.duration, Some(Duration::from_millis(10)));
+        assert_eq!(entry.exit_code, Some(0));
+        assert_eq!(core.readline(String::from("false")).unwrap(), 1);
+        assert_eq!(core.history_entry_at(0).unwrap().command, String::from("false"));
+        assert_eq!(core.history_entry_at(0).unwrap().exit_code, Some(1));
+        assert!(core.history_entry_at(0).unwrap().duration.is_some());
     }
     
+    #[test]
+    fn test_core_history_file() {
+        let tmpdir: tempfile::TempDir = create_tmpdir();
+        let path: PathBuf = tmpdir.path().join("history");
+        let (mut first, _): (ShellCore, UserStream) = ShellCore::new(None, 16, Box::new(Bash::new()));
+        let (mut second, _): (ShellCore, UserStream) = ShellCore::new(None, 16, Box::new(Bash::new()));
+        assert_eq!(first.history_read_file(path.as_path(), false).unwrap_err(), ShellError::NoSuchFileOrDirectory(path.clone()));
+        //Entries are appended from the oldest, with their metadata and escaped newlines
+        assert!(first.history_push(String::from("echo a")));
+        assert!(first.history_push(String::from("for x in 1 2\ndo echo $x\ndone")));
+        first.history_complete("for x in 1 2\ndo echo $x\ndone", Duration::from_secs(3), 0);
+        assert!(first.history_write_file(path.as_path(), true).is_ok());
+        let timestamp: u64 = first.history_entry_at(0).unwrap().timestamp;
+        assert_eq!(std::fs::read_to_string(path.as_path()).unwrap(), format!(": {}:0;echo a\n: {}:3;for x in 1 2\\\ndo echo $x\\\ndone\n", first.history_entry_at(1).unwrap().timestamp, timestamp));
+        //Only the new entries are appended
+        assert!(first.history_write_file(path.as_path(), true).is_ok());
+        assert_eq!(std::fs::read_to_string(path.as_path()).unwrap().lines().count(), 4);
+        assert!(second.history_push(String::from("echo b")));
+        second.history[0].timestamp = timestamp + 10;
+        assert!(second.history_write_file(path.as_path(), true).is_ok());
+        //Merge the entries appended by the other shells; the entries which haven't been saved yet are kept the newest ones
+        assert!(first.history_push(String::from("echo a2")));
+        assert_eq!(first.history_read_file(path.as_path(), true).unwrap(), 1);
+        assert_eq!(first.history_get(), vec![String::from("echo a2"), String::from("echo b"), String::from("for x in 1 2\ndo echo $x\ndone"), String::from("echo a")]);
+        assert_eq!(first.history_read_file(path.as_path(), true).unwrap(), 0);
+        assert_eq!(second.history_read_file(path.as_path(), true).unwrap(), 2);
+        assert_eq!(second.history_get(), vec![String::from("echo b"), String::from("for x in 1 2\ndo echo $x\ndone"), String::from("echo a")]);
+        assert_eq!(second.history_entry_at(1).unwrap().duration, Some(Duration::from_secs(3)));
+        //Write the whole history
+        assert!(first.history_write_file(path.as_path(), false).is_ok());
+        second.history_clear();
+        assert_eq!(second.history_read_file(path.as_path(), false).unwrap(), 4);
+        assert_eq!(second.history_get(), first.history_get());
+        //Load the lines of a history file
+        first.history_load(VecDeque::from(vec![String::from("#100"), String::from("echo plain"), String::from(": 200:2;echo x\\"), String::from("y"), String::from("ls")]));
+        assert_eq!(first.history_get(), vec![String::from("ls"), String::from("echo x\ny"), String::from("echo plain")]);
+        assert_eq!(first.history_entry_at(1).unwrap().timestamp, 200);
+        assert_eq!(first.history_entry_at(1).unwrap().duration, Some(Duration::from_secs(2)));
+        assert_eq!(first.history_entry_at(2).unwrap().timestamp, 100);
+        //Commands ending with a backslash are not continued on the next line
+        first.history_clear();
+        assert!(first.history_push(String::from("echo foo\\")));
+        assert!(first.history_push(String::from("echo \\\\\nbar\\n")));
+        assert!(first.history_push(String::from("ls")));
+        assert!(first.history_write_file(path.as_path(), false).is_ok());
+        second.history_clear();
+        assert_eq!(second.history_read_file(path.as_path(), false).unwrap(), 3);
+        assert_eq!(second.history_get(), vec![String::from("ls"), String::from("echo \\\\\nbar\\n"), String::from("echo foo\\")]);
+        //Only the newest entries are loaded
+        let lines: VecDeque<String> = (0..20).map(|x| format!("echo {}", x)).collect();
+        first.history_load(lines);
+        assert_eq!(first.history_get().len(), 16);
+        assert_eq!(first.history_at(0).unwrap(), String::from("echo 19"));
+        assert_eq!(first.history_at(15).unwrap(), String::from("echo 4"));
+    }
+
+    #[test]
+    fn test_core_history_expansion() {
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 16, Box::new(Bash::new()));
+        assert_eq!(core.readline(String::from("FOO=bar")).unwrap(), 0);
+        //The expanded line is echoed, run and stored in the history
+        assert_eq!(core.readline(String::from("!!; BAR=$FOO")).unwrap(), 0);
+        assert!(matches!(ustream.receive().unwrap().first(), Some(ShellStreamMessage::HistoryExpansion(line)) if line == "FOO=bar; BAR=$FOO"));
+        assert_eq!(core.history_at(0).unwrap(), String::from("FOO=bar; BAR=$FOO"));
+        assert_eq!(core.value_get(&String::from("BAR")).unwrap(), String::from("bar"));
+        //`:p` prints the line without running it
+        assert_eq!(core.readline(String::from("^BAR^BAZ^:p")).unwrap(), 0);
+        assert!(matches!(ustream.receive().unwrap().first(), Some(ShellStreamMessage::HistoryExpansion(line)) if line == "FOO=bar; BAZ=$FOO"));
+        assert_eq!(core.history_at(0).unwrap(), String::from("FOO=bar; BAZ=$FOO"));
+        assert!(core.value_get(&String::from("BAZ")).is_none());
+        //Failed expansions are neither run nor stored
+        assert!(matches!(core.readline(String::from("!nothing")), Err(ShellError::Parser(_))));
+        assert_eq!(core.history_get().len(), 3);
+        assert_eq!(core.state, ShellState::Idle);
+    }
+
     #[test]
     fn test_core_misc_resolve_path() {
         let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 2048, Box::new(Bash::new()));
@@ -919,6 +2794,46 @@ mod tests {
         assert_eq!(core.resolve_path(String::from("~/develop/Rust/")), dev_home_path);
         //Check previous directory
         assert_eq!(core.resolve_path(String::from("-")), core.get_prev_dir());
+        //Escaped tilde
+        assert_eq!(core.resolve_path(String::from("\\~/foo")), PathBuf::from("~/foo"));
+    }
+
+    #[test]
+    fn test_core_misc_expand_tilde() {
+        let (mut core, _): (ShellCore, UserStream) = ShellCore::new(Some(PathBuf::from("/tmp")), 64, Box::new(Bash::new()));
+        let home: String = String::from(core.get_home().to_str().unwrap());
+        //Home
+        assert_eq!(core.expand_tilde(&String::from("~"), false), home);
+        assert_eq!(core.expand_tilde(&String::from("~/bin"), false), format!("{}/bin", home));
+        //Other users
+        let user: User = User::from_uid(getuid()).unwrap().unwrap();
+        let user_home: String = String::from(user.dir.to_str().unwrap());
+        assert_eq!(core.expand_tilde(&format!("~{}", user.name), false), user_home);
+        assert_eq!(core.expand_tilde(&format!("~{}/bin", user.name), false), format!("{}/bin", user_home));
+        assert_eq!(core.expand_tilde(&String::from("~thisuserdoesnotexist/bin"), false), String::from("~thisuserdoesnotexist/bin"));
+        //Working directories
+        assert!(core.change_directory(PathBuf::from("/var"), false).is_ok());
+        assert_eq!(core.expand_tilde(&String::from("~+/log"), false), String::from("/var/log"));
+        assert_eq!(core.expand_tilde(&String::from("~-"), false), String::from("/tmp"));
+        //Dirs stack
+        core.pushd(PathBuf::from("/etc"));
+        assert_eq!(core.expand_tilde(&String::from("~0"), false), String::from("/etc"));
+        assert_eq!(core.expand_tilde(&String::from("~+1"), false), home);
+        assert_eq!(core.expand_tilde(&String::from("~-0/bin"), false), format!("{}/bin", home));
+        assert_eq!(core.expand_tilde(&String::from("~-1"), false), String::from("/etc"));
+        assert_eq!(core.expand_tilde(&String::from("~5"), false), String::from("~5"));
+        //Tildes which are not at the beginning of the word
+        assert_eq!(core.expand_tilde(&String::from("foo~"), false), String::from("foo~"));
+        assert_eq!(core.expand_tilde(&String::from("a:~/bin"), false), String::from("a:~/bin"));
+        //Assignments
+        assert_eq!(core.expand_tilde(&String::from("~/bin:~/.local/bin:/usr/bin"), true), format!("{}/bin:{}/.local/bin:/usr/bin", home, home));
+        assert_eq!(core.expand_tilde(&format!("PATH=~/bin:~{}", user.name), true), format!("PATH={}/bin:{}", home, user_home));
+        assert_eq!(core.expand_tilde(&String::from("A=B=~"), true), String::from("A=B=~"));
+        //Quoted (escaped) tildes
+        assert_eq!(core.expand_tilde(&String::from("\\~/bin"), false), String::from("~/bin"));
+        assert_eq!(core.expand_tilde(&String::from("PATH=\\~/bin:~"), true), format!("PATH=~/bin:{}", home));
+        assert_eq!(core.expand_tilde(&String::from("\"~\"/bin"), false), String::from("\"~\"/bin"));
+        assert_eq!(core.expand_tilde(&String::from("PATH='a:~':~"), true), format!("PATH='a:~':{}", home));
     }
 
     #[test]
@@ -960,10 +2875,128 @@ mod tests {
         assert!(core.reverse_search(&String::from("foobar"), None).is_none());
     }
 
+    #[test]
+    fn test_core_misc_history_search() {
+        let (mut core, _): (ShellCore, UserStream) = ShellCore::new(None, 64, Box::new(Bash::new()));
+        core.history_push(String::from("git status"));
+        core.history_push(String::from("git commit -a"));
+        core.history_push(String::from("git commit README.md"));
+        core.history_push(String::from("git push origin master"));
+        core.history_push(String::from("ll"));
+        core.history_push(String::from("cd /tmp/"));
+        core.history_push(String::from("mc -e test.cpp"));
+        core.history_push(String::from("g++ test.cpp -o test"));
+        core.history_push(String::from("./test"));
+        core.history_push(String::from("cd -"));
+        core.history_complete("cd -", Duration::from_secs(1), 1);
+        let search = |pattern: &str, mode: HistorySearchMode| -> HistorySearch {
+            HistorySearch {
+                pattern: String::from(pattern),
+                mode,
+                case_sensitive: true,
+                cwd: None,
+                exit_code: None,
+                max_entries: None
+            }
+        };
+        //Substring: all the occurrences are highlighted
+        let matches: Vec<HistoryMatch> = core.history_search(&search("test", HistorySearchMode::Substring)).unwrap();
+        assert_eq!(matches.iter().map(|x| x.index).collect::<Vec<usize>>(), vec![1, 2, 3]);
+        assert_eq!(matches[1].command, String::from("g++ test.cpp -o test"));
+        assert_eq!(matches[1].spans, vec![(4, 8), (16, 20)]);
+        assert!(core.history_search(&search("readme", HistorySearchMode::Substring)).unwrap().is_empty());
+        let mut insensitive: HistorySearch = search("readme", HistorySearchMode::Substring);
+        insensitive.case_sensitive = false;
+        let matches: Vec<HistoryMatch> = core.history_search(&insensitive).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].index, 7);
+        assert_eq!(matches[0].spans, vec![(11, 17)]);
+        //Prefix
+        let matches: Vec<HistoryMatch> = core.history_search(&search("cd", HistorySearchMode::Prefix)).unwrap();
+        assert_eq!(matches.iter().map(|x| x.index).collect::<Vec<usize>>(), vec![0, 4]);
+        assert_eq!(matches[1].spans, vec![(0, 2)]);
+        let mut limited: HistorySearch = search("cd", HistorySearchMode::Prefix);
+        limited.max_entries = Some(1);
+        assert_eq!(core.history_search(&limited).unwrap().len(), 1);
+        limited.max_entries = Some(0);
+        assert!(core.history_search(&limited).unwrap().is_empty());
+        //Regex
+        let matches: Vec<HistoryMatch> = core.history_search(&search("test\\.(cpp)?", HistorySearchMode::Regex)).unwrap();
+        assert_eq!(matches.iter().map(|x| x.index).collect::<Vec<usize>>(), vec![2, 3]);
+        assert_eq!(matches[0].spans, vec![(4, 12)]);
+        assert_eq!(core.history_search(&search("(", HistorySearchMode::Regex)).unwrap_err(), ShellError::BadValue(String::from("(")));
+        //Fuzzy: word boundaries and consecutive characters rank first; the newest entry wins on draw
+        let matches: Vec<HistoryMatch> = core.history_search(&search("gco", HistorySearchMode::Fuzzy)).unwrap();
+        assert_eq!(matches.iter().map(|x| x.index).collect::<Vec<usize>>(), vec![7, 8, 2]);
+        assert_eq!(matches[0].score, matches[1].score);
+        assert!(matches[1].score > matches[2].score);
+        assert_eq!(matches[0].spans, vec![(0, 1), (4, 6)]);
+        assert_eq!(matches[2].spans, vec![(0, 1), (9, 10), (14, 15)]);
+        let matches: Vec<HistoryMatch> = core.history_search(&search("gs", HistorySearchMode::Fuzzy)).unwrap();
+        assert_eq!(matches[0].command, String::from("git status"));
+        assert_eq!(matches[0].spans, vec![(0, 1), (4, 5)]);
+        let mut limited: HistorySearch = search("gs", HistorySearchMode::Fuzzy);
+        limited.max_entries = Some(1);
+        assert_eq!(core.history_search(&limited).unwrap()[0].index, 9);
+        limited.max_entries = Some(0);
+        assert!(core.history_search(&limited).unwrap().is_empty());
+        assert!(core.history_search(&search("xyz", HistorySearchMode::Fuzzy)).unwrap().is_empty());
+        //Filters
+        let mut filtered: HistorySearch = search("", HistorySearchMode::Fuzzy);
+        filtered.exit_code = Some(1);
+        let matches: Vec<HistoryMatch> = core.history_search(&filtered).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].command, String::from("cd -"));
+        assert!(matches[0].spans.is_empty());
+        let mut filtered: HistorySearch = search("", HistorySearchMode::Substring);
+        filtered.cwd = Some(core.get_wrkdir());
+        assert_eq!(core.history_search(&filtered).unwrap().len(), 10);
+        filtered.cwd = Some(PathBuf::from("/nonexistent/"));
+        assert!(core.history_search(&filtered).unwrap().is_empty());
+    }
+
     //TODO: readline
     //TODO: eval
     //TODO: source
 
+    #[test]
+    fn test_core_startup() {
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash::new()));
+        let tmpdir: tempfile::TempDir = create_tmpdir();
+        let profile: PathBuf = tmpdir.path().join("profile");
+        let bashrc: PathBuf = tmpdir.path().join("bashrc");
+        let broken: PathBuf = tmpdir.path().join("broken");
+        let missing: PathBuf = tmpdir.path().join("missing");
+        std::fs::write(profile.as_path(), "PROFILE=yes\nalias ll='ls -l'\n").unwrap();
+        std::fs::write(bashrc.as_path(), "BASHRC=$PROFILE\n").unwrap();
+        std::fs::write(broken.as_path(), "echo \"\n").unwrap();
+        //Files named by the environment
+        assert!(core.storage_set(String::from("BASH_ENV"), format!("{}", bashrc.display())));
+        assert_eq!(core.startup_files(StartupMode::NonInteractive), vec![bashrc.clone()]);
+        assert!(core.storage_set(String::from("ENV"), format!("{}", profile.display())));
+        assert_eq!(core.startup_files(StartupMode::Interactive).last().unwrap(), &profile);
+        //Errors are reported without stopping the startup
+        core.set_startup_files(StartupMode::Login, Some(vec![profile.clone(), missing.clone(), broken.clone(), bashrc.clone()]));
+        assert_eq!(core.startup_files(StartupMode::Login).len(), 4);
+        assert_eq!(core.startup(StartupMode::Login), 0);
+        assert_eq!(core.value_get(&String::from("BASHRC")).unwrap(), String::from("yes"));
+        assert_eq!(core.alias_get(&String::from("ll")).unwrap(), String::from("ls -l"));
+        let errors: Vec<ShellError> = ustream.receive().unwrap().into_iter().filter_map(|message| match message {
+            ShellStreamMessage::Error(err) => Some(err),
+            _ => None
+        }).collect();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0], ShellError::NoSuchFileOrDirectory(missing.clone()));
+        assert!(matches!(errors[1], ShellError::Parser(_)));
+        //The list can be disabled and restored
+        core.set_startup_files(StartupMode::NonInteractive, Some(vec![]));
+        assert!(core.startup_files(StartupMode::NonInteractive).is_empty());
+        core.set_startup_files(StartupMode::NonInteractive, None);
+        assert_eq!(core.startup_files(StartupMode::NonInteractive), vec![bashrc.clone()]);
+        assert!(core.storage_set(String::from("BASH_ENV"), format!("{}", missing.display())));
+        assert_eq!(core.startup(StartupMode::NonInteractive), 1);
+    }
+
     #[test]
     fn test_core_storage() {
         let (mut core, _): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash::new()));
@@ -982,6 +3015,16 @@ mod tests {
         //Unset value
         core.value_unset(&String::from("FOO"));
         assert!(core.value_get(&String::from("FOO")).is_none());
+        //Arrays
+        assert!(core.storage_array_set(String::from("ARR"), vec![String::from("a"), String::from("b"), String::from("c")]));
+        assert_eq!(core.value_get(&String::from("ARR")).unwrap(), String::from("a"));
+        assert_eq!(core.value_get(&String::from("ARR[2]")).unwrap(), String::from("c"));
+        assert!(core.storage_array_set(String::from("ARR"), vec![String::from("d")]));
+        assert_eq!(core.value_get(&String::from("ARR[0]")).unwrap(), String::from("d"));
+        assert!(core.value_get(&String::from("ARR[1]")).is_none());
+        core.value_unset(&String::from("ARR"));
+        assert!(core.value_get(&String::from("ARR[0]")).is_none());
+        assert!(! core.storage_array_set(String::from("1ARR"), vec![]));
         //Set value in the environ
         core.environ_set(String::from("MYKEY"), String::from("305"));
         assert!(core.environ_set(String::from("MYKEY2"), String::from("840")));
@@ -1008,6 +3051,80 @@ mod tests {
         assert!(! core.storage_arg_set(String::from("FOO"), String::from("HI")));
     }
 
+    #[test]
+    fn test_core_environ_override() {
+        let (mut core, _): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash::new()));
+        assert!(core.environ_set(String::from("OVERRIDE_EXISTING"), String::from("OLD")));
+        let mut overrides: HashMap<String, String> = HashMap::new();
+        overrides.insert(String::from("OVERRIDE_EXISTING"), String::from("NEW"));
+        overrides.insert(String::from("OVERRIDE_NEW"), String::from("FOO"));
+        overrides.insert(String::from("7OVERRIDE"), String::from("BAD"));
+        core.environ_override(&overrides);
+        //Bad name is ignored
+        assert_eq!(core.environ_overridden().len(), 2);
+        assert_eq!(core.environ_get(&String::from("OVERRIDE_EXISTING")).unwrap(), String::from("NEW"));
+        assert_eq!(core.environ_get(&String::from("OVERRIDE_NEW")).unwrap(), String::from("FOO"));
+        //The process environment is untouched
+        assert_eq!(env::var("OVERRIDE_EXISTING").unwrap(), String::from("OLD"));
+        assert!(env::var_os("OVERRIDE_NEW").is_none());
+        //Assignments change the override only
+        assert!(core.environ_set(String::from("OVERRIDE_NEW"), String::from("BAR")));
+        assert_eq!(core.environ_get(&String::from("OVERRIDE_NEW")).unwrap(), String::from("BAR"));
+        assert!(env::var_os("OVERRIDE_NEW").is_none());
+        //Restore
+        core.environ_restore();
+        assert!(core.environ_overridden().is_empty());
+        assert_eq!(core.environ_get(&String::from("OVERRIDE_EXISTING")).unwrap(), String::from("OLD"));
+        assert!(core.environ_get(&String::from("OVERRIDE_NEW")).is_none());
+    }
+
+    #[test]
+    fn test_core_traps() {
+        let (mut core, _ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash::new()));
+        //Set and unset
+        assert!(core.trap_set(TrapSignal::Err, String::from("echo error")));
+        assert!(core.trap_set(TrapSignal::Signal(UnixSignal::Sigusr1), String::new()));
+        assert_eq!(core.trap_get(&TrapSignal::Err).unwrap(), String::from("echo error"));
+        assert_eq!(core.trap_get_all().len(), 2);
+        //Ignored signals are never pending
+        assert!(core.trap_pending().is_empty());
+        core.trap_unset(&TrapSignal::Err);
+        core.trap_unset(&TrapSignal::Signal(UnixSignal::Sigusr1));
+        assert!(core.trap_get(&TrapSignal::Err).is_none());
+        //SIGKILL can't be trapped
+        assert!(! core.trap_set(TrapSignal::Signal(UnixSignal::Sigkill), String::from("echo")));
+        assert!(core.trap_get_all().is_empty());
+        //Restoring a snapshot (e.g. at the end of a subshell) reinstalls the signal handlers
+        assert!(core.trap_set(TrapSignal::Signal(UnixSignal::Sigurg), String::new()));
+        let snapshot: ShellCoreSnapshot = core.snapshot();
+        assert!(core.trap_set(TrapSignal::Signal(UnixSignal::Sigurg), String::from("echo")));
+        assert!(core.trap_set(TrapSignal::Signal(UnixSignal::Sigvtalrm), String::from("echo")));
+        assert_ne!(signal_handler(UnixSignal::Sigvtalrm), libc::SIG_DFL);
+        core.restore(snapshot);
+        assert_eq!(signal_handler(UnixSignal::Sigurg), libc::SIG_IGN);
+        assert_eq!(signal_handler(UnixSignal::Sigvtalrm), libc::SIG_DFL);
+        core.trap_unset(&TrapSignal::Signal(UnixSignal::Sigurg));
+        assert_eq!(signal_handler(UnixSignal::Sigurg), libc::SIG_DFL);
+        //EXIT set by a sourced script is run only when the shell exits
+        let log: tempfile::NamedTempFile = create_tmpfile();
+        let mut script: tempfile::NamedTempFile = create_tmpfile();
+        writeln!(script, "trap 'echo exited >> {}' EXIT", log.path().display()).unwrap();
+        assert_eq!(core.source(script.path().to_path_buf()).unwrap(), 0);
+        assert_eq!(core.source(script.path().to_path_buf()).unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(log.path()).unwrap(), String::new());
+        assert!(core.trap_get(&TrapSignal::Exit).is_some());
+        assert!(core.trap_set(TrapSignal::Signal(UnixSignal::Sigusr1), String::from("echo")));
+        core.exit();
+        assert_eq!(std::fs::read_to_string(log.path()).unwrap(), String::from("exited\n"));
+        assert!(core.trap_get_all().is_empty());
+    }
+
+    fn signal_handler(signal: UnixSignal) -> libc::sighandler_t {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        unsafe { libc::sigaction(signal as libc::c_int, std::ptr::null(), &mut action) };
+        action.sa_sigaction
+    }
+
     fn create_tmpfile() -> tempfile::NamedTempFile {
         tempfile::NamedTempFile::new().unwrap()
     }
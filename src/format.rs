@@ -0,0 +1,711 @@
+//! # Format
+//!
+//! `format` provides the output formatting used by the echo and printf builtins
+
+//
+//   Shell-Core
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use nix::libc;
+use std::ffi::CString;
+
+/// ### MAX_FIELD
+///
+/// Greatest width or precision accepted by printf; greater values would allocate huge outputs
+const MAX_FIELD: usize = 1 << 20;
+
+/// ## Escape
+///
+/// Escape describes the result of a backslash escape sequence
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+enum Escape {
+    Text(String),
+    Stop            //`\c`: no further output is produced
+}
+
+/// ### echo
+///
+/// Format the arguments of echo. The leading options are `-n` (no trailing newline), `-e` (interpret escapes) and `-E` (don't interpret escapes)
+pub(crate) fn echo(args: &[String]) -> String {
+    let mut newline: bool = true;
+    let mut escapes: bool = false;
+    let mut index: usize = 0;
+    //Parse options; an argument is an option only if all its characters are valid options
+    while let Some(arg) = args.get(index) {
+        if arg.len() < 2 || ! arg.starts_with('-') || ! arg[1..].chars().all(|c| "neE".contains(c)) {
+            break;
+        }
+        for c in arg[1..].chars() {
+            match c {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false
+            }
+        }
+        index += 1;
+    }
+    let text: String = args[index..].join(" ");
+    let (mut text, stop): (String, bool) = match escapes {
+        true => unescape(text.as_str(), true),
+        false => (text, false)
+    };
+    if newline && ! stop {
+        text.push('\n');
+    }
+    text
+}
+
+/// ### printf
+///
+/// Format the arguments according to format. The format is reused as long as there are arguments left.
+/// Returns the output and the errors (e.g. invalid numbers) found while formatting
+pub(crate) fn printf(format: &str, args: &[String]) -> (String, Vec<String>) {
+    let format: Vec<char> = format.chars().collect();
+    let mut output: String = String::new();
+    let mut errors: Vec<String> = Vec::new();
+    let mut next_arg: usize = 0;
+    loop {
+        let first_arg: usize = next_arg;
+        let stop: bool = printf_once(&format, args, &mut next_arg, &mut output, &mut errors);
+        //Stop if no argument has been consumed, otherwise the format would be repeated forever
+        if stop || next_arg >= args.len() || next_arg == first_arg {
+            break;
+        }
+    }
+    (output, errors)
+}
+
+/// ### quote
+///
+/// Quote text so that it can be reused as shell input (`%q`)
+pub(crate) fn quote(text: &str) -> String {
+    if text.is_empty() {
+        return String::from("''")
+    }
+    let mut quoted: String = String::with_capacity(text.len());
+    if text.chars().any(|c| c.is_control()) {
+        //Use ANSI-C quoting
+        quoted.push_str("$'");
+        for c in text.chars() {
+            match c {
+                '\n' => quoted.push_str("\\n"),
+                '\t' => quoted.push_str("\\t"),
+                '\r' => quoted.push_str("\\r"),
+                '\x1b' => quoted.push_str("\\E"),
+                '\'' => quoted.push_str("\\'"),
+                '\\' => quoted.push_str("\\\\"),
+                c if c.is_control() => quoted.push_str(format!("\\{:03o}", c as u32).as_str()),
+                c => quoted.push(c)
+            }
+        }
+        quoted.push('\'');
+    } else {
+        for c in text.chars() {
+            if ! c.is_alphanumeric() && ! "_./:=@%+,-".contains(c) {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+    }
+    quoted
+}
+
+/// ### strftime
+///
+/// Format the local time of timestamp (seconds since the epoch) according to format (e.g. for `HISTTIMEFORMAT`)
+pub(crate) fn strftime(format: &str, timestamp: u64) -> String {
+    let format: CString = match CString::new(format) {
+        Ok(format) if ! format.as_bytes().is_empty() => format,
+        _ => return String::new()
+    };
+    let time: libc::time_t = timestamp as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return String::new()
+    }
+    //strftime returns 0 if the buffer is too small, so it's grown until the output fits
+    let mut buffer: Vec<u8> = vec![0; 128];
+    loop {
+        let written: usize = unsafe { libc::strftime(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len(), format.as_ptr(), &tm) };
+        if written > 0 || buffer.len() >= 4096 {
+            buffer.truncate(written);
+            break;
+        }
+        buffer.resize(buffer.len() * 2, 0);
+    }
+    String::from_utf8_lossy(&buffer).into_owned()
+}
+
+/// ### unescape
+///
+/// Expand the backslash escapes of text. With echo style escapes (`echo -e` and `%b`), octal values are written as `\0nnn` and `\c` stops the output.
+/// Returns the text and whether the output must stop
+pub(crate) fn unescape(text: &str, echo: bool) -> (String, bool) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut output: String = String::with_capacity(text.len());
+    let mut index: usize = 0;
+    while index < chars.len() {
+        if chars[index] != '\\' {
+            output.push(chars[index]);
+            index += 1;
+            continue;
+        }
+        let (escape, next): (Escape, usize) = escape_sequence(&chars, index, echo);
+        match escape {
+            Escape::Text(text) => output.push_str(text.as_str()),
+            Escape::Stop => return (output, true)
+        }
+        index = next;
+    }
+    (output, false)
+}
+
+/// ### printf_once
+///
+/// Format the arguments starting from next_arg once. Returns whether the output must stop
+fn printf_once(format: &[char], args: &[String], next_arg: &mut usize, output: &mut String, errors: &mut Vec<String>) -> bool {
+    let mut index: usize = 0;
+    while index < format.len() {
+        match format[index] {
+            '\\' => {
+                let (escape, next): (Escape, usize) = escape_sequence(format, index, false);
+                match escape {
+                    Escape::Text(text) => output.push_str(text.as_str()),
+                    Escape::Stop => return true
+                }
+                index = next;
+            },
+            '%' if format.get(index + 1) == Some(&'%') => {
+                output.push('%');
+                index += 2;
+            },
+            '%' => {
+                let start: usize = index;
+                index += 1;
+                //Flags
+                let mut flags: String = String::new();
+                while index < format.len() && "-+ #0".contains(format[index]) {
+                    flags.push(format[index]);
+                    index += 1;
+                }
+                //Width
+                let width: usize = if format.get(index) == Some(&'*') {
+                    let arg: String = args.get(*next_arg).cloned().unwrap_or_default();
+                    let value: i64 = integer_argument(args, next_arg, errors);
+                    if value.unsigned_abs() > MAX_FIELD as u64 {
+                        errors.push(format!("printf: {}: invalid field width", arg));
+                        return true
+                    }
+                    if value < 0 {
+                        flags.push('-');
+                    }
+                    index += 1;
+                    value.unsigned_abs() as usize
+                } else {
+                    match format_number(format, &mut index) {
+                        Some(width) => width,
+                        None => {
+                            errors.push(format!("printf: `{}': invalid format", format[start..index].iter().collect::<String>()));
+                            return true
+                        }
+                    }
+                };
+                //Precision
+                let mut precision: Option<usize> = None;
+                if format.get(index) == Some(&'.') {
+                    index += 1;
+                    if format.get(index) == Some(&'*') {
+                        //A negative precision is like no precision
+                        let arg: String = args.get(*next_arg).cloned().unwrap_or_default();
+                        let value: i64 = integer_argument(args, next_arg, errors);
+                        if value > MAX_FIELD as i64 {
+                            errors.push(format!("printf: {}: invalid precision", arg));
+                            return true
+                        }
+                        if value >= 0 {
+                            precision = Some(value as usize);
+                        }
+                        index += 1;
+                    } else {
+                        precision = match format_number(format, &mut index) {
+                            Some(value) => Some(value),
+                            None => {
+                                errors.push(format!("printf: `{}': invalid format", format[start..index].iter().collect::<String>()));
+                                return true
+                            }
+                        };
+                    }
+                }
+                //Conversion
+                let kind: char = match format.get(index) {
+                    Some(kind) => *kind,
+                    None => {
+                        errors.push(String::from("printf: `%': missing format character"));
+                        return true
+                    }
+                };
+                index += 1;
+                let arg: Option<&String> = args.get(*next_arg);
+                let text: String = match kind {
+                    's' | 'b' | 'q' | 'c' | 'd' | 'i' | 'u' | 'o' | 'x' | 'X' | 'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                        *next_arg += 1;
+                        let arg: &str = arg.map(|x| x.as_str()).unwrap_or("");
+                        match kind {
+                            's' => pad("", truncate(arg, precision).as_str(), width, flags.as_str(), false),
+                            'b' => {
+                                let (text, stop): (String, bool) = unescape(arg, true);
+                                output.push_str(pad("", truncate(text.as_str(), precision).as_str(), width, flags.as_str(), false).as_str());
+                                if stop {
+                                    return true
+                                }
+                                String::new()
+                            },
+                            'q' => pad("", quote(arg).as_str(), width, flags.as_str(), false),
+                            'c' => pad("", arg.chars().next().map(|c| c.to_string()).unwrap_or_default().as_str(), width, flags.as_str(), false),
+                            'd' | 'i' | 'u' | 'o' | 'x' | 'X' => {
+                                let value: i64 = parse_integer(arg).unwrap_or_else(|err| {
+                                    errors.push(err);
+                                    0
+                                });
+                                format_integer(value, kind, flags.as_str(), width, precision)
+                            },
+                            _ => {
+                                let value: f64 = parse_float(arg).unwrap_or_else(|err| {
+                                    errors.push(err);
+                                    0.0
+                                });
+                                format_float(value, kind, flags.as_str(), width, precision)
+                            }
+                        }
+                    },
+                    _ => {
+                        errors.push(format!("printf: `{}': invalid format character", kind));
+                        return true
+                    }
+                };
+                output.push_str(text.as_str());
+            },
+            c => {
+                output.push(c);
+                index += 1;
+            }
+        }
+    }
+    false
+}
+
+/// ### escape_sequence
+///
+/// Read the escape sequence which starts at index (the backslash). Returns the escape and the index of the first character after it
+fn escape_sequence(chars: &[char], index: usize, echo: bool) -> (Escape, usize) {
+    let c: char = match chars.get(index + 1) {
+        Some(c) => *c,
+        None => return (Escape::Text(String::from("\\")), index + 1)
+    };
+    let text: String = match c {
+        'a' => String::from("\x07"),
+        'b' => String::from("\x08"),
+        'e' | 'E' => String::from("\x1b"),
+        'f' => String::from("\x0c"),
+        'n' => String::from("\n"),
+        'r' => String::from("\r"),
+        't' => String::from("\t"),
+        'v' => String::from("\x0b"),
+        '\\' => String::from("\\"),
+        '"' if ! echo => String::from("\""),
+        'c' if echo => return (Escape::Stop, index + 2),
+        '0'..='7' => {
+            //Octal value; echo style requires a leading 0, which isn't part of the 3 digits
+            let start: usize = match echo {
+                true if c == '0' => index + 2,
+                true => return (Escape::Text(format!("\\{}", c)), index + 2),
+                false => index + 1
+            };
+            let (value, end): (u32, usize) = read_digits(chars, start, 8, 3);
+            return (Escape::Text(char::from_u32(value).map(|c| c.to_string()).unwrap_or_default()), end)
+        },
+        'x' | 'u' | 'U' => {
+            let max_digits: usize = match c {
+                'x' => 2,
+                'u' => 4,
+                _ => 8
+            };
+            let (value, end): (u32, usize) = read_digits(chars, index + 2, 16, max_digits);
+            if end == index + 2 {
+                //No digits: the escape is kept as it is
+                return (Escape::Text(format!("\\{}", c)), index + 2)
+            }
+            return (Escape::Text(char::from_u32(value).map(|c| c.to_string()).unwrap_or_default()), end)
+        },
+        c => format!("\\{}", c)
+    };
+    (Escape::Text(text), index + 2)
+}
+
+/// ### read_digits
+///
+/// Read up to max_digits digits in radix starting from index. Returns the value and the index of the first character after the digits
+fn read_digits(chars: &[char], index: usize, radix: u32, max_digits: usize) -> (u32, usize) {
+    let mut value: u32 = 0;
+    let mut end: usize = index;
+    while end < chars.len() && end - index < max_digits {
+        match chars[end].to_digit(radix) {
+            Some(digit) => value = value.wrapping_mul(radix).wrapping_add(digit),
+            None => break
+        }
+        end += 1;
+    }
+    (value, end)
+}
+
+/// ### format_number
+///
+/// Read the decimal digits of a width or a precision, moving index after them. Returns None if the value is greater than MAX_FIELD
+fn format_number(chars: &[char], index: &mut usize) -> Option<usize> {
+    let mut value: Option<usize> = Some(0);
+    while *index < chars.len() && chars[*index].is_ascii_digit() {
+        value = value.and_then(|x| x.checked_mul(10)).and_then(|x| x.checked_add(chars[*index].to_digit(10).unwrap() as usize));
+        *index += 1;
+    }
+    value.filter(|x| *x <= MAX_FIELD)
+}
+
+/// ### integer_argument
+///
+/// Get the next argument as an integer (for `*` width and precision)
+fn integer_argument(args: &[String], next_arg: &mut usize, errors: &mut Vec<String>) -> i64 {
+    let arg: &str = args.get(*next_arg).map(|x| x.as_str()).unwrap_or("");
+    *next_arg += 1;
+    parse_integer(arg).unwrap_or_else(|err| {
+        errors.push(err);
+        0
+    })
+}
+
+/// ### parse_integer
+///
+/// Parse a printf integer argument: decimal, octal (leading 0), hexadecimal (leading 0x) or the character code of the character after a leading quote
+fn parse_integer(arg: &str) -> Result<i64, String> {
+    let value: &str = arg.trim_start();
+    if value.is_empty() {
+        return Ok(0)
+    }
+    if value.starts_with('\'') || value.starts_with('"') {
+        return Ok(value.chars().nth(1).map(|c| c as i64).unwrap_or(0))
+    }
+    let (negative, digits): (bool, &str) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value.strip_prefix('+').unwrap_or(value))
+    };
+    let parsed: Result<u64, std::num::ParseIntError> = if digits.starts_with("0x") || digits.starts_with("0X") {
+        u64::from_str_radix(&digits[2..], 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse::<u64>()
+    };
+    match parsed {
+        Ok(value) if negative => Ok((value as i64).wrapping_neg()),
+        Ok(value) => Ok(value as i64),
+        Err(_) => Err(format!("printf: {}: invalid number", arg))
+    }
+}
+
+/// ### parse_float
+///
+/// Parse a printf floating point argument; a leading quote gives the character code of the next character
+fn parse_float(arg: &str) -> Result<f64, String> {
+    let value: &str = arg.trim();
+    if value.is_empty() {
+        return Ok(0.0)
+    }
+    if value.starts_with('\'') || value.starts_with('"') {
+        return Ok(value.chars().nth(1).map(|c| c as u32 as f64).unwrap_or(0.0))
+    }
+    match value.parse::<f64>() {
+        Ok(value) => Ok(value),
+        Err(_) => parse_integer(value).map(|x| x as f64)
+    }
+}
+
+/// ### format_integer
+///
+/// Format an integer conversion (`%d %i %u %o %x %X`)
+fn format_integer(value: i64, kind: char, flags: &str, width: usize, precision: Option<usize>) -> String {
+    let unsigned: u64 = match kind {
+        'd' | 'i' => value.unsigned_abs(),
+        _ => value as u64
+    };
+    let mut digits: String = match kind {
+        'o' => format!("{:o}", unsigned),
+        'x' => format!("{:x}", unsigned),
+        'X' => format!("{:X}", unsigned),
+        _ => unsigned.to_string()
+    };
+    //Precision is the minimum amount of digits
+    if let Some(precision) = precision {
+        if precision == 0 && unsigned == 0 {
+            digits.clear();
+        }
+        while digits.len() < precision {
+            digits.insert(0, '0');
+        }
+    }
+    let mut prefix: String = String::new();
+    match kind {
+        'd' | 'i' => prefix.push_str(sign(value < 0, flags)),
+        'o' if flags.contains('#') && ! digits.starts_with('0') => digits.insert(0, '0'),
+        'x' if flags.contains('#') && unsigned != 0 => prefix.push_str("0x"),
+        'X' if flags.contains('#') && unsigned != 0 => prefix.push_str("0X"),
+        _ => {}
+    }
+    pad(prefix.as_str(), digits.as_str(), width, flags, precision.is_none())
+}
+
+/// ### format_float
+///
+/// Format a floating point conversion (`%f %F %e %E %g %G`); the default precision is 6
+fn format_float(value: f64, kind: char, flags: &str, width: usize, precision: Option<usize>) -> String {
+    let prefix: &str = sign(value.is_sign_negative() && ! value.is_nan(), flags);
+    let upper: bool = kind.is_ascii_uppercase();
+    let value: f64 = value.abs();
+    if ! value.is_finite() {
+        let text: &str = match (value.is_nan(), upper) {
+            (true, false) => "nan",
+            (true, true) => "NAN",
+            (false, false) => "inf",
+            (false, true) => "INF"
+        };
+        return pad(prefix, text, width, flags, false)
+    }
+    let precision: usize = precision.unwrap_or(6);
+    let text: String = match kind {
+        'f' | 'F' => format!("{:.*}", precision, value),
+        'e' | 'E' => exponential(value, precision, upper),
+        _ => {
+            //%g uses the exponential notation if the exponent is less than -4 or greater than or equal to the precision
+            let precision: usize = std::cmp::max(precision, 1);
+            let exponent: i32 = match value == 0.0 {
+                true => 0,
+                false => exponent_of(value, precision - 1)
+            };
+            let text: String = match exponent < -4 || exponent >= precision as i32 {
+                true => exponential(value, precision - 1, upper),
+                false => format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+            };
+            match flags.contains('#') {
+                true => text,
+                false => strip_zeros(text.as_str())
+            }
+        }
+    };
+    pad(prefix, text.as_str(), width, flags, true)
+}
+
+/// ### exponential
+///
+/// Format value in the exponential notation, with at least two digits for the exponent (e.g. `1.500000e+00`)
+fn exponential(value: f64, precision: usize, upper: bool) -> String {
+    let exponent: i32 = exponent_of(value, precision);
+    let text: String = format!("{:.*e}", precision, value);
+    let mantissa: &str = text.split('e').next().unwrap_or("");
+    let sign: char = match exponent < 0 {
+        true => '-',
+        false => '+'
+    };
+    match upper {
+        true => format!("{}E{}{:02}", mantissa, sign, exponent.abs()),
+        false => format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+    }
+}
+
+/// ### exponent_of
+///
+/// Get the decimal exponent of value, once rounded to precision digits after the point
+fn exponent_of(value: f64, precision: usize) -> i32 {
+    let text: String = format!("{:.*e}", precision, value);
+    text.split('e').nth(1).and_then(|x| x.parse::<i32>().ok()).unwrap_or(0)
+}
+
+/// ### strip_zeros
+///
+/// Remove the trailing zeros of the fractional part (and the point, if nothing is left); the exponent is preserved
+fn strip_zeros(text: &str) -> String {
+    let (number, exponent): (&str, &str) = match text.find(['e', 'E']) {
+        Some(index) => (&text[..index], &text[index..]),
+        None => (text, "")
+    };
+    let number: &str = match number.contains('.') {
+        true => number.trim_end_matches('0').trim_end_matches('.'),
+        false => number
+    };
+    format!("{}{}", number, exponent)
+}
+
+/// ### truncate
+///
+/// Truncate text to precision characters (`%.3s`)
+fn truncate(text: &str, precision: Option<usize>) -> String {
+    match precision {
+        Some(precision) => text.chars().take(precision).collect(),
+        None => String::from(text)
+    }
+}
+
+/// ### sign
+///
+/// Get the sign of a number, according to the `+` and ` ` flags
+fn sign(negative: bool, flags: &str) -> &'static str {
+    if negative {
+        "-"
+    } else if flags.contains('+') {
+        "+"
+    } else if flags.contains(' ') {
+        " "
+    } else {
+        ""
+    }
+}
+
+/// ### pad
+///
+/// Pad prefix and body to width. With the `-` flag text is left justified, while with the `0` flag numbers are padded with zeros after the prefix
+fn pad(prefix: &str, body: &str, width: usize, flags: &str, numeric: bool) -> String {
+    let length: usize = prefix.chars().count() + body.chars().count();
+    if length >= width {
+        return format!("{}{}", prefix, body)
+    }
+    let fill: usize = width - length;
+    if flags.contains('-') {
+        format!("{}{}{}", prefix, body, " ".repeat(fill))
+    } else if numeric && flags.contains('0') {
+        format!("{}{}{}", prefix, "0".repeat(fill), body)
+    } else {
+        format!("{}{}{}", " ".repeat(fill), prefix, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn printf_str(format: &str, args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|x| String::from(*x)).collect();
+        printf(format, &args).0
+    }
+
+    #[test]
+    fn test_format_echo() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|x| String::from(*x)).collect() };
+        assert_eq!(echo(&args(&[])), String::from("\n"));
+        assert_eq!(echo(&args(&["foo", "bar"])), String::from("foo bar\n"));
+        assert_eq!(echo(&args(&["-n", "foo"])), String::from("foo"));
+        assert_eq!(echo(&args(&["foo\\tbar"])), String::from("foo\\tbar\n"));
+        assert_eq!(echo(&args(&["-e", "foo\\tbar\\x41\\0101\\u00e8"])), String::from("foo\tbarAAè\n"));
+        assert_eq!(echo(&args(&["-ne", "a\\nb"])), String::from("a\nb"));
+        assert_eq!(echo(&args(&["-e", "-E", "a\\nb"])), String::from("a\\nb\n"));
+        assert_eq!(echo(&args(&["-e", "foo\\cbar"])), String::from("foo"));
+        //Not options
+        assert_eq!(echo(&args(&["-x", "foo"])), String::from("-x foo\n"));
+        assert_eq!(echo(&args(&["foo", "-n"])), String::from("foo -n\n"));
+        assert_eq!(echo(&args(&["-"])), String::from("-\n"));
+    }
+
+    #[test]
+    fn test_format_printf_strings() {
+        assert_eq!(printf_str("hello\\n", &[]), String::from("hello\n"));
+        assert_eq!(printf_str("%s-%s\\n", &["a", "b"]), String::from("a-b\n"));
+        assert_eq!(printf_str("[%5s][%-5s][%.2s]", &["ab", "cd", "efgh"]), String::from("[   ab][cd   ][ef]"));
+        assert_eq!(printf_str("[%*s][%-*s]", &["4", "a", "3", "b"]), String::from("[   a][b  ]"));
+        assert_eq!(printf_str("%c%c", &["hello", "world"]), String::from("hw"));
+        assert_eq!(printf_str("%b|%s", &["a\\tb", "a\\tb"]), String::from("a\tb|a\\tb"));
+        assert_eq!(printf_str("%b%s", &["stop\\chere", "never"]), String::from("stop"));
+        assert_eq!(printf_str("%q %q %q", &["a b", "it's", ""]), String::from("a\\ b it\\'s ''"));
+        assert_eq!(printf_str("%q", &["a\nb"]), String::from("$'a\\nb'"));
+        assert_eq!(printf_str("100%%\\101\\x42\\\"", &[]), String::from("100%AB\""));
+        //Format is reused for extra arguments
+        assert_eq!(printf_str("%s=%s;", &["a", "1", "b", "2", "c"]), String::from("a=1;b=2;c=;"));
+        //Missing arguments
+        assert_eq!(printf_str("%s|%d|%f", &[]), String::from("|0|0.000000"));
+    }
+
+    #[test]
+    fn test_format_printf_numbers() {
+        assert_eq!(printf_str("%d %i %u", &["42", "-7", "5"]), String::from("42 -7 5"));
+        assert_eq!(printf_str("[%5d][%-5d][%05d][%+d][% d]", &["42", "42", "-42", "42", "42"]), String::from("[   42][42   ][-0042][+42][ 42]"));
+        assert_eq!(printf_str("%.3d|%x|%X|%#x|%o|%#o", &["7", "255", "255", "255", "8", "8"]), String::from("007|ff|FF|0xff|10|010"));
+        assert_eq!(printf_str("%d %d %d", &["0x10", "010", "'A"]), String::from("16 8 65"));
+        assert_eq!(printf_str("%u", &["-1"]), String::from("18446744073709551615"));
+        assert_eq!(printf_str("%f|%.2f|%8.3f|%-8.1f|", &["1.5", "3.14159", "2.5", "2"]), String::from("1.500000|3.14|   2.500|2.0     |"));
+        assert_eq!(printf_str("%e|%.2E|%e", &["1234.5", "0.000123", "0"]), String::from("1.234500e+03|1.23E-04|0.000000e+00"));
+        assert_eq!(printf_str("%g|%g|%g|%g|%G", &["100000", "1000000", "0.0001", "3.14159", "0.00001"]), String::from("100000|1e+06|0.0001|3.14159|1E-05"));
+        assert_eq!(printf_str("%.3g|%#g", &["3.14159", "2"]), String::from("3.14|2.00000"));
+        assert_eq!(printf_str("%f", &["inf"]), String::from("inf"));
+        //Invalid numbers
+        let (output, errors): (String, Vec<String>) = printf("%d|%d", &[String::from("abc"), String::from("3")]);
+        assert_eq!(output, String::from("0|3"));
+        assert_eq!(errors, vec![String::from("printf: abc: invalid number")]);
+        //Invalid format
+        let (output, errors): (String, Vec<String>) = printf("a%zb", &[]);
+        assert_eq!(output, String::from("a"));
+        assert_eq!(errors.len(), 1);
+        let (output, errors): (String, Vec<String>) = printf("a%99999999999999999999d|%.99999999999999999999s", &[String::from("1")]);
+        assert_eq!(output, String::from("a"));
+        assert_eq!(errors, vec![String::from("printf: `%99999999999999999999': invalid format")]);
+        let (output, errors): (String, Vec<String>) = printf("%.99999999999999999999s", &[]);
+        assert!(output.is_empty());
+        assert_eq!(errors, vec![String::from("printf: `%.99999999999999999999': invalid format")]);
+        //Width and precision are capped
+        let (output, errors): (String, Vec<String>) = printf("a%2000000s", &[String::from("x")]);
+        assert_eq!(output, String::from("a"));
+        assert_eq!(errors, vec![String::from("printf: `%2000000': invalid format")]);
+        let (output, errors): (String, Vec<String>) = printf("a%*s", &[String::from("9999999999"), String::from("x")]);
+        assert_eq!(output, String::from("a"));
+        assert_eq!(errors, vec![String::from("printf: 9999999999: invalid field width")]);
+        let (output, errors): (String, Vec<String>) = printf("a%-*s", &[String::from("-9999999999"), String::from("x")]);
+        assert_eq!(output, String::from("a"));
+        assert_eq!(errors, vec![String::from("printf: -9999999999: invalid field width")]);
+        let (output, errors): (String, Vec<String>) = printf("a%.*f", &[String::from("9999999999"), String::from("1")]);
+        assert_eq!(output, String::from("a"));
+        assert_eq!(errors, vec![String::from("printf: 9999999999: invalid precision")]);
+        assert_eq!(printf_str("%*s|%.*s", &["1048576", "", "-9999999999", "ab"]).len(), 1048579);
+    }
+
+    #[test]
+    fn test_format_quote() {
+        assert_eq!(quote("simple"), String::from("simple"));
+        assert_eq!(quote("/tmp/a-b_c.txt"), String::from("/tmp/a-b_c.txt"));
+        assert_eq!(quote("$HOME"), String::from("\\$HOME"));
+        assert_eq!(quote("a\tb'"), String::from("$'a\\tb\\''"));
+    }
+
+    #[test]
+    fn test_format_strftime() {
+        assert_eq!(strftime("%s", 1600000000), String::from("1600000000"));
+        assert_eq!(strftime("at %s%% ", 42), String::from("at 42% "));
+        assert_eq!(strftime("%Y", 1600000000).len(), 4);
+        assert_eq!(strftime("%s", 1).repeat(1024), strftime(&"%s".repeat(1024), 1));
+        assert!(strftime("", 42).is_empty());
+    }
+
+}
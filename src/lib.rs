@@ -26,16 +26,25 @@
 //
 
 pub mod core;
+mod builtins;
+mod format;
 mod runner;
+mod signals;
 pub mod streams;
 pub mod parsers;
 pub mod tasks;
+mod wildcard;
 
+use std::cell::RefCell;
+use std::fs::File;
 use std::collections::{HashMap, VecDeque};
+use std::io::{Read, Write};
 use std::path::PathBuf;
+use std::rc::Rc;
 use std::sync::mpsc;
 use std::time::{Duration, Instant};
 
+use runner::Substitution;
 use tasks::TaskManager;
 use tasks::TaskError;
 use tasks::Task;
@@ -57,14 +66,61 @@ pub struct ShellCore {
     execution_started: Instant,                     //The instant when the last process was started
     storage: HashMap<String, String>,               //Session storage
     alias: HashMap<String, String>,                 //Aliases
-    functions: HashMap<String, ShellExpression>,    //Functions
+    functions: HashMap<String, ShellFunction>,      //Functions
     dirs: VecDeque<PathBuf>,                        //Directory stack
-    history: VecDeque<String>,                      //Shell history
+    glob_options: GlobOptions,                      //Filename expansion options
+    history: VecDeque<HistoryEntry>,                //Shell history (newest first)
+    history_size: usize,                            //History size, unless HISTSIZE is set
+    history_unsaved: usize,                         //Entries at the front of the history which haven't been written to a history file yet
+    history_files: HashMap<PathBuf, u64>,           //Offset up to which the history files have been read or written
+    traps: HashMap<TrapSignal, String>,             //Trap actions
+    signals: signals::Signals,                      //Dispositions replaced by the traps and handled signal deliveries
+    hash: HashMap<String, (PathBuf, usize)>,        //Command hash table (path, hits)
+    hash_path: Option<String>,                      //PATH the hash table has been filled with
+    command_not_found: Option<CommandNotFoundHandler>, //Handler for commands which can't be found
+    builtins: HashMap<String, Rc<dyn NativeBuiltin>>, //Native builtins
+    redirection: Redirection,                       //Shell stdout redirection (`exec > file`)
+    stderr_redirection: Redirection,                //Shell stderr redirection (`exec 2> file`)
+    input_redirection: Option<Rc<File>>,            //Shell input redirection (`exec < file`)
+    environ_overrides: Vec<HashMap<String, String>>, //Environment overrides of the running commands (`FOO=bar cmd`), innermost last
+    startup_files: HashMap<StartupMode, Vec<PathBuf>>, //Startup files overridden by the host
     parser: Box<dyn ParseStatement>,                //Parser
     buf_in: String,                                 //Input buffer
     pub(crate) sstream: ShellStream                 //ShellStream
 }
 
+/// ## ShellCoreSnapshot
+/// 
+/// ShellCoreSnapshot contains the state of the shell core which is restored when a subshell terminates
+pub(crate) struct ShellCoreSnapshot {
+    wrk_dir: PathBuf,
+    prev_dir: PathBuf,
+    storage: HashMap<String, String>,
+    alias: HashMap<String, String>,
+    functions: HashMap<String, ShellFunction>,
+    dirs: VecDeque<PathBuf>,
+    glob_options: GlobOptions,
+    traps: HashMap<TrapSignal, String>,
+    hash: HashMap<String, (PathBuf, usize)>,
+    hash_path: Option<String>,
+    redirection: Redirection,
+    stderr_redirection: Redirection,
+    input_redirection: Option<Rc<File>>,
+    environ: HashMap<String, String>,
+    environ_overrides: Vec<HashMap<String, String>>
+}
+
+/// ## ShellFunction
+/// 
+/// ShellFunction is a function defined in the shell; its source is the compound command which makes up its body,
+/// which is printed back by `declare -f` and passed to the child processes when the function is exported
+#[derive(Clone, std::fmt::Debug)]
+pub(crate) struct ShellFunction {
+    expression: ShellExpression,
+    source: String,
+    exported: bool
+}
+
 /// ## ShellState
 ///
 /// The shell state describes the current shell state and is very useful to choose the behaviour of your shell (for example to print or not the prompt etc)
@@ -95,9 +151,11 @@ pub enum ShellError {
     ShellNotInIdle,             //The shell must be in Idle state to perform this action
     DirsStackEmpty,             //Directory stack is empty
     NoSuchAlias(String),        //Alias doesn't exist
+    NoMatch(String),            //No file matches the pattern (with failglob)
     TaskError(TaskError),       //Error reported by task; please refer to task error
     Parser(ParserError),        //Error reported by the Parser
     Math(MathError),            //Math error
+    FunctionNesting(String, usize), //Maximum function nesting level (FUNCNEST) exceeded by function
     Other                       //Anything which is an undefined behaviour. This should never be raised
 }
 
@@ -117,45 +175,64 @@ pub struct ShellExpression {
 /// - Alias: Association between name and command. Alias(None, None) => returns all aliases; Alias(Some, None) => returns alias command, Alias(Some, Some) => set alias
 /// - Break: Break from current expression block if possible
 /// - Case: case statement Case(Expression output to match, List of case => expression)
-/// - Cd: change directory
+/// - Cd: change directory Cd(Path, physical); with physical, symlinks are resolved
+/// - Command: perform Task, bypassing functions and aliases for the first command
 /// - Continue: Continue in the current expression block if possible
+/// - Describe: describe how the commands are resolved Describe(Names, verbose) (`command -v` and `command -V`)
+/// - Eval: evaluate the arguments as a command Eval(Arguments); arguments are expanded and joined before being parsed
 /// - Exec: Perform Task
 /// - ExecHistory: Perform command from history
+/// - ExecReplace: replace the shell with the task ExecReplace(Task, Redirections); the redirections are applied to the shell itself (and then inherited by the task)
 /// - Exit: exit from expression
 /// - Export: export a variable into environ
 /// - For: For(String, Condition, Perform) iterator String: key name
-/// - Function: defines a new function (Name, expression)
+/// - Function: defines a new function Function(Name, Expression, Source); the source is the function body as it was written
+/// - Functions: manage the functions (see FunctionOptions)
+/// - Group: command group `{ ...; }` Group(Expression, Redirection); it runs in the current shell with a single redirection
+/// - Hash: manage the command hash table (see HashOptions)
 /// - History: perform on history
 /// - If: If(Condition, Then, Else) condition
 /// - Let: perform math operation to values Let(Result, operator1, operation, operator2)
 /// - Output: send output message (Stdout, Stderr)
 /// - Popd: Pop directory from stack
 /// - Pushd: Push directory to directory stack
-/// - Read: Read command (Prompt, length, result_key)
+/// - Read: Read command (see ReadOptions)
 /// - Rc: set return code to value
 /// - Return: return value
 /// - Set: Set value into storage
 /// - Source: source file
+/// - Subshell: subshell `( ... )` Subshell(Expression, Redirection); changes to the shell state don't affect the current shell
 /// - Task: execute task
+/// - Test: evaluate a conditional expression Test(Arguments, extended); extended is `[[ ]]`, otherwise `test` or `[ ]`
 /// - Time: execute with time
+/// - Trap: manage the traps (see TrapOptions)
+/// - Type: describe how the commands are resolved Type(Names, format, all); with all, every location is reported
 /// - Unalias: remove an alias
 /// - Value: simple value or key
 /// - While: While(Condition, Perform) iterator
+/// - WithEnv: WithEnv(Variables, Perform) perform expression with variables temporarily set in the environment (e.g. `FOO=bar cd /tmp`)
 /// - WriteFile: FilePath, Content, truncate: write file
 #[derive(Clone, std::fmt::Debug)]
 pub enum ShellStatement {
     Alias(Option<String>, Option<String>),
     Break,
     Case(ShellExpression, Vec<(ShellExpression, ShellExpression)>),
-    Cd(PathBuf),
+    Cd(PathBuf, bool),
+    Command(Task),
     Continue,
+    Describe(Vec<String>, bool),
     Dirs,
+    Eval(Vec<String>),
     Exec(Task),
     ExecHistory(usize),
+    ExecReplace(Option<Task>, Vec<ExecRedirection>),
     Exit(u8),
     Export(String, ShellExpression),
     For(String, ShellExpression, ShellExpression),
-    Function(String, ShellExpression),
+    Function(String, ShellExpression, String),
+    Functions(FunctionOptions),
+    Group(ShellExpression, Redirection),
+    Hash(HashOptions),
     History(HistoryOptions),
     If(ShellExpression, ShellExpression, Option<ShellExpression>),
     Let(String, ShellExpression, MathOperator, ShellExpression),
@@ -164,15 +241,20 @@ pub enum ShellStatement {
     PopdFront,
     Pushd(PathBuf),
     Rc(u8),
-    Read(Option<String>, Option<usize>, Option<String>),
+    Read(ReadOptions),
     Return(u8),
     Set(String, ShellExpression),
     Source(PathBuf),
+    Subshell(ShellExpression, Redirection),
+    Test(Vec<String>, bool),
     Time(Task),
+    Trap(TrapOptions),
+    Type(Vec<String>, TypeFormat, bool),
     Unalias(String),
     Unset(String),
     Value(String),
     While(ShellExpression, ShellExpression),
+    WithEnv(HashMap<String, String>, ShellExpression),
     WriteFile(String, String, bool)
 }
 
@@ -181,8 +263,13 @@ pub enum ShellStatement {
 /// The shell runner is the struct which takes care of running Shell Expressions
 pub struct ShellRunner {
     buffer: Option<String>, //Input buffer
+    piped: bool,            //Whether the input of the current statement is the output of the previous one in a pipeline
     exit_flag: Option<u8>,  //When active, exit from expression execution
-    break_loop: bool        //Indicates whether parent loop has to be stopped
+    break_loop: bool,       //Indicates whether parent loop has to be stopped
+    in_trap: bool,          //Whether a trap action is running (traps are not run inside traps)
+    conditions: usize,      //Depth of the conditions being evaluated (the ERR trap is not run inside conditions)
+    substitutions: Vec<Substitution>, //Process substitutions running alongside the command which consumes them
+    funcnest: usize         //Function nesting level
 }
 
 //@! Streams
@@ -194,6 +281,7 @@ pub struct ShellRunner {
 pub(crate) struct ShellStream {
     receiver: mpsc::Receiver<UserStreamMessage>,    //Receive User messages
     sender: mpsc::Sender<ShellStreamMessage>,       //Sends Shell messages
+    captures: RefCell<Vec<String>>,                 //Stdout captures (e.g. redirected command groups); the output goes to the last one
 }
 
 /// ## UserStream
@@ -213,7 +301,9 @@ pub enum ShellStreamMessage {
     Error(ShellError),                          //Shell Error
     Dirs(VecDeque<PathBuf>),                    //Dirs output
     Alias(HashMap<String, String>),             //List of alias
-    Time(Duration)                              //Command duration
+    Time(Duration),                             //Command duration
+    InputEcho(bool),                            //Whether the user input must be echoed (disabled by `read -s` while reading)
+    HistoryExpansion(String)                    //Input line after the history expansion, which is echoed before being run
 }
 
 /// ## UserStreamMessage
@@ -248,6 +338,20 @@ pub enum Redirection {
     File(String, FileRedirectionType),
 }
 
+/// ## ExecRedirection
+///
+/// ExecRedirection describes a redirection applied to the shell by `exec`
+///
+/// - Stdout: the shell stdout is redirected (`exec > file`)
+/// - Stderr: the shell stderr is redirected (`exec 2> file`)
+/// - Stdin: the shell input is read from the file (`exec < file`)
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub enum ExecRedirection {
+    Stdout(Redirection),
+    Stderr(Redirection),
+    Stdin(String),
+}
+
 //@! Maths
 
 /// ## MathOperator
@@ -319,31 +423,213 @@ pub trait ParseStatement {
     /// e.g. if the statement is a variable assignment, the method MUST call the shellcore set method.
     /// Obviously, in case of error the core method hasn't to be called
     fn parse(&self, core: &ShellCore, statement: &String) -> Result<ShellExpression, ParserError>;
+
+    /// ### has_builtin
+    ///
+    /// Returns whether the command is a builtin handled by the parser (e.g. cd); by default there are none
+    fn has_builtin(&self, _command: &str) -> bool {
+        false
+    }
+
+    /// ### expand_history
+    ///
+    /// Perform history expansion on an input line, before it's parsed and stored in the history.
+    /// Returns the expanded line and whether it must be executed, or None if the line doesn't refer to the history; by default nothing is expanded
+    fn expand_history(&self, _core: &ShellCore, _line: &str) -> Result<Option<(String, bool)>, ParserError> {
+        Ok(None)
+    }
+
+    /// ### builtin_names
+    ///
+    /// Returns the names of the builtins handled by the parser; by default there are none
+    fn builtin_names(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// ### completion_word
+    ///
+    /// Find the word which ends at cursor (a byte offset of line) to complete it, along with the command it belongs to.
+    /// Returns None if the word can't be completed; by default no word is found
+    fn completion_word(&self, _line: &str, _cursor: usize) -> Option<CompletionWord> {
+        None
+    }
 }
 
 //@! History Options
 
+/// ## HistoryEntry
+/// 
+/// HistoryEntry is a command stored in the shell history, along with the time it has been run at (seconds since the epoch).
+/// Duration and exit code are set once the command has terminated; the working directory is unknown for the entries read from a history file
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub struct HistoryEntry {
+    pub command: String,
+    pub timestamp: u64,
+    pub duration: Option<Duration>,
+    pub exit_code: Option<u8>,
+    pub cwd: Option<PathBuf>
+}
+
+/// ## HistoryMatch
+/// 
+/// HistoryMatch is an history entry matched by a search
+/// 
+/// - index: index of the entry in the history (0 is the newest)
+/// - command: command of the entry
+/// - score: the higher, the better the entry matches the pattern (fuzzy search only; 0 for the other modes)
+/// - spans: byte ranges (start, end) of the command matched by the pattern, sorted and not overlapping
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub struct HistoryMatch {
+    pub index: usize,
+    pub command: String,
+    pub score: i64,
+    pub spans: Vec<(usize, usize)>
+}
+
 /// ### HistoryOptions
 /// 
 /// - Clear: clear history
 /// - Del: delete history from offset
 /// - Print: print history
-/// - Write: write history to file (bool is truncate)
+/// - Read: read history file
+/// - ReadNew: read the entries appended to the history file since it was last read or written
+/// - Write: write history to file (bool is truncate; otherwise only the new entries are appended)
 #[derive(Clone, PartialEq, std::fmt::Debug)]
 pub enum HistoryOptions {
     Clear,
     Del(usize),
     Print,
     Read(String),
+    ReadNew(String),
     Write(String, bool)
 }
 
+/// ## HistorySearch
+/// 
+/// HistorySearch describes a search in the history
+/// 
+/// - pattern: the pattern to search for
+/// - mode: how the pattern is matched against the commands
+/// - case_sensitive: if false, the case is ignored
+/// - cwd: only the entries run in this directory are matched
+/// - exit_code: only the entries which have terminated with this exit code are matched
+/// - max_entries: return at most max_entries matches
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub struct HistorySearch {
+    pub pattern: String,
+    pub mode: HistorySearchMode,
+    pub case_sensitive: bool,
+    pub cwd: Option<PathBuf>,
+    pub exit_code: Option<u8>,
+    pub max_entries: Option<usize>
+}
+
+/// ### HistorySearchMode
+/// 
+/// - Substring: the command contains the pattern
+/// - Prefix: the command starts with the pattern
+/// - Fuzzy: the characters of the pattern appear in the command in the same order; matches are sorted by score
+/// - Regex: the command matches the pattern as a regular expression
+#[derive(Copy, Clone, PartialEq, std::fmt::Debug)]
+pub enum HistorySearchMode {
+    Substring,
+    Prefix,
+    Fuzzy,
+    Regex
+}
+
+//@! Read Options
+
+/// ## ReadOptions
+/// 
+/// ReadOptions describes the behaviour of the read builtin
+/// 
+/// - prompt: prompt shown before reading (-p)
+/// - length: read at most length characters (-n)
+/// - delimiter: the line ends at this character instead of newline (-d)
+/// - raw: backslashes don't escape characters (-r)
+/// - silent: the user input is not echoed (-s)
+/// - timeout: fail if the input is not read within timeout (-t)
+/// - array: the words are assigned to the indexes of this array (-a)
+/// - names: the variables the words are assigned to; the last one gets the remainder of the line (REPLY if empty)
+/// - input: the file to read from (`< file`); if None, the input is read from the pipe or from the user stream
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub struct ReadOptions {
+    pub prompt: Option<String>,
+    pub length: Option<usize>,
+    pub delimiter: char,
+    pub raw: bool,
+    pub silent: bool,
+    pub timeout: Option<Duration>,
+    pub array: Option<String>,
+    pub names: Vec<String>,
+    pub input: Option<String>
+}
+
+impl ReadOptions {
+
+    /// ### new
+    /// 
+    /// Instantiates a new ReadOptions with the default read behaviour (a line is read into REPLY)
+    pub fn new() -> ReadOptions {
+        ReadOptions {
+            prompt: None,
+            length: None,
+            delimiter: '\n',
+            raw: false,
+            silent: false,
+            timeout: None,
+            array: None,
+            names: Vec::new(),
+            input: None
+        }
+    }
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions::new()
+    }
+}
+
+//@! Glob
+
+/// ## GlobOptions
+/// 
+/// GlobOptions describes the behaviour of the filename expansion
+/// 
+/// - nullglob: patterns which don't match any file are removed
+/// - failglob: patterns which don't match any file make the command fail
+/// - dotglob: hidden files are matched even if the pattern doesn't start with a dot
+#[derive(Copy, Clone, PartialEq, std::fmt::Debug)]
+pub struct GlobOptions {
+    pub nullglob: bool,
+    pub failglob: bool,
+    pub dotglob: bool
+}
+
+//@! Startup
+
+/// ## StartupMode
+/// 
+/// StartupMode describes which startup files are sourced by the shell, unless they've been overridden by the host
+/// 
+/// - Login: `/etc/profile`, then the first one of `~/.bash_profile`, `~/.bash_login` and `~/.profile`
+/// - Interactive: `~/.bashrc`, then the file named by `ENV`
+/// - NonInteractive: the file named by `BASH_ENV`
+#[derive(Copy, Clone, PartialEq, Eq, Hash, std::fmt::Debug)]
+pub enum StartupMode {
+    Login,
+    Interactive,
+    NonInteractive
+}
+
 //@! Signals
 
 /// ## UnixSignal
 ///
 /// The UnixSignal enums represents the UNIX signals
-#[derive(Copy, Clone, PartialEq, std::fmt::Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, std::fmt::Debug)]
 pub enum UnixSignal {
     Sighup = 1,
     Sigint = 2,
@@ -378,6 +664,194 @@ pub enum UnixSignal {
     Sigsys = 31
 }
 
+//@! Traps
+
+/// ## TrapSignal
+/// 
+/// TrapSignal describes when the action of a trap is run
+/// 
+/// - Exit: when the shell exits (`exit` or end of a sourced script)
+/// - Err: when a command returns a non-zero exit status (except in conditions and in `&&`/`||` lists)
+/// - Debug: before each statement
+/// - Return: when a function returns
+/// - Signal: when the signal arrives on the user stream or is delivered to the shell process
+#[derive(Copy, Clone, PartialEq, Eq, Hash, std::fmt::Debug)]
+pub enum TrapSignal {
+    Exit,
+    Err,
+    Debug,
+    Return,
+    Signal(UnixSignal)
+}
+
+/// ### TrapOptions
+/// 
+/// - List: list the signal names
+/// - Print: print the traps for the provided signals (all the traps if empty)
+/// - Reset: reset the signals to their original disposition
+/// - Set: set the action for the signals; an empty action ignores them
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub enum TrapOptions {
+    List,
+    Print(Vec<TrapSignal>),
+    Reset(Vec<TrapSignal>),
+    Set(String, Vec<TrapSignal>)
+}
+
+//@! Commands
+
+/// ## CommandType
+/// 
+/// CommandType describes what a command name resolves to
+/// 
+/// - Alias: alias with its command
+/// - Function: shell function
+/// - Builtin: shell builtin
+/// - File: executable file, with its path
+/// - NotFound: the command couldn't be resolved
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub enum CommandType {
+    Alias(String),
+    Function,
+    Builtin,
+    File(PathBuf),
+    NotFound
+}
+
+/// ## CommandNotFoundHandler
+/// 
+/// Callback run when a command can't be found, with the command argv.
+/// If it handles the command (e.g. running a fallback) it returns the exit status, otherwise None and the error is reported with exit status 127
+pub type CommandNotFoundHandler = Box<dyn FnMut(&mut ShellCore, &[String]) -> Option<u8>>;
+
+/// ## NativeBuiltin
+/// 
+/// NativeBuiltin is the trait for the builtins registered into the shell core (see `ShellCore::builtin_register`).
+/// Native builtins are resolved after functions and before PATH, and like any task they can be part of pipelines,
+/// have their output redirected and be chained with `&&` and `||`. Names which are builtins of the parser (e.g. `cd`) can't be overridden.
+/// Since a builtin may run itself again through the core (e.g. with `eval`), `run` takes `&self`: use interior mutability to keep state
+pub trait NativeBuiltin {
+
+    /// ### name
+    /// 
+    /// Returns the name of the command
+    fn name(&self) -> &str;
+
+    /// ### run
+    /// 
+    /// Run the builtin with its argv (the first argument is the command name). The input is the output of the previous command in the pipeline.
+    /// Returns the exit status
+    fn run(&self, core: &mut ShellCore, argv: &[String], stdin: &mut dyn Read, stdout: &mut dyn Write, stderr: &mut dyn Write) -> u8;
+}
+
+/// ### FunctionOptions
+/// 
+/// - Export: export the functions to the child processes (`export -f`)
+/// - Names: print the names of the functions; all of them if no name is provided (`declare -F`)
+/// - Print: print the definitions of the functions; all of them if no name is provided (`declare -f`)
+/// - Unexport: stop exporting the functions (`export -n -f`)
+/// - Unset: remove the functions (`unset -f`)
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub enum FunctionOptions {
+    Export(Vec<String>),
+    Names(Vec<String>),
+    Print(Vec<String>),
+    Unexport(Vec<String>),
+    Unset(Vec<String>)
+}
+
+/// ### HashOptions
+/// 
+/// - Add: look up the commands in PATH and add them to the hash table
+/// - Del: remove the commands from the hash table
+/// - Print: print the hash table
+/// - Reset: forget all the commands
+/// - Set: add the commands with the provided path
+/// - Show: print the paths of the commands
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub enum HashOptions {
+    Add(Vec<String>),
+    Del(Vec<String>),
+    Print,
+    Reset,
+    Set(PathBuf, Vec<String>),
+    Show(Vec<String>)
+}
+
+/// ### TypeFormat
+/// 
+/// - Verbose: describe the command (`type`)
+/// - Kind: print the kind of command (`type -t`)
+/// - Path: print the path of the files (`type -p`)
+/// - ForcePath: print the path of the command in PATH, even if it's an alias, a function or a builtin (`type -P`)
+#[derive(Copy, Clone, PartialEq, std::fmt::Debug)]
+pub enum TypeFormat {
+    Verbose,
+    Kind,
+    Path,
+    ForcePath
+}
+
+//@! Completion
+
+/// ## Completion
+/// 
+/// Completion is a candidate to complete the word under the cursor
+/// 
+/// - value: the candidate, as it should be displayed (files are shown without their directory, directories end with `/`)
+/// - replacement: the text which replaces the word, quoted like the word being completed
+/// - kind: what the candidate is
+/// - start: byte offset of the buffer where the replaced text begins
+/// - end: byte offset of the buffer where the replaced text ends (the cursor)
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub struct Completion {
+    pub value: String,
+    pub replacement: String,
+    pub kind: CompletionKind,
+    pub start: usize,
+    pub end: usize
+}
+
+/// ### CompletionKind
+/// 
+/// - Alias: alias name
+/// - Builtin: shell builtin
+/// - Command: executable file in PATH
+/// - Directory: directory (including the ones found in CDPATH)
+/// - DirStack: entry of the directory stack (`~N`, or `+N`/`-N` for pushd and popd)
+/// - File: file which is not a directory
+/// - Function: shell function
+/// - User: home directory of a user (`~user`)
+/// - Variable: variable name (`$NAME` or `${NAME}`)
+#[derive(Copy, Clone, PartialEq, Eq, Hash, std::fmt::Debug)]
+pub enum CompletionKind {
+    Alias,
+    Builtin,
+    Command,
+    Directory,
+    DirStack,
+    File,
+    Function,
+    User,
+    Variable
+}
+
+/// ## CompletionWord
+/// 
+/// CompletionWord is the word to complete, as found by the parser
+/// 
+/// - start: byte offset of the line where the word begins
+/// - word: the text of the word up to the cursor, as it has been written (quotes and escapes are kept)
+/// - command_position: whether the word is a command name
+/// - command: the command name the word is an argument of, if any (the target of a redirection may have none)
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub struct CompletionWord {
+    pub start: usize,
+    pub word: String,
+    pub command_position: bool,
+    pub command: Option<String>
+}
+
 //@! Task
 /// ## TaskRelation
 ///
@@ -433,9 +907,16 @@ impl PartialEq for ShellStatement {
                     false
                 }
             },
-            ShellStatement::Cd(path) => {
-                if let ShellStatement::Cd(path_cmp) = other {
-                    path == path_cmp
+            ShellStatement::Cd(path, physical) => {
+                if let ShellStatement::Cd(path_cmp, physical_cmp) = other {
+                    path == path_cmp && physical == physical_cmp
+                } else {
+                    false
+                }
+            },
+            ShellStatement::Command(t) => {
+                if let ShellStatement::Command(t_cmp) = other {
+                    t.command == t_cmp.command && t.env == t_cmp.env
                 } else {
                     false
                 }
@@ -447,6 +928,13 @@ impl PartialEq for ShellStatement {
                     false
                 }
             },
+            ShellStatement::Describe(names, verbose) => {
+                if let ShellStatement::Describe(names_cmp, verbose_cmp) = other {
+                    names == names_cmp && verbose == verbose_cmp
+                } else {
+                    false
+                }
+            },
             ShellStatement::Dirs => {
                 if let ShellStatement::Dirs = other {
                     true
@@ -454,9 +942,16 @@ impl PartialEq for ShellStatement {
                     false
                 }
             },
+            ShellStatement::Eval(args) => {
+                if let ShellStatement::Eval(args_cmp) = other {
+                    args == args_cmp
+                } else {
+                    false
+                }
+            },
             ShellStatement::Exec(t) => {
                 if let ShellStatement::Exec(t_cmp) = other {
-                    t.command == t_cmp.command
+                    t.command == t_cmp.command && t.env == t_cmp.env
                 } else {
                     false
                 }
@@ -468,6 +963,13 @@ impl PartialEq for ShellStatement {
                     false
                 }
             },
+            ShellStatement::ExecReplace(t, redirections) => {
+                if let ShellStatement::ExecReplace(t_cmp, redirections_cmp) = other {
+                    t.as_ref().map(|x| (&x.command, &x.env)) == t_cmp.as_ref().map(|x| (&x.command, &x.env)) && redirections == redirections_cmp
+                } else {
+                    false
+                }
+            },
             ShellStatement::Exit(rc) => {
                 if let ShellStatement::Exit(rc_cmp) = other {
                     rc == rc_cmp
@@ -489,9 +991,30 @@ impl PartialEq for ShellStatement {
                     false
                 }
             },
-            ShellStatement::Function(func, expr) => {
-                if let ShellStatement::Function(func_cmp, expr_cmp) = other {
-                    func == func_cmp && expr == expr_cmp
+            ShellStatement::Function(func, expr, source) => {
+                if let ShellStatement::Function(func_cmp, expr_cmp, source_cmp) = other {
+                    func == func_cmp && expr == expr_cmp && source == source_cmp
+                } else {
+                    false
+                }
+            },
+            ShellStatement::Functions(opt) => {
+                if let ShellStatement::Functions(opt_cmp) = other {
+                    opt == opt_cmp
+                } else {
+                    false
+                }
+            },
+            ShellStatement::Group(expr, redirection) => {
+                if let ShellStatement::Group(expr_cmp, redirection_cmp) = other {
+                    expr == expr_cmp && redirection == redirection_cmp
+                } else {
+                    false
+                }
+            },
+            ShellStatement::Hash(opt) => {
+                if let ShellStatement::Hash(opt_cmp) = other {
+                    opt == opt_cmp
                 } else {
                     false
                 }
@@ -552,9 +1075,9 @@ impl PartialEq for ShellStatement {
                     false
                 }
             },
-            ShellStatement::Read(prompt, length, result) => {
-                if let ShellStatement::Read(prompt_cmp, length_cmp, result_cmp) = other {
-                    prompt == prompt_cmp && length == length_cmp && result == result_cmp
+            ShellStatement::Read(options) => {
+                if let ShellStatement::Read(options_cmp) = other {
+                    options == options_cmp
                 } else {
                     false
                 }
@@ -580,6 +1103,20 @@ impl PartialEq for ShellStatement {
                     false
                 }
             },
+            ShellStatement::Subshell(expr, redirection) => {
+                if let ShellStatement::Subshell(expr_cmp, redirection_cmp) = other {
+                    expr == expr_cmp && redirection == redirection_cmp
+                } else {
+                    false
+                }
+            },
+            ShellStatement::Test(args, extended) => {
+                if let ShellStatement::Test(args_cmp, extended_cmp) = other {
+                    args == args_cmp && extended == extended_cmp
+                } else {
+                    false
+                }
+            },
             ShellStatement::Time(t) => {
                 if let ShellStatement::Time(t_cmp) = other {
                     t.command == t_cmp.command
@@ -587,6 +1124,20 @@ impl PartialEq for ShellStatement {
                     false
                 }
             },
+            ShellStatement::Trap(opt) => {
+                if let ShellStatement::Trap(opt_cmp) = other {
+                    opt == opt_cmp
+                } else {
+                    false
+                }
+            },
+            ShellStatement::Type(names, format, all) => {
+                if let ShellStatement::Type(names_cmp, format_cmp, all_cmp) = other {
+                    names == names_cmp && format == format_cmp && all == all_cmp
+                } else {
+                    false
+                }
+            },
             ShellStatement::Unalias(alias) => {
                 if let ShellStatement::Unalias(alias_cmp) = other {
                     alias == alias_cmp
@@ -615,6 +1166,13 @@ impl PartialEq for ShellStatement {
                     false
                 }
             },
+            ShellStatement::WithEnv(env, perform) => {
+                if let ShellStatement::WithEnv(env_cmp, perform_cmp) = other {
+                    env == env_cmp && perform == perform_cmp
+                } else {
+                    false
+                }
+            },
             ShellStatement::WriteFile(file, content, trunc) => {
                 if let ShellStatement::WriteFile(file_cmp, content_cmp, trunc_cmp) = other {
                     file == file_cmp && content == content_cmp && trunc == trunc_cmp
@@ -651,24 +1209,48 @@ mod tests {
         assert_ne!(ShellStatement::Case(case_match.clone(), vec![]), ShellStatement::Case(case_match2.clone(), vec![]));
         assert_ne!(ShellStatement::Case(case_match.clone(), vec![]), ShellStatement::Break);
         //Cd
-        assert_eq!(ShellStatement::Cd(PathBuf::from("/tmp/")), ShellStatement::Cd(PathBuf::from("/tmp/")));
-        assert_ne!(ShellStatement::Cd(PathBuf::from("/tmp/")), ShellStatement::Cd(PathBuf::from("/home/")));
-        assert_ne!(ShellStatement::Cd(PathBuf::from("/tmp/")), ShellStatement::Break);
+        assert_eq!(ShellStatement::Cd(PathBuf::from("/tmp/"), false), ShellStatement::Cd(PathBuf::from("/tmp/"), false));
+        assert_ne!(ShellStatement::Cd(PathBuf::from("/tmp/"), false), ShellStatement::Cd(PathBuf::from("/home/"), false));
+        assert_ne!(ShellStatement::Cd(PathBuf::from("/tmp/"), false), ShellStatement::Cd(PathBuf::from("/tmp/"), true));
+        assert_ne!(ShellStatement::Cd(PathBuf::from("/tmp/"), false), ShellStatement::Break);
+        //Command
+        let task: Task = Task::new(vec![String::from("ls")], Redirection::Stdout, Redirection::Stderr);
+        assert_eq!(ShellStatement::Command(task.clone()), ShellStatement::Command(task.clone()));
+        assert_ne!(ShellStatement::Command(task.clone()), ShellStatement::Command(Task::new(vec![String::from("echo")], Redirection::Stdout, Redirection::Stderr)));
+        assert_ne!(ShellStatement::Command(task.clone()), ShellStatement::Exec(task.clone()));
         //Continue
         assert_eq!(ShellStatement::Continue, ShellStatement::Continue);
         assert_ne!(ShellStatement::Continue, ShellStatement::Alias(None, None));
+        //Describe
+        assert_eq!(ShellStatement::Describe(vec![String::from("ls")], true), ShellStatement::Describe(vec![String::from("ls")], true));
+        assert_ne!(ShellStatement::Describe(vec![String::from("ls")], true), ShellStatement::Describe(vec![String::from("ls")], false));
+        assert_ne!(ShellStatement::Describe(vec![String::from("ls")], true), ShellStatement::Break);
         //Dirs
         assert_eq!(ShellStatement::Dirs, ShellStatement::Dirs);
         assert_ne!(ShellStatement::Dirs, ShellStatement::Alias(None, None));
+        //Eval
+        assert_eq!(ShellStatement::Eval(vec![String::from("ls")]), ShellStatement::Eval(vec![String::from("ls")]));
+        assert_ne!(ShellStatement::Eval(vec![String::from("ls")]), ShellStatement::Eval(vec![String::from("cd")]));
+        assert_ne!(ShellStatement::Eval(vec![String::from("ls")]), ShellStatement::Break);
         //Exec
         let task: Task = Task::new(vec![String::from("ls")], Redirection::Stdout, Redirection::Stderr);
         assert_eq!(ShellStatement::Exec(task.clone()), ShellStatement::Exec(task.clone()));
         assert_ne!(ShellStatement::Exec(task.clone()), ShellStatement::Exec(Task::new(vec![String::from("echo")], Redirection::Stdout, Redirection::Stderr)));
         assert_ne!(ShellStatement::Exec(task.clone()), ShellStatement::Break);
+        let mut task_env: Task = task.clone();
+        task_env.set_env(String::from("FOO"), String::from("BAR"));
+        assert_ne!(ShellStatement::Exec(task.clone()), ShellStatement::Exec(task_env.clone()));
         //Exec history
         assert_eq!(ShellStatement::ExecHistory(8), ShellStatement::ExecHistory(8));
         assert_ne!(ShellStatement::ExecHistory(8), ShellStatement::ExecHistory(128));
         assert_ne!(ShellStatement::ExecHistory(8), ShellStatement::Break);
+        //Exec replace
+        assert_eq!(ShellStatement::ExecReplace(Some(task.clone()), vec![]), ShellStatement::ExecReplace(Some(task.clone()), vec![]));
+        assert_ne!(ShellStatement::ExecReplace(Some(task.clone()), vec![]), ShellStatement::ExecReplace(None, vec![]));
+        assert_ne!(ShellStatement::ExecReplace(Some(task.clone()), vec![]), ShellStatement::ExecReplace(Some(task_env.clone()), vec![]));
+        assert_ne!(ShellStatement::ExecReplace(None, vec![]), ShellStatement::ExecReplace(None, vec![ExecRedirection::Stdout(Redirection::File(String::from("/tmp/log"), FileRedirectionType::Append))]));
+        assert_ne!(ShellStatement::ExecReplace(None, vec![ExecRedirection::Stdin(String::from("/tmp/log"))]), ShellStatement::ExecReplace(None, vec![ExecRedirection::Stderr(Redirection::File(String::from("/tmp/log"), FileRedirectionType::Append))]));
+        assert_ne!(ShellStatement::ExecReplace(None, vec![]), ShellStatement::Break);
         //Exit
         assert_eq!(ShellStatement::Exit(0), ShellStatement::Exit(0));
         assert_ne!(ShellStatement::Exit(0), ShellStatement::Exit(128));
@@ -682,9 +1264,19 @@ mod tests {
         assert_ne!(ShellStatement::For(String::from("VAR"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}, ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}), ShellStatement::For(String::from("VAR2"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}, ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}));
         assert_ne!(ShellStatement::For(String::from("VAR"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}, ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}), ShellStatement::Break);
         //Function
-        assert_eq!(ShellStatement::Function(String::from("foo"), ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}), ShellStatement::Function(String::from("foo"), ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}));
-        assert_ne!(ShellStatement::Function(String::from("foo"), ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}), ShellStatement::Function(String::from("bar"), ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}));
-        assert_ne!(ShellStatement::Function(String::from("foo"), ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}), ShellStatement::Break);
+        assert_eq!(ShellStatement::Function(String::from("foo"), ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, String::from("{ return 0; }")), ShellStatement::Function(String::from("foo"), ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, String::from("{ return 0; }")));
+        assert_ne!(ShellStatement::Function(String::from("foo"), ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, String::from("{ return 0; }")), ShellStatement::Function(String::from("bar"), ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, String::from("{ return 0; }")));
+        assert_ne!(ShellStatement::Function(String::from("foo"), ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, String::from("{ return 0; }")), ShellStatement::Break);
+        assert_eq!(ShellStatement::Functions(FunctionOptions::Print(vec![])), ShellStatement::Functions(FunctionOptions::Print(vec![])));
+        assert_ne!(ShellStatement::Functions(FunctionOptions::Print(vec![])), ShellStatement::Functions(FunctionOptions::Names(vec![])));
+        assert_ne!(ShellStatement::Functions(FunctionOptions::Unset(vec![String::from("foo")])), ShellStatement::Break);
+        assert_eq!(ShellStatement::Group(ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, Redirection::Stdout), ShellStatement::Group(ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, Redirection::Stdout));
+        assert_ne!(ShellStatement::Group(ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, Redirection::Stdout), ShellStatement::Group(ShellExpression {statements: vec![(ShellStatement::Return(1), TaskRelation::Unrelated)]}, Redirection::Stdout));
+        assert_ne!(ShellStatement::Group(ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, Redirection::Stdout), ShellStatement::Break);
+        //Hash
+        assert_eq!(ShellStatement::Hash(HashOptions::Print), ShellStatement::Hash(HashOptions::Print));
+        assert_ne!(ShellStatement::Hash(HashOptions::Add(vec![String::from("ls")])), ShellStatement::Hash(HashOptions::Del(vec![String::from("ls")])));
+        assert_ne!(ShellStatement::Hash(HashOptions::Reset), ShellStatement::Break);
         //History
         assert_eq!(ShellStatement::History(HistoryOptions::Clear), ShellStatement::History(HistoryOptions::Clear));
         assert_ne!(ShellStatement::History(HistoryOptions::Clear), ShellStatement::History(HistoryOptions::Del(8)));
@@ -716,9 +1308,11 @@ mod tests {
         assert_ne!(ShellStatement::Rc(0), ShellStatement::Rc(2));
         assert_ne!(ShellStatement::Rc(0), ShellStatement::Break);
         //Read
-        assert_eq!(ShellStatement::Read(None, None, None), ShellStatement::Read(None, None, None));
-        assert_ne!(ShellStatement::Read(None, None, None), ShellStatement::Read(None, Some(32), None));
-        assert_ne!(ShellStatement::Read(None, None, None), ShellStatement::Break);
+        assert_eq!(ShellStatement::Read(ReadOptions::new()), ShellStatement::Read(ReadOptions::new()));
+        let mut options: ReadOptions = ReadOptions::new();
+        options.length = Some(32);
+        assert_ne!(ShellStatement::Read(ReadOptions::new()), ShellStatement::Read(options));
+        assert_ne!(ShellStatement::Read(ReadOptions::new()), ShellStatement::Break);
         //Return
         assert_eq!(ShellStatement::Return(0), ShellStatement::Return(0));
         assert_ne!(ShellStatement::Return(0), ShellStatement::Return(2));
@@ -731,11 +1325,29 @@ mod tests {
         assert_eq!(ShellStatement::Source(PathBuf::from("/tmp/set.sh")), ShellStatement::Source(PathBuf::from("/tmp/set.sh")));
         assert_ne!(ShellStatement::Source(PathBuf::from("/tmp/set.sh")), ShellStatement::Source(PathBuf::from("/tmp/get.sh")));
         assert_ne!(ShellStatement::Source(PathBuf::from("/tmp/set.sh")), ShellStatement::Break);
+        assert_eq!(ShellStatement::Subshell(ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, Redirection::Stdout), ShellStatement::Subshell(ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, Redirection::Stdout));
+        assert_ne!(ShellStatement::Subshell(ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, Redirection::Stdout), ShellStatement::Subshell(ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, Redirection::Stderr));
+        assert_ne!(ShellStatement::Subshell(ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, Redirection::Stdout), ShellStatement::Group(ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, Redirection::Stdout));
+        //Test
+        assert_eq!(ShellStatement::Test(vec![String::from("-f"), String::from("/tmp")], false), ShellStatement::Test(vec![String::from("-f"), String::from("/tmp")], false));
+        assert_ne!(ShellStatement::Test(vec![String::from("-f"), String::from("/tmp")], false), ShellStatement::Test(vec![String::from("-f"), String::from("/tmp")], true));
+        assert_ne!(ShellStatement::Test(vec![String::from("-f"), String::from("/tmp")], false), ShellStatement::Test(vec![String::from("-d"), String::from("/tmp")], false));
+        assert_ne!(ShellStatement::Test(vec![String::from("-f"), String::from("/tmp")], false), ShellStatement::Break);
         //Time
         assert_eq!(ShellStatement::Time(Task::new(vec![String::from("echo")], Redirection::Stdout, Redirection::Stderr)), ShellStatement::Time(Task::new(vec![String::from("echo")], Redirection::Stdout, Redirection::Stderr)));
         assert_ne!(ShellStatement::Time(Task::new(vec![String::from("echo")], Redirection::Stdout, Redirection::Stderr)), ShellStatement::Time(Task::new(vec![String::from("ls")], Redirection::Stdout, Redirection::Stderr)));
         assert_ne!(ShellStatement::Time(Task::new(vec![String::from("echo")], Redirection::Stdout, Redirection::Stderr)), ShellStatement::Break);
+        //Trap
+        assert_eq!(ShellStatement::Trap(TrapOptions::List), ShellStatement::Trap(TrapOptions::List));
+        assert_ne!(ShellStatement::Trap(TrapOptions::Reset(vec![TrapSignal::Exit])), ShellStatement::Trap(TrapOptions::Reset(vec![TrapSignal::Err])));
+        assert_ne!(ShellStatement::Trap(TrapOptions::Set(String::from("echo"), vec![TrapSignal::Signal(UnixSignal::Sigint)])), ShellStatement::Trap(TrapOptions::Set(String::from("echo"), vec![TrapSignal::Signal(UnixSignal::Sigterm)])));
+        assert_ne!(ShellStatement::Trap(TrapOptions::List), ShellStatement::Break);
         //Unalias
+        //Type
+        assert_eq!(ShellStatement::Type(vec![String::from("ls")], TypeFormat::Verbose, false), ShellStatement::Type(vec![String::from("ls")], TypeFormat::Verbose, false));
+        assert_ne!(ShellStatement::Type(vec![String::from("ls")], TypeFormat::Verbose, false), ShellStatement::Type(vec![String::from("ls")], TypeFormat::Kind, false));
+        assert_ne!(ShellStatement::Type(vec![String::from("ls")], TypeFormat::Verbose, false), ShellStatement::Type(vec![String::from("ls")], TypeFormat::Verbose, true));
+        assert_ne!(ShellStatement::Type(vec![String::from("ls")], TypeFormat::Verbose, false), ShellStatement::Break);
         assert_eq!(ShellStatement::Unalias(String::from("ll")), ShellStatement::Unalias(String::from("ll")));
         assert_ne!(ShellStatement::Unalias(String::from("ll")), ShellStatement::Unalias(String::from("filesize")));
         assert_ne!(ShellStatement::Unalias(String::from("ll")), ShellStatement::Break);
@@ -751,6 +1363,12 @@ mod tests {
         assert_eq!(ShellStatement::While(ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}, ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}), ShellStatement::While(ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}, ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}));
         assert_ne!(ShellStatement::While(ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}, ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}), ShellStatement::While(ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}, ShellExpression {statements: vec![(ShellStatement::Value(String::from("2")), TaskRelation::Unrelated)]}));
         assert_ne!(ShellStatement::While(ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}, ShellExpression {statements: vec![(ShellStatement::Value(String::from("0")), TaskRelation::Unrelated)]}), ShellStatement::Value(String::from("5")));
+        //WithEnv
+        let mut env: HashMap<String, String> = HashMap::new();
+        env.insert(String::from("FOO"), String::from("BAR"));
+        assert_eq!(ShellStatement::WithEnv(env.clone(), ShellExpression {statements: vec![(ShellStatement::Dirs, TaskRelation::Unrelated)]}), ShellStatement::WithEnv(env.clone(), ShellExpression {statements: vec![(ShellStatement::Dirs, TaskRelation::Unrelated)]}));
+        assert_ne!(ShellStatement::WithEnv(env.clone(), ShellExpression {statements: vec![(ShellStatement::Dirs, TaskRelation::Unrelated)]}), ShellStatement::WithEnv(HashMap::new(), ShellExpression {statements: vec![(ShellStatement::Dirs, TaskRelation::Unrelated)]}));
+        assert_ne!(ShellStatement::WithEnv(env.clone(), ShellExpression {statements: vec![(ShellStatement::Dirs, TaskRelation::Unrelated)]}), ShellStatement::Dirs);
         //Write
         assert_eq!(ShellStatement::WriteFile(String::from("/tmp/out"), String::from("hi"), true), ShellStatement::WriteFile(String::from("/tmp/out"), String::from("hi"), true));
         assert_ne!(ShellStatement::WriteFile(String::from("/tmp/out"), String::from("hi"), true), ShellStatement::WriteFile(String::from("/tmp/out"), String::from("hi"), false));
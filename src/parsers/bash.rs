@@ -27,14 +27,26 @@
 
 extern crate getopts;
 
-use crate::{HistoryOptions, MathOperator, ParseStatement, ParserError, ParserErrorCode, ShellCore, ShellExpression, ShellStatement, TaskRelation};
+use crate::{CompletionWord, ExecRedirection, FileRedirectionType, HistoryOptions, MathOperator, ParseStatement, ParserError, ParserErrorCode, ReadOptions, Redirection, ShellCore, ShellExpression, ShellStatement, TaskRelation};
+use crate::{FunctionOptions, HashOptions, TrapOptions, TrapSignal, TypeFormat, UnixSignal};
+use crate::tasks::Task;
 use getopts::Options;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::path::PathBuf;
+use std::time::Duration;
 
 pub struct Bash {}
 
+//Builtins handled by the parser
+const BUILTINS: [&str; 27] = [
+    "alias", "break", "cd", "command", "continue", "declare", "dirs", "eval", "exec", "exit", "export", "hash", "history", "let", "local",
+    "popd", "pushd", "read", "return", "source", ".", "test", "[", "[[", "trap", "type", "unset"
+];
+
+//Greatest number of words a brace expansion can generate; greater expansions are left untouched
+const MAX_BRACE_WORDS: usize = 1 << 20;
+
 /// ## BashParserState
 /// 
 /// Bash parser state describes the current parser state during the parsing of a bash script.
@@ -72,12 +84,28 @@ impl ParseStatement for Bash {
     fn parse(&self, core: &ShellCore, statement: &String) -> Result<ShellExpression, ParserError> {
         //Instantiate BashParserState
         let mut state: BashParserState = BashParserState::new();
-        let mut argv: VecDeque<String> = match self.readline(statement) {
-            Ok(argv) => argv,
-            Err(err) => return Err(err)
-        };
+        let argv: VecDeque<String> = self.readline(statement)?;
+        //Perform alias and brace expansion
+        let argv: VecDeque<String> = self.expand_aliases(core, argv)?;
+        let mut argv: VecDeque<String> = self.expand_braces(argv);
         self.parse_argv(core, state, &mut argv)
     }
+
+    fn has_builtin(&self, command: &str) -> bool {
+        self.is_builtin(&String::from(command))
+    }
+
+    fn expand_history(&self, core: &ShellCore, line: &str) -> Result<Option<(String, bool)>, ParserError> {
+        self.history_expansion(core, line)
+    }
+
+    fn builtin_names(&self) -> Vec<String> {
+        BUILTINS.iter().map(|x| String::from(*x)).collect()
+    }
+
+    fn completion_word(&self, line: &str, cursor: usize) -> Option<CompletionWord> {
+        self.find_completion_word(line, cursor)
+    }
 }
 
 impl Bash {
@@ -96,208 +124,1043 @@ impl Bash {
         //Start iterating
         let mut statements: Vec<(ShellStatement, TaskRelation)> = Vec::new();
         loop {
-            //TODO: impl
-            //TODO: args becomes with '!', '$', '`'
+            //TODO: args becomes with '$', '`' ('!' is expanded before parsing, see history_expansion)
+            //Get first argument
+            let arg: String = match argv.front() {
+                Some(arg) => arg.clone(),
+                None => break
+            };
+            //Skip separators
+            if arg == ";" || arg == "&" {
+                argv.pop_front();
+                continue;
+            }
+            //Function definitions
+            if self.is_function_definition(argv) {
+                let statement: ShellStatement = self.parse_function(core, argv)?;
+                let relation: TaskRelation = self.parse_relation(argv)?;
+                statements.push((statement, relation));
+                continue;
+            }
+            //Subshells and command groups
+            if self.is_group(&arg) {
+                let statement: ShellStatement = self.parse_group(core, argv)?;
+                let relation: TaskRelation = self.parse_relation(argv)?;
+                statements.push((statement, relation));
+                continue;
+            }
+            //Get prefix assignments
+            let assignments: Vec<(String, String)> = self.parse_assignments(argv);
+            //Get command
+            let command: Option<String> = match argv.front() {
+                Some(arg) => match self.is_ligature(arg) {
+                    true => None,
+                    false => Some(arg.clone())
+                },
+                None => None
+            };
+            let block: Vec<ShellStatement> = match command {
+                None => {
+                    if assignments.is_empty() {
+                        return Err(ParserError::new(ParserErrorCode::BadToken, format!("bash: syntax error near unexpected token `{}'", arg)))
+                    }
+                    //Bare assignments are set into the storage
+                    let mut block: Vec<ShellStatement> = Vec::with_capacity(assignments.len());
+                    for (key, value) in assignments.into_iter() {
+                        block.push(ShellStatement::Set(key, ShellExpression { statements: vec![(ShellStatement::Value(value), TaskRelation::Unrelated)] }));
+                    }
+                    block
+                },
+                Some(command) => {
+                    argv.pop_front();
+                    //Builtins arguments are unquoted at parse time; tasks arguments are unquoted after the expansions by the runner
+                    //Conditional expressions, the builtins which run commands and the assigned values are expanded by the runner too
+                    let builtin: String = self.unquote(&command);
+                    if self.is_builtin(&builtin) && ! self.has_raw_args(&builtin) {
+                        self.unquote_builtin_argv(&builtin, argv);
+                    }
+                    match self.parse_builtin(core, &builtin, argv) {
+                        Ok(Some(block)) => match assignments.is_empty() {
+                            true => block,
+                            false => {
+                                //Builtin with environment overrides
+                                let env: HashMap<String, String> = assignments.into_iter().collect();
+                                let mut expression: ShellExpression = ShellExpression { statements: Vec::with_capacity(block.len()) };
+                                for statement in block.into_iter() {
+                                    expression.statements.push((statement, TaskRelation::Unrelated));
+                                }
+                                vec![ShellStatement::WithEnv(env, expression)]
+                            }
+                        },
+                        Ok(None) => match self.parse_task(command, assignments, argv) {
+                            Ok(task) => vec![ShellStatement::Exec(task)],
+                            Err(err) => return Err(err)
+                        },
+                        Err(err) => return Err(err)
+                    }
+                }
+            };
+            //Get relation with the next statement
+            let relation: TaskRelation = self.parse_relation(argv)?;
+            //Push statements; relation is set for the last one only
+            let last_index: usize = block.len() - 1;
+            for (index, statement) in block.into_iter().enumerate() {
+                match index == last_index {
+                    true => statements.push((statement, relation)),
+                    false => statements.push((statement, TaskRelation::Unrelated))
+                }
+            }
+        }
+        Ok(ShellExpression { statements })
+    }
+
+    /// ### parse_builtin
+    /// 
+    /// Parse a builtin command. The command has already been removed from argv.
+    /// Returns None if the command is not a builtin
+    fn parse_builtin(&self, core: &ShellCore, command: &str, argv: &mut VecDeque<String>) -> Result<Option<Vec<ShellStatement>>, ParserError> {
+        let statement: Result<ShellStatement, ParserError> = match command {
+            "alias" => self.parse_alias(core, argv),
+            "break" => {
+                let _ = self.cut_argv_to_delim(argv);
+                Ok(ShellStatement::Break)
+            },
+            "cd" => self.parse_cd(core, argv),
+            "command" => return self.parse_command(core, argv).map(Some),
+            "continue" => {
+                let _ = self.cut_argv_to_delim(argv);
+                Ok(ShellStatement::Continue)
+            },
+            "declare" => self.parse_declare(core, argv),
+            "dirs" => self.parse_dirs(argv),
+            "eval" => Ok(ShellStatement::Eval(self.cut_argv_to_delim(argv))),
+            "exec" => self.parse_exec(argv),
+            "exit" => self.parse_exit(argv),
+            "export" => self.parse_export(core, argv),
+            "hash" => self.parse_hash(argv),
+            "history" => self.parse_history(core, argv),
+            "let" => return self.parse_let(core, argv).map(Some),
+            "local" => self.parse_local(core, argv),
+            "popd" => self.parse_popd(argv),
+            "pushd" => self.parse_pushd(core, argv),
+            "read" => self.parse_read(argv),
+            "return" => self.parse_return(argv),
+            "source" | "." => self.parse_source(core, argv),
+            "test" | "[" | "[[" => self.parse_test(command, argv),
+            "trap" => self.parse_trap(argv),
+            "type" => self.parse_type(argv),
+            "unset" => return self.parse_unset(argv).map(Some),
+            _ => return Ok(None)
+        };
+        statement.map(|statement| Some(vec![statement]))
+    }
+
+    /// ### parse_task
+    /// 
+    /// Parse a command which is not a builtin into a Task.
+    /// The following commands are chained into the same task, as long as they're not builtins
+    fn parse_task(&self, command: String, assignments: Vec<(String, String)>, argv: &mut VecDeque<String>) -> Result<Task, ParserError> {
+        let mut blocks: Vec<(Task, TaskRelation)> = Vec::new();
+        let mut command: String = command;
+        let mut assignments: Vec<(String, String)> = assignments;
+        let last: Task = loop {
+            //Get arguments and redirections
+            let mut args: Vec<String> = vec![command];
+            args.extend(self.cut_argv_to_delim(argv));
+            let mut stdout_redirection: Redirection = Redirection::Stdout;
+            let mut stderr_redirection: Redirection = Redirection::Stderr;
+            while let Some((fd, redirection)) = self.parse_redirection(argv)? {
+                match fd {
+                    2 => stderr_redirection = redirection,
+                    _ => stdout_redirection = redirection
+                }
+                //Arguments can follow the redirection
+                args.extend(self.cut_argv_to_delim(argv));
+            }
+            let mut task: Task = Task::new(args, stdout_redirection, stderr_redirection);
+            for (key, value) in assignments.drain(..) {
+                task.set_env(key, value);
+            }
+            //Check whether the next command can be chained to this task
+            let relation: TaskRelation = match argv.front().map(|x| x.as_str()) {
+                Some("&&") => TaskRelation::And,
+                Some("||") => TaskRelation::Or,
+                Some("|") => TaskRelation::Pipe,
+                _ => TaskRelation::Unrelated
+            };
+            let next_command: Option<String> = match relation {
+                TaskRelation::Unrelated => None,
+                _ => {
+                    //Look for the next command, after the prefix assignments
+                    let next_command: Option<&String> = argv.iter().skip(1).find(|x| ! self.is_assignment(x));
+                    match next_command {
+                        Some(next) if ! self.is_ligature(next) && ! self.is_group(next) && ! self.is_builtin(&self.unquote(next)) => Some(next.clone()),
+                        _ => None
+                    }
+                }
+            };
+            match next_command {
+                None => break task,
+                Some(next) => {
+                    blocks.push((task, relation));
+                    //Remove relation and get assignments
+                    argv.pop_front();
+                    assignments = self.parse_assignments(argv);
+                    argv.pop_front();
+                    command = next;
+                }
+            }
+        };
+        //Link tasks, starting from the last one
+        let head: Task = blocks.into_iter().rev().fold(last, |next, (mut task, relation)| {
+            task.relation = relation;
+            task.next = Some(Box::new(next));
+            task
+        });
+        Ok(head)
+    }
+
+    /// ### parse_redirection
+    /// 
+    /// Parse an output redirection (`> file`, `>> file`, `2> file`...), if argv starts with one. The redirection is removed from argv.
+    /// Returns the redirected file descriptor (1 or 2) and the redirection
+    fn parse_redirection(&self, argv: &mut VecDeque<String>) -> Result<Option<(u32, Redirection)>, ParserError> {
+        let (fd, mode): (u32, FileRedirectionType) = match argv.front().and_then(|x| self.split_redirection(x)) {
+            Some((fd, ">")) => (fd.unwrap_or(1), FileRedirectionType::Truncate),
+            Some((fd, ">>")) => (fd.unwrap_or(1), FileRedirectionType::Append),
+            _ => return Ok(None)
+        };
+        if fd != 1 && fd != 2 {
+            return Err(ParserError::new(ParserErrorCode::BadArgs, format!("bash: {}: bad file descriptor", fd)))
+        }
+        argv.pop_front();
+        let file: String = match argv.front() {
+            Some(file) if ! self.is_ligature(file) => self.unquote(file),
+            Some(file) => return Err(ParserError::new(ParserErrorCode::BadToken, format!("bash: syntax error near unexpected token `{}'", file))),
+            None => return Err(ParserError::new(ParserErrorCode::BadToken, String::from("bash: syntax error near unexpected token `newline'")))
+        };
+        argv.pop_front();
+        Ok(Some((fd, Redirection::File(file, mode))))
+    }
+
+    /// ### split_redirection
+    /// 
+    /// If the token is a redirection operator (`>`, `>>` or `<`), optionally prefixed by a file descriptor number (`2>`), returns the file descriptor and the operator
+    fn split_redirection<'a>(&self, token: &'a str) -> Option<(Option<u32>, &'a str)> {
+        let operator: &str = token.trim_start_matches(|c: char| c.is_ascii_digit());
+        if operator != ">" && operator != ">>" && operator != "<" {
+            return None
+        }
+        match &token[..token.len() - operator.len()] {
+            "" => Some((None, operator)),
+            fd => fd.parse::<u32>().ok().map(|fd| (Some(fd), operator))
+        }
+    }
+
+    /// ### parse_relation
+    /// 
+    /// Parse the relation with the next statement. The relation token is removed from argv.
+    /// If the relation is not followed by anything, an Incomplete error is returned
+    fn parse_relation(&self, argv: &mut VecDeque<String>) -> Result<TaskRelation, ParserError> {
+        let relation: TaskRelation = match argv.front().map(|x| x.as_str()) {
+            Some("&&") => TaskRelation::And,
+            Some("||") => TaskRelation::Or,
+            Some("|") => TaskRelation::Pipe,
+            Some(";") | Some("&") | None => return Ok(TaskRelation::Unrelated),
+            Some(token) => return Err(ParserError::new(ParserErrorCode::BadToken, format!("bash: syntax error near unexpected token `{}'", token)))
+        };
+        argv.pop_front();
+        match argv.is_empty() {
+            true => Err(ParserError::new(ParserErrorCode::Incomplete, String::from(">"))),
+            false => Ok(relation)
+        }
+    }
+
+    /// ### parse_assignments
+    /// 
+    /// Remove the leading NAME=value words from argv and return them. Values keep their quotes, since they're expanded by the runner
+    fn parse_assignments(&self, argv: &mut VecDeque<String>) -> Vec<(String, String)> {
+        let mut assignments: Vec<(String, String)> = Vec::new();
+        while let Some(arg) = argv.front() {
+            if ! self.is_assignment(arg) {
+                break;
+            }
+            let arg: String = argv.pop_front().unwrap();
+            let separator: usize = arg.find('=').unwrap();
+            assignments.push((String::from(&arg[..separator]), String::from(&arg[separator + 1..])));
         }
+        assignments
     }
 
     /// ### eval_expression
     /// 
     /// Evaluates an expression argument into a shell expression
+    /// Command substitutions (`$(cmd)` or `` `cmd` ``) are parsed, anything else is treated as a value
     fn eval_expression(&self, core: &ShellCore, expression: &String) -> Result<ShellExpression, ParserError> {
+        //Values are expanded by the runner, but a quoted command substitution is still a command substitution
+        let substitution: String = match expression.starts_with("\"$(") || expression.starts_with("\"`") {
+            true => self.unquote(expression),
+            false => expression.clone()
+        };
+        let command: String = if substitution.starts_with("$(") && substitution.ends_with(")") {
+            String::from(&substitution[2..substitution.len() - 1])
+        } else if substitution.len() > 1 && substitution.starts_with("`") && substitution.ends_with("`") {
+            String::from(&substitution[1..substitution.len() - 1])
+        } else {
+            return Ok(ShellExpression { statements: vec![(ShellStatement::Value(expression.clone()), TaskRelation::Unrelated)] })
+        };
         //Instantiate BashParserState
         let mut state: BashParserState = BashParserState::new();
-        let mut argv: VecDeque<String> = match self.readline(expression) {
-            Ok(argv) => argv,
-            Err(err) => return Err(err)
-        };
+        let argv: VecDeque<String> = self.readline(&command)?;
+        let argv: VecDeque<String> = self.expand_aliases(core, argv)?;
+        let mut argv: VecDeque<String> = self.expand_braces(argv);
         self.parse_argv(core, state, &mut argv)
     }
 
     /// ### readline
     /// 
-    /// Get arguments from input string
+    /// Split the input string into words and tokens.
+    /// Quotes and escapes are kept in the words, since quote removal is performed after the expansions
     fn readline(&self, input: &String) -> Result<VecDeque<String>, ParserError> {
         let mut argv: VecDeque<String> = VecDeque::new();
         let mut states: BashParserState = BashParserState::new();
-        let mut buffer: Vec<String> = Vec::new();
-        //Split by word
+        let mut word: String = String::new();
+        let mut newline_separator: bool = false; //Whether the last separator has been pushed because of a newline
+        let mut parens: usize = 0; //Open parenthesis tokens
+        let chars: Vec<char> = input.chars().collect();
         let mut index: usize = 0;
-        for (row, line) in input.split("\n").enumerate() { //Iter over lines
-            if row > 0 && line.len() > 0 {
-                //Newlines are pushed as semicolon
-                argv.push_back(String::from(";"));
-            }
-            for word in line.split_whitespace() { //Iter over word in lines
-                let mut word_buf: String = String::with_capacity(word.len());
-                //Iterate over word
-                for (i, c) in word.chars().enumerate() {
-                    let next: char = word.chars().nth(i + 1).unwrap_or(' ');
-                    let mut skip_char: bool = false;
-                    let prev_char: char = states.previous_char;
-                    //If quote block is closed
-                    if ((states.is_on_top(BashParserBlock::Quoted('"')) && c == '"') || (states.is_on_top(BashParserBlock::Quoted('\'')) && c == '\'')) && !states.is_escaped() {
-                        skip_char = true;
+        while index < chars.len() {
+            let c: char = chars[index];
+            //Whitespaces and operators are not part of words, unless they're quoted or escaped
+            if states.empty() {
+                let token: Option<&str> = match (c, chars.get(index + 1)) {
+                    ('\n', _) => Some(";"), //Newlines are pushed as semicolon
+                    ('&', Some('&')) => Some("&&"),
+                    ('|', Some('|')) => Some("||"),
+                    ('>', Some('>')) => Some(">>"),
+                    ('<', Some('<')) => Some("<<"),
+                    //Process substitutions are words
+                    ('<', Some('(')) | ('>', Some('(')) => None,
+                    ('&', _) => Some("&"),
+                    ('|', _) => Some("|"),
+                    ('>', _) => Some(">"),
+                    ('<', _) => Some("<"),
+                    (';', _) => Some(";"),
+                    //Parenthesis are tokens, unless they open a command substitution, a process substitution or an extended pattern
+                    ('(', _) if ! word.ends_with(['$', '@', '!', '?', '*', '+', '<', '>']) => Some("("),
+                    (')', _) if parens > 0 => Some(")"),
+                    _ => None
+                };
+                if c.is_whitespace() || token.is_some() {
+                    //Redirection operators can be prefixed by the file descriptor number (`2> file`)
+                    let fd: String = match token {
+                        Some(">") | Some(">>") | Some("<") if ! word.is_empty() && word.chars().all(|x| x.is_ascii_digit()) => std::mem::take(&mut word),
+                        _ => String::new()
+                    };
+                    //Push word
+                    if ! word.is_empty() {
+                        argv.push_back(std::mem::take(&mut word));
+                        newline_separator = false;
                     }
-                    if let Some(err) = states.update_state(c) {
-                        return Err(ParserError::new(err, String::from(format!("bash: error at {}", index))))
+                    match token {
+                        //Don't push newline separators after separators or at the beginning
+                        Some(";") if c == '\n' && argv.back().map(|x| x != ";").unwrap_or(false) => {
+                            argv.push_back(String::from(";"));
+                            newline_separator = true;
+                        },
+                        Some(token) if c != '\n' => {
+                            match token {
+                                "(" => parens += 1,
+                                ")" => parens -= 1,
+                                _ => {}
+                            }
+                            argv.push_back(format!("{}{}", fd, token));
+                            newline_separator = false;
+                            index += token.len() - 1;
+                        },
+                        _ => {}
                     }
                     index += 1;
-                    //If quote block is opened, ignore char
-                    if ((states.is_on_top(BashParserBlock::Quoted('"')) && c == '"') || (states.is_on_top(BashParserBlock::Quoted('\'')) && c == '\'')) && prev_char != '\\' {
-                        skip_char = true;
+                    continue;
+                }
+            }
+            if let Some(err) = states.update_state(c) {
+                return Err(ParserError::new(err, format!("bash: error at {}", index)))
+            }
+            word.push(c);
+            index += 1;
+        }
+        //If a block is still open, the statement is incomplete
+        if ! states.empty() || parens > 0 {
+            return Err(ParserError::new(ParserErrorCode::Incomplete, String::from(">")))
+        }
+        if ! word.is_empty() {
+            argv.push_back(word);
+        } else if newline_separator {
+            //Remove trailing newline separator
+            argv.pop_back();
+        }
+        Ok(argv)
+    }
+
+    /// ### find_completion_word
+    /// 
+    /// Split the line up to the cursor like readline, to find the word which ends at the cursor and the command of its simple command.
+    /// Words following separators, reserved words and prefix assignments are in command position; words following a redirection are file names
+    fn find_completion_word(&self, line: &str, cursor: usize) -> Option<CompletionWord> {
+        let input: &str = line.get(..cursor)?;
+        let mut states: BashParserState = BashParserState::new();
+        let mut word: String = String::new();
+        let mut start: usize = 0;
+        let mut command: Option<String> = None;
+        let mut command_position: bool = true;
+        let mut redirection: bool = false; //Whether the next word is the target of a redirection
+        let chars: Vec<(usize, char)> = input.char_indices().collect();
+        for (index, (offset, c)) in chars.iter().enumerate() {
+            if states.empty() {
+                let next: Option<char> = chars.get(index + 1).map(|(_, x)| *x);
+                let separator: bool = match c {
+                    '\n' | ';' | '&' | '|' | ')' => true,
+                    '(' => ! word.ends_with(['$', '@', '!', '?', '*', '+', '<', '>']),
+                    _ => false
+                };
+                let redirect: bool = (*c == '<' || *c == '>') && next != Some('(');
+                if c.is_whitespace() || separator || redirect {
+                    if ! word.is_empty() {
+                        let word: String = std::mem::take(&mut word);
+                        if redirection {
+                            redirection = false;
+                        } else if redirect && word.chars().all(|x| x.is_ascii_digit()) {
+                            //File descriptor of the redirection (e.g. `2>`)
+                        } else if command_position && ! self.is_assignment(&word) && ! matches!(word.as_str(), "{" | "!" | "do" | "elif" | "else" | "if" | "then" | "time" | "until" | "while") {
+                            command = Some(self.unquote(word.as_str()));
+                            command_position = false;
+                        }
                     }
-                    if (c == '>' || c == '<' || c == '|' || c == '&') && states.is_quoted() {
-                        word_buf.push('\\');
+                    if separator {
+                        command = None;
+                        command_position = true;
+                        redirection = false;
                     }
-                    //Ignore escape block opener
-                    if states.is_escaped() && c == '\\' && next == '"' {
-                        skip_char = true;
+                    redirection = redirection || redirect;
+                    start = offset + c.len_utf8();
+                    continue;
+                }
+            }
+            if states.update_state(*c).is_some() {
+                return None
+            }
+            word.push(*c);
+        }
+        Some(CompletionWord {
+            start,
+            word,
+            command_position: command_position && ! redirection,
+            command
+        })
+    }
+
+    /// ### history_expansion
+    /// 
+    /// Perform csh-style history expansion on the input line: `!!`, `!n` (index in the history list), `!-n`, `!prefix`, `!?str?`, `!#`,
+    /// word designators (`!$`, `!*`, `:0`, `:2-3`...) and modifiers (`:h`, `:t`, `:r`, `:e`, `:s/old/new/`, `:gs/old/new/`, `:&`, `:q`, `:p`).
+    /// `^old^new^` at the beginning of the line is `!!:s^old^new^`. Single quotes and backslashes prevent the expansion.
+    /// Returns the expanded line and whether it must be executed (`:p` only prints it), or None if there's nothing to expand
+    fn history_expansion(&self, core: &ShellCore, line: &str) -> Result<Option<(String, bool)>, ParserError> {
+        let line: String = match line.starts_with('^') {
+            true => format!("!!:s{}", line),
+            false => String::from(line)
+        };
+        let chars: Vec<char> = line.chars().collect();
+        let mut expanded: String = String::with_capacity(line.len());
+        let mut changed: bool = false;
+        let mut execute: bool = true;
+        let mut substitution: Option<(String, String)> = None; //Last substitution, repeated by `:&`
+        let mut single_quoted: bool = false;
+        let mut double_quoted: bool = false;
+        let mut index: usize = 0;
+        while index < chars.len() {
+            let c: char = chars[index];
+            match c {
+                '\\' if ! single_quoted => {
+                    expanded.extend(chars[index..].iter().take(2));
+                    index += 2;
+                    continue;
+                },
+                '\'' if ! double_quoted => single_quoted = ! single_quoted,
+                '"' if ! single_quoted => double_quoted = ! double_quoted,
+                //`$!` and `${!name}` are parameter expansions; `!` followed by a blank, `=` or `(` is not expanded
+                '!' if ! single_quoted && ! expanded.ends_with('$') && ! expanded.ends_with("${") => match chars.get(index + 1) {
+                    None | Some(' ') | Some('\t') | Some('\n') | Some('=') | Some('(') => {},
+                    Some('"') if double_quoted => {},
+                    Some(_) => {
+                        let (text, next, print): (String, usize, bool) = self.history_event(core, &chars, index + 1, expanded.as_str(), &mut substitution)?;
+                        expanded.push_str(text.as_str());
+                        execute = execute && ! print;
+                        changed = true;
+                        index = next;
+                        continue;
+                    }
+                },
+                _ => {}
+            }
+            expanded.push(c);
+            index += 1;
+        }
+        match changed {
+            true => Ok(Some((expanded, execute))),
+            false => Ok(None)
+        }
+    }
+
+    /// ### history_event
+    /// 
+    /// Expand the history event which starts at index (after `!`), with its word designator and modifiers.
+    /// Returns the expansion, the index of the first character after it and whether the `:p` modifier was found
+    fn history_event(&self, core: &ShellCore, chars: &[char], index: usize, line: &str, substitution: &mut Option<(String, String)>) -> Result<(String, usize, bool), ParserError> {
+        let is_delimiter = |c: &char| c.is_whitespace() || ":;&|()<>\"'".contains(*c);
+        let start: usize = index;
+        let mut index: usize = index;
+        let mut search: Option<String> = None; //String searched by `!?str?`, used by `%`
+        //@! Event designator
+        let event: Option<String> = match chars[index] {
+            '!' => {
+                index += 1;
+                core.history_at(0)
+            },
+            '#' => {
+                index += 1;
+                Some(String::from(line))
+            },
+            //Word designators without an event refer to the previous command
+            ':' | '$' | '^' | '*' | '%' => core.history_at(0),
+            '?' => {
+                let end: usize = chars[index + 1..].iter().position(|x| *x == '?' || *x == '\n').map(|x| x + index + 1).unwrap_or(chars.len());
+                let needle: String = chars[index + 1..end].iter().collect();
+                index = match chars.get(end) {
+                    Some('?') => end + 1,
+                    _ => end
+                };
+                let event: Option<String> = core.history_get().into_iter().find(|x| x.contains(needle.as_str()));
+                search = Some(needle);
+                event
+            },
+            c if c.is_ascii_digit() || (c == '-' && chars.get(index + 1).map(|x| x.is_ascii_digit()).unwrap_or(false)) => {
+                let start: usize = index;
+                index += 1;
+                while chars.get(index).map(|x| x.is_ascii_digit()).unwrap_or(false) {
+                    index += 1;
+                }
+                let number: String = chars[start..index].iter().collect();
+                match number.strip_prefix('-') {
+                    Some(digits) => digits.parse::<usize>().ok().and_then(|n| n.checked_sub(1)).and_then(|n| core.history_at(n)),
+                    None => number.parse::<usize>().ok().and_then(|n| core.history_at(n))
+                }
+            },
+            _ => {
+                let start: usize = index;
+                while chars.get(index).map(|x| ! is_delimiter(x)).unwrap_or(false) {
+                    index += 1;
+                }
+                let prefix: String = chars[start..index].iter().collect();
+                core.history_get().into_iter().find(|x| x.starts_with(prefix.as_str()))
+            }
+        };
+        let event: String = match event {
+            Some(event) => event,
+            None => return Err(ParserError::new(ParserErrorCode::BadToken, format!("bash: !{}: event not found", chars[start..index].iter().collect::<String>())))
+        };
+        //@! Word designator
+        let word_designator: bool = match (chars.get(index), chars.get(index + 1)) {
+            (Some(':'), Some(c)) => c.is_ascii_digit() || "^$*-%".contains(*c),
+            (Some(c), _) => "^$*-%".contains(*c),
+            _ => false
+        };
+        let mut text: String = match word_designator {
+            false => event,
+            true => {
+                if chars[index] == ':' {
+                    index += 1;
+                }
+                let words: Vec<String> = match self.readline(&event) {
+                    Ok(words) => words.into_iter().collect(),
+                    Err(_) => event.split_whitespace().map(String::from).collect()
+                };
+                let last: usize = words.len().saturating_sub(1);
+                let number = |index: &mut usize| -> Option<usize> {
+                    match chars.get(*index) {
+                        Some('$') => {
+                            *index += 1;
+                            Some(last)
+                        },
+                        Some('^') => {
+                            *index += 1;
+                            Some(1)
+                        },
+                        Some(c) if c.is_ascii_digit() => {
+                            let start: usize = *index;
+                            while chars.get(*index).map(|x| x.is_ascii_digit()).unwrap_or(false) {
+                                *index += 1;
+                            }
+                            chars[start..*index].iter().collect::<String>().parse::<usize>().ok()
+                        },
+                        _ => None
                     }
-                    if ! skip_char {
-                        word_buf.push(c);
+                };
+                let range: Option<(usize, usize)> = match chars.get(index) {
+                    Some('*') => {
+                        index += 1;
+                        Some((1, last))
+                    },
+                    Some('%') => {
+                        index += 1;
+                        let needle: String = search.clone().unwrap_or_default();
+                        words.iter().position(|x| ! needle.is_empty() && x.contains(needle.as_str())).map(|x| (x, x))
+                    },
+                    Some('-') => {
+                        index += 1;
+                        number(&mut index).map(|y| (0, y))
+                    },
+                    _ => match number(&mut index) {
+                        Some(x) => match (chars.get(index), chars.get(index + 1)) {
+                            (Some('*'), _) => {
+                                index += 1;
+                                Some((x, last))
+                            },
+                            (Some('-'), next) => {
+                                index += 1;
+                                match next.map(|x| x.is_ascii_digit() || *x == '$').unwrap_or(false) {
+                                    true => number(&mut index).map(|y| (x, y)),
+                                    false => Some((x, last.saturating_sub(1)))
+                                }
+                            },
+                            _ => Some((x, x))
+                        },
+                        None => None
                     }
+                };
+                match range {
+                    //`*` is empty if the event has only one word
+                    Some((first, last)) if first > last && first == 1 => String::new(),
+                    Some((first, last)) if first <= last && last < words.len() => words[first..=last].join(" "),
+                    _ => return Err(ParserError::new(ParserErrorCode::BadToken, format!("bash: !{}: bad word specifier", chars[start..index].iter().collect::<String>())))
                 }
-                //Push word buffer if big enough
-                if word_buf.len() > 0 {
-                    //Iter over word buffer and split some tokens
-                    let mut remainder: Option<char> = None;
-                    let mut escaped: bool = false;
-                    let orig_word_buf = word_buf.clone();
-                    for (index, c) in orig_word_buf.chars().enumerate() {
-                        let next: Option<char> = orig_word_buf.chars().nth(index + 1);
-                        if escaped && (c == '>' || c == '<' || c == '|' || c == '&') {
-                            word_buf = word_buf.replace("\\>", ">");
-                            word_buf = word_buf.replace("\\<", "<");
-                            word_buf = word_buf.replace("\\|", "|");
-                            word_buf = word_buf.replace("\\&", "&");
+            }
+        };
+        //@! Modifiers
+        let mut print: bool = false;
+        while chars.get(index) == Some(&':') {
+            let modifier: char = match chars.get(index + 1) {
+                Some(c) if "htreqpsga&".contains(*c) => *c,
+                _ => break
+            };
+            index += 2;
+            match modifier {
+                'h' => if let Some(slash) = text.rfind('/') {
+                    text.truncate(slash);
+                },
+                't' => if let Some(slash) = text.rfind('/') {
+                    text = text.split_off(slash + 1);
+                },
+                'r' => if let Some(dot) = text.rfind('.').filter(|dot| ! text[*dot..].contains('/')) {
+                    text.truncate(dot);
+                },
+                'e' => text = match text.rfind('.').filter(|dot| ! text[*dot..].contains('/')) {
+                    Some(dot) => text.split_off(dot),
+                    None => String::new()
+                },
+                'q' => text = format!("'{}'", text.replace('\'', "'\\''")),
+                'p' => print = true,
+                _ => {
+                    //Substitutions: `s/old/new/`, `gs/old/new/` (or `as`, every occurrence) and `&` (repeat the last substitution)
+                    let global: bool = modifier == 'g' || modifier == 'a';
+                    if global {
+                        match chars.get(index) {
+                            Some('s') | Some('&') => index += 1,
+                            _ => return Err(ParserError::new(ParserErrorCode::BadToken, format!("bash: {}: unrecognized history modifier", modifier)))
                         }
-                        if let Some(prev) = remainder {
-                            //Look for &&
-                            if prev == '&' && c == '&' {
-                                let prev_part: String = String::from(&word_buf[..index - 1]);
-                                if prev_part.len() > 0 {
-                                    argv.push_back(prev_part); //Push word before &&
-                                }
-                                //Push &&
-                                argv.push_back(String::from("&&"));
-                                //Word buf becomes from the first char after &&
-                                word_buf = String::from(&word_buf[index + 1..]);
-                            } else if prev == '|' && c == '|' {
-                                let prev_part: String = String::from(&word_buf[..index - 1]);
-                                if prev_part.len() > 0 {
-                                    argv.push_back(prev_part); //Push word before ||
-                                }
-                                //Push ||
-                                argv.push_back(String::from("||"));
-                                //Word buf becomes from the first char after ||
-                                word_buf = String::from(&word_buf[index + 1..]);
-                            } else if prev == '>' && c == '>' {
-                                let prev_part: String = String::from(&word_buf[..index - 1]);
-                                if prev_part.len() > 0 {
-                                    argv.push_back(prev_part); //Push word before >>
-                                }
-                                //Push >>
-                                argv.push_back(String::from(">>"));
-                                //Word buf becomes from the first char after >>
-                                word_buf = String::from(&word_buf[index + 1..]);
-                            } else if prev == '<' && c == '<' {
-                                let prev_part: String = String::from(&word_buf[..index - 1]);
-                                if prev_part.len() > 0 {
-                                    argv.push_back(prev_part); //Push word before <<
-                                }
-                                //Push <<
-                                argv.push_back(String::from("<<"));
-                                //Word buf becomes from the first char after <<
-                                word_buf = String::from(&word_buf[index + 1..]);
-                            }
-                        } else if ! escaped {
-                            //Check if pipe
-                            if let Some(next) = next {
-                                if c == '|' && next != '|' {
-                                    let prev_part: String = String::from(&word_buf[..index]);
-                                    if prev_part.len() > 0 {
-                                        argv.push_back(prev_part); //Push word before |
-                                    }
-                                    //Push &&
-                                    argv.push_back(String::from("|"));
-                                    //Word buf becomes from the first char after |
-                                    word_buf = String::from(&word_buf[index + 1..]);
-                                } else if c == '|' && next == '|' {
-                                    //Set remainder
-                                    remainder = Some('|');
-                                } else if c == '>' && next != '>' {
-                                    let prev_part: String = String::from(&word_buf[..index]);
-                                    if prev_part.len() > 0 {
-                                        argv.push_back(prev_part); //Push word before >
-                                    }
-                                    //Push >
-                                    argv.push_back(String::from(">"));
-                                    //Word buf becomes from the first char after >
-                                    word_buf = String::from(&word_buf[index + 1..]);
-                                } else if c == '>' && next == '>' {
-                                    //Set remainder
-                                    remainder = Some('>');
-                                } else if c == '<' && next != '<' {
-                                    let prev_part: String = String::from(&word_buf[..index]);
-                                    if prev_part.len() > 0 {
-                                        argv.push_back(prev_part); //Push word before <
-                                    }
-                                    //Push >
-                                    argv.push_back(String::from("<"));
-                                    //Word buf becomes from the first char after <
-                                    word_buf = String::from(&word_buf[index + 1..]);
-                                } else if c == '<' && next == '<' {
-                                    //Set remainder
-                                    remainder = Some('<');
-                                } else if c == '&' && next == '&' {
-                                    //Set remainder
-                                    remainder = Some('&');
-                                } else if c == '&' && next != '&' {
-                                    let prev_part: String = String::from(&word_buf[..index]);
-                                    if prev_part.len() > 0 {
-                                        argv.push_back(prev_part); //Push word before &
-                                    }
-                                    //Push &
-                                    argv.push_back(String::from("&"));
-                                    //Word buf becomes from the first char after &
-                                    word_buf = String::from(&word_buf[index + 1..]);
+                    }
+                    if modifier == 's' || (global && chars[index - 1] == 's') {
+                        let delimiter: char = match chars.get(index) {
+                            Some(c) => *c,
+                            None => return Err(ParserError::new(ParserErrorCode::BadToken, String::from("bash: :s: substitution failed")))
+                        };
+                        index += 1;
+                        let mut parts: Vec<String> = vec![String::new(), String::new()];
+                        for part in parts.iter_mut() {
+                            while let Some(c) = chars.get(index) {
+                                index += 1;
+                                match c {
+                                    c if *c == delimiter => break,
+                                    '\\' if chars.get(index) == Some(&delimiter) => {
+                                        part.push(delimiter);
+                                        index += 1;
+                                    },
+                                    '\n' => {
+                                        index -= 1;
+                                        break;
+                                    },
+                                    c => part.push(*c)
                                 }
                             }
-                            //Handle semicolon
-                            if c == ';' {
-                                let prev_part: String = String::from(&word_buf[..index]);
-                                if prev_part.len() > 0 {
-                                    argv.push_back(prev_part); //Push word before ;
+                        }
+                        let new: String = parts.pop().unwrap();
+                        let old: String = match parts.pop().unwrap() {
+                            old if old.is_empty() => substitution.as_ref().map(|x| x.0.clone()).or_else(|| search.clone()).unwrap_or_default(),
+                            old => old
+                        };
+                        //`&` in the replacement is the old string
+                        let mut replacement: String = String::with_capacity(new.len());
+                        let mut escaped: bool = false;
+                        for c in new.chars() {
+                            match c {
+                                '\\' if ! escaped => escaped = true,
+                                '&' if ! escaped => replacement.push_str(old.as_str()),
+                                c => {
+                                    replacement.push(c);
+                                    escaped = false;
                                 }
-                                //Push ;
-                                argv.push_back(String::from(";"));
-                                //Word buf becomes from the first char after ;
-                                word_buf = String::from(&word_buf[index + 1..]);
                             }
                         }
-                        if c == '\\' {
-                            escaped = true;
-                        } else {
-                            escaped = false;
+                        *substitution = Some((old, replacement));
+                    }
+                    let (old, new): (String, String) = match substitution.as_ref() {
+                        Some((old, new)) if ! old.is_empty() && text.contains(old.as_str()) => (old.clone(), new.clone()),
+                        _ => return Err(ParserError::new(ParserErrorCode::BadToken, String::from("bash: :s: substitution failed")))
+                    };
+                    text = match global {
+                        true => text.replace(old.as_str(), new.as_str()),
+                        false => text.replacen(old.as_str(), new.as_str(), 1)
+                    };
+                }
+            }
+        }
+        Ok((text, index, print))
+    }
+
+    /// ### join_words
+    /// 
+    /// Join the words back into a command line; separators are attached to the previous word
+    fn join_words(&self, words: &[String]) -> String {
+        let mut line: String = String::new();
+        let mut prev: Option<&str> = None;
+        for word in words.iter() {
+            //Newlines after an opening token are not separators
+            if word == ";" && matches!(prev, None | Some("{") | Some("(") | Some(";")) {
+                continue;
+            }
+            prev = Some(word.as_str());
+            if ! line.is_empty() && word != ";" && word != "&" {
+                line.push(' ');
+            }
+            line.push_str(word.as_str());
+        }
+        line
+    }
+
+    /// ### unquote
+    /// 
+    /// Perform quote removal on a word which is used at parse time (e.g. builtins arguments).
+    /// Quoted and escaped tildes are kept escaped (`\~`), so they won't be expanded by the core
+    fn unquote(&self, word: &str) -> String {
+        let mut output: String = String::with_capacity(word.len());
+        let mut quote: Option<char> = None;
+        let mut chars = word.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (quote, c) {
+                (None, '\'') | (None, '"') => quote = Some(c),
+                (Some(q), c) if q == c => quote = None,
+                (Some(_), '~') => output.push_str("\\~"),
+                (Some('\''), '\\') if chars.peek() == Some(&'\'') => {
+                    //Escaped quote inside single quotes is kept as it is
+                    output.push('\\');
+                    output.push(chars.next().unwrap());
+                },
+                (None, '\\') => match chars.next() {
+                    Some('~') => output.push_str("\\~"),
+                    Some(c) => output.push(c),
+                    None => output.push('\\')
+                },
+                (Some('"'), '\\') => match chars.peek() {
+                    //Inside double quotes, backslash escapes only these characters
+                    Some('$') | Some('`') | Some('"') | Some('\\') => output.push(chars.next().unwrap()),
+                    _ => output.push('\\')
+                },
+                (_, c) => output.push(c)
+            }
+        }
+        output
+    }
+
+    /// ### unquote_argv
+    /// 
+    /// Perform quote removal on the arguments until the first delimiter.
+    /// Quoted delimiters are kept quoted, otherwise they would be treated as delimiters by the builtin parsers
+    fn unquote_argv(&self, argv: &mut VecDeque<String>) {
+        for arg in argv.iter_mut() {
+            if self.is_ligature(arg) {
+                break;
+            }
+            let unquoted: String = self.unquote(arg);
+            if ! self.is_ligature(&unquoted) {
+                *arg = unquoted;
+            }
+        }
+    }
+
+    /// ### unquote_builtin_argv
+    /// 
+    /// Perform quote removal on the arguments of a builtin until the first delimiter.
+    /// The builtins which assign variables (declare, export and local) keep the quotes of the values, since they're removed by the runner after the expansions
+    fn unquote_builtin_argv(&self, builtin: &str, argv: &mut VecDeque<String>) {
+        if ! matches!(builtin, "declare" | "export" | "local") {
+            return self.unquote_argv(argv)
+        }
+        for arg in argv.iter_mut() {
+            if self.is_ligature(arg) {
+                break;
+            }
+            //Names can't be quoted, so assignments are kept as they are
+            if self.is_assignment(arg) {
+                continue;
+            }
+            let unquoted: String = self.unquote(arg);
+            if ! self.is_ligature(&unquoted) {
+                *arg = unquoted;
+            }
+        }
+    }
+
+    /// ### skip_quoted
+    /// 
+    /// Returns the index of the quote which closes the quote opened at index (or the length of chars if it's never closed)
+    fn skip_quoted(&self, chars: &[char], index: usize) -> usize {
+        let quote: char = chars[index];
+        let mut cursor: usize = index + 1;
+        while cursor < chars.len() && chars[cursor] != quote {
+            //Skip escaped characters
+            if chars[cursor] == '\\' {
+                cursor += 1;
+            }
+            cursor += 1;
+        }
+        cursor
+    }
+
+    /// ### expand_aliases
+    /// 
+    /// Replace the aliases found in command position with their value, as bash does while reading the input.
+    /// Aliases are expanded recursively, but an alias is never expanded inside its own expansion;
+    /// if the alias value ends with a blank, the word following the alias is checked too
+    fn expand_aliases(&self, core: &ShellCore, argv: VecDeque<String>) -> Result<VecDeque<String>, ParserError> {
+        let mut expanding: Vec<String> = Vec::new();
+        self.alias_expansion(core, argv, &mut expanding).map(|(argv, _)| argv)
+    }
+
+    /// ### alias_expansion
+    /// 
+    /// Expand the aliases in argv, skipping the aliases which are being expanded.
+    /// Returns the expanded words and whether the word following them is in command position
+    fn alias_expansion(&self, core: &ShellCore, argv: VecDeque<String>, expanding: &mut Vec<String>) -> Result<(VecDeque<String>, bool), ParserError> {
+        let mut expanded: VecDeque<String> = VecDeque::with_capacity(argv.len());
+        let mut command_position: bool = true;
+        for word in argv.into_iter() {
+            if command_position && ! expanding.contains(&word) {
+                if let Some(value) = core.alias_get(&word) {
+                    //The alias value is split into words keeping its quotes, then its own commands are expanded
+                    let words: VecDeque<String> = self.readline(&value)?;
+                    expanding.push(word);
+                    let (words, next_is_command): (VecDeque<String>, bool) = self.alias_expansion(core, words, expanding)?;
+                    expanding.pop();
+                    expanded.extend(words);
+                    command_position = next_is_command || value.ends_with(char::is_whitespace);
+                    continue;
+                }
+            }
+            //Commands follow separators, and reserved words which are themselves in command position; prefix assignments keep the command position
+            command_position = match word.as_str() {
+                ";" | "&" | "&&" | "||" | "|" | "(" => true,
+                "{" | "!" | "do" | "elif" | "else" | "if" | "then" | "time" | "until" | "while" => command_position,
+                _ => command_position && self.is_assignment(&word)
+            };
+            expanded.push_back(word);
+        }
+        Ok((expanded, command_position))
+    }
+
+    /// ### expand_braces
+    /// 
+    /// Perform brace expansion on each word of argv (e.g. `src/{bin,lib}` becomes `src/bin src/lib`).
+    /// Quoted and escaped braces are not expanded
+    fn expand_braces(&self, argv: VecDeque<String>) -> VecDeque<String> {
+        let mut expanded: VecDeque<String> = VecDeque::with_capacity(argv.len());
+        for word in argv.into_iter() {
+            //Empty words produced by the expansion are removed
+            for word in self.brace_expansion(&word).into_iter().filter(|x| ! x.is_empty()) {
+                expanded.push_back(word);
+            }
+        }
+        expanded
+    }
+
+    /// ### brace_expansion
+    /// 
+    /// Expand the braces in a word. Supports comma lists (`{a,b}`), sequences (`{1..10}`, `{01..10..2}`, `{a..z}`) and nesting.
+    /// Parameter expansions (`${...}`) and braces which contain neither a comma nor a sequence are left untouched
+    fn brace_expansion(&self, word: &str) -> Vec<String> {
+        let chars: Vec<char> = word.chars().collect();
+        let mut index: usize = 0;
+        while index < chars.len() {
+            match chars[index] {
+                '\\' => {
+                    //Skip escaped char
+                    index += 2;
+                    continue;
+                },
+                '\'' | '"' => {
+                    //Skip quoted text
+                    index = self.skip_quoted(&chars, index) + 1;
+                    continue;
+                },
+                '{' => {},
+                _ => {
+                    index += 1;
+                    continue;
+                }
+            }
+            //Find the matching brace and the commas at the first level
+            let mut depth: usize = 0;
+            let mut commas: Vec<usize> = Vec::new();
+            let mut close: Option<usize> = None;
+            let mut cursor: usize = index + 1;
+            while cursor < chars.len() {
+                match chars[cursor] {
+                    '\\' => cursor += 1,
+                    '\'' | '"' => cursor = self.skip_quoted(&chars, cursor),
+                    '{' => depth += 1,
+                    '}' if depth == 0 => {
+                        close = Some(cursor);
+                        break;
+                    },
+                    '}' => depth -= 1,
+                    ',' if depth == 0 => commas.push(cursor),
+                    _ => {}
+                }
+                cursor += 1;
+            }
+            let close: usize = match close {
+                Some(close) => close,
+                None => break //No more closed braces
+            };
+            //Parameter expansion, skip it
+            if index > 0 && chars[index - 1] == '$' {
+                index = close + 1;
+                continue;
+            }
+            //Get alternatives
+            let alternatives: Vec<String> = match commas.len() {
+                0 => {
+                    let body: String = chars[index + 1..close].iter().collect();
+                    match self.brace_sequence(&body) {
+                        Some(sequence) => sequence,
+                        None => {
+                            //Not an expansion, look for braces inside it
+                            index += 1;
+                            continue;
                         }
                     }
-                    //Push remaining word buffer
-                    if word_buf.len() > 0 {
-                        buffer.push(word_buf);
+                },
+                _ => {
+                    let mut alternatives: Vec<String> = Vec::new();
+                    let mut start: usize = index + 1;
+                    for separator in commas.iter().chain(std::iter::once(&close)) {
+                        let alternative: String = chars[start..*separator].iter().collect();
+                        alternatives.extend(self.brace_expansion(&alternative));
+                        start = separator + 1;
                     }
+                    alternatives
                 }
-                index += 1; //Increment cause of whitespace
-                //If states stack is empty, push argument
-                if states.empty() {
-                    if buffer.len() > 0 {
-                        argv.push_back(buffer.join(" "));
-                        buffer.clear();
-                    }
+            };
+            //Combine prefix, alternatives and suffix
+            let prefix: String = chars[..index].iter().collect();
+            let suffix: String = chars[close + 1..].iter().collect();
+            let suffixes: Vec<String> = self.brace_expansion(&suffix);
+            if alternatives.len().saturating_mul(suffixes.len()) > MAX_BRACE_WORDS {
+                break
+            }
+            let mut words: Vec<String> = Vec::with_capacity(alternatives.len() * suffixes.len());
+            for alternative in alternatives.iter() {
+                for suffix in suffixes.iter() {
+                    words.push(format!("{}{}{}", prefix, alternative, suffix));
                 }
-            } //End of line
+            }
+            return words
         }
-        Ok(argv)
+        vec![String::from(word)]
+    }
+
+    /// ### brace_sequence
+    /// 
+    /// Evaluate a brace sequence expression (`x..y[..incr]`), where x and y are both integers or both single characters.
+    /// Integers are zero padded if either x or y has a leading zero. Returns None if the body is not a sequence expression
+    fn brace_sequence(&self, body: &str) -> Option<Vec<String>> {
+        let parts: Vec<&str> = body.split("..").collect();
+        if parts.len() != 2 && parts.len() != 3 {
+            return None
+        }
+        //Get step (0 is treated as 1)
+        let step: usize = match parts.get(2) {
+            Some(step) => match step.parse::<isize>() {
+                Ok(0) => 1,
+                Ok(step) => step.unsigned_abs(),
+                Err(_) => return None
+            },
+            None => 1
+        };
+        let mut sequence: Vec<String> = Vec::new();
+        match (parts[0].parse::<isize>(), parts[1].parse::<isize>()) {
+            (Ok(start), Ok(end)) => {
+                //Get padding
+                let padded = |x: &str| -> bool {
+                    let digits: &str = x.trim_start_matches(['-', '+']);
+                    digits.len() > 1 && digits.starts_with('0')
+                };
+                let width: usize = match padded(parts[0]) || padded(parts[1]) {
+                    true => std::cmp::max(parts[0].len(), parts[1].len()),
+                    false => 0
+                };
+                if start.abs_diff(end) / step >= MAX_BRACE_WORDS {
+                    return None
+                }
+                let values: Vec<isize> = match start <= end {
+                    true => (start..=end).step_by(step).collect(),
+                    false => (end..=start).rev().step_by(step).collect()
+                };
+                for value in values.into_iter() {
+                    sequence.push(match value < 0 {
+                        true => format!("-{:0width$}", value.unsigned_abs(), width = width.saturating_sub(1)),
+                        false => format!("{:0width$}", value, width = width)
+                    });
+                }
+            },
+            (Err(_), Err(_)) => {
+                let mut start = parts[0].chars();
+                let mut end = parts[1].chars();
+                let (start, end): (char, char) = match (start.next(), start.next(), end.next(), end.next()) {
+                    (Some(start), None, Some(end), None) if start.is_ascii_alphabetic() && end.is_ascii_alphabetic() => (start, end),
+                    _ => return None
+                };
+                let values: Vec<char> = match start <= end {
+                    true => (start..=end).step_by(step).collect(),
+                    false => (end..=start).rev().step_by(step).collect()
+                };
+                for value in values.into_iter() {
+                    sequence.push(value.to_string());
+                }
+            },
+            _ => return None
+        }
+        Some(sequence)
     }
 
     /// ### is_ligature
@@ -325,10 +1188,56 @@ impl Bash {
         } else if arg == "<<" {
             true
         } else {
-            false
+            self.split_redirection(arg).is_some()
+        }
+    }
+
+    /// ### is_group
+    /// 
+    /// Returns whether the token opens a subshell or a command group
+    fn is_group(&self, arg: &str) -> bool {
+        arg == "(" || arg == "{"
+    }
+
+    /// ### is_function_definition
+    /// 
+    /// Returns whether argv starts with a function definition (`function NAME ...` or `NAME() ...`)
+    fn is_function_definition(&self, argv: &VecDeque<String>) -> bool {
+        match (argv.front(), argv.get(1), argv.get(2)) {
+            (Some(keyword), _, _) if keyword == "function" => true,
+            (Some(name), Some(open), Some(close)) => open == "(" && close == ")" && ! self.is_ligature(name) && ! self.is_group(name),
+            _ => false
         }
     }
 
+    /// ### is_assignment
+    /// 
+    /// Returns whether the argument is a variable assignment (NAME=value)
+    fn is_assignment(&self, arg: &str) -> bool {
+        match arg.find('=') {
+            None | Some(0) => false,
+            Some(separator) => {
+                let name: &str = &arg[..separator];
+                (name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            }
+        }
+    }
+
+    /// ### is_builtin
+    /// 
+    /// Returns whether the command is a shell builtin
+    fn is_builtin(&self, command: &str) -> bool {
+        BUILTINS.contains(&command)
+    }
+
+    /// ### has_raw_args
+    /// 
+    /// Returns whether the arguments of the builtin are kept raw, since they're expanded by the runner:
+    /// conditional expressions (test, `[` and `[[`), since quoting changes the meaning of patterns, and the builtins which run commands (command, eval and exec)
+    fn has_raw_args(&self, command: &str) -> bool {
+        matches!(command, "test" | "[" | "[[" | "command" | "eval" | "exec")
+    }
+
     /// ### cut_argv_to_delim
     /// 
     /// Cut arguments until the first delimiter is found.
@@ -424,7 +1333,53 @@ impl Bash {
     }
 
     //TODO: case
-    //TODO: command
+
+    /// ### parse_command
+    /// 
+    /// Parse command arguments: `command [-pVv] name [args]`.
+    /// With -v or -V the names are described, otherwise the command is executed bypassing functions and aliases
+    fn parse_command(&self, core: &ShellCore, argv: &mut VecDeque<String>) -> Result<Vec<ShellStatement>, ParserError> {
+        let mut describe: Option<bool> = None; //Some(verbose)
+        //Parse options
+        while let Some(arg) = argv.front().map(|x| self.unquote(x)) {
+            if arg == "--" {
+                argv.pop_front();
+                break;
+            } else if arg.starts_with('-') && arg.len() > 1 {
+                for opt in arg.chars().skip(1) {
+                    match opt {
+                        'p' => {},
+                        'v' => describe = Some(describe.unwrap_or(false)),
+                        'V' => describe = Some(true),
+                        _ => return Err(ParserError::new(ParserErrorCode::BadArgs, format!("bash: command: -{}: invalid option", opt)))
+                    }
+                }
+                argv.pop_front();
+            } else {
+                break;
+            }
+        }
+        if let Some(verbose) = describe {
+            self.unquote_argv(argv);
+            return Ok(vec![ShellStatement::Describe(self.cut_argv_to_delim(argv), verbose)])
+        }
+        let command: String = match argv.front() {
+            Some(command) if ! self.is_ligature(command) => command.clone(),
+            _ => return Ok(vec![ShellStatement::Rc(0)])
+        };
+        argv.pop_front();
+        //Builtins are executed as usual
+        let builtin: String = self.unquote(&command);
+        if self.is_builtin(&builtin) {
+            if ! self.has_raw_args(&builtin) {
+                self.unquote_builtin_argv(&builtin, argv);
+            }
+            if let Some(block) = self.parse_builtin(core, &builtin, argv)? {
+                return Ok(block)
+            }
+        }
+        Ok(vec![ShellStatement::Command(self.parse_task(command, Vec::new(), argv)?)])
+    }
 
     /// ### parse_cd
     /// 
@@ -433,12 +1388,36 @@ impl Bash {
     fn parse_cd(&self, core: &ShellCore, argv: &mut VecDeque<String>) -> Result<ShellStatement, ParserError> {
         //Get arguments for this command (ligaturs are removed)
         let argv: Vec<String> = self.cut_argv_to_delim(argv);
+        //Parse options (-L and -P; the last one wins)
+        let mut physical: bool = false;
+        let mut args: Vec<String> = Vec::with_capacity(argv.len());
+        let mut parse_options: bool = true;
+        for arg in argv.into_iter() {
+            if parse_options && arg == "--" {
+                parse_options = false;
+            } else if parse_options && arg.starts_with("-") && arg.len() > 1 {
+                for opt in arg.chars().skip(1) {
+                    match opt {
+                        'L' => physical = false,
+                        'P' => physical = true,
+                        _ => return Err(ParserError::new(ParserErrorCode::BadArgs, format!("bash: cd: -{}: invalid option", opt)))
+                    }
+                }
+            } else {
+                parse_options = false;
+                args.push(arg);
+            }
+        }
         //If dir is none, return get home or buffer, otherwise resolve path
-        let dir: PathBuf = match argv.len() {
+        let dir: PathBuf = match args.len() {
             0 => core.get_home(),
             1 => {
-                //Returns the resolved first argument
-                core.resolve_path(String::from(argv.first().unwrap().as_str().trim()))
+                let arg: &str = args.first().unwrap().as_str().trim();
+                match arg {
+                    //Previous directory is resolved by the runner
+                    "-" => PathBuf::from(arg),
+                    _ => core.resolve_path(String::from(arg))
+                }
             },
             _ => {
                 //Check if second argument is ligature
@@ -446,7 +1425,7 @@ impl Bash {
             }
         };
         //Return Cd statement
-        Ok(ShellStatement::Cd(dir))
+        Ok(ShellStatement::Cd(dir, physical))
     }
 
     /// ### parse_declare
@@ -462,6 +1441,8 @@ impl Bash {
         opts.optflag("u", "", "to convert the value of each NAME to upper case on assignment");
         opts.optflag("x", "", "to make NAMEs export");
         opts.optflag("p", "", "display the attributes and value of each NAME");
+        opts.optflag("f", "", "restrict action or display to function names and definitions");
+        opts.optflag("F", "", "restrict display to function names only");
         opts.optflag("h", "", "display help");
         let matches = match opts.parse(&argv) {
             Ok(m) => m,
@@ -473,6 +1454,15 @@ impl Bash {
         if matches.opt_present("h") {
             return Ok(ShellStatement::Output(Some(opts.usage("declare")), None))
         }
+        //Handle functions
+        if matches.opt_present("F") {
+            return Ok(ShellStatement::Functions(FunctionOptions::Names(matches.free)))
+        } else if matches.opt_present("f") {
+            return match matches.opt_present("x") {
+                true => Ok(ShellStatement::Functions(FunctionOptions::Export(matches.free))),
+                false => Ok(ShellStatement::Functions(FunctionOptions::Print(matches.free)))
+            }
+        }
         //Handle print
         if matches.free.len() == 0 || matches.opt_present("p") {
             //Retrieve all values from environ and storage
@@ -523,7 +1513,10 @@ impl Bash {
                 key = buff.clone();
             }
             //FIXME: declare does not support eval_expression
-            //Treat value
+            //Treat value; the attributes are applied at parse time, so the value is unquoted here
+            if to_integer || uppercase || lowercase {
+                val = self.unquote(&val);
+            }
             if to_integer {
                 val = val.parse::<isize>().unwrap_or(0).to_string();
             } else if uppercase {
@@ -555,7 +1548,41 @@ impl Bash {
         Ok(ShellStatement::Dirs)
     }
 
-    //TODO: exec
+    /// ### parse_exec
+    /// 
+    /// Parse exec arguments: `exec [redirection...] [command [args]]`.
+    /// The redirections (`> file`, `>> file`, `2> file`, `< file`) are applied to the shell itself; with a command the shell is replaced by the command
+    fn parse_exec(&self, argv: &mut VecDeque<String>) -> Result<ShellStatement, ParserError> {
+        let mut redirections: Vec<ExecRedirection> = Vec::new();
+        while let Some((fd, operator)) = argv.front().and_then(|x| self.split_redirection(x)) {
+            match operator {
+                "<" => {
+                    if fd.unwrap_or(0) != 0 {
+                        return Err(ParserError::new(ParserErrorCode::BadArgs, format!("bash: {}: bad file descriptor", fd.unwrap_or(0))))
+                    }
+                    argv.pop_front();
+                    match argv.pop_front() {
+                        Some(file) if ! self.is_ligature(&file) => redirections.push(ExecRedirection::Stdin(self.unquote(&file))),
+                        Some(file) => return Err(ParserError::new(ParserErrorCode::BadToken, format!("bash: syntax error near unexpected token `{}'", file))),
+                        None => return Err(ParserError::new(ParserErrorCode::BadToken, String::from("bash: syntax error near unexpected token `newline'")))
+                    }
+                },
+                _ => match self.parse_redirection(argv)? {
+                    Some((2, redirection)) => redirections.push(ExecRedirection::Stderr(redirection)),
+                    Some((_, redirection)) => redirections.push(ExecRedirection::Stdout(redirection)),
+                    None => break
+                }
+            }
+        }
+        let task: Option<Task> = match argv.front().filter(|x| ! self.is_ligature(x)).cloned() {
+            Some(command) => {
+                argv.pop_front();
+                Some(self.parse_task(command, Vec::new(), argv)?)
+            },
+            None => None
+        };
+        Ok(ShellStatement::ExecReplace(task, redirections))
+    }
 
     /// ### parse_exit
     /// 
@@ -583,6 +1610,7 @@ impl Bash {
         let mut opts = Options::new();
         opts.optflag("p", "", "Print all exported variables");
         opts.optflag("n", "", "Remove NAME from environment");
+        opts.optflag("f", "", "Refer to shell functions");
         opts.optflag("h", "", "Display help");
         let matches = match opts.parse(&argv) {
             Ok(m) => m,
@@ -594,6 +1622,13 @@ impl Bash {
         if matches.opt_present("h") {
             return Ok(ShellStatement::Output(Some(opts.usage("export")), None))
         }
+        //Handle functions
+        if matches.opt_present("f") {
+            return match matches.opt_present("n") {
+                true => Ok(ShellStatement::Functions(FunctionOptions::Unexport(matches.free))),
+                false => Ok(ShellStatement::Functions(FunctionOptions::Export(matches.free)))
+            }
+        }
         //Handle print
         if matches.free.len() == 0 || matches.opt_present("p") {
             //Retrieve all values from environ
@@ -653,32 +1688,35 @@ impl Bash {
     
     /// ### parse_function
     /// 
-    /// parse function arguments
-    fn parse_function(&self, core: &mut ShellCore, argv: &mut VecDeque<String>) -> Result<ShellStatement, ParserError> {
-        let function_name: String = match argv.get(0) {
-            Some(name) => name.clone(),
-            None => return Err(ParserError::new(ParserErrorCode::BadArgs, String::from("bash: syntax error near unexpected newline")))
-        };
-        //Verify next argument is '{'
-        let next_arg: String = match argv.get(1) {
-            Some(arg) => arg.clone(),
-            None => return Err(ParserError::new(ParserErrorCode::BadArgs, String::from("bash: syntax error near unexpected newline")))
-        };
-        if next_arg != "{" {
-            return Err(ParserError::new(ParserErrorCode::BadArgs, format!("bash: syntax error near unexpected token {}", next_arg)))
+    /// Parse a function definition: `function NAME [()] compound-command` or `NAME() compound-command`.
+    /// The body is a command group or a subshell, along with its redirections, which are applied every time the function is called
+    fn parse_function(&self, core: &ShellCore, argv: &mut VecDeque<String>) -> Result<ShellStatement, ParserError> {
+        if argv.front().map(|x| x == "function").unwrap_or(false) {
+            argv.pop_front();
         }
-        //Instantiate sub states
-        let mut states: BashParserState = BashParserState::new();
-        //Remove the 2 first arguments
-        argv.pop_front();
-        argv.pop_front();
-        //Evaluate function
-        let function_expr: ShellExpression = match self.parse_argv(core, states, argv) {
-            Ok(expr) => expr,
-            Err(err) => return Err(err)
+        let name: String = match argv.pop_front() {
+            Some(name) if ! self.is_ligature(&name) && ! self.is_group(&name) => self.unquote(&name),
+            Some(token) => return Err(ParserError::new(ParserErrorCode::BadToken, format!("bash: syntax error near unexpected token `{}'", token))),
+            None => return Err(ParserError::new(ParserErrorCode::BadToken, String::from("bash: syntax error near unexpected token `newline'")))
         };
-        //Return fuinction
-        Ok(ShellStatement::Function(function_name, function_expr))
+        //Parenthesis are optional after the function keyword
+        if argv.front().map(|x| x == "(").unwrap_or(false) && argv.get(1).map(|x| x == ")").unwrap_or(false) {
+            argv.pop_front();
+            argv.pop_front();
+        }
+        //The body can start on the next line
+        while argv.front().map(|x| x == ";").unwrap_or(false) {
+            argv.pop_front();
+        }
+        match argv.front() {
+            Some(token) if self.is_group(token) => {},
+            Some(token) => return Err(ParserError::new(ParserErrorCode::BadToken, format!("bash: syntax error near unexpected token `{}'", token))),
+            None => return Err(ParserError::new(ParserErrorCode::Incomplete, String::from(">")))
+        }
+        let words: Vec<String> = argv.iter().cloned().collect();
+        let body: ShellStatement = self.parse_group(core, argv)?;
+        let source: String = self.join_words(&words[..words.len() - argv.len()]);
+        Ok(ShellStatement::Function(name, ShellExpression { statements: vec![(body, TaskRelation::Unrelated)] }, source))
     }
 
     /* TODO: getopts (requires statement getopts) 
@@ -690,6 +1728,110 @@ impl Bash {
     }
     */
 
+    /// ### parse_group
+    /// 
+    /// Parse a subshell `( ... )` or a command group `{ ...; }`, followed by its redirections.
+    /// Braces are reserved words, so they're recognized only where a command starts
+    fn parse_group(&self, core: &ShellCore, argv: &mut VecDeque<String>) -> Result<ShellStatement, ParserError> {
+        let subshell: bool = argv.pop_front().map(|x| x == "(").unwrap_or(false);
+        //Look for the closing token
+        let mut depth: usize = 0;
+        let mut end: Option<usize> = None;
+        for (index, arg) in argv.iter().enumerate() {
+            let prev: Option<&str> = match index {
+                0 => None,
+                _ => Some(argv[index - 1].as_str())
+            };
+            let (open, close): (bool, bool) = match subshell {
+                true => (arg == "(", arg == ")"),
+                false => (
+                    arg == "{" && matches!(prev, None | Some(";") | Some("&") | Some("&&") | Some("||") | Some("|") | Some("(") | Some("{")),
+                    arg == "}" && matches!(prev, Some(";") | Some("&") | Some("}") | Some(")"))
+                )
+            };
+            if open {
+                depth += 1;
+            } else if close && depth > 0 {
+                depth -= 1;
+            } else if close {
+                end = Some(index);
+                break;
+            }
+        }
+        let end: usize = match end {
+            Some(end) => end,
+            None => return Err(ParserError::new(ParserErrorCode::Incomplete, String::from(">")))
+        };
+        let mut body: VecDeque<String> = argv.drain(..end).collect();
+        let close: String = argv.pop_front().unwrap();
+        if body.iter().all(|x| x == ";" || x == "&") {
+            return Err(ParserError::new(ParserErrorCode::BadToken, format!("bash: syntax error near unexpected token `{}'", close)))
+        }
+        let expression: ShellExpression = self.parse_argv(core, BashParserState::new(), &mut body)?;
+        //Get redirections
+        let mut redirection: Redirection = Redirection::Stdout;
+        while let Some((fd, file)) = self.parse_redirection(argv)? {
+            //Only the group stdout can be redirected
+            if fd != 1 {
+                return Err(ParserError::new(ParserErrorCode::BadArgs, format!("bash: {}: bad file descriptor", fd)))
+            }
+            redirection = file;
+        }
+        match subshell {
+            true => Ok(ShellStatement::Subshell(expression, redirection)),
+            false => Ok(ShellStatement::Group(expression, redirection))
+        }
+    }
+
+    /// ### parse_hash
+    /// 
+    /// Parse hash arguments: `hash [-lr] [-p path] [-dt] [name ...]`
+    fn parse_hash(&self, argv: &mut VecDeque<String>) -> Result<ShellStatement, ParserError> {
+        //Get arguments for this command (ligaturs are removed)
+        let mut argv: VecDeque<String> = VecDeque::from(self.cut_argv_to_delim(argv));
+        let mut delete: bool = false;
+        let mut show: bool = false;
+        let mut path: Option<PathBuf> = None;
+        //Parse options
+        while let Some(arg) = argv.pop_front() {
+            match arg.as_str() {
+                "--" => break,
+                opt if opt.starts_with('-') && opt.len() > 1 => {
+                    for flag in opt.chars().skip(1) {
+                        match flag {
+                            'd' => delete = true,
+                            'l' => {},
+                            'p' => match argv.pop_front() {
+                                Some(file) => path = Some(PathBuf::from(file)),
+                                None => return Err(ParserError::new(ParserErrorCode::BadArgs, String::from("bash: hash: -p: option requires an argument")))
+                            },
+                            'r' => return Ok(ShellStatement::Hash(HashOptions::Reset)),
+                            't' => show = true,
+                            _ => return Err(ParserError::new(ParserErrorCode::BadArgs, format!("bash: hash: -{}: invalid option", flag)))
+                        }
+                    }
+                },
+                _ => {
+                    argv.push_front(arg);
+                    break;
+                }
+            }
+        }
+        let names: Vec<String> = argv.into_iter().collect();
+        if names.is_empty() {
+            return match (delete, show, path.is_some()) {
+                (false, false, false) => Ok(ShellStatement::Hash(HashOptions::Print)),
+                _ => Err(ParserError::new(ParserErrorCode::BadArgs, String::from("bash: hash: option requires an argument")))
+            }
+        }
+        Ok(ShellStatement::Hash(match path {
+            Some(path) => HashOptions::Set(path, names),
+            None if delete => HashOptions::Del(names),
+            None if show => HashOptions::Show(names),
+            None => HashOptions::Add(names)
+        }))
+    }
+
     //TODO: help
     
     /// ### parse_history
@@ -703,6 +1845,7 @@ impl Bash {
         opts.optopt("a", "", "Append the new history lines to the history file", "<file>");
         opts.optflag("c", "", "Clear the history list. This may be combined with the other options to replace the history list completely.");
         opts.optopt("d", "", "Delete the history entry at position offset", "<offset>");
+        opts.optopt("n", "", "Read the history lines not already read from the history file and append them to the history list.", "<file>");
         opts.optopt("r", "", "Read the history file and append its contents to the history list.", "<file>");
         opts.optopt("w", "", "Write out the current history list to the history file.", "<file>");
         opts.optflag("h", "help", "Display help");
@@ -730,6 +1873,11 @@ impl Bash {
             } else {
                 Err(ParserError::new(ParserErrorCode::BadArgs, String::from("history del index must be a number")))
             }
+        } else if let Some(file) = matches.opt_str("n") {
+            //Resolve path
+            let file: String = core.resolve_path(file.clone()).into_os_string().into_string().unwrap_or(file);
+            //Read the new entries
+            Ok(ShellStatement::History(HistoryOptions::ReadNew(file)))
         } else if let Some(file) = matches.opt_str("r") {
             //Resolve path
             let file: String = core.resolve_path(file.clone()).into_os_string().into_string().unwrap_or(file);
@@ -1032,13 +2180,28 @@ impl Bash {
     /// Parse read commands arguments
     fn parse_read(&self, argv: &mut VecDeque<String>) -> Result<ShellStatement, ParserError> {
         //Get arguments for this command (ligaturs are removed)
-        let argv: Vec<String> = self.cut_argv_to_delim(argv);
+        let mut args: Vec<String> = self.cut_argv_to_delim(argv);
+        //Get input redirection (`< file`); arguments can follow the file
+        let mut input: Option<String> = None;
+        if argv.front().map(|x| x == "<").unwrap_or(false) {
+            argv.pop_front();
+            match argv.pop_front() {
+                Some(file) if ! self.is_ligature(&file) => input = Some(file),
+                _ => return Err(ParserError::new(ParserErrorCode::BadArgs, String::from("bash: syntax error near unexpected newline")))
+            }
+            args.extend(self.cut_argv_to_delim(argv));
+        }
         //Parse argv
         let mut opts = Options::new();
-        opts.optopt("n", "", "Return only after reading exactly NCHARS characters, unless EOF is encountered or read times out, ignoring any delimiter", "nchars");
+        opts.optopt("a", "", "assign the words read to sequential indices of the array variable ARRAY, starting at zero", "array");
+        opts.optopt("d", "", "continue until the first character of DELIM is read, rather than newline", "delim");
+        opts.optopt("n", "", "return after reading NCHARS characters rather than waiting for a newline, but honor a delimiter if fewer than NCHARS characters are read before the delimiter", "nchars");
         opts.optopt("p", "", "output the string PROMPT without a trailing newline before attempting to read", "prompt");
+        opts.optflag("r", "", "do not allow backslashes to escape any characters");
+        opts.optflag("s", "", "do not echo input coming from a terminal");
+        opts.optopt("t", "", "time out and return failure if a complete line of input is not read within TIMEOUT seconds", "timeout");
         opts.optflag("h", "help", "Display help");
-        let matches = match opts.parse(&argv) {
+        let matches = match opts.parse(&args) {
             Ok(m) => m,
             Err(e) => {
                 return Ok(ShellStatement::Output(None, Some(String::from(format!("bash: Read: invalid option: {}", e.to_string())))))
@@ -1048,10 +2211,6 @@ impl Bash {
         if matches.opt_present("h") {
             return Ok(ShellStatement::Output(Some(opts.usage("read")), None))
         }
-        let prompt: Option<String> = match matches.opt_str("p") {
-            Some(p) => Some(p),
-            None => None
-        };
         let length: Option<usize> = match matches.opt_str("n") {
             Some(l) => {
                 match l.parse::<usize>() {
@@ -1061,11 +2220,29 @@ impl Bash {
             },
             None => None
         };
-        let dest: Option<String> = match matches.free.get(0) {
-            Some(arg) => Some(arg.clone()),
+        let timeout: Option<Duration> = match matches.opt_str("t") {
+            Some(t) => match t.parse::<f64>() {
+                Ok(t) if t >= 0.0 && t.is_finite() => Some(Duration::from_secs_f64(t)),
+                _ => return Ok(ShellStatement::Output(None, Some(format!("bash: read: {}: invalid timeout specification", t))))
+            },
             None => None
         };
-        Ok(ShellStatement::Read(prompt, length, dest))
+        //An empty delimiter means NUL
+        let delimiter: char = match matches.opt_str("d") {
+            Some(d) => d.chars().next().unwrap_or('\0'),
+            None => '\n'
+        };
+        Ok(ShellStatement::Read(ReadOptions {
+            prompt: matches.opt_str("p"),
+            length,
+            delimiter,
+            raw: matches.opt_present("r"),
+            silent: matches.opt_present("s"),
+            timeout,
+            array: matches.opt_str("a"),
+            names: matches.free,
+            input
+        }))
     }
     
     /// ### parse_return
@@ -1098,7 +2275,142 @@ impl Bash {
         res
     }
 
+    /// ### parse_test
+    /// 
+    /// Parse the arguments of a conditional expression (`test`, `[ ]` and `[[ ]]`).
+    /// Arguments are kept raw, since they're expanded by the runner
+    fn parse_test(&self, command: &str, argv: &mut VecDeque<String>) -> Result<ShellStatement, ParserError> {
+        match command {
+            "[[" => {
+                //Operators such as `&&`, `||`, `<` and `>` are part of the expression, so arguments are collected until `]]`
+                let mut args: Vec<String> = Vec::new();
+                loop {
+                    match argv.pop_front() {
+                        None => return Err(ParserError::new(ParserErrorCode::Incomplete, String::from("bash: unexpected EOF while looking for matching `]]'"))),
+                        Some(arg) if arg == "]]" => break,
+                        Some(arg) if arg == ";" => continue, //Newlines are allowed inside the expression
+                        Some(arg) if arg == "=~" => {
+                            //The regex may contain `|`, which is read as a ligature: join the tokens of the operand
+                            let mut regex: String = String::new();
+                            while let Some(token) = argv.front() {
+                                if token == "]]" || token == "&&" || token == "||" {
+                                    break;
+                                }
+                                regex.push_str(token.as_str());
+                                argv.pop_front();
+                            }
+                            args.push(arg);
+                            args.push(regex);
+                        },
+                        Some(arg) => args.push(arg)
+                    }
+                }
+                Ok(ShellStatement::Test(args, true))
+            },
+            "[" => {
+                let mut args: Vec<String> = self.cut_argv_to_delim(argv);
+                match args.pop() {
+                    Some(last) if last == "]" => Ok(ShellStatement::Test(args, false)),
+                    _ => Err(ParserError::new(ParserErrorCode::BadArgs, String::from("bash: [: missing `]'")))
+                }
+            },
+            _ => Ok(ShellStatement::Test(self.cut_argv_to_delim(argv), false))
+        }
+    }
+
     //TODO: time
+
+    /// ### parse_trap
+    /// 
+    /// Parse trap arguments: `trap [-lp] [[action] signal_spec ...]`.
+    /// If the action is `-` or is missing (with a single signal), the signals are reset
+    fn parse_trap(&self, argv: &mut VecDeque<String>) -> Result<ShellStatement, ParserError> {
+        //Get arguments for this command (ligaturs are removed)
+        let mut argv: VecDeque<String> = VecDeque::from(self.cut_argv_to_delim(argv));
+        let mut print: bool = false;
+        //Parse options
+        while let Some(arg) = argv.front() {
+            match arg.as_str() {
+                "-l" => return Ok(ShellStatement::Trap(TrapOptions::List)),
+                "-p" => print = true,
+                "--" => {
+                    argv.pop_front();
+                    break;
+                },
+                opt if opt.starts_with('-') && opt.len() > 1 => return Err(ParserError::new(ParserErrorCode::BadArgs, format!("bash: trap: {}: invalid option", opt))),
+                _ => break
+            }
+            argv.pop_front();
+        }
+        if print || argv.is_empty() {
+            let signals: Vec<TrapSignal> = argv.iter().map(|x| self.parse_trap_signal(x)).collect::<Result<Vec<TrapSignal>, ParserError>>()?;
+            return Ok(ShellStatement::Trap(TrapOptions::Print(signals)))
+        }
+        //A single signal resets it
+        let action: Option<String> = match argv.len() {
+            1 => None,
+            _ => argv.pop_front().filter(|x| x != "-")
+        };
+        let signals: Vec<TrapSignal> = argv.iter().map(|x| self.parse_trap_signal(x)).collect::<Result<Vec<TrapSignal>, ParserError>>()?;
+        match action {
+            Some(action) => Ok(ShellStatement::Trap(TrapOptions::Set(action, signals))),
+            None => Ok(ShellStatement::Trap(TrapOptions::Reset(signals)))
+        }
+    }
+
+    /// ### parse_trap_signal
+    /// 
+    /// Parse a trap signal specification: a signal name (with or without `SIG`), a signal number or one of EXIT, ERR, DEBUG and RETURN
+    fn parse_trap_signal(&self, spec: &String) -> Result<TrapSignal, ParserError> {
+        let signal: Option<TrapSignal> = match spec.to_uppercase().as_str() {
+            "0" | "EXIT" | "SIGEXIT" => Some(TrapSignal::Exit),
+            "ERR" => Some(TrapSignal::Err),
+            "DEBUG" => Some(TrapSignal::Debug),
+            "RETURN" => Some(TrapSignal::Return),
+            name => match name.parse::<u8>() {
+                Ok(number) => UnixSignal::from_number(number),
+                Err(_) => UnixSignal::from_name(name)
+            }.map(TrapSignal::Signal)
+        };
+        match signal {
+            Some(signal) => Ok(signal),
+            None => Err(ParserError::new(ParserErrorCode::BadArgs, format!("bash: trap: {}: invalid signal specification", spec)))
+        }
+    }
+
+    /// ### parse_type
+    /// 
+    /// Parse type arguments: `type [-aPpt] name ...`
+    fn parse_type(&self, argv: &mut VecDeque<String>) -> Result<ShellStatement, ParserError> {
+        //Get arguments for this command (ligaturs are removed)
+        let mut argv: VecDeque<String> = VecDeque::from(self.cut_argv_to_delim(argv));
+        let mut format: TypeFormat = TypeFormat::Verbose;
+        let mut all: bool = false;
+        //Parse options
+        while let Some(arg) = argv.pop_front() {
+            match arg.as_str() {
+                "--" => break,
+                opt if opt.starts_with('-') && opt.len() > 1 => {
+                    for flag in opt.chars().skip(1) {
+                        match flag {
+                            'a' => all = true,
+                            'P' => format = TypeFormat::ForcePath,
+                            'p' if format != TypeFormat::ForcePath => format = TypeFormat::Path,
+                            'p' => {},
+                            't' => format = TypeFormat::Kind,
+                            _ => return Err(ParserError::new(ParserErrorCode::BadArgs, format!("bash: type: -{}: invalid option", flag)))
+                        }
+                    }
+                },
+                _ => {
+                    argv.push_front(arg);
+                    break;
+                }
+            }
+        }
+        Ok(ShellStatement::Type(argv.into_iter().collect(), format, all))
+    }
+
     //TODO: until/while
     
     /// ### parse_unset
@@ -1106,7 +2418,24 @@ impl Bash {
     /// Parse unset command arguments
     fn parse_unset(&self, argv: &mut VecDeque<String>) -> Result<Vec<ShellStatement>, ParserError> {
         //Get arguments for this command
-        let argv: Vec<String> = self.cut_argv_to_delim(argv);
+        let mut argv: VecDeque<String> = self.cut_argv_to_delim(argv).into();
+        //Get options; with `-f` names refer to functions
+        let mut functions: bool = false;
+        while let Some(opt) = argv.front().filter(|x| x.starts_with('-') && x.len() > 1) {
+            match opt.as_str() {
+                "-f" => functions = true,
+                "-v" => functions = false,
+                "--" => {
+                    argv.pop_front();
+                    break;
+                },
+                _ => return Err(ParserError::new(ParserErrorCode::BadArgs, format!("bash: unset: {}: invalid option", opt)))
+            }
+            argv.pop_front();
+        }
+        if functions && ! argv.is_empty() {
+            return Ok(vec![ShellStatement::Functions(FunctionOptions::Unset(argv.into()))])
+        }
         //Instantiate statements
         let mut statements: Vec<ShellStatement> = Vec::with_capacity(argv.len());
         //Iterate over variables
@@ -1171,7 +2500,7 @@ impl BashParserState {
                     self.stack_state(BashParserBlock::Quoted('\''));
                 }
             } else if ! self.is_quoted() { //If not quoted, try expressions
-                if ch == '(' && self.previous_char == '$' { //Expression open and not quoted and If previous character is '$'
+                if ch == '(' && "$@!?*+<>".contains(self.previous_char) { //Expression open and not quoted and If previous character is '$' (or an extended pattern or a process substitution)
                     //Start expression
                     self.stack_state(BashParserBlock::Expression('('));
                 } else if ch == '`' { //Expression open/close
@@ -1364,24 +2693,133 @@ mod tests {
         assert!(argv.get(1).is_none());
     }
 
+    #[test]
+    fn test_bash_parser_completion_word() {
+        let parser: Bash = Bash::new();
+        let word = |start: usize, word: &str, command_position: bool, command: Option<&str>| -> Option<CompletionWord> {
+            Some(CompletionWord { start, word: String::from(word), command_position, command: command.map(String::from) })
+        };
+        assert_eq!(parser.completion_word("ec", 2), word(0, "ec", true, None));
+        assert_eq!(parser.completion_word("", 0), word(0, "", true, None));
+        assert_eq!(parser.completion_word("echo fo", 7), word(5, "fo", false, Some("echo")));
+        assert_eq!(parser.completion_word("ls -l ", 6), word(6, "", false, Some("ls")));
+        //The word ends at the cursor
+        assert_eq!(parser.completion_word("echo foo bar", 7), word(5, "fo", false, Some("echo")));
+        //Separators, reserved words and assignments keep the command position
+        assert_eq!(parser.completion_word("echo foo; l", 11), word(10, "l", true, None));
+        assert_eq!(parser.completion_word("true&&(l", 8), word(7, "l", true, None));
+        assert_eq!(parser.completion_word("if tr", 5), word(3, "tr", true, None));
+        assert_eq!(parser.completion_word("FOO=1 gi", 8), word(6, "gi", true, None));
+        //Redirections
+        assert_eq!(parser.completion_word("cat < fi", 8), word(6, "fi", false, Some("cat")));
+        assert_eq!(parser.completion_word("> fi", 4), word(2, "fi", false, None));
+        assert_eq!(parser.completion_word("ls 2>fi", 7), word(5, "fi", false, Some("ls")));
+        assert_eq!(parser.completion_word(">out ec", 7), word(5, "ec", true, None));
+        //Quotes and escapes
+        assert_eq!(parser.completion_word("cd \"My Dir/su", 13), word(3, "\"My Dir/su", false, Some("cd")));
+        assert_eq!(parser.completion_word("cd My\\ Di", 9), word(3, "My\\ Di", false, Some("cd")));
+        assert_eq!(parser.completion_word("'ls' -", 6), word(5, "-", false, Some("ls")));
+        //Cursor out of range or not at a char boundary
+        assert!(parser.completion_word("ls", 3).is_none());
+        assert!(parser.completion_word("è", 1).is_none());
+        assert!(parser.builtin_names().contains(&String::from("cd")));
+    }
+
+    #[test]
+    fn test_bash_parser_history_expansion() {
+        let parser: Bash = Bash::new();
+        let (mut core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let expand = |core: &ShellCore, line: &str| -> Option<String> {
+            parser.history_expansion(core, line).unwrap().map(|(line, execute)| {
+                assert!(execute);
+                line
+            })
+        };
+        //Nothing to expand
+        assert!(expand(&core, "echo foo").is_none());
+        assert!(parser.history_expansion(&core, "!!").is_err());
+        assert!(core.history_push(String::from("tar -xzf /tmp/archive.tar.gz -C /opt")));
+        assert!(core.history_push(String::from("echo foo bar")));
+        assert!(core.history_push(String::from("ls -l /usr/share/doc.d/file.txt")));
+        //Event designators
+        assert_eq!(expand(&core, "!!").unwrap(), String::from("ls -l /usr/share/doc.d/file.txt"));
+        assert_eq!(expand(&core, "sudo !! | less").unwrap(), String::from("sudo ls -l /usr/share/doc.d/file.txt | less"));
+        assert_eq!(expand(&core, "!1").unwrap(), String::from("echo foo bar"));
+        assert_eq!(expand(&core, "!-3").unwrap(), String::from("tar -xzf /tmp/archive.tar.gz -C /opt"));
+        assert_eq!(expand(&core, "!ec; !ta").unwrap(), String::from("echo foo bar; tar -xzf /tmp/archive.tar.gz -C /opt"));
+        assert_eq!(expand(&core, "!?archive?").unwrap(), String::from("tar -xzf /tmp/archive.tar.gz -C /opt"));
+        assert_eq!(expand(&core, "echo a !#").unwrap(), String::from("echo a echo a "));
+        assert_eq!(parser.history_expansion(&core, "!foo").unwrap_err().message, String::from("bash: !foo: event not found"));
+        assert!(parser.history_expansion(&core, "!9").is_err());
+        assert!(parser.history_expansion(&core, "!-9223372036854775808").is_err());
+        assert!(parser.history_expansion(&core, "!99999999999999999999").is_err());
+        //Word designators
+        assert_eq!(expand(&core, "cat !$").unwrap(), String::from("cat /usr/share/doc.d/file.txt"));
+        assert_eq!(expand(&core, "echo !*").unwrap(), String::from("echo -l /usr/share/doc.d/file.txt"));
+        assert_eq!(expand(&core, "!^").unwrap(), String::from("-l"));
+        assert_eq!(expand(&core, "!ec:0 !ec:2").unwrap(), String::from("echo bar"));
+        assert_eq!(expand(&core, "!ta:2-3").unwrap(), String::from("/tmp/archive.tar.gz -C"));
+        assert_eq!(expand(&core, "!ta:-1").unwrap(), String::from("tar -xzf"));
+        assert_eq!(expand(&core, "!ta:3*").unwrap(), String::from("-C /opt"));
+        assert_eq!(expand(&core, "!ta:2-").unwrap(), String::from("/tmp/archive.tar.gz -C"));
+        assert_eq!(expand(&core, "!?archive?%").unwrap(), String::from("/tmp/archive.tar.gz"));
+        assert!(parser.history_expansion(&core, "!!:5").is_err());
+        //Modifiers
+        assert_eq!(expand(&core, "cd !$:h").unwrap(), String::from("cd /usr/share/doc.d"));
+        assert_eq!(expand(&core, "!$:t").unwrap(), String::from("file.txt"));
+        assert_eq!(expand(&core, "!$:r").unwrap(), String::from("/usr/share/doc.d/file"));
+        assert_eq!(expand(&core, "!$:h:r").unwrap(), String::from("/usr/share/doc"));
+        assert_eq!(expand(&core, "!$:e").unwrap(), String::from(".txt"));
+        assert_eq!(expand(&core, "!ec:s/foo/baz/").unwrap(), String::from("echo baz bar"));
+        assert_eq!(expand(&core, "!ec:s/o/[&]").unwrap(), String::from("ech[o] foo bar"));
+        assert_eq!(expand(&core, "!ec:gs/o/0/").unwrap(), String::from("ech0 f00 bar"));
+        assert_eq!(expand(&core, "!ec:s/o/0/:&").unwrap(), String::from("ech0 f0o bar"));
+        assert_eq!(expand(&core, "!ec:2:q").unwrap(), String::from("'bar'"));
+        assert!(parser.history_expansion(&core, "!ec:s/xyz/abc/").is_err());
+        assert_eq!(parser.history_expansion(&core, "!ec:p").unwrap(), Some((String::from("echo foo bar"), false)));
+        //Quick substitution
+        assert_eq!(expand(&core, "^share^lib^").unwrap(), String::from("ls -l /usr/lib/doc.d/file.txt"));
+        assert_eq!(expand(&core, "^-l^-a").unwrap(), String::from("ls -a /usr/share/doc.d/file.txt"));
+        //Quoting and characters which are not expanded
+        assert!(expand(&core, "echo '!!' \\!! ! != !( $! ${!name}").is_none());
+        assert_eq!(expand(&core, "echo \"!!\"").unwrap(), String::from("echo \"ls -l /usr/share/doc.d/file.txt\""));
+    }
+
     #[test]
     fn test_bash_parser_readline() {
         let parser: Bash = Bash::new();
         assert_eq!(parser.readline(&String::from("cd /tmp/")).unwrap(), vec![String::from("cd"), String::from("/tmp/")]);
         assert_eq!(parser.readline(&String::from("cd;")).unwrap(), vec![String::from("cd"), String::from(";")]);
-        assert_eq!(parser.readline(&String::from("echo \"foo bar\"")).unwrap(), vec![String::from("echo"), String::from("foo bar")]);
-        assert_eq!(parser.readline(&String::from("echo \"'foo' 'bar'\"")).unwrap(), vec![String::from("echo"), String::from("'foo' 'bar'")]);
-        assert_eq!(parser.readline(&String::from("echo \"\\\"foo bar\\\"\"")).unwrap(), vec![String::from("echo"), String::from("\"foo bar\"")]);
+        assert_eq!(parser.readline(&String::from("echo \"foo bar\"")).unwrap(), vec![String::from("echo"), String::from("\"foo bar\"")]);
+        assert_eq!(parser.readline(&String::from("echo \"'foo' 'bar'\"")).unwrap(), vec![String::from("echo"), String::from("\"'foo' 'bar'\"")]);
+        assert_eq!(parser.readline(&String::from("echo \"\\\"foo bar\\\"\"")).unwrap(), vec![String::from("echo"), String::from("\"\\\"foo bar\\\"\"")]);
+        //Spacing is preserved inside quotes
+        assert_eq!(parser.readline(&String::from("echo \"foo   bar\"  baz")).unwrap(), vec![String::from("echo"), String::from("\"foo   bar\""), String::from("baz")]);
+        assert_eq!(parser.readline(&String::from("echo foo\\ \\ bar")).unwrap(), vec![String::from("echo"), String::from("foo\\ \\ bar")]);
+        //Unterminated quotes
+        assert_eq!(parser.readline(&String::from("echo \"foo")).err().unwrap().code, ParserErrorCode::Incomplete);
         //Escapes
         assert_eq!(parser.readline(&String::from("cd \\;")).unwrap(), vec![String::from("cd"), String::from("\\;")]);
+        //Tildes
+        assert_eq!(parser.readline(&String::from("cd ~/foo")).unwrap(), vec![String::from("cd"), String::from("~/foo")]);
+        assert_eq!(parser.readline(&String::from("cd \"~/foo\"")).unwrap(), vec![String::from("cd"), String::from("\"~/foo\"")]);
+        assert_eq!(parser.readline(&String::from("cd '~'")).unwrap(), vec![String::from("cd"), String::from("'~'")]);
         //Try error
         assert!(parser.readline(&String::from("echo \"$(pw\"d)")).is_err());
+        //Parenthesis
+        assert_eq!(parser.readline(&String::from("[[ (a) ]]")).unwrap(), vec![String::from("[["), String::from("("), String::from("a"), String::from(")"), String::from("]]")]);
+        assert_eq!(parser.readline(&String::from("ls @(a|b) $(pwd)")).unwrap(), vec![String::from("ls"), String::from("@(a|b)"), String::from("$(pwd)")]);
+        assert_eq!(parser.readline(&String::from("( a")).err().unwrap().code, ParserErrorCode::Incomplete);
+        //Process substitutions
+        assert_eq!(parser.readline(&String::from("diff <(sort a) <(sort b)")).unwrap(), vec![String::from("diff"), String::from("<(sort a)"), String::from("<(sort b)")]);
+        assert_eq!(parser.readline(&String::from("tee >(cat > /tmp/foo)")).unwrap(), vec![String::from("tee"), String::from(">(cat > /tmp/foo)")]);
+        assert_eq!(parser.readline(&String::from("cat <(echo \"foo\"")).err().unwrap().code, ParserErrorCode::Incomplete);
         //Redirections
-        assert_eq!(parser.readline(&String::from("echo \"5>\"")).unwrap(), vec![String::from("echo"), String::from("5>")]);
-        assert_eq!(parser.readline(&String::from("echo \">/tmp/\"")).unwrap(), vec![String::from("echo"), String::from(">/tmp/")]);
-        assert_eq!(parser.readline(&String::from("echo \"5>/tmp/\"")).unwrap(), vec![String::from("echo"), String::from("5>/tmp/")]);
-        assert_eq!(parser.readline(&String::from("echo \"5>>/tmp/\"")).unwrap(), vec![String::from("echo"), String::from("5>>/tmp/")]);
-        assert_eq!(parser.readline(&String::from("echo \">>\"")).unwrap(), vec![String::from("echo"), String::from(">>")]);
+        assert_eq!(parser.readline(&String::from("echo \"5>\"")).unwrap(), vec![String::from("echo"), String::from("\"5>\"")]);
+        assert_eq!(parser.readline(&String::from("echo \">/tmp/\"")).unwrap(), vec![String::from("echo"), String::from("\">/tmp/\"")]);
+        assert_eq!(parser.readline(&String::from("echo \"5>/tmp/\"")).unwrap(), vec![String::from("echo"), String::from("\"5>/tmp/\"")]);
+        assert_eq!(parser.readline(&String::from("echo \"5>>/tmp/\"")).unwrap(), vec![String::from("echo"), String::from("\"5>>/tmp/\"")]);
+        assert_eq!(parser.readline(&String::from("echo \">>\"")).unwrap(), vec![String::from("echo"), String::from("\">>\"")]);
         //Over lines
         assert_eq!(parser.readline(&String::from("cd /tmp/\ncd /home/")).unwrap(), vec![String::from("cd"), String::from("/tmp/"), String::from(";"), String::from("cd"), String::from("/home/")]);
         //Separators (&&)
@@ -1416,6 +2854,244 @@ mod tests {
         assert_eq!(parser.readline(&String::from("cd /tmp/ < exit")).unwrap(), vec![String::from("cd"), String::from("/tmp/"), String::from("<"), String::from("exit")]);
         assert_eq!(parser.readline(&String::from("cd /tmp/ <exit")).unwrap(), vec![String::from("cd"), String::from("/tmp/"), String::from("<"), String::from("exit")]);
         assert_eq!(parser.readline(&String::from("cd /tmp/<exit")).unwrap(), vec![String::from("cd"), String::from("/tmp/"), String::from("<"), String::from("exit")]);
+        //File descriptor prefix
+        assert_eq!(parser.readline(&String::from("ls 2> /tmp/x")).unwrap(), vec![String::from("ls"), String::from("2>"), String::from("/tmp/x")]);
+        assert_eq!(parser.readline(&String::from("ls 2>>/tmp/x")).unwrap(), vec![String::from("ls"), String::from("2>>"), String::from("/tmp/x")]);
+        assert_eq!(parser.readline(&String::from("cat 0<file")).unwrap(), vec![String::from("cat"), String::from("0<"), String::from("file")]);
+        assert_eq!(parser.readline(&String::from("echo 2 > /tmp/x")).unwrap(), vec![String::from("echo"), String::from("2"), String::from(">"), String::from("/tmp/x")]);
+        assert_eq!(parser.readline(&String::from("echo a2> /tmp/x")).unwrap(), vec![String::from("echo"), String::from("a2"), String::from(">"), String::from("/tmp/x")]);
+        assert_eq!(parser.readline(&String::from("echo '2'> /tmp/x")).unwrap(), vec![String::from("echo"), String::from("'2'"), String::from(">"), String::from("/tmp/x")]);
+    }
+
+    #[test]
+    fn test_bash_parser_alias_expansion() {
+        let parser: Bash = Bash::new();
+        let (mut core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        assert!(core.alias_set(String::from("ll"), String::from("ls -l")));
+        assert!(core.alias_set(String::from("ls"), String::from("ls --color=auto")));
+        assert!(core.alias_set(String::from("noise"), String::from("echo \"a  b\" 'c'")));
+        assert!(core.alias_set(String::from("sudo"), String::from("sudo ")));
+        assert!(core.alias_set(String::from("home"), String::from("cd /tmp;")));
+        assert!(core.alias_set(String::from("loop1"), String::from("loop2 x")));
+        assert!(core.alias_set(String::from("loop2"), String::from("loop1 y")));
+        let expand = |line: &str| -> Vec<String> {
+            parser.expand_aliases(&core, parser.readline(&String::from(line)).unwrap()).unwrap().into_iter().collect()
+        };
+        //Recursive expansion; an alias is not expanded inside its own expansion
+        assert_eq!(expand("ll /tmp"), vec!["ls", "--color=auto", "-l", "/tmp"]);
+        assert_eq!(expand("loop1"), vec!["loop1", "y", "x"]);
+        //Only words in command position are expanded
+        assert_eq!(expand("echo ll | ll && FOO=1 ll; (ll)"), vec!["echo", "ll", "|", "ls", "--color=auto", "-l", "&&", "FOO=1", "ls", "--color=auto", "-l", ";", "(", "ls", "--color=auto", "-l", ")"]);
+        assert_eq!(expand("command ll"), vec!["command", "ll"]);
+        //Reserved words are followed by a command only when they are commands themselves
+        assert_eq!(expand("echo do ll"), vec!["echo", "do", "ll"]);
+        assert_eq!(expand("echo then ! { ll"), vec!["echo", "then", "!", "{", "ll"]);
+        assert_eq!(expand("while ll; do ll"), vec!["while", "ls", "--color=auto", "-l", ";", "do", "ls", "--color=auto", "-l"]);
+        assert_eq!(expand("echo x; ! ll"), vec!["echo", "x", ";", "!", "ls", "--color=auto", "-l"]);
+        //Quotes are preserved
+        assert_eq!(expand("noise"), vec!["echo", "\"a  b\"", "'c'"]);
+        assert_eq!(expand("'ll'"), vec!["'ll'"]);
+        //Trailing blank expands the next word
+        assert_eq!(expand("sudo ll"), vec!["sudo", "ls", "--color=auto", "-l"]);
+        assert_eq!(expand("sudo sudo ll x"), vec!["sudo", "sudo", "ls", "--color=auto", "-l", "x"]);
+        //Aliases can expand to builtins and separators
+        assert_eq!(expand("home ll"), vec!["cd", "/tmp", ";", "ls", "--color=auto", "-l"]);
+        assert_eq!(parser.parse(&core, &String::from("home")).unwrap().statements[0].0, ShellStatement::Cd(PathBuf::from("/tmp"), false));
+        //Bad alias values are reported
+        assert!(core.alias_set(String::from("broken"), String::from("echo \"")));
+        assert!(parser.parse(&core, &String::from("broken")).is_err());
+    }
+
+    #[test]
+    fn test_bash_parser_brace_expansion() {
+        let parser: Bash = Bash::new();
+        let expand = |words: Vec<&str>| -> Vec<String> {
+            parser.expand_braces(words.iter().map(|x| String::from(*x)).collect()).into_iter().collect()
+        };
+        //Comma lists
+        assert_eq!(expand(vec!["mkdir", "-p", "src/{bin,lib}"]), vec!["mkdir", "-p", "src/bin", "src/lib"]);
+        assert_eq!(expand(vec!["{a,b}{1,2}"]), vec!["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand(vec!["x{,y}"]), vec!["x", "xy"]);
+        //Nesting
+        assert_eq!(expand(vec!["{a,b{1,2},c}d"]), vec!["ad", "b1d", "b2d", "cd"]);
+        assert_eq!(expand(vec!["/usr/{bin,lib/{x,y}}"]), vec!["/usr/bin", "/usr/lib/x", "/usr/lib/y"]);
+        //Numeric sequences
+        assert_eq!(expand(vec!["{1..5}"]), vec!["1", "2", "3", "4", "5"]);
+        assert_eq!(expand(vec!["{3..1}"]), vec!["3", "2", "1"]);
+        assert_eq!(expand(vec!["{-2..1}"]), vec!["-2", "-1", "0", "1"]);
+        assert_eq!(expand(vec!["{1..10..3}"]), vec!["1", "4", "7", "10"]);
+        assert_eq!(expand(vec!["{10..1..3}"]), vec!["10", "7", "4", "1"]);
+        assert_eq!(expand(vec!["{01..10..2}"]), vec!["01", "03", "05", "07", "09"]);
+        assert_eq!(expand(vec!["file{8..010}"]), vec!["file008", "file009", "file010"]);
+        assert_eq!(expand(vec!["{-9223372036854775808..-9223372036854775807}"]), vec!["-9223372036854775808", "-9223372036854775807"]);
+        assert_eq!(expand(vec!["{-09223372036854775808..-9223372036854775807}"]), vec!["-09223372036854775808", "-09223372036854775807"]);
+        assert_eq!(expand(vec!["{1..99999999999..50000000000}"]), vec!["1", "50000000001"]);
+        //Expansions generating too many words are left untouched
+        assert_eq!(expand(vec!["{1..99999999999}"]), vec!["{1..99999999999}"]);
+        assert_eq!(expand(vec!["{-9223372036854775808..9223372036854775807}"]), vec!["{-9223372036854775808..9223372036854775807}"]);
+        assert_eq!(expand(vec!["{1..1024}{1..1024}{1..2}"]), vec!["{1..1024}{1..1024}{1..2}"]);
+        assert_eq!(expand(vec!["{1..1024}{1..1024}"]).len(), 1 << 20);
+        //Char sequences
+        assert_eq!(expand(vec!["{a..e}"]), vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(expand(vec!["{e..a..2}"]), vec!["e", "c", "a"]);
+        //Not expansions
+        assert_eq!(expand(vec!["{a}"]), vec!["{a}"]);
+        assert_eq!(expand(vec!["{}"]), vec!["{}"]);
+        assert_eq!(expand(vec!["{a,b"]), vec!["{a,b"]);
+        assert_eq!(expand(vec!["{a..3}"]), vec!["{a..3}"]);
+        assert_eq!(expand(vec!["{a}b{c,d}"]), vec!["{a}bc", "{a}bd"]);
+        assert_eq!(expand(vec!["${HOME}"]), vec!["${HOME}"]);
+        assert_eq!(expand(vec!["${A}{1,2}"]), vec!["${A}1", "${A}2"]);
+        //Quoted and escaped braces
+        assert_eq!(expand(vec!["\\{a,b}"]), vec!["\\{a,b}"]);
+        assert_eq!(expand(vec!["{a\\,b,c}"]), vec!["a\\,b", "c"]);
+        assert_eq!(expand(vec!["\"{a,b}\""]), vec!["\"{a,b}\""]);
+        assert_eq!(expand(vec!["'{a,b}'{c,d}"]), vec!["'{a,b}'c", "'{a,b}'d"]);
+        assert_eq!(expand(vec!["{\"a,b\",c}"]), vec!["\"a,b\"", "c"]);
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let expr: ShellExpression = parser.parse(&core, &String::from("mkdir -p src/{bin,lib}")).unwrap();
+        match &expr.statements[0].0 {
+            ShellStatement::Exec(task) => assert_eq!(task.command, vec![String::from("mkdir"), String::from("-p"), String::from("src/bin"), String::from("src/lib")]),
+            _ => panic!("Expected Exec")
+        }
+        let expr: ShellExpression = parser.parse(&core, &String::from("mkdir -p \"src/{bin,lib}\"")).unwrap();
+        match &expr.statements[0].0 {
+            ShellStatement::Exec(task) => assert_eq!(task.command, vec![String::from("mkdir"), String::from("-p"), String::from("\"src/{bin,lib}\"")]),
+            _ => panic!("Expected Exec")
+        }
+    }
+
+    #[test]
+    fn test_bash_parser_unquote() {
+        let parser: Bash = Bash::new();
+        assert_eq!(parser.unquote("foo"), String::from("foo"));
+        assert_eq!(parser.unquote("\"foo   bar\""), String::from("foo   bar"));
+        assert_eq!(parser.unquote("'$HOME'\"$HOME\""), String::from("$HOME$HOME"));
+        assert_eq!(parser.unquote("foo\\ bar\\;"), String::from("foo bar;"));
+        assert_eq!(parser.unquote("\"a\\\"b\\c\""), String::from("a\"b\\c"));
+        //Quoted tildes are kept escaped
+        assert_eq!(parser.unquote("\"~\"/bin"), String::from("\\~/bin"));
+        assert_eq!(parser.unquote("\\~"), String::from("\\~"));
+        assert_eq!(parser.unquote("~/bin"), String::from("~/bin"));
+        //Quoted delimiters are kept quoted
+        let mut argv: VecDeque<String> = parser.readline(&String::from("\"a b\" \";\" c; \"d\"")).unwrap();
+        parser.unquote_argv(&mut argv);
+        assert_eq!(argv, vec![String::from("a b"), String::from("\";\""), String::from("c"), String::from(";"), String::from("\"d\"")]);
+        //Builtins are unquoted at parse time, tasks arguments are kept as they are
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let expr: ShellExpression = parser.parse(&core, &String::from("\"cd\" \"/tmp\"; echo \"a  b\" > \"/tmp/my file\"")).unwrap();
+        assert_eq!(expr.statements[0].0, ShellStatement::Cd(PathBuf::from("/tmp"), false));
+        match &expr.statements[1].0 {
+            ShellStatement::Exec(task) => {
+                assert_eq!(task.command, vec![String::from("echo"), String::from("\"a  b\"")]);
+                assert_eq!(task.stdout_redirection, Redirection::File(String::from("/tmp/my file"), FileRedirectionType::Truncate));
+            },
+            _ => panic!("Expected Exec")
+        }
+    }
+
+    #[test]
+    fn test_bash_parser_parse_argv() {
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let parser: Bash = Bash::new();
+        //Builtins and tasks
+        let expr: ShellExpression = parser.parse(&core, &String::from("cd /tmp && echo foo | head -n 1 > /tmp/out; dirs")).unwrap();
+        assert_eq!(expr.statements.len(), 3);
+        assert_eq!(expr.statements[0], (ShellStatement::Cd(PathBuf::from("/tmp"), false), TaskRelation::And));
+        match &expr.statements[1].0 {
+            ShellStatement::Exec(task) => {
+                assert_eq!(task.command, vec![String::from("echo"), String::from("foo")]);
+                assert_eq!(task.relation, TaskRelation::Pipe);
+                assert_eq!(task.stdout_redirection, Redirection::Stdout);
+                let next: &Task = task.next.as_ref().unwrap();
+                assert_eq!(next.command, vec![String::from("head"), String::from("-n"), String::from("1")]);
+                assert_eq!(next.stdout_redirection, Redirection::File(String::from("/tmp/out"), FileRedirectionType::Truncate));
+                assert!(next.next.is_none());
+            },
+            _ => panic!("Not an exec statement")
+        }
+        assert_eq!(expr.statements[1].1, TaskRelation::Unrelated);
+        assert_eq!(expr.statements[2], (ShellStatement::Dirs, TaskRelation::Unrelated));
+        //Incomplete
+        assert_eq!(parser.parse(&core, &String::from("echo foo &&")).err().unwrap().code, ParserErrorCode::Incomplete);
+        //Bad token
+        assert_eq!(parser.parse(&core, &String::from("&& echo foo")).err().unwrap().code, ParserErrorCode::BadToken);
+        assert_eq!(parser.parse(&core, &String::from("echo foo >")).err().unwrap().code, ParserErrorCode::BadToken);
+    }
+
+    #[test]
+    fn test_bash_parser_assignments() {
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let parser: Bash = Bash::new();
+        //Bare assignments
+        let expr: ShellExpression = parser.parse(&core, &String::from("FOO=bar BAR=$FOO")).unwrap();
+        assert_eq!(expr.statements.len(), 2);
+        assert_eq!(expr.statements[0], (ShellStatement::Set(String::from("FOO"), ShellExpression { statements: vec![(ShellStatement::Value(String::from("bar")), TaskRelation::Unrelated)] }), TaskRelation::Unrelated));
+        assert_eq!(expr.statements[1], (ShellStatement::Set(String::from("BAR"), ShellExpression { statements: vec![(ShellStatement::Value(String::from("$FOO")), TaskRelation::Unrelated)] }), TaskRelation::Unrelated));
+        //Assignment with command
+        let expr: ShellExpression = parser.parse(&core, &String::from("LANG=C _X1=\"a b\" ls -l | A=1 wc -l")).unwrap();
+        assert_eq!(expr.statements.len(), 1);
+        match &expr.statements[0].0 {
+            ShellStatement::Exec(task) => {
+                assert_eq!(task.command, vec![String::from("ls"), String::from("-l")]);
+                assert_eq!(task.env.len(), 2);
+                assert_eq!(task.env.get("LANG").unwrap(), "C");
+                //Quotes are removed by the runner, after the expansions
+                assert_eq!(task.env.get("_X1").unwrap(), "\"a b\"");
+                let next: &Task = task.next.as_ref().unwrap();
+                assert_eq!(next.command, vec![String::from("wc"), String::from("-l")]);
+                assert_eq!(next.env.len(), 1);
+                assert_eq!(next.env.get("A").unwrap(), "1");
+            },
+            _ => panic!("Not an exec statement")
+        }
+        //Not assignments
+        let expr: ShellExpression = parser.parse(&core, &String::from("echo FOO=bar")).unwrap();
+        match &expr.statements[0].0 {
+            ShellStatement::Exec(task) => {
+                assert_eq!(task.command, vec![String::from("echo"), String::from("FOO=bar")]);
+                assert_eq!(task.env.len(), 0);
+            },
+            _ => panic!("Not an exec statement")
+        }
+        let expr: ShellExpression = parser.parse(&core, &String::from("=foo")).unwrap();
+        match &expr.statements[0].0 {
+            ShellStatement::Exec(task) => assert_eq!(task.command, vec![String::from("=foo")]),
+            _ => panic!("Not an exec statement")
+        }
+        //Builtin with assignment
+        let expr: ShellExpression = parser.parse(&core, &String::from("FOO=bar dirs && BAR=1")).unwrap();
+        let mut env: HashMap<String, String> = HashMap::new();
+        env.insert(String::from("FOO"), String::from("bar"));
+        assert_eq!(expr.statements[0], (ShellStatement::WithEnv(env, ShellExpression { statements: vec![(ShellStatement::Dirs, TaskRelation::Unrelated)] }), TaskRelation::And));
+        assert_eq!(expr.statements[1], (ShellStatement::Set(String::from("BAR"), ShellExpression { statements: vec![(ShellStatement::Value(String::from("1")), TaskRelation::Unrelated)] }), TaskRelation::Unrelated));
+    }
+
+    #[test]
+    fn test_bash_parser_redirections() {
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let parser: Bash = Bash::new();
+        let expr: ShellExpression = parser.parse(&core, &String::from("ls -l 2> /tmp/err > /tmp/out")).unwrap();
+        match &expr.statements[0].0 {
+            ShellStatement::Exec(task) => {
+                assert_eq!(task.command, vec![String::from("ls"), String::from("-l")]);
+                assert_eq!(task.stdout_redirection, Redirection::File(String::from("/tmp/out"), FileRedirectionType::Truncate));
+                assert_eq!(task.stderr_redirection, Redirection::File(String::from("/tmp/err"), FileRedirectionType::Truncate));
+            },
+            _ => panic!("Not an exec statement")
+        }
+        let expr: ShellExpression = parser.parse(&core, &String::from("ls 1>>/tmp/out 2>>/tmp/err")).unwrap();
+        match &expr.statements[0].0 {
+            ShellStatement::Exec(task) => {
+                assert_eq!(task.command, vec![String::from("ls")]);
+                assert_eq!(task.stdout_redirection, Redirection::File(String::from("/tmp/out"), FileRedirectionType::Append));
+                assert_eq!(task.stderr_redirection, Redirection::File(String::from("/tmp/err"), FileRedirectionType::Append));
+            },
+            _ => panic!("Not an exec statement")
+        }
+        //Unsupported file descriptors
+        assert_eq!(parser.parse(&core, &String::from("ls 3> /tmp/x")).err().unwrap().code, ParserErrorCode::BadArgs);
+        assert_eq!(parser.parse(&core, &String::from("ls 2>")).err().unwrap().code, ParserErrorCode::BadToken);
     }
 
     #[test]
@@ -1428,16 +3104,20 @@ mod tests {
         assert_eq!(input.len(), 0); //Should be empty
         //Simple set case with quote
         let mut input: VecDeque<String> = parser.readline(&String::from("ll='ls -l'")).unwrap();
+        parser.unquote_argv(&mut input);
         assert_eq!(parser.parse_alias(&core, &mut input).unwrap(), ShellStatement::Alias(Some(String::from("ll")), Some(String::from("ls -l"))));
         assert_eq!(input.len(), 0); //Should be empty
         let mut input: VecDeque<String> = parser.readline(&String::from("ll=\"ls -l\"")).unwrap();
+        parser.unquote_argv(&mut input);
         assert_eq!(parser.parse_alias(&core, &mut input).unwrap(), ShellStatement::Alias(Some(String::from("ll")), Some(String::from("ls -l"))));
         assert_eq!(input.len(), 0); //Should be empty
         //Set case with escapes
         let mut input: VecDeque<String> = parser.readline(&String::from("noise='echo \"ZZZ\"'")).unwrap();
+        parser.unquote_argv(&mut input);
         assert_eq!(parser.parse_alias(&core, &mut input).unwrap(), ShellStatement::Alias(Some(String::from("noise")), Some(String::from("echo \"ZZZ\""))));
         assert_eq!(input.len(), 0); //Should be empty
         let mut input: VecDeque<String> = parser.readline(&String::from("noise='echo \\'ZZZ\\''")).unwrap();
+        parser.unquote_argv(&mut input);
         assert_eq!(parser.parse_alias(&core, &mut input).unwrap(), ShellStatement::Alias(Some(String::from("noise")), Some(String::from("echo \\'ZZZ\\'"))));
         assert_eq!(input.len(), 0); //Should be empty
         //Alias getter
@@ -1457,15 +3137,15 @@ mod tests {
         //Parse some CD statements
         //Simple case
         let mut input: VecDeque<String> = parser.readline(&String::from("/tmp")).unwrap();
-        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/tmp")));
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/tmp"), false));
         assert_eq!(input.len(), 0); //Should be empty
         //With semicolon
         let mut input: VecDeque<String> = parser.readline(&String::from("/tmp;")).unwrap();
-        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/tmp")));
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/tmp"), false));
         assert_eq!(input, vec![String::from(";")]); //Should be empty
         //With newline
         let mut input: VecDeque<String> = parser.readline(&String::from("/tmp\n")).unwrap();
-        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/tmp")));
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/tmp"), false));
         assert_eq!(input.len(), 0); //Should be empty
         //Too many arguments
         let mut input: VecDeque<String> = parser.readline(&String::from("/tmp /home/")).unwrap();
@@ -1477,37 +3157,83 @@ mod tests {
         assert_eq!(input, vec![String::from(";")]); //Should be empty
         //False too many arguments
         let mut input: VecDeque<String> = parser.readline(&String::from("/tmp ;")).unwrap();
-        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/tmp")));
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/tmp"), false));
         assert_eq!(input, vec![String::from(";")]); //Should be empty
         //Too many arguments due to escape
         let mut input: VecDeque<String> = parser.readline(&String::from("/tmp \\;")).unwrap();
+        parser.unquote_argv(&mut input);
         assert!(parser.parse_cd(&core, &mut input).is_err());
         assert_eq!(input.len(), 0); //Should be empty
         //Quotes
         let mut input: VecDeque<String> = parser.readline(&String::from("\"/home\"")).unwrap();
-        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/home/")));
+        parser.unquote_argv(&mut input);
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/home/"), false));
         assert_eq!(input.len(), 0); //Should be empty
         //Escaped quotes
         let mut input: VecDeque<String> = parser.readline(&String::from("/home/\\\"foo\\\"")).unwrap();
-        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/home/\"foo\"")));
+        parser.unquote_argv(&mut input);
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/home/\"foo\""), false));
         assert_eq!(input.len(), 0); //Should be empty
         //With and
         let mut input: VecDeque<String> = parser.readline(&String::from("/tmp &&")).unwrap();
-        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/tmp")));
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/tmp"), false));
         assert_eq!(input, vec![String::from("&&")]); //Should be &&
         //Special cases
         let mut input: VecDeque<String> = parser.readline(&String::from("~")).unwrap();
-        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(core.get_home()));
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(core.get_home(), false));
         assert_eq!(input.len(), 0); //Should be empty
         let mut input: VecDeque<String> = parser.readline(&String::from("-")).unwrap();
-        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(core.get_prev_dir()));
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("-"), false));
         assert_eq!(input.len(), 0); //Should be empty
+        //Options
+        let mut input: VecDeque<String> = parser.readline(&String::from("-P /tmp")).unwrap();
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/tmp"), true));
+        let mut input: VecDeque<String> = parser.readline(&String::from("-P -L /tmp")).unwrap();
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("/tmp"), false));
+        let mut input: VecDeque<String> = parser.readline(&String::from("-LP -")).unwrap();
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("-"), true));
+        let mut input: VecDeque<String> = parser.readline(&String::from("-P")).unwrap();
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(core.get_home(), true));
+        let mut input: VecDeque<String> = parser.readline(&String::from("-- -P")).unwrap();
+        assert_eq!(parser.parse_cd(&core, &mut input).unwrap(), ShellStatement::Cd(PathBuf::from("-P"), false));
+        let mut input: VecDeque<String> = parser.readline(&String::from("-x /tmp")).unwrap();
+        assert_eq!(parser.parse_cd(&core, &mut input).err().unwrap().code, ParserErrorCode::BadArgs);
+    }
+
+    #[test]
+    fn test_bash_parser_command() {
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let parser: Bash = Bash::new();
+        //Command
+        let expr: ShellExpression = parser.parse(&core, &String::from("command -p ls -l \"$HOME\"; dirs")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Command(Task::new(vec![String::from("ls"), String::from("-l"), String::from("\"$HOME\"")], Redirection::Stdout, Redirection::Stderr)), TaskRelation::Unrelated));
+        assert_eq!(expr.statements[1], (ShellStatement::Dirs, TaskRelation::Unrelated));
+        //Builtins are parsed as usual
+        let expr: ShellExpression = parser.parse(&core, &String::from("command -- cd /tmp")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Cd(PathBuf::from("/tmp"), false), TaskRelation::Unrelated));
+        //Describe
+        let expr: ShellExpression = parser.parse(&core, &String::from("command -v ls 'cd'")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Describe(vec![String::from("ls"), String::from("cd")], false), TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("command -vV ls")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Describe(vec![String::from("ls")], true), TaskRelation::Unrelated));
+        //No command
+        let expr: ShellExpression = parser.parse(&core, &String::from("command")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Rc(0), TaskRelation::Unrelated));
+        //Bad option
+        assert_eq!(parser.parse(&core, &String::from("command -x ls")).err().unwrap().code, ParserErrorCode::BadArgs);
     }
 
     #[test]
     fn test_bash_parser_declare() {
         let (mut core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
         let parser: Bash = Bash::new();
+        //Functions
+        let mut input: VecDeque<String> = parser.readline(&String::from("-f")).unwrap();
+        assert_eq!(parser.parse_declare(&core, &mut input).unwrap(), ShellStatement::Functions(FunctionOptions::Print(vec![])));
+        let mut input: VecDeque<String> = parser.readline(&String::from("-F foo")).unwrap();
+        assert_eq!(parser.parse_declare(&core, &mut input).unwrap(), ShellStatement::Functions(FunctionOptions::Names(vec![String::from("foo")])));
+        let mut input: VecDeque<String> = parser.readline(&String::from("-fx foo")).unwrap();
+        assert_eq!(parser.parse_declare(&core, &mut input).unwrap(), ShellStatement::Functions(FunctionOptions::Export(vec![String::from("foo")])));
         //Simple case
         let mut input: VecDeque<String> = parser.readline(&String::from("A=FOO")).unwrap();
         assert_eq!(parser.parse_declare(&core, &mut input).unwrap(), ShellStatement::Set(String::from("A"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("FOO")), TaskRelation::Unrelated)]}));
@@ -1536,7 +3262,7 @@ mod tests {
         assert_eq!(input.len(), 0); //Should be empty
         //Help
         let mut input: VecDeque<String> = parser.readline(&String::from("-h")).unwrap();
-        assert_eq!(parser.parse_declare(&core, &mut input).unwrap(), ShellStatement::Output(Some(String::from("declare\n\nOptions:\n    -i                  to make NAMEs have the `integer\' attribute\n    -l                  to convert the value of each NAME to lower case on\n                        assignment\n    -u                  to convert the value of each NAME to upper case on\n                        assignment\n    -x                  to make NAMEs export\n    -p                  display the attributes and value of each NAME\n    -h                  display help\n")), None));
+        assert_eq!(parser.parse_declare(&core, &mut input).unwrap(), ShellStatement::Output(Some(String::from("declare\n\nOptions:\n    -i                  to make NAMEs have the `integer\' attribute\n    -l                  to convert the value of each NAME to lower case on\n                        assignment\n    -u                  to convert the value of each NAME to upper case on\n                        assignment\n    -x                  to make NAMEs export\n    -p                  display the attributes and value of each NAME\n    -f                  restrict action or display to function names and\n                        definitions\n    -F                  restrict display to function names only\n    -h                  display help\n")), None));
         assert_eq!(input.len(), 0); //Should be empty
     }
 
@@ -1558,6 +3284,52 @@ mod tests {
         assert_eq!(input.len(), 0); //Should be empty
     }
 
+    #[test]
+    fn test_bash_parser_eval() {
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let parser: Bash = Bash::new();
+        //Arguments are kept raw
+        let expr: ShellExpression = parser.parse(&core, &String::from("eval 'echo $HOME' \"foo\" && dirs")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Eval(vec![String::from("'echo $HOME'"), String::from("\"foo\"")]), TaskRelation::And));
+        assert_eq!(expr.statements[1], (ShellStatement::Dirs, TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("eval")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Eval(vec![]), TaskRelation::Unrelated));
+    }
+
+    #[test]
+    fn test_bash_parser_exec() {
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let parser: Bash = Bash::new();
+        //Replace shell
+        let expr: ShellExpression = parser.parse(&core, &String::from("exec ls -l")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::ExecReplace(Some(Task::new(vec![String::from("ls"), String::from("-l")], Redirection::Stdout, Redirection::Stderr)), vec![]), TaskRelation::Unrelated));
+        //Redirections
+        let expr: ShellExpression = parser.parse(&core, &String::from("exec > '/tmp/out.log'")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::ExecReplace(None, vec![ExecRedirection::Stdout(Redirection::File(String::from("/tmp/out.log"), FileRedirectionType::Truncate))]), TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("exec >> /tmp/out.log; dirs")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::ExecReplace(None, vec![ExecRedirection::Stdout(Redirection::File(String::from("/tmp/out.log"), FileRedirectionType::Append))]), TaskRelation::Unrelated));
+        assert_eq!(expr.statements[1], (ShellStatement::Dirs, TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("exec 2>/tmp/err.log < /tmp/in 1>>/tmp/out.log")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::ExecReplace(None, vec![
+            ExecRedirection::Stderr(Redirection::File(String::from("/tmp/err.log"), FileRedirectionType::Truncate)),
+            ExecRedirection::Stdin(String::from("/tmp/in")),
+            ExecRedirection::Stdout(Redirection::File(String::from("/tmp/out.log"), FileRedirectionType::Append))
+        ]), TaskRelation::Unrelated));
+        //Redirections followed by a command
+        let expr: ShellExpression = parser.parse(&core, &String::from("exec 2> /tmp/err.log ls")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::ExecReplace(Some(Task::new(vec![String::from("ls")], Redirection::Stdout, Redirection::Stderr)), vec![
+            ExecRedirection::Stderr(Redirection::File(String::from("/tmp/err.log"), FileRedirectionType::Truncate))
+        ]), TaskRelation::Unrelated));
+        //No arguments
+        let expr: ShellExpression = parser.parse(&core, &String::from("exec")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::ExecReplace(None, vec![]), TaskRelation::Unrelated));
+        //Missing file
+        assert_eq!(parser.parse(&core, &String::from("exec >")).err().unwrap().code, ParserErrorCode::BadToken);
+        assert_eq!(parser.parse(&core, &String::from("exec <")).err().unwrap().code, ParserErrorCode::BadToken);
+        //Unsupported file descriptors
+        assert_eq!(parser.parse(&core, &String::from("exec 3< /tmp/in")).err().unwrap().code, ParserErrorCode::BadArgs);
+    }
+
     #[test]
     fn test_bash_parser_exit() {
         let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
@@ -1590,6 +3362,11 @@ mod tests {
         //No args
         let mut input: VecDeque<String> = parser.readline(&String::from("")).unwrap();
         assert!(parser.parse_export(&core, &mut input).is_ok()); //Print environment, but it's too long to be compared, it's variable too
+        //Functions
+        let mut input: VecDeque<String> = parser.readline(&String::from("-f foo")).unwrap();
+        assert_eq!(parser.parse_export(&core, &mut input).unwrap(), ShellStatement::Functions(FunctionOptions::Export(vec![String::from("foo")])));
+        let mut input: VecDeque<String> = parser.readline(&String::from("-nf foo")).unwrap();
+        assert_eq!(parser.parse_export(&core, &mut input).unwrap(), ShellStatement::Functions(FunctionOptions::Unexport(vec![String::from("foo")])));
         assert_eq!(input.len(), 0); //Should be empty
         //Print argument
         let mut input: VecDeque<String> = parser.readline(&String::from("-p")).unwrap();
@@ -1597,7 +3374,7 @@ mod tests {
         assert_eq!(input.len(), 0); //Should be empty
         //Help argument
         let mut input: VecDeque<String> = parser.readline(&String::from("-h")).unwrap();
-        assert_eq!(parser.parse_export(&core, &mut input).unwrap(), ShellStatement::Output(Some(String::from("export\n\nOptions:\n    -p                  Print all exported variables\n    -n                  Remove NAME from environment\n    -h                  Display help\n")), None)); //Prints help
+        assert_eq!(parser.parse_export(&core, &mut input).unwrap(), ShellStatement::Output(Some(String::from("export\n\nOptions:\n    -p                  Print all exported variables\n    -n                  Remove NAME from environment\n    -f                  Refer to shell functions\n    -h                  Display help\n")), None)); //Prints help
         assert_eq!(input.len(), 0); //Should be empty
         //TODO: parse_argv required for value assignation
         //TODO: -n argument
@@ -1605,6 +3382,110 @@ mod tests {
 
     //TODO: test function
 
+    #[test]
+    fn test_bash_parser_group() {
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let parser: Bash = Bash::new();
+        let echo = |arg: &str| -> ShellStatement {
+            ShellStatement::Exec(Task::new(vec![String::from("echo"), String::from(arg)], Redirection::Stdout, Redirection::Stderr))
+        };
+        //Subshell
+        let expr: ShellExpression = parser.parse(&core, &String::from("(cd /tmp && echo foo); echo bar")).unwrap();
+        assert_eq!(expr.statements.len(), 2);
+        assert_eq!(expr.statements[0], (ShellStatement::Subshell(ShellExpression { statements: vec![
+            (ShellStatement::Cd(PathBuf::from("/tmp"), false), TaskRelation::And),
+            (echo("foo"), TaskRelation::Unrelated)
+        ]}, Redirection::Stdout), TaskRelation::Unrelated));
+        assert_eq!(expr.statements[1], (echo("bar"), TaskRelation::Unrelated));
+        //Group with redirection and relation
+        let expr: ShellExpression = parser.parse(&core, &String::from("{ echo foo; echo bar; } > /tmp/log || echo baz")).unwrap();
+        assert_eq!(expr.statements.len(), 2);
+        assert_eq!(expr.statements[0], (ShellStatement::Group(ShellExpression { statements: vec![
+            (echo("foo"), TaskRelation::Unrelated),
+            (echo("bar"), TaskRelation::Unrelated)
+        ]}, Redirection::File(String::from("/tmp/log"), FileRedirectionType::Truncate)), TaskRelation::Or));
+        assert_eq!(expr.statements[1], (echo("baz"), TaskRelation::Unrelated));
+        //Pipelines
+        let expr: ShellExpression = parser.parse(&core, &String::from("echo foo | { cat; } | (cat)")).unwrap();
+        assert_eq!(expr.statements.len(), 3);
+        assert_eq!(expr.statements[0].1, TaskRelation::Pipe);
+        assert_eq!(expr.statements[1], (ShellStatement::Group(ShellExpression { statements: vec![
+            (ShellStatement::Exec(Task::new(vec![String::from("cat")], Redirection::Stdout, Redirection::Stderr)), TaskRelation::Unrelated)
+        ]}, Redirection::Stdout), TaskRelation::Pipe));
+        assert_eq!(expr.statements[2], (ShellStatement::Subshell(ShellExpression { statements: vec![
+            (ShellStatement::Exec(Task::new(vec![String::from("cat")], Redirection::Stdout, Redirection::Stderr)), TaskRelation::Unrelated)
+        ]}, Redirection::Stdout), TaskRelation::Unrelated));
+        //Nested; braces are reserved words only where a command starts
+        let expr: ShellExpression = parser.parse(&core, &String::from("{ (echo }); { echo foo; }; }")).unwrap();
+        assert_eq!(expr.statements.len(), 1);
+        assert_eq!(expr.statements[0], (ShellStatement::Group(ShellExpression { statements: vec![
+            (ShellStatement::Subshell(ShellExpression { statements: vec![(echo("}"), TaskRelation::Unrelated)] }, Redirection::Stdout), TaskRelation::Unrelated),
+            (ShellStatement::Group(ShellExpression { statements: vec![(echo("foo"), TaskRelation::Unrelated)] }, Redirection::Stdout), TaskRelation::Unrelated)
+        ]}, Redirection::Stdout), TaskRelation::Unrelated));
+        //Errors
+        assert_eq!(parser.parse(&core, &String::from("{ echo foo")).err().unwrap().code, ParserErrorCode::Incomplete);
+        assert_eq!(parser.parse(&core, &String::from("{ echo foo }")).err().unwrap().code, ParserErrorCode::Incomplete);
+        assert_eq!(parser.parse(&core, &String::from("(echo foo")).err().unwrap().code, ParserErrorCode::Incomplete);
+        assert_eq!(parser.parse(&core, &String::from("( )")).err().unwrap().code, ParserErrorCode::BadToken);
+        assert_eq!(parser.parse(&core, &String::from("{ ; }")).err().unwrap().code, ParserErrorCode::BadToken);
+        assert_eq!(parser.parse(&core, &String::from("(echo foo) bar")).err().unwrap().code, ParserErrorCode::BadToken);
+    }
+
+    #[test]
+    fn test_bash_parser_function() {
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let parser: Bash = Bash::new();
+        let body = |redirection: Redirection| -> ShellExpression {
+            ShellExpression { statements: vec![(ShellStatement::Group(ShellExpression { statements: vec![
+                (ShellStatement::Exec(Task::new(vec![String::from("echo"), String::from("\"$1\"")], Redirection::Stdout, Redirection::Stderr)), TaskRelation::Unrelated)
+            ]}, redirection), TaskRelation::Unrelated)] }
+        };
+        //POSIX form
+        let expr: ShellExpression = parser.parse(&core, &String::from("foo() { echo \"$1\"; }; foo bar")).unwrap();
+        assert_eq!(expr.statements.len(), 2);
+        assert_eq!(expr.statements[0], (ShellStatement::Function(String::from("foo"), body(Redirection::Stdout), String::from("{ echo \"$1\"; }")), TaskRelation::Unrelated));
+        //Keyword form, with redirection and relation
+        let expr: ShellExpression = parser.parse(&core, &String::from("function foo { echo \"$1\"; } >> /tmp/log && echo ok")).unwrap();
+        assert_eq!(expr.statements.len(), 2);
+        assert_eq!(expr.statements[0], (ShellStatement::Function(String::from("foo"), body(Redirection::File(String::from("/tmp/log"), FileRedirectionType::Append)), String::from("{ echo \"$1\"; } >> /tmp/log")), TaskRelation::And));
+        //Body on the next lines
+        let expr: ShellExpression = parser.parse(&core, &String::from("function foo ()\n{\n  echo \"$1\"\n}")).unwrap();
+        assert_eq!(expr.statements, vec![(ShellStatement::Function(String::from("foo"), body(Redirection::Stdout), String::from("{ echo \"$1\"; }")), TaskRelation::Unrelated)]);
+        //Subshell body
+        let expr: ShellExpression = parser.parse(&core, &String::from("foo () (cd /tmp)")).unwrap();
+        assert_eq!(expr.statements, vec![(ShellStatement::Function(String::from("foo"), ShellExpression { statements: vec![
+            (ShellStatement::Subshell(ShellExpression { statements: vec![(ShellStatement::Cd(PathBuf::from("/tmp"), false), TaskRelation::Unrelated)] }, Redirection::Stdout), TaskRelation::Unrelated)
+        ]}, String::from("( cd /tmp )")), TaskRelation::Unrelated)]);
+        //Errors
+        assert_eq!(parser.parse(&core, &String::from("foo() {")).err().unwrap().code, ParserErrorCode::Incomplete);
+        assert_eq!(parser.parse(&core, &String::from("foo()")).err().unwrap().code, ParserErrorCode::Incomplete);
+        assert_eq!(parser.parse(&core, &String::from("foo() echo bar")).err().unwrap().code, ParserErrorCode::BadToken);
+        assert_eq!(parser.parse(&core, &String::from("function")).err().unwrap().code, ParserErrorCode::BadToken);
+    }
+
+    #[test]
+    fn test_bash_parser_hash() {
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let parser: Bash = Bash::new();
+        let names = |names: &[&str]| -> Vec<String> { names.iter().map(|x| String::from(*x)).collect() };
+        let expr: ShellExpression = parser.parse(&core, &String::from("hash; hash -l")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Hash(HashOptions::Print), TaskRelation::Unrelated));
+        assert_eq!(expr.statements[1], (ShellStatement::Hash(HashOptions::Print), TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("hash ls cat")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Hash(HashOptions::Add(names(&["ls", "cat"]))), TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("hash -r")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Hash(HashOptions::Reset), TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("hash -d ls")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Hash(HashOptions::Del(names(&["ls"]))), TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("hash -t ls cat")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Hash(HashOptions::Show(names(&["ls", "cat"]))), TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("hash -p /bin/ls list")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Hash(HashOptions::Set(PathBuf::from("/bin/ls"), names(&["list"]))), TaskRelation::Unrelated));
+        //Errors
+        assert_eq!(parser.parse(&core, &String::from("hash -t")).err().unwrap().code, ParserErrorCode::BadArgs);
+        assert_eq!(parser.parse(&core, &String::from("hash -x ls")).err().unwrap().code, ParserErrorCode::BadArgs);
+    }
+
     #[test]
     fn test_bash_parser_history() {
         let (mut core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
@@ -1630,13 +3511,17 @@ mod tests {
         let mut input: VecDeque<String> = parser.readline(&String::from("-r ~/.bash_history")).unwrap();
         assert_eq!(parser.parse_history(&core, &mut input).unwrap(), ShellStatement::History(HistoryOptions::Read(history.clone())));
         assert_eq!(input.len(), 0);
+        //Read the new entries
+        let mut input: VecDeque<String> = parser.readline(&String::from("-n ~/.bash_history")).unwrap();
+        assert_eq!(parser.parse_history(&core, &mut input).unwrap(), ShellStatement::History(HistoryOptions::ReadNew(history.clone())));
+        assert_eq!(input.len(), 0);
         //Write history
         let mut input: VecDeque<String> = parser.readline(&String::from("-w ~/.bash_history")).unwrap();
         assert_eq!(parser.parse_history(&core, &mut input).unwrap(), ShellStatement::History(HistoryOptions::Write(history.clone(), true)));
         assert_eq!(input.len(), 0);
         //Help
         let mut input: VecDeque<String> = parser.readline(&String::from("-h")).unwrap();
-        assert_eq!(parser.parse_history(&core, &mut input).unwrap(), ShellStatement::Output(Some(String::from("history\n\nOptions:\n    -a <file>           Append the new history lines to the history file\n    -c                  Clear the history list. This may be combined with the\n                        other options to replace the history list completely.\n    -d <offset>         Delete the history entry at position offset\n    -r <file>           Read the history file and append its contents to the\n                        history list.\n    -w <file>           Write out the current history list to the history\n                        file.\n    -h, --help          Display help\n")), None));
+        assert_eq!(parser.parse_history(&core, &mut input).unwrap(), ShellStatement::Output(Some(String::from("history\n\nOptions:\n    -a <file>           Append the new history lines to the history file\n    -c                  Clear the history list. This may be combined with the\n                        other options to replace the history list completely.\n    -d <offset>         Delete the history entry at position offset\n    -n <file>           Read the history lines not already read from the\n                        history file and append them to the history list.\n    -r <file>           Read the history file and append its contents to the\n                        history list.\n    -w <file>           Write out the current history list to the history\n                        file.\n    -h, --help          Display help\n")), None));
         assert_eq!(input.len(), 0);
     }
 
@@ -1657,10 +3542,12 @@ mod tests {
         //Other operators
         //Less
         let mut input: VecDeque<String> = parser.readline(&String::from("\"Y=X<5\"")).unwrap();
+        parser.unquote_argv(&mut input);
         assert_eq!(parser.parse_let(&core, &mut input).unwrap(), vec![ShellStatement::Let(String::from("Y"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("$X")), TaskRelation::Unrelated)]}, MathOperator::Less, ShellExpression {statements: vec![(ShellStatement::Value(String::from("5")), TaskRelation::Unrelated)]})]);
         assert_eq!(input.len(), 0);
         //Greater
         let mut input: VecDeque<String> = parser.readline(&String::from("\"Y=X>5\"")).unwrap();
+        parser.unquote_argv(&mut input);
         assert_eq!(parser.parse_let(&core, &mut input).unwrap(), vec![ShellStatement::Let(String::from("Y"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("$X")), TaskRelation::Unrelated)]}, MathOperator::Greater, ShellExpression {statements: vec![(ShellStatement::Value(String::from("5")), TaskRelation::Unrelated)]})]);
         assert_eq!(input.len(), 0);
         //Xor
@@ -1669,10 +3556,12 @@ mod tests {
         assert_eq!(input.len(), 0);
         //And
         let mut input: VecDeque<String> = parser.readline(&String::from("\"Y=X&5\"")).unwrap();
+        parser.unquote_argv(&mut input);
         assert_eq!(parser.parse_let(&core, &mut input).unwrap(), vec![ShellStatement::Let(String::from("Y"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("$X")), TaskRelation::Unrelated)]}, MathOperator::And, ShellExpression {statements: vec![(ShellStatement::Value(String::from("5")), TaskRelation::Unrelated)]})]);
         assert_eq!(input.len(), 0);
         //Or
         let mut input: VecDeque<String> = parser.readline(&String::from("\"Y=X|5\"")).unwrap();
+        parser.unquote_argv(&mut input);
         assert_eq!(parser.parse_let(&core, &mut input).unwrap(), vec![ShellStatement::Let(String::from("Y"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("$X")), TaskRelation::Unrelated)]}, MathOperator::Or, ShellExpression {statements: vec![(ShellStatement::Value(String::from("5")), TaskRelation::Unrelated)]})]);
         assert_eq!(input.len(), 0);
         //Divide
@@ -1694,10 +3583,12 @@ mod tests {
         assert_eq!(input.len(), 0);
         //Shift right
         let mut input: VecDeque<String> = parser.readline(&String::from("\"Y=X>>5\"")).unwrap();
+        parser.unquote_argv(&mut input);
         assert_eq!(parser.parse_let(&core, &mut input).unwrap(), vec![ShellStatement::Let(String::from("Y"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("$X")), TaskRelation::Unrelated)]}, MathOperator::ShiftRight, ShellExpression {statements: vec![(ShellStatement::Value(String::from("5")), TaskRelation::Unrelated)]})]);
         assert_eq!(input.len(), 0);
         //Shift left
         let mut input: VecDeque<String> = parser.readline(&String::from("\"Y=X<<5\"")).unwrap();
+        parser.unquote_argv(&mut input);
         assert_eq!(parser.parse_let(&core, &mut input).unwrap(), vec![ShellStatement::Let(String::from("Y"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("$X")), TaskRelation::Unrelated)]}, MathOperator::ShiftLeft, ShellExpression {statements: vec![(ShellStatement::Value(String::from("5")), TaskRelation::Unrelated)]})]);
         assert_eq!(input.len(), 0);
         //Power
@@ -1706,10 +3597,12 @@ mod tests {
         assert_eq!(input.len(), 0);
         //Greater or equal
         let mut input: VecDeque<String> = parser.readline(&String::from("\"Y=X>=5\"")).unwrap();
+        parser.unquote_argv(&mut input);
         assert_eq!(parser.parse_let(&core, &mut input).unwrap(), vec![ShellStatement::Let(String::from("Y"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("$X")), TaskRelation::Unrelated)]}, MathOperator::GreaterOrEqual, ShellExpression {statements: vec![(ShellStatement::Value(String::from("5")), TaskRelation::Unrelated)]})]);
         assert_eq!(input.len(), 0);
         //Less or equal
         let mut input: VecDeque<String> = parser.readline(&String::from("\"Y=X<=5\"")).unwrap();
+        parser.unquote_argv(&mut input);
         assert_eq!(parser.parse_let(&core, &mut input).unwrap(), vec![ShellStatement::Let(String::from("Y"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("$X")), TaskRelation::Unrelated)]}, MathOperator::LessOrEqual, ShellExpression {statements: vec![(ShellStatement::Value(String::from("5")), TaskRelation::Unrelated)]})]);
         assert_eq!(input.len(), 0);
         //Errors
@@ -1787,19 +3680,48 @@ mod tests {
         let parser: Bash = Bash::new();
         //Simple case
         let mut input: VecDeque<String> = parser.readline(&String::from("")).unwrap();
-        assert_eq!(parser.parse_read(&mut input).unwrap(), ShellStatement::Read(None, None, None));
+        assert_eq!(parser.parse_read(&mut input).unwrap(), ShellStatement::Read(ReadOptions::new()));
         assert_eq!(input.len(), 0); //Should be empty
         //Simple case with ligature
         let mut input: VecDeque<String> = parser.readline(&String::from("&&")).unwrap();
-        assert_eq!(parser.parse_read(&mut input).unwrap(), ShellStatement::Read(None, None, None));
+        assert_eq!(parser.parse_read(&mut input).unwrap(), ShellStatement::Read(ReadOptions::new()));
         assert_eq!(input.len(), 1); //Should has ligature
         //With options
         let mut input: VecDeque<String> = parser.readline(&String::from("-p INPUT: -n 4 PIN")).unwrap();
-        assert_eq!(parser.parse_read(&mut input).unwrap(), ShellStatement::Read(Some(String::from("INPUT:")), Some(4), Some(String::from("PIN"))));
+        let mut options: ReadOptions = ReadOptions::new();
+        options.prompt = Some(String::from("INPUT:"));
+        options.length = Some(4);
+        options.names = vec![String::from("PIN")];
+        assert_eq!(parser.parse_read(&mut input).unwrap(), ShellStatement::Read(options));
         assert_eq!(input.len(), 0); //Should be empty
+        //All the options
+        let mut input: VecDeque<String> = parser.readline(&String::from("-r -s -t 1.5 -d : -a WORDS")).unwrap();
+        let mut options: ReadOptions = ReadOptions::new();
+        options.raw = true;
+        options.silent = true;
+        options.timeout = Some(Duration::from_millis(1500));
+        options.delimiter = ':';
+        options.array = Some(String::from("WORDS"));
+        assert_eq!(parser.parse_read(&mut input).unwrap(), ShellStatement::Read(options));
+        //Multiple names and input file
+        let mut input: VecDeque<String> = parser.readline(&String::from("FIRST < /tmp/input.txt SECOND; echo")).unwrap();
+        let mut options: ReadOptions = ReadOptions::new();
+        options.names = vec![String::from("FIRST"), String::from("SECOND")];
+        options.input = Some(String::from("/tmp/input.txt"));
+        assert_eq!(parser.parse_read(&mut input).unwrap(), ShellStatement::Read(options));
+        assert_eq!(input.len(), 2); //Should have ligature
+        //Empty delimiter (argv is already unquoted)
+        let mut input: VecDeque<String> = VecDeque::from(vec![String::from("-d"), String::new()]);
+        assert_eq!(parser.parse_read(&mut input).unwrap(), ShellStatement::Read(ReadOptions { delimiter: '\0', ..ReadOptions::new() }));
+        //Missing file
+        let mut input: VecDeque<String> = VecDeque::from(vec![String::from("X"), String::from("<")]);
+        assert!(parser.parse_read(&mut input).is_err());
+        //Bad timeout
+        let mut input: VecDeque<String> = parser.readline(&String::from("-t abc")).unwrap();
+        assert_eq!(parser.parse_read(&mut input).unwrap(), ShellStatement::Output(None, Some(String::from("bash: read: abc: invalid timeout specification"))));
         //Help
         let mut input: VecDeque<String> = parser.readline(&String::from("-h")).unwrap();
-        assert_eq!(parser.parse_read(&mut input).unwrap(), ShellStatement::Output(Some(String::from("read\n\nOptions:\n    -n nchars           Return only after reading exactly NCHARS characters,\n                        unless EOF is encountered or read times out, ignoring\n                        any delimiter\n    -p prompt           output the string PROMPT without a trailing newline\n                        before attempting to read\n    -h, --help          Display help\n")), None));
+        assert_eq!(parser.parse_read(&mut input).unwrap(), ShellStatement::Output(Some(String::from("read\n\nOptions:\n    -a array            assign the words read to sequential indices of the\n                        array variable ARRAY, starting at zero\n    -d delim            continue until the first character of DELIM is read,\n                        rather than newline\n    -n nchars           return after reading NCHARS characters rather than\n                        waiting for a newline, but honor a delimiter if fewer\n                        than NCHARS characters are read before the delimiter\n    -p prompt           output the string PROMPT without a trailing newline\n                        before attempting to read\n    -r                  do not allow backslashes to escape any characters\n    -s                  do not echo input coming from a terminal\n    -t timeout          time out and return failure if a complete line of\n                        input is not read within TIMEOUT seconds\n    -h, --help          Display help\n")), None));
         assert_eq!(input.len(), 0); //Should be empty
     }
 
@@ -1848,6 +3770,74 @@ mod tests {
         assert_eq!(input.len(), 1); //Should contain ligature
     }
 
+    #[test]
+    fn test_bash_parser_test() {
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let parser: Bash = Bash::new();
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|x| String::from(*x)).collect() };
+        //test
+        let expr: ShellExpression = parser.parse(&core, &String::from("test -f \"$FILE\" && echo yes")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Test(args(&["-f", "\"$FILE\""]), false), TaskRelation::And));
+        //[ ]; arguments are kept raw
+        let expr: ShellExpression = parser.parse(&core, &String::from("[ 'a b' \\< c ]; dirs")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Test(args(&["'a b'", "\\<", "c"]), false), TaskRelation::Unrelated));
+        assert_eq!(expr.statements[1], (ShellStatement::Dirs, TaskRelation::Unrelated));
+        assert_eq!(parser.parse(&core, &String::from("[ -f /tmp")).err().unwrap().code, ParserErrorCode::BadArgs);
+        //[[ ]]; ligatures are part of the expression
+        let expr: ShellExpression = parser.parse(&core, &String::from("[[ -n $A && ( $B < c || ! -d /tmp ) ]] || dirs")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Test(args(&["-n", "$A", "&&", "(", "$B", "<", "c", "||", "!", "-d", "/tmp", ")"]), true), TaskRelation::Or));
+        assert_eq!(expr.statements[1], (ShellStatement::Dirs, TaskRelation::Unrelated));
+        //Regex operand with alternatives
+        let expr: ShellExpression = parser.parse(&core, &String::from("[[ $A =~ ^(foo|bar)$ && $B == \"*\" ]]")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Test(args(&["$A", "=~", "^(foo|bar)$", "&&", "$B", "==", "\"*\""]), true), TaskRelation::Unrelated));
+        //Incomplete
+        assert_eq!(parser.parse(&core, &String::from("[[ -f /tmp")).err().unwrap().code, ParserErrorCode::Incomplete);
+    }
+
+    #[test]
+    fn test_bash_parser_trap() {
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let parser: Bash = Bash::new();
+        let sig = |signal: UnixSignal| -> TrapSignal { TrapSignal::Signal(signal) };
+        //Set
+        let expr: ShellExpression = parser.parse(&core, &String::from("trap 'rm -f /tmp/lock' EXIT INT sigterm 1; dirs")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Trap(TrapOptions::Set(String::from("rm -f /tmp/lock"), vec![TrapSignal::Exit, sig(UnixSignal::Sigint), sig(UnixSignal::Sigterm), sig(UnixSignal::Sighup)])), TaskRelation::Unrelated));
+        assert_eq!(expr.statements[1], (ShellStatement::Dirs, TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("trap -- '' ERR DEBUG RETURN")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Trap(TrapOptions::Set(String::new(), vec![TrapSignal::Err, TrapSignal::Debug, TrapSignal::Return])), TaskRelation::Unrelated));
+        //Reset
+        let expr: ShellExpression = parser.parse(&core, &String::from("trap - 0 USR1")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Trap(TrapOptions::Reset(vec![TrapSignal::Exit, sig(UnixSignal::Sigusr1)])), TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("trap INT")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Trap(TrapOptions::Reset(vec![sig(UnixSignal::Sigint)])), TaskRelation::Unrelated));
+        //Print and list
+        let expr: ShellExpression = parser.parse(&core, &String::from("trap")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Trap(TrapOptions::Print(vec![])), TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("trap -p EXIT")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Trap(TrapOptions::Print(vec![TrapSignal::Exit])), TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("trap -l")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Trap(TrapOptions::List), TaskRelation::Unrelated));
+        //Errors
+        assert_eq!(parser.parse(&core, &String::from("trap 'echo' FOO")).err().unwrap().code, ParserErrorCode::BadArgs);
+        assert_eq!(parser.parse(&core, &String::from("trap 'echo' 64")).err().unwrap().code, ParserErrorCode::BadArgs);
+        assert_eq!(parser.parse(&core, &String::from("trap -x")).err().unwrap().code, ParserErrorCode::BadArgs);
+    }
+
+    #[test]
+    fn test_bash_parser_type() {
+        let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
+        let parser: Bash = Bash::new();
+        let expr: ShellExpression = parser.parse(&core, &String::from("type ls cd")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Type(vec![String::from("ls"), String::from("cd")], TypeFormat::Verbose, false), TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("type -at ls")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Type(vec![String::from("ls")], TypeFormat::Kind, true), TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("type -p -- ls")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Type(vec![String::from("ls")], TypeFormat::Path, false), TaskRelation::Unrelated));
+        let expr: ShellExpression = parser.parse(&core, &String::from("type -P -p ls")).unwrap();
+        assert_eq!(expr.statements[0], (ShellStatement::Type(vec![String::from("ls")], TypeFormat::ForcePath, false), TaskRelation::Unrelated));
+        assert_eq!(parser.parse(&core, &String::from("type -x ls")).err().unwrap().code, ParserErrorCode::BadArgs);
+    }
+
     #[test]
     fn test_bash_parser_unset() {
         let (core, _): (ShellCore, UserStream) = ShellCore::new(None, 32, Box::new(Bash::new()));
@@ -1864,6 +3854,13 @@ mod tests {
         let mut input: VecDeque<String> = parser.readline(&String::from("FOO BAR &&")).unwrap();
         assert_eq!(parser.parse_unset(&mut input).unwrap(), vec![ShellStatement::Unset(String::from("FOO")), ShellStatement::Unset(String::from("BAR"))]);
         assert_eq!(input.len(), 1); //Should have ligature
+        //Functions
+        let mut input: VecDeque<String> = parser.readline(&String::from("-f foo bar")).unwrap();
+        assert_eq!(parser.parse_unset(&mut input).unwrap(), vec![ShellStatement::Functions(FunctionOptions::Unset(vec![String::from("foo"), String::from("bar")]))]);
+        let mut input: VecDeque<String> = parser.readline(&String::from("-f -v FOO")).unwrap();
+        assert_eq!(parser.parse_unset(&mut input).unwrap(), vec![ShellStatement::Unset(String::from("FOO"))]);
+        let mut input: VecDeque<String> = parser.readline(&String::from("-z FOO")).unwrap();
+        assert!(parser.parse_unset(&mut input).is_err());
 
     }
 
@@ -26,24 +26,40 @@
 // SOFTWARE.
 //
 
-extern crate glob;
-
-use crate::{FileRedirectionType, HistoryOptions, MathError, MathOperator, Redirection};
-use crate::{ShellCore, ShellError, ShellExpression, ShellRunner, ShellStatement};
+use crate::{CommandType, ExecRedirection, FileRedirectionType, FunctionOptions, GlobOptions, HashOptions, HistoryOptions, MathError, MathOperator, Redirection};
+use crate::{ReadOptions, ShellCore, ShellCoreSnapshot, ShellError, ShellExpression, ShellRunner, ShellStatement, TrapOptions, TrapSignal, TypeFormat};
 use crate::{ShellStream, ShellStreamMessage, UserStreamMessage};
 use crate::{TaskManager, Task, TaskRelation};
 use crate::tasks::{TaskError, TaskErrorCode, TaskMessageRx, TaskMessageTx};
+use crate::format;
+use crate::signals;
+use crate::wildcard;
+
+extern crate regex;
 
-use glob::glob;
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::stat::Mode;
+use nix::sys::signal::{kill, Signal};
+use nix::fcntl::OFlag;
+use nix::unistd::{access, mkfifo, pipe2, AccessFlags, Pid};
+use regex::Regex;
 use std::collections::{HashMap, VecDeque};
-use std::fs::File;
+use std::env;
+use std::fs::{self, File, Metadata};
 use std::fs::OpenOptions;
-use std::io::BufRead;
-use std::io::Write;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::time::Instant;
 use std::time::Duration;
-use std::thread::sleep;
+use std::thread::{self, sleep, JoinHandle};
+use subprocess::{Exec, ExitStatus, NullFile, Pipeline, Popen, PopenError};
 
 /// ## TaskChain
 /// 
@@ -53,6 +69,7 @@ use std::thread::sleep;
 struct TaskChain {
     pub task: Option<Task>,
     pub function: Option<Function>,
+    pub builtin: Option<Builtin>,
     pub prev_relation: TaskRelation,
     pub next_relation: TaskRelation,
     pub next: Option<Box<TaskChain>>
@@ -66,6 +83,53 @@ struct Function {
     pub expression: ShellExpression,
     pub args: Vec<String>,
     pub redirection: Redirection,
+    pub env: HashMap<String, String>,
+}
+
+/// ## Builtin
+/// 
+/// A Builtin is the wrapper for a native builtin command (e.g. echo) inside a TaskChain.
+/// Unlike the other builtins, these can be part of pipelines and have their output redirected
+#[derive(std::fmt::Debug)]
+struct Builtin {
+    pub args: Vec<String>,  //Arguments; the first one is the command
+    pub redirection: Redirection,
+    pub stderr_redirection: Redirection,
+    pub env: HashMap<String, String>,
+}
+
+/// ## Substitution
+/// 
+/// A Substitution is a process substitution (`<(cmd)` or `>(cmd)`), whose commands run alongside the command which consumes it.
+/// The consumer gets the path of a fifo; a dedicated thread opens it and starts the commands with the fifo as their stdout (or stdin),
+/// so the data never goes through the runner
+pub(crate) struct Substitution {
+    fifo: PathBuf,
+    input: bool,                   //Whether the consumer reads the output of the commands (`<(cmd)`)
+    opened: Arc<AtomicBool>,       //Set by the worker once the fifo has been opened
+    stop: Arc<AtomicBool>,         //Set once the consumer has terminated; no other command is started
+    processes: Arc<Mutex<Vec<u32>>>, //Pids of the running commands
+    worker: JoinHandle<()>
+}
+
+/// Counter for the fifo names of the process substitutions
+static FIFO_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Maximum function nesting level when FUNCNEST is not set
+const FUNCNEST_DEFAULT: usize = 10000;
+/// Stack which must be left when an expression is nested; when less is left, the expression runs on a new stack segment
+const STACK_RED_ZONE: usize = 1024 * 1024;
+/// Size of the stack segments allocated by the runner
+const STACK_SIZE: usize = 16 * 1024 * 1024;
+
+/// ## WordChar
+/// 
+/// WordChar describes how a character of a word must be treated after the expansions
+#[derive(Clone, Copy, PartialEq, std::fmt::Debug)]
+enum WordChar {
+    Literal,    //Unquoted character of the word
+    Quoted,     //Quoted or escaped character
+    Expanded    //Character which is the result of an unquoted expansion
 }
 
 impl ShellRunner {
@@ -76,8 +140,13 @@ impl ShellRunner {
     pub(crate) fn new() -> ShellRunner {
         ShellRunner {
             buffer: None,
+            piped: false,
             exit_flag: None,
-            break_loop: false
+            break_loop: false,
+            in_trap: false,
+            conditions: 0,
+            substitutions: Vec::new(),
+            funcnest: 0
         }
     }
 
@@ -166,7 +235,7 @@ impl ShellRunner {
         for case in cases.iter() {
             let (_, case_match): (u8, String) = self.run_expression(core, case.0.clone());
             //If case match is equal to output, execute case perform
-            if case_match == output || case_match == "\\*" {
+            if case_match == output || case_match == "*" {
                 let (rc, _): (u8, String) = self.run_expression(core, case.1.clone());
                 exitcode = Some(rc);
                 break; //Stop iterating
@@ -175,11 +244,32 @@ impl ShellRunner {
         exitcode
     }
 
+    /// ### cd
+    /// 
+    /// Execute cd statement. `-` changes directory to the previous directory, while relative paths are searched in CDPATH too.
+    /// The new working directory is printed for `-` and for the directories found through a non-empty CDPATH entry
+    fn cd(&mut self, core: &mut ShellCore, path: PathBuf, physical: bool) -> u8 {
+        let (path, print): (PathBuf, bool) = match path.to_str() {
+            Some("-") => (core.get_prev_dir(), true),
+            //The directory is printed only if it has been found through a non-empty CDPATH entry
+            _ => match core.cdpath_resolve(&path) {
+                Some((dir, print)) => (dir, print),
+                None => (path, false)
+            }
+        };
+        let rc: u8 = self.change_directory(core, path, physical);
+        if rc == 0 && print && ! core.sstream.send(ShellStreamMessage::Output((Some(format!("{}\n", core.get_wrkdir().display())), None))) {
+            //Set exit flag
+            self.exit_flag = Some(255);
+        }
+        rc
+    }
+
     /// ### change_directory
     /// 
-    /// Execute cd statement
-    fn change_directory(&mut self, core: &mut ShellCore, path: PathBuf) -> u8 {
-        if let Err(err) = core.change_directory(path) {
+    /// Change directory, reporting errors to the shell stream
+    fn change_directory(&mut self, core: &mut ShellCore, path: PathBuf, physical: bool) -> u8 {
+        if let Err(err) = core.change_directory(path, physical) {
             //Send error
             if !core.sstream.send(ShellStreamMessage::Error(err)) {
                 //Set exit flag
@@ -191,6 +281,75 @@ impl ShellRunner {
         }
     }
 
+    /// ### describe
+    /// 
+    /// Describe how each command would be resolved (`command -v` and `command -V`).
+    /// Returns 1 if any of the commands couldn't be found
+    fn describe(&mut self, core: &mut ShellCore, names: Vec<String>, verbose: bool) -> u8 {
+        if verbose {
+            return self.describe_type(core, "command", names, TypeFormat::Verbose, false)
+        }
+        let mut rc: u8 = 0;
+        let mut stdout: Vec<String> = Vec::new();
+        for name in names.iter() {
+            match core.resolve_command(name) {
+                CommandType::Alias(alias) => stdout.push(format!("alias {}='{}'", name, alias)),
+                CommandType::Function | CommandType::Builtin => stdout.push(name.clone()),
+                CommandType::File(path) => stdout.push(path.display().to_string()),
+                CommandType::NotFound => rc = 1
+            }
+        }
+        self.send_output(core, stdout, Vec::new());
+        rc
+    }
+
+    /// ### describe_type
+    /// 
+    /// Describe how each command would be resolved in the provided format (`type`); with all, every resolution is reported.
+    /// Returns 1 if any of the commands couldn't be found
+    fn describe_type(&mut self, core: &mut ShellCore, builtin: &str, names: Vec<String>, format: TypeFormat, all: bool) -> u8 {
+        let mut rc: u8 = 0;
+        let mut stdout: Vec<String> = Vec::new();
+        let mut stderr: Vec<String> = Vec::new();
+        for name in names.iter() {
+            let resolved: Vec<CommandType> = match (format, all) {
+                (TypeFormat::ForcePath, true) => core.resolve_command_all(name).into_iter().filter(|x| matches!(x, CommandType::File(_))).collect(),
+                (TypeFormat::ForcePath, false) => core.hash_get(name).or_else(|| core.path_lookup(name)).map(CommandType::File).into_iter().collect(),
+                (_, true) => core.resolve_command_all(name),
+                (_, false) => vec![core.resolve_command(name)].into_iter().filter(|x| *x != CommandType::NotFound).collect()
+            };
+            if resolved.is_empty() {
+                rc = 1;
+                if format == TypeFormat::Verbose {
+                    stderr.push(format!("bash: {}: {}: not found", builtin, name));
+                }
+            }
+            let hashed: Option<PathBuf> = match all {
+                true => None,
+                false => core.hash_get(name)
+            };
+            for command in resolved.into_iter() {
+                match (format, command) {
+                    (TypeFormat::Verbose, CommandType::Alias(alias)) => stdout.push(format!("{} is aliased to `{}'", name, alias)),
+                    (TypeFormat::Verbose, CommandType::Function) => stdout.push(format!("{} is a function", name)),
+                    (TypeFormat::Verbose, CommandType::Builtin) => stdout.push(format!("{} is a shell builtin", name)),
+                    (TypeFormat::Verbose, CommandType::File(path)) => match hashed.as_ref() == Some(&path) {
+                        true => stdout.push(format!("{} is hashed ({})", name, path.display())),
+                        false => stdout.push(format!("{} is {}", name, path.display()))
+                    },
+                    (TypeFormat::Kind, CommandType::Alias(_)) => stdout.push(String::from("alias")),
+                    (TypeFormat::Kind, CommandType::Function) => stdout.push(String::from("function")),
+                    (TypeFormat::Kind, CommandType::Builtin) => stdout.push(String::from("builtin")),
+                    (TypeFormat::Kind, CommandType::File(_)) => stdout.push(String::from("file")),
+                    (TypeFormat::Path, CommandType::File(path)) | (TypeFormat::ForcePath, CommandType::File(path)) => stdout.push(path.display().to_string()),
+                    _ => {}
+                }
+            }
+        }
+        self.send_output(core, stdout, stderr);
+        rc
+    }
+
     /// ### dirs
     /// 
     /// Sends the directories in the core stack
@@ -203,15 +362,73 @@ impl ShellRunner {
         0
     }
 
+    /// ### eval
+    /// 
+    /// Expand the arguments, join them with spaces and execute the result as a command in the current shell
+    fn eval(&mut self, core: &mut ShellCore, args: Vec<String>) -> (u8, String) {
+        let mut words: Vec<String> = Vec::with_capacity(args.len());
+        for arg in args.iter() {
+            match self.expand_word(core, arg.as_str()) {
+                Ok(expanded) => words.extend(expanded),
+                Err(err) => {
+                    if ! core.sstream.send(ShellStreamMessage::Error(err)) {
+                        self.exit_flag = Some(255);
+                    }
+                    return (1, String::new())
+                }
+            }
+        }
+        let command: String = words.join(" ");
+        if command.trim().is_empty() {
+            return (0, String::new())
+        }
+        //Parse and run the command
+        match core.parse(&command) {
+            Ok(expression) => self.run_expression(core, expression),
+            Err(err) => {
+                if ! core.sstream.send(ShellStreamMessage::Error(ShellError::Parser(err))) {
+                    self.exit_flag = Some(255);
+                }
+                (2, String::new())
+            }
+        }
+    }
+
     /// ### exec
     /// 
     /// Executes through the task manager a Task
     fn exec(&mut self, core: &mut ShellCore, task: Task) -> (u8, String) {
+        self.exec_with_relation(core, task, TaskRelation::Unrelated, false)
+    }
+
+    /// ### exec_with_relation
+    /// 
+    /// Executes through the task manager a Task, which has relation with the next statement.
+    /// If the relation is Pipe, the output of the last block is written to the buffer, so that it can be read by the next statement (e.g. read)
+    /// If bypass is true, functions are not resolved for the first command (`command`)
+    fn exec_with_relation(&mut self, core: &mut ShellCore, task: Task, relation: TaskRelation, bypass: bool) -> (u8, String) {
         //Execution flags
         let mut brutally_terminated: bool = false;
         let mut relation_satisfied: bool = true;
+        let mut trapped: Vec<TrapSignal> = Vec::new(); //Trapped signals received while the task was running
+        let substitutions: usize = self.substitutions.len(); //The process substitutions started by this command come after
         //Create command chain from Task
-        let mut chain: TaskChain = self.chain_task(core, task);
+        let mut chain: TaskChain = match self.chain_task(core, task, bypass) {
+            Ok(chain) => chain,
+            Err(err) => {
+                //Report error; the command is not executed
+                if ! core.sstream.send(ShellStreamMessage::Error(err)) {
+                    self.exit_flag = Some(255);
+                }
+                self.close_substitutions(substitutions);
+                return (1, String::new())
+            }
+        };
+        chain.set_last_relation(relation);
+        //If the statement is part of a pipeline, the first block reads the pipe
+        if self.piped {
+            chain.prev_relation = TaskRelation::Pipe;
+        }
         let mut rc: u8 = 0;
         let mut output: String = String::new(); //Output is both returned here and sent to the user
         //Iterate over task chain
@@ -232,6 +449,17 @@ impl ShellRunner {
                         let _ = task_manager.send_message(TaskMessageTx::Input(input.to_string()));
                     }
                     self.buffer = None;
+                    //The pipe is the whole input of the task
+                    if chain.prev_relation == TaskRelation::Pipe {
+                        let _ = task_manager.send_message(TaskMessageTx::CloseInput);
+                    } else if let Some(file) = core.get_input_redirection() {
+                        //Otherwise the task reads the shell input file (`exec < file`) up to the end
+                        let mut input: Vec<u8> = Vec::new();
+                        if (&*file).read_to_end(&mut input).is_ok() && ! input.is_empty() {
+                            let _ = task_manager.send_message(TaskMessageTx::Input(String::from_utf8_lossy(&input).to_string()));
+                        }
+                        let _ = task_manager.send_message(TaskMessageTx::CloseInput);
+                    }
                     //Iterate until task manager is running
                     loop {
                         //Fetch messages
@@ -250,7 +478,8 @@ impl ShellRunner {
                                                 let _ = core.sstream.send(ShellStreamMessage::Output((stdout.clone(), stderr.clone())));
                                                 output.push_str(stdout.as_ref().unwrap().as_str());
                                             } else if chain.next_relation == TaskRelation::Pipe && stdout.is_some() {
-                                                self.buffer = Some(stdout.as_ref().unwrap().clone());
+                                                //Output may arrive in several messages
+                                                self.buffer.get_or_insert_with(String::new).push_str(stdout.as_ref().unwrap().as_str());
                                             }
                                         }
                                     }
@@ -293,6 +522,10 @@ impl ShellRunner {
                                             if let Err(err) = task_manager.send_message(TaskMessageTx::Signal(signal.clone())) {
                                                 core.sstream.send(ShellStreamMessage::Error(ShellError::TaskError(err)));
                                             }
+                                            //The trap is run once the task has terminated
+                                            if core.trap_get(&TrapSignal::Signal(*signal)).is_some() {
+                                                trapped.push(TrapSignal::Signal(*signal));
+                                            }
                                         }
                                     }
                                 }
@@ -312,11 +545,23 @@ impl ShellRunner {
                             break;
                         }
                     } //@! End of task manager loop
+                    //Run traps of the signals received while the task was running
+                    trapped.append(&mut core.trap_pending());
+                    for signal in trapped.drain(..) {
+                        self.run_trap(core, signal);
+                    }
                     if brutally_terminated {
                         //Set exit flag to true and break
                         self.exit_flag = Some(rc);
                         break;
                     }
+                } else if chain.function.is_some() && self.funcnest >= self.funcnest_limit(core) { //@! Function nesting limit
+                    //The function is not executed
+                    let name: String = chain.function.as_ref().map(|func| func.args[0].clone()).unwrap_or_default();
+                    if ! core.sstream.send(ShellStreamMessage::Error(ShellError::FunctionNesting(name, self.funcnest))) {
+                        break; //Endpoint hung up
+                    }
+                    rc = 1;
                 } else if let Some(func) = chain.function { //@! Functions
                     //Prepare backup of tmp values
                     let mut current_tmp_values: Vec<String> = Vec::with_capacity(func.args.len());
@@ -330,8 +575,14 @@ impl ShellRunner {
                         }
                         core.storage_arg_set(index.to_string(), arg.clone());
                     }
-                    //@! Execute function
+                    //@! Execute function (with its environment overrides)
+                    core.environ_override(&func.env);
+                    self.piped = chain.prev_relation == TaskRelation::Pipe;
+                    self.funcnest += 1;
                     let (exitcode, out): (u8, String) = self.run_expression(core, func.expression);
+                    self.funcnest -= 1;
+                    core.environ_restore();
+                    self.run_trap(core, TrapSignal::Return);
                     //remove arguments from storage
                     for (index, _) in func.args.iter().enumerate() {
                         core.value_unset(&index.to_string());
@@ -356,6 +607,54 @@ impl ShellRunner {
                             }
                         }
                     }
+                } else if let Some(builtin) = chain.builtin { //@! Builtins
+                    //Builtins read the output of the previous command in the pipeline
+                    let stdin: String = self.buffer.take().unwrap_or_default();
+                    //@! Execute builtin (with its environment overrides)
+                    core.environ_override(&builtin.env);
+                    let (exitcode, out, err): (u8, String, String) = self.exec_builtin(core, builtin.args, stdin);
+                    core.environ_restore();
+                    rc = exitcode;
+                    //Redirect stderr
+                    let result: Result<(), ShellError> = match builtin.stderr_redirection {
+                        _ if err.is_empty() => Ok(()),
+                        Redirection::Stdout | Redirection::Stderr => {
+                            core.sstream.send(ShellStreamMessage::Output((None, Some(err))));
+                            Ok(())
+                        },
+                        redirection => self.redirect_function_output(&core.sstream, redirection, err)
+                    };
+                    if let Err(err) = result {
+                        //Report error
+                        if !core.sstream.send(ShellStreamMessage::Error(err)) {
+                            break; //Endpoint hung up
+                        }
+                    }
+                    if chain.next_relation == TaskRelation::Pipe {
+                        //Push output to buffer
+                        self.buffer = Some(out);
+                    } else {
+                        output.push_str(out.as_str());
+                        //Redirect output; unlike functions, the output hasn't been sent yet
+                        let result: Result<(), ShellError> = match builtin.redirection {
+                            Redirection::Stdout | Redirection::Stderr if out.is_empty() => Ok(()),
+                            Redirection::Stdout => {
+                                core.sstream.send(ShellStreamMessage::Output((Some(out), None)));
+                                Ok(())
+                            },
+                            Redirection::Stderr => {
+                                core.sstream.send(ShellStreamMessage::Output((None, Some(out))));
+                                Ok(())
+                            },
+                            redirection => self.redirect_function_output(&core.sstream, redirection, out)
+                        };
+                        if let Err(err) = result {
+                            //Report error
+                            if !core.sstream.send(ShellStreamMessage::Error(err)) {
+                                break; //Endpoint hung up
+                            }
+                        }
+                    }
                 }
             }
             //Set chain to next if possible
@@ -370,6 +669,8 @@ impl ShellRunner {
                 break;
             }
         } //@! End of loop
+        //Process substitutions live as long as the command
+        self.close_substitutions(substitutions);
         //Remove last new line from output
         if output.ends_with("\n") {
             let _ = output.pop();
@@ -377,6 +678,39 @@ impl ShellRunner {
         (rc, output)
     }
 
+    /// ### exec_builtin
+    /// 
+    /// Execute a native builtin with the provided input; commands which couldn't be found are handled here too. Returns the exit code, the output and the errors
+    fn exec_builtin(&mut self, core: &mut ShellCore, args: Vec<String>, stdin: String) -> (u8, String, String) {
+        match core.builtin_get(&args[0]) {
+            Some(builtin) => {
+                let mut stdout: Vec<u8> = Vec::new();
+                let mut stderr: Vec<u8> = Vec::new();
+                let rc: u8 = builtin.run(core, &args, &mut stdin.as_bytes(), &mut stdout, &mut stderr);
+                (rc, String::from_utf8_lossy(&stdout).to_string(), String::from_utf8_lossy(&stderr).to_string())
+            },
+            None => {
+                let (rc, out): (u8, String) = self.command_not_found(core, args);
+                (rc, out, String::new())
+            }
+        }
+    }
+
+    /// ### command_not_found
+    /// 
+    /// Handle a command which couldn't be found: the command not found handler is run, if any;
+    /// otherwise the error is reported and the exit status is 127
+    fn command_not_found(&mut self, core: &mut ShellCore, args: Vec<String>) -> (u8, String) {
+        if let Some(rc) = core.command_not_found(&args) {
+            return (rc, String::new())
+        }
+        let err: TaskError = TaskError::new(TaskErrorCode::CommandNotFound, format!("bash: {}: command not found", args[0]));
+        if ! core.sstream.send(ShellStreamMessage::Error(ShellError::TaskError(err))) {
+            self.exit_flag = Some(255);
+        }
+        (127, String::new())
+    }
+
     /// ### exec_history
     /// 
     /// Exec a command located in the history
@@ -402,72 +736,156 @@ impl ShellRunner {
         }
     }
 
+    /// ### exec_replace
+    /// 
+    /// Apply the redirections to the shell (`exec > file`, `exec 2> file`, `exec < file`), then replace the shell with the task (`exec command`), if any;
+    /// the shell terminates with the task exit code and the EXIT trap is not run
+    fn exec_replace(&mut self, core: &mut ShellCore, task: Option<Task>, redirections: Vec<ExecRedirection>) -> (u8, String) {
+        for redirection in redirections.into_iter() {
+            let result: Result<(), (String, std::io::Error)> = match redirection {
+                ExecRedirection::Stdout(Redirection::File(file, mode)) => match self.create_file(file.as_str(), mode) {
+                    //Create file (truncate if required), then append all the following output
+                    Ok(()) => {
+                        core.set_redirection(Redirection::File(file, FileRedirectionType::Append));
+                        Ok(())
+                    },
+                    Err(err) => Err((file, err))
+                },
+                ExecRedirection::Stderr(Redirection::File(file, mode)) => match self.create_file(file.as_str(), mode) {
+                    Ok(()) => {
+                        core.set_stderr_redirection(Redirection::File(file, FileRedirectionType::Append));
+                        Ok(())
+                    },
+                    Err(err) => Err((file, err))
+                },
+                ExecRedirection::Stdout(redirection) => {
+                    core.set_redirection(redirection);
+                    Ok(())
+                },
+                ExecRedirection::Stderr(redirection) => {
+                    core.set_stderr_redirection(redirection);
+                    Ok(())
+                },
+                ExecRedirection::Stdin(file) => match File::open(core.get_wrkdir().join(file.as_str())) {
+                    Ok(input) => {
+                        core.set_input_redirection(input);
+                        Ok(())
+                    },
+                    Err(err) => Err((file, err))
+                }
+            };
+            if let Err((file, err)) = result {
+                if ! core.sstream.send(ShellStreamMessage::Output((None, Some(format!("bash: {}: {}", file, err))))) {
+                    self.exit_flag = Some(255);
+                }
+                return (1, String::new())
+            }
+        }
+        match task {
+            Some(task) => {
+                let (rc, stdout): (u8, String) = self.exec_with_relation(core, task, TaskRelation::Unrelated, true);
+                core.trap_unset(&TrapSignal::Exit);
+                if self.exit_flag.is_none() {
+                    self.exit_flag = Some(rc);
+                }
+                (rc, stdout)
+            },
+            None => (0, String::new())
+        }
+    }
+
+    /// ### create_file
+    /// 
+    /// Create the file a redirection points to; the file is truncated unless mode is Append
+    fn create_file(&self, file: &str, mode: FileRedirectionType) -> std::io::Result<()> {
+        OpenOptions::new().create(true).write(true).append(mode == FileRedirectionType::Append).truncate(mode == FileRedirectionType::Truncate).open(file).map(|_| ())
+    }
+
     /// ### Resolve tasks commands building
     /// 
     /// Separate functions from tasks into individual blocks.
     /// This function is kinda compley, I don't know exactly what it does, but works. Don't touch it.
-    fn chain_task(&self, core: &mut ShellCore, mut head: Task) -> TaskChain {
+    /// If bypass is true, the first command is not resolved as a function
+    fn chain_task(&mut self, core: &mut ShellCore, mut head: Task, bypass: bool) -> Result<TaskChain, ShellError> {
         let mut chain: Option<TaskChain> = None;
         let mut previous_was_function: bool = false;
         let mut last_relation: TaskRelation = TaskRelation::Unrelated;
         let mut last_chain_block: Option<Task> = None;
-        let mut chain_block_length: usize = 0;
         //Iterate over tasks
         loop {
             //Resolve task command
-            let mut command: String = head.command[0].clone();
-            let mut argv: Vec<String> = Vec::new(); //New argv
-            //Check if command is an alias
-            if let Some(resolved) = core.alias_get(&command) {
-                //Split resolved by space
-                for arg in resolved.split_whitespace() {
-                    argv.push(String::from(arg));
-                }
-                //Push head.command[1..] to argv
-                if head.command.len() > 1 {
-                    for arg in head.command[1..].iter() {
-                        argv.push(String::from(arg));
-                    }
+            let bypass_head: bool = bypass && chain.is_none() && last_chain_block.is_none();
+            //@! Expand words (aliases have already been expanded by the parser)
+            let mut words: Vec<String> = Vec::with_capacity(head.command.len());
+            for arg in head.command.iter() {
+                match self.process_substitution(core, arg.as_str())? {
+                    Some(fifo) => words.push(fifo),
+                    None => words.extend(self.expand_word(core, arg.as_str())?)
                 }
-            } else {
-                //argv is head command
-                argv = head.command.clone();
             }
-            command = argv[0].clone();
-            //@! Evaluate values
-            if argv.len() > 1 {
-                for arg in argv[1..].iter_mut() {
-                    //Resolve value
-                    *arg = self.eval_value(core, arg.to_string());
-                }
+            if words.is_empty() {
+                words.push(String::new());
             }
+            let argv: Vec<String> = words;
+            let command: String = argv[0].clone();
             //Push argv to task
             head.command = argv.clone();
-            //Check if first element is a function
-            if let Some(func) = core.function_get(&command) {
+            //Evaluate environment overrides values
+            for value in head.env.values_mut() {
+                *value = self.eval_assignment(core, value.to_string());
+            }
+            //Pass the overrides of the running commands (e.g. `FOO=bar func`) to the process
+            for (key, value) in core.environ_overridden().into_iter() {
+                head.env.entry(key).or_insert(value);
+            }
+            //Apply shell stdout redirection (`exec > file`)
+            if head.stdout_redirection == Redirection::Stdout && head.relation != TaskRelation::Pipe {
+                head.stdout_redirection = core.get_redirection();
+            }
+            //Apply shell stderr redirection (`exec 2> file`)
+            if head.stderr_redirection == Redirection::Stderr {
+                head.stderr_redirection = core.get_stderr_redirection();
+            }
+            //Check if first element is a function or a native builtin (functions come first)
+            let function: Option<ShellExpression> = match bypass_head {
+                true => None,
+                false => core.function_get(&command)
+            };
+            let native_builtin: bool = function.is_none() && core.is_native_builtin(&command);
+            //Resolve executable through the hash table; commands which can't be found are chained as builtins, so that the runner reports them (see command_not_found)
+            let executable: Option<PathBuf> = match function.is_some() || native_builtin {
+                true => None,
+                false => core.hash_lookup(&command)
+            };
+            let not_found: bool = function.is_none() && ! native_builtin && executable.is_none() && ! command.contains('/');
+            let builtin: bool = native_builtin || not_found;
+            if function.is_some() || builtin {
                 //If it's a function, chain previous task block
                 if let Some(mut chain_block) = last_chain_block.take() {
-                    //Truncate task at index and get last relation
-                    last_relation = chain_block.truncate(chain_block_length - 1);
-                    chain_block_length = 0;
+                    //Get last relation
+                    last_relation = chain_block.take_last_relation();
                     //Chain task
                     match chain.as_mut() {
                         None => {
-                            chain = Some(TaskChain::new(Some(chain_block.clone()), None, TaskRelation::Unrelated));
+                            chain = Some(TaskChain::new(Some(chain_block.clone()), None, None, TaskRelation::Unrelated));
                         },
                         Some(chain_obj) => {
-                            chain_obj.chain(Some(chain_block.clone()), None, last_relation);
+                            chain_obj.chain(Some(chain_block.clone()), None, None, last_relation);
                         }
                     }
                 }
-                //If it's a function chain a function
+                //If it's a function chain a function, otherwise chain the builtin
                 previous_was_function = true;
+                let (function, builtin): (Option<Function>, Option<Builtin>) = match function {
+                    Some(func) => (Some(Function::new(func, argv, head.stdout_redirection.clone(), head.env.clone())), None),
+                    None => (None, Some(Builtin::new(argv, head.stdout_redirection.clone(), head.stderr_redirection.clone(), head.env.clone())))
+                };
                 match chain.as_mut() {
                     None => {
-                        chain = Some(TaskChain::new(None, Some(Function::new(func, argv, head.stdout_redirection.clone())), TaskRelation::Unrelated));
+                        chain = Some(TaskChain::new(None, function, builtin, TaskRelation::Unrelated));
                     },
                     Some(chain_obj) => {
-                        chain_obj.chain(None, Some(Function::new(func, argv, head.stdout_redirection.clone())), last_relation);
+                        chain_obj.chain(None, function, builtin, last_relation);
                     }
                 };
                 last_relation = head.relation.clone();
@@ -481,12 +899,16 @@ impl ShellRunner {
                     break;
                 }
             } else { //Not a function
-                //Incremenet chain block length
-                chain_block_length += 1;
-                //If previous was function or last chain block is none
-                if previous_was_function || last_chain_block.is_none() {
-                    previous_was_function = false;
-                    last_chain_block = Some(head.clone());
+                head.executable = executable;
+                //Push task to the chain block; if previous was function or last chain block is none, start a new one
+                let mut block_task: Task = head.clone();
+                block_task.next = None;
+                match last_chain_block.as_mut() {
+                    Some(chain_block) if ! previous_was_function => chain_block.append(block_task),
+                    _ => {
+                        previous_was_function = false;
+                        last_chain_block = Some(block_task);
+                    }
                 }
                 //Go ahead
                 if let Some(task) = head.next {
@@ -503,15 +925,15 @@ impl ShellRunner {
             //Chain task
             match chain.as_mut() {
                 None => {
-                    chain = Some(TaskChain::new(Some(chain_block), None, TaskRelation::Unrelated));
+                    chain = Some(TaskChain::new(Some(chain_block), None, None, TaskRelation::Unrelated));
                 },
                 Some(chain_obj) => {
-                    chain_obj.chain(Some(chain_block.clone()), None, last_relation);
+                    chain_obj.chain(Some(chain_block.clone()), None, None, last_relation);
                 }
             }
         }
         //Return chain
-        chain.unwrap()
+        Ok(chain.unwrap())
     }
 
     /// ### exec_time
@@ -533,16 +955,21 @@ impl ShellRunner {
     /// ### exit
     /// 
     /// Terminates Expression execution and shell
-    fn exit(&mut self, _core: &mut ShellCore, exit_code: u8) {
+    fn exit(&mut self, core: &mut ShellCore, exit_code: u8) {
+        //Run exit trap (only once); exit in the trap overrides the exit code
+        self.run_trap(core, TrapSignal::Exit);
+        core.trap_unset(&TrapSignal::Exit);
         //Exit
-        self.exit_flag = Some(exit_code);
+        if self.exit_flag.is_none() {
+            self.exit_flag = Some(exit_code);
+        }
     }
 
     /// ### export
     /// 
     /// Export a variable in the environment
     fn export(&mut self, core: &mut ShellCore, key: String, value: ShellExpression) -> u8 {
-        let (_, value): (u8, String) = self.run_expression(core, value);
+        let value: String = self.eval_assignment_expression(core, value);
         match core.environ_set(key.clone(), value) {
             true => 0,
             false => {
@@ -562,12 +989,24 @@ impl ShellRunner {
     fn foreach(&mut self, core: &mut ShellCore, key: String, condition: ShellExpression, expression: ShellExpression) -> Option<u8> {
         //Get result of condition
         let mut exitcode: Option<u8> = None;
-        let (rc, output): (u8, String) = self.run_expression(core, condition);
-        if rc != 0 {
-            return Some(1);
-        }
-        //Iterate over output split by whitespace
-        for i in output.split_whitespace() {
+        //Values are iterated word by word (e.g. file names with blanks), while the output of any other expression is split by IFS
+        let words: Vec<String> = match condition.statements.as_slice() {
+            [(ShellStatement::Value(value), _)] => match self.eval_value(core, value.clone()) {
+                Ok(words) => words,
+                Err(err) => {
+                    core.sstream.send(ShellStreamMessage::Error(err));
+                    return Some(1);
+                }
+            },
+            _ => {
+                let (rc, output): (u8, String) = self.run_expression(core, condition);
+                if rc != 0 {
+                    return Some(1);
+                }
+                self.split_words(core, output.as_str())
+            }
+        };
+        for i in words.iter() {
             //Export key to storage
             core.storage_set(key.clone(), i.to_string());
             //Execute expression
@@ -586,17 +1025,157 @@ impl ShellRunner {
     /// ### function
     /// 
     /// Add a new function to core
-    fn function(&mut self, core: &mut ShellCore, name: String, expression: ShellExpression) -> u8 {
-        match core.function_set(name, expression) {
+    fn function(&mut self, core: &mut ShellCore, name: String, expression: ShellExpression, source: String) -> u8 {
+        match core.function_define(name, expression, source) {
             true => 0,
             false => 1
         }
     }
 
+    /// ### functions
+    /// 
+    /// Manage the shell functions: print, remove and export them
+    fn functions(&mut self, core: &mut ShellCore, opt: FunctionOptions) -> u8 {
+        let mut rc: u8 = 0;
+        let mut stdout: Vec<String> = Vec::new();
+        let mut stderr: Vec<String> = Vec::new();
+        match opt {
+            FunctionOptions::Export(names) | FunctionOptions::Unexport(names) if names.is_empty() => {
+                for (name, _) in core.function_names().into_iter().filter(|(_, exported)| *exported) {
+                    stdout.push(format!("declare -fx {}", name));
+                }
+            },
+            FunctionOptions::Export(names) => {
+                for name in names.iter() {
+                    if ! core.function_export(name, true) {
+                        stderr.push(format!("bash: export: {}: not a function", name));
+                        rc = 1;
+                    }
+                }
+            },
+            FunctionOptions::Names(names) if names.is_empty() => {
+                for (name, exported) in core.function_names().into_iter() {
+                    stdout.push(format!("declare -f{} {}", if exported { "x" } else { "" }, name));
+                }
+            },
+            FunctionOptions::Names(names) => {
+                for name in names.into_iter() {
+                    match core.function_get(&name) {
+                        Some(_) => stdout.push(name),
+                        None => rc = 1
+                    }
+                }
+            },
+            FunctionOptions::Print(names) => {
+                let names: Vec<String> = match names.is_empty() {
+                    true => core.function_names().into_iter().map(|(name, _)| name).collect(),
+                    false => names
+                };
+                for name in names.into_iter() {
+                    match core.function_source(&name) {
+                        Some(source) => stdout.push(format!("{} () \n{}", name, source)),
+                        None => rc = 1
+                    }
+                }
+            },
+            FunctionOptions::Unexport(names) => {
+                for name in names.iter() {
+                    if ! core.function_export(name, false) {
+                        stderr.push(format!("bash: export: {}: not a function", name));
+                        rc = 1;
+                    }
+                }
+            },
+            FunctionOptions::Unset(names) => {
+                for name in names.iter() {
+                    core.function_unset(name);
+                }
+            }
+        }
+        self.send_output(core, stdout, stderr);
+        rc
+    }
+
+    /// ### group
+    /// 
+    /// Execute a command group in the current shell. When the group is redirected or piped, its output is captured
+    fn group(&mut self, core: &mut ShellCore, expression: ShellExpression, redirection: Redirection, relation: TaskRelation) -> (u8, String) {
+        let capture: bool = self.capture_start(core, &redirection, relation);
+        let (rc, output): (u8, String) = self.run_expression(core, expression);
+        match capture {
+            true => (rc, self.capture_stop(core, redirection, relation)),
+            false => (rc, output)
+        }
+    }
+
+    /// ### hash
+    /// 
+    /// Manage the command hash table
+    fn hash(&mut self, core: &mut ShellCore, opt: HashOptions) -> u8 {
+        let mut rc: u8 = 0;
+        let mut stdout: Vec<String> = Vec::new();
+        let mut stderr: Vec<String> = Vec::new();
+        match opt {
+            HashOptions::Add(names) => {
+                for name in names.iter() {
+                    //Functions and builtins are not hashed
+                    if core.function_get(name).is_some() || core.is_builtin(name) {
+                        continue;
+                    }
+                    match core.path_lookup(name) {
+                        Some(path) => core.hash_set(name.clone(), path),
+                        None => {
+                            stderr.push(format!("bash: hash: {}: not found", name));
+                            rc = 1;
+                        }
+                    }
+                }
+            },
+            HashOptions::Del(names) => {
+                for name in names.iter() {
+                    if ! core.hash_remove(name) {
+                        stderr.push(format!("bash: hash: {}: not found", name));
+                        rc = 1;
+                    }
+                }
+            },
+            HashOptions::Print => {
+                let commands: Vec<(String, PathBuf, usize)> = core.hash_get_all();
+                if commands.is_empty() {
+                    stdout.push(String::from("hash: hash table empty"));
+                } else {
+                    stdout.push(String::from("hits\tcommand"));
+                    stdout.extend(commands.iter().map(|(_, path, hits)| format!("{:4}\t{}", hits, path.display())));
+                }
+            },
+            HashOptions::Reset => core.hash_clear(),
+            HashOptions::Set(path, names) => {
+                for name in names.into_iter() {
+                    core.hash_set(name, path.clone());
+                }
+            },
+            HashOptions::Show(names) => {
+                for name in names.iter() {
+                    match core.hash_get(name) {
+                        Some(path) if names.len() > 1 => stdout.push(format!("{}\t{}", name, path.display())),
+                        Some(path) => stdout.push(path.display().to_string()),
+                        None => {
+                            stderr.push(format!("bash: hash: {}: not found", name));
+                            rc = 1;
+                        }
+                    }
+                }
+            }
+        }
+        self.send_output(core, stdout, stderr);
+        rc
+    }
+
     /// ### history
     /// 
     /// Handle history shell statement
     fn history(&mut self, core: &mut ShellCore, opt: HistoryOptions) -> u8 {
+        let only_new: bool = matches!(opt, HistoryOptions::ReadNew(_));
         match opt {
             HistoryOptions::Clear => {
                 core.history_clear();
@@ -608,9 +1187,14 @@ impl ShellRunner {
             },
             HistoryOptions::Print => {
                 let mut out: String = String::new();
-                let history: VecDeque<String> = core.history_get();
-                for (index, line) in history.iter().enumerate() {
-                    out += format!("{} {}\n", index, line).as_str();
+                //With HISTTIMEFORMAT, the time each command has been run at is printed before it
+                let time_format: Option<String> = core.value_get(&String::from("HISTTIMEFORMAT")).filter(|x| ! x.is_empty());
+                for (index, entry) in core.history_get_entries().iter().enumerate() {
+                    let time: String = match time_format.as_ref() {
+                        Some(time_format) => format::strftime(time_format.as_str(), entry.timestamp),
+                        None => String::new()
+                    };
+                    out += format!("{} {}{}\n", index, time, entry.command).as_str();
                 }
                 if ! core.sstream.send(ShellStreamMessage::Output((Some(out), None))) {
                     //Set exit flag
@@ -619,30 +1203,25 @@ impl ShellRunner {
                 }
                 0
             },
-            HistoryOptions::Read(file) => {
-                let file = match File::open(file) {
-                    Ok(f) => f,
-                    Err(_) => return 1
-                };
-                let lines = std::io::BufReader::new(file).lines();
-                for line in lines {
-                    if let Ok(line) = line {
-                        //Push line to history
-                        core.history_push(line.clone());
-                    } else {
-                        return 1
+            HistoryOptions::Read(file) | HistoryOptions::ReadNew(file) => {
+                match core.history_read_file(Path::new(file.as_str()), only_new) {
+                    Ok(_) => 0,
+                    Err(err) => {
+                        if ! core.sstream.send(ShellStreamMessage::Error(err)) {
+                            self.exit_flag = Some(255);
+                        }
+                        1
                     }
                 }
-                0
             },
-            HistoryOptions::Write(file, trunc) => {
-                let mut out: String = String::new();
-                let history: VecDeque<String> = core.history_get();
-                for line in history.iter() {
-                    out += line;
-                    out += "\n";
+            HistoryOptions::Write(file, trunc) => match core.history_write_file(Path::new(file.as_str()), ! trunc) {
+                Ok(_) => 0,
+                Err(err) => {
+                    if ! core.sstream.send(ShellStreamMessage::Error(err)) {
+                        self.exit_flag = Some(255);
+                    }
+                    1
                 }
-                self.write_file(file, out, trunc)
             }
         }
     }
@@ -654,7 +1233,9 @@ impl ShellRunner {
     fn ifcond(&mut self, core: &mut ShellCore, condition: ShellExpression, if_perform: ShellExpression, else_perform: Option<ShellExpression>) -> Option<u8> {
         //Get result of condition
         let mut exitcode: Option<u8> = None;
+        self.conditions += 1;
         let (rc, _): (u8, String) = self.run_expression(core, condition);
+        self.conditions -= 1;
         //If rc is 0 => execute if perform
         if rc == 0 {
             //Execute expression
@@ -776,7 +1357,7 @@ impl ShellRunner {
                 self.exit_flag = Some(255);
             }
             //Change directory
-            self.change_directory(core, dir)
+            self.change_directory(core, dir, false)
         } else {
             1
         }
@@ -794,7 +1375,7 @@ impl ShellRunner {
                 self.exit_flag = Some(255);
             }
             //Change directory
-            self.change_directory(core, dir)
+            self.change_directory(core, dir, false)
         } else {
             1
         }
@@ -805,7 +1386,7 @@ impl ShellRunner {
     /// Execute pushd statement.
     fn pushd(&mut self, core: &mut ShellCore, dir: PathBuf) -> u8 {
         //Cd to dir
-        if self.change_directory(core, dir.clone()) != 0 {
+        if self.change_directory(core, dir.clone(), false) != 0 {
             return 1
         }
         core.pushd(dir);
@@ -816,71 +1397,273 @@ impl ShellRunner {
 
     /// ### read
     /// 
-    /// Execute read statement, which means it waits for input until arrives; if the input has a maximum size, it gets cut to the maximum size
-    /// The data read is exported to result_key or to REPLY if not provided
-    fn read(&mut self, core: &mut ShellCore, prompt: Option<String>, max_size: Option<usize>, result_key: Option<String>) -> u8 {
-        let prompt: String = match prompt {
-            Some(p) => p,
-            None => String::new()
-        };
-        //Send prompt as output
-        let _ = core.sstream.send(ShellStreamMessage::Output((Some(prompt), None)));
-        //Define the key name
-        let key: String = match result_key {
-            Some(k) => k,
-            None => String::from("REPLY")
-        };
-        //Read
-        loop {
-            //Try to read from sstream
-            match core.sstream.receive() {
-                Ok(inbox) => {
-                    //Iterate over inbox
-                    for message in inbox.iter() {
-                        match message {
-                            UserStreamMessage::Input(input) => { //If input return input or 
-                                match max_size {
-                                    None => {
-                                        //Export variable to storage
-                                        match core.storage_set(key, input.clone()) {
-                                            true => return 0,
-                                            false => return 1
-                                        }
-                                    },
-                                    Some(size) => {
-                                        let value: String = String::from(&input[..size]);
-                                        match core.storage_set(key, value) {
-                                            true => return 0,
-                                            false => return 1
-                                        }
-                                    }
-                                }
-                            },
-                            UserStreamMessage::Kill => return 1,
-                            UserStreamMessage::Signal(_) => return 1,
-                            UserStreamMessage::Interrupt => {
-                                self.exit_flag = Some(255);
-                                return 1
-                            }
-                        }
+    /// Execute read statement: a line is read from the input file, from the pipe or from the user stream (waiting until it arrives).
+    /// The line is split into words using IFS and the words are assigned to the names (the last one gets the remainder of the line), to the array or to REPLY
+    fn read(&mut self, core: &mut ShellCore, options: ReadOptions) -> u8 {
+        //With a zero timeout, just check whether there is input available
+        if options.timeout == Some(Duration::from_secs(0)) {
+            return self.read_available(core, &options)
+        }
+        //Get input from file, from buffer (pipe) or from the shell input file; if there's none, the input is read from the user stream
+        let mut input_file: Option<Rc<File>> = None;
+        let (input, interactive): (String, bool) = match &options.input {
+            Some(file) => {
+                let path: PathBuf = core.get_wrkdir().join(file);
+                match fs::read_to_string(path.as_path()) {
+                    Ok(content) => (content, false),
+                    Err(_) => {
+                        let _ = core.sstream.send(ShellStreamMessage::Error(ShellError::NoSuchFileOrDirectory(path)));
+                        return 1
                     }
-                },
-                Err(_) => {
-                    self.exit_flag = Some(255);
-                    return 1
                 }
+            },
+            None => match (self.buffer.take(), core.get_input_redirection()) {
+                (Some(buffer), _) => (buffer, false),
+                (None, Some(file)) => {
+                    //Shell input file (`exec < file`)
+                    let mut content: String = String::new();
+                    if let Err(err) = (&*file).read_to_string(&mut content) {
+                        let _ = core.sstream.send(ShellStreamMessage::Output((None, Some(format!("bash: read: {}", err)))));
+                        return 1
+                    }
+                    input_file = Some(file);
+                    (content, false)
+                },
+                (None, None) => (String::new(), true)
             }
+        };
+        if interactive {
+            //Send prompt as output
+            let _ = core.sstream.send(ShellStreamMessage::Output((Some(options.prompt.clone().unwrap_or_default()), None)));
+            if options.silent {
+                let _ = core.sstream.send(ShellStreamMessage::InputEcho(false));
+            }
+        }
+        let result: Result<(Vec<(char, WordChar)>, u8), u8> = self.read_input(core, &options, input, interactive);
+        //Give back what hasn't been read to the shell input file
+        if let Some(file) = input_file {
+            if let Some(remainder) = self.buffer.take() {
+                let _ = (&*file).seek(SeekFrom::Current(-(remainder.len() as i64)));
+            }
+        }
+        if interactive && options.silent {
+            let _ = core.sstream.send(ShellStreamMessage::InputEcho(true));
+        }
+        let (line, rc): (Vec<(char, WordChar)>, u8) = match result {
+            Ok(result) => result,
+            Err(rc) => return rc
+        };
+        //Assign words
+        let assigned: bool = match (&options.array, options.names.len()) {
+            (Some(array), _) => core.storage_array_set(array.clone(), self.split_read_fields(core, line, usize::MAX)),
+            (None, 0) => core.storage_set(String::from("REPLY"), line.into_iter().map(|(c, _)| c).collect()),
+            (None, amount) => {
+                let mut fields: Vec<String> = self.split_read_fields(core, line, amount);
+                fields.resize(amount, String::new());
+                options.names.iter().zip(fields).all(|(name, value)| core.storage_set(name.clone(), value))
+            }
+        };
+        match assigned {
+            true => rc,
+            false => 1
         }
     }
 
-    /// ### set
+    /// ### read_available
     /// 
-    /// Set a key with its associated value in the Shell session storage
-    fn set(&mut self, core: &mut ShellCore, key: String, value: ShellExpression) -> u8 {
-        let (_, value): (u8, String) = self.run_expression(core, value);
-        match core.storage_set(key.clone(), value) {
-            true => 0,
-            false => {
+    /// Returns 0 if there is input available for read, 1 otherwise. The input received from the user stream is kept in the buffer
+    fn read_available(&mut self, core: &mut ShellCore, options: &ReadOptions) -> u8 {
+        //Input file
+        if let Some(file) = &options.input {
+            let path: PathBuf = core.get_wrkdir().join(file);
+            return match OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(path.as_path()) {
+                Ok(file) => match self.input_available(&file) {
+                    true => 0,
+                    false => 1
+                },
+                Err(_) => {
+                    let _ = core.sstream.send(ShellStreamMessage::Error(ShellError::NoSuchFileOrDirectory(path)));
+                    1
+                }
+            }
+        }
+        //Shell input file (`exec < file`)
+        if let (None, Some(file)) = (&self.buffer, core.get_input_redirection()) {
+            return match self.input_available(&file) {
+                true => 0,
+                false => 1
+            }
+        }
+        match core.sstream.receive() {
+            Ok(inbox) => {
+                for message in inbox.into_iter() {
+                    match message {
+                        UserStreamMessage::Input(input) => self.buffer.get_or_insert_with(String::new).push_str(input.as_str()),
+                        UserStreamMessage::Interrupt => self.exit_flag = Some(255),
+                        _ => {}
+                    }
+                }
+            },
+            Err(_) => self.exit_flag = Some(255)
+        }
+        match &self.buffer {
+            Some(buffer) if ! buffer.is_empty() => 0,
+            _ => 1
+        }
+    }
+
+    /// ### input_available
+    /// 
+    /// Returns whether the file has data which can be read without blocking
+    fn input_available(&self, file: &File) -> bool {
+        match file.metadata() {
+            //Regular files have data until the end of file
+            Ok(metadata) if metadata.is_file() => match (&*file).stream_position() {
+                Ok(position) => position < metadata.len(),
+                Err(_) => false
+            },
+            //Pipes, terminals...
+            _ => {
+                let mut fds: [PollFd; 1] = [PollFd::new(file.as_raw_fd(), PollFlags::POLLIN)];
+                match poll(&mut fds, 0) {
+                    Ok(ready) => ready > 0 && fds[0].revents().map(|x| x.contains(PollFlags::POLLIN)).unwrap_or(false),
+                    Err(_) => false
+                }
+            }
+        }
+    }
+
+    /// ### read_input
+    /// 
+    /// Read a line from input; if the input is interactive, it waits for the user stream until the line is complete or the timeout expires.
+    /// Returns the line and the exit code (1 at end of file, 142 on timeout) or Err with the exit code if read has been aborted.
+    /// The remainder of a buffered input is put back into the buffer
+    fn read_input(&mut self, core: &mut ShellCore, options: &ReadOptions, mut input: String, interactive: bool) -> Result<(Vec<(char, WordChar)>, u8), u8> {
+        let deadline: Option<Instant> = options.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let (line, consumed, complete): (Vec<(char, WordChar)>, usize, bool) = self.read_line(input.as_str(), options);
+            if complete || ! interactive {
+                if ! interactive && options.input.is_none() && consumed < input.len() {
+                    self.buffer = Some(input.split_off(consumed));
+                }
+                return Ok((line, match complete {
+                    true => 0,
+                    false => 1 //End of file
+                }))
+            }
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return Ok((line, 142))
+                }
+            }
+            //Try to read from sstream
+            match core.sstream.receive() {
+                Ok(inbox) => {
+                    //Iterate over inbox
+                    for message in inbox.iter() {
+                        match message {
+                            UserStreamMessage::Input(text) => {
+                                input.push_str(text.as_str());
+                                //Each input message is a line
+                                if options.delimiter == '\n' && ! text.ends_with('\n') {
+                                    input.push('\n');
+                                }
+                            },
+                            UserStreamMessage::Kill => return Err(1),
+                            UserStreamMessage::Signal(_) => return Err(1),
+                            UserStreamMessage::Interrupt => {
+                                self.exit_flag = Some(255);
+                                return Err(1)
+                            }
+                        }
+                    }
+                    if inbox.is_empty() {
+                        sleep(Duration::from_millis(50));
+                    }
+                },
+                Err(_) => {
+                    self.exit_flag = Some(255);
+                    return Err(1)
+                }
+            }
+        }
+    }
+
+    /// ### read_line
+    /// 
+    /// Read a line from input, until the delimiter or until the maximum amount of characters has been read.
+    /// Unless raw, backslashes escape the next character (which is then marked as Quoted) and a backslash-newline pair is removed.
+    /// Returns the characters read, the amount of bytes consumed and whether the line is complete
+    fn read_line(&self, input: &str, options: &ReadOptions) -> (Vec<(char, WordChar)>, usize, bool) {
+        let mut line: Vec<(char, WordChar)> = Vec::new();
+        let mut escaped: bool = false;
+        for (index, c) in input.char_indices() {
+            if options.length.map(|length| line.len() >= length).unwrap_or(false) {
+                return (line, index, true)
+            }
+            if escaped {
+                escaped = false;
+                if c != '\n' {
+                    line.push((c, WordChar::Quoted));
+                }
+            } else if c == options.delimiter {
+                return (line, index + c.len_utf8(), true)
+            } else if c == '\\' && ! options.raw {
+                escaped = true;
+            } else {
+                line.push((c, WordChar::Expanded));
+            }
+        }
+        let complete: bool = options.length.map(|length| line.len() >= length).unwrap_or(false);
+        (line, input.len(), complete)
+    }
+
+    /// ### split_read_fields
+    /// 
+    /// Split the line read by `read` into at most max fields according to IFS.
+    /// The last field gets the remainder of the line; leading and trailing IFS whitespaces are removed
+    fn split_read_fields(&self, core: &ShellCore, line: Vec<(char, WordChar)>, max: usize) -> Vec<String> {
+        let ifs: String = match core.value_get(&String::from("IFS")) {
+            Some(ifs) => ifs,
+            None => String::from(" \t\n")
+        };
+        let is_separator = |(c, kind): &(char, WordChar)| *kind == WordChar::Expanded && ifs.contains(*c);
+        let is_blank = |x: &(char, WordChar)| is_separator(x) && x.0.is_whitespace();
+        //Trim IFS whitespaces
+        let start: usize = line.iter().position(|x| ! is_blank(x)).unwrap_or(line.len());
+        let end: usize = line.iter().rposition(|x| ! is_blank(x)).map(|x| x + 1).unwrap_or(start);
+        let line: &[(char, WordChar)] = &line[start..end];
+        let mut fields: Vec<String> = Vec::new();
+        let mut index: usize = 0;
+        while index < line.len() {
+            let end: usize = match fields.len() + 1 == max {
+                true => line.len(),
+                false => line[index..].iter().position(is_separator).map(|x| x + index).unwrap_or(line.len())
+            };
+            fields.push(line[index..end].iter().map(|(c, _)| *c).collect());
+            //Skip separator: IFS whitespaces and at most one other IFS character
+            index = end;
+            while index < line.len() && is_blank(&line[index]) {
+                index += 1;
+            }
+            if index < line.len() && is_separator(&line[index]) && ! line[index].0.is_whitespace() {
+                index += 1;
+                while index < line.len() && is_blank(&line[index]) {
+                    index += 1;
+                }
+            }
+        }
+        fields
+    }
+
+    /// ### set
+    /// 
+    /// Set a key with its associated value in the Shell session storage
+    fn set(&mut self, core: &mut ShellCore, key: String, value: ShellExpression) -> u8 {
+        let value: String = self.eval_assignment_expression(core, value);
+        match core.storage_set(key.clone(), value) {
+            true => 0,
+            false => {
                 //Report error
                 if ! core.sstream.send(ShellStreamMessage::Error(ShellError::BadValue(key))) {
                     //Set exit flag
@@ -905,64 +1688,649 @@ impl ShellRunner {
         }
     }
 
-    /// ### eval_value
+    /// ### subshell
     /// 
-    /// Evaluate value
-    fn eval_value(&self, core: &mut ShellCore, value: String) -> String {
-        //Treat variables
-        let mut outval: String = value.clone();
-        if value.starts_with("${") {
-            //Get value from core
-            let last_pos_index: usize = value.len() - 1;
-            let value: String = String::from(&value[2..last_pos_index]);
-            outval = match core.value_get(&value) {
-                Some(val) => val,
-                None => String::from("")
-            };
-        } else if value.starts_with("$") {
-            //Get value from core
-            let value: String = String::from(&value[1..]);
-            outval = match core.value_get(&value) {
-                Some(val) => val,
-                None => String::from("")
-            };
+    /// Execute an expression in a subshell: it runs with its own runner and the shell state is restored once it has terminated,
+    /// so changes to variables, directory, aliases, functions and options don't affect the current shell
+    fn subshell(&mut self, core: &mut ShellCore, expression: ShellExpression, redirection: Redirection, relation: TaskRelation) -> (u8, String) {
+        let snapshot: ShellCoreSnapshot = core.snapshot();
+        let capture: bool = self.capture_start(core, &redirection, relation);
+        //The subshell reads the input of the current shell; exit terminates the subshell only
+        let mut runner: ShellRunner = ShellRunner::new();
+        runner.buffer = self.buffer.take();
+        runner.piped = self.piped;
+        runner.conditions = self.conditions;
+        runner.funcnest = self.funcnest;
+        let (rc, output): (u8, String) = runner.run_expression(core, expression);
+        //Restore state before redirecting, since the redirection is relative to the current shell directory
+        core.restore(snapshot);
+        match capture {
+            true => (rc, self.capture_stop(core, redirection, relation)),
+            false => (rc, output)
+        }
+    }
+
+    /// ### test
+    /// 
+    /// Evaluate a conditional expression (`test`, `[ ]` or `[[ ]]`).
+    /// With `test` and `[ ]` the words are expanded before the evaluation, while with `[[ ]]` the operands are expanded without word splitting and wildcards.
+    /// Returns 0 if the expression is true, 1 if it is false and 2 in case of error
+    fn test(&self, core: &mut ShellCore, args: Vec<String>, extended: bool) -> u8 {
+        let result: Result<bool, String> = match extended {
+            true => self.test_expression(core, &args, true),
+            false => {
+                let mut words: Vec<String> = Vec::with_capacity(args.len());
+                for arg in args.iter() {
+                    match self.expand_word(core, arg.as_str()) {
+                        Ok(expanded) => words.extend(expanded),
+                        Err(err) => {
+                            let _ = core.sstream.send(ShellStreamMessage::Error(err));
+                            return 2
+                        }
+                    }
+                }
+                self.test_posix(core, &words)
+            }
+        };
+        match result {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(err) => {
+                let _ = core.sstream.send(ShellStreamMessage::Error(ShellError::BadValue(err)));
+                2
+            }
+        }
+    }
+
+    /// ### test_posix
+    /// 
+    /// Evaluate the arguments of `test` and `[ ]`. Up to four arguments, the expression is evaluated according to the number of arguments,
+    /// as POSIX requires (e.g. `[ -n ]` is true); longer expressions are parsed with precedence
+    fn test_posix(&self, core: &mut ShellCore, args: &[String]) -> Result<bool, String> {
+        match args.len() {
+            0 => Ok(false),
+            1 => Ok(! args[0].is_empty()),
+            2 if args[0] == "!" => Ok(args[1].is_empty()),
+            2 if self.is_test_unary_operator(args[0].as_str()) => self.test_unary(core, args[0].as_str(), args[1].as_str(), false),
+            2 => Err(format!("{}: unary operator expected", args[0])),
+            3 if self.is_test_binary_operator(args[1].as_str(), false) => self.test_binary(core, args[0].as_str(), args[1].as_str(), args[2].as_str(), false),
+            3 if args[1] == "-a" || args[1] == "-o" => self.test_expression(core, args, false),
+            3 if args[0] == "!" => self.test_posix(core, &args[1..]).map(|result| ! result),
+            3 if args[0] == "(" && args[2] == ")" => Ok(! args[1].is_empty()),
+            3 => Err(format!("{}: binary operator expected", args[1])),
+            4 if args[0] == "!" => self.test_posix(core, &args[1..]).map(|result| ! result),
+            4 if args[0] == "(" && args[3] == ")" => self.test_posix(core, &args[1..3]),
+            _ => self.test_expression(core, args, false)
+        }
+    }
+
+    /// ### test_expression
+    /// 
+    /// Parse and evaluate a conditional expression. Operators by precedence are: `!`, `-a` (`&&` if extended) and `-o` (`||` if extended).
+    /// Parenthesis can be used to group expressions
+    fn test_expression(&self, core: &mut ShellCore, args: &[String], extended: bool) -> Result<bool, String> {
+        let mut index: usize = 0;
+        let result: bool = self.test_or(core, args, &mut index, extended)?;
+        match args.get(index) {
+            Some(arg) => Err(format!("syntax error near `{}'", arg)),
+            None => Ok(result)
+        }
+    }
+
+    /// ### test_or
+    /// 
+    /// Evaluate a sequence of expressions joined by `-o` (`||` if extended)
+    fn test_or(&self, core: &mut ShellCore, args: &[String], index: &mut usize, extended: bool) -> Result<bool, String> {
+        let operator: &str = match extended {
+            true => "||",
+            false => "-o"
+        };
+        let mut result: bool = self.test_and(core, args, index, extended)?;
+        while args.get(*index).map(|x| x.as_str()) == Some(operator) {
+            *index += 1;
+            let next: bool = self.test_and(core, args, index, extended)?;
+            result = result || next;
+        }
+        Ok(result)
+    }
+
+    /// ### test_and
+    /// 
+    /// Evaluate a sequence of expressions joined by `-a` (`&&` if extended)
+    fn test_and(&self, core: &mut ShellCore, args: &[String], index: &mut usize, extended: bool) -> Result<bool, String> {
+        let operator: &str = match extended {
+            true => "&&",
+            false => "-a"
+        };
+        let mut result: bool = self.test_not(core, args, index, extended)?;
+        while args.get(*index).map(|x| x.as_str()) == Some(operator) {
+            *index += 1;
+            let next: bool = self.test_not(core, args, index, extended)?;
+            result = result && next;
+        }
+        Ok(result)
+    }
+
+    /// ### test_not
+    /// 
+    /// Evaluate a primary expression, negated if preceeded by `!`
+    fn test_not(&self, core: &mut ShellCore, args: &[String], index: &mut usize, extended: bool) -> Result<bool, String> {
+        if args.get(*index).map(|x| x.as_str()) == Some("!") {
+            *index += 1;
+            return self.test_not(core, args, index, extended).map(|result| ! result)
+        }
+        self.test_primary(core, args, index, extended)
+    }
+
+    /// ### test_primary
+    /// 
+    /// Evaluate a primary expression: a grouped expression, a binary or unary test or a single string
+    fn test_primary(&self, core: &mut ShellCore, args: &[String], index: &mut usize, extended: bool) -> Result<bool, String> {
+        let arg: &str = match args.get(*index) {
+            Some(arg) => arg.as_str(),
+            None => return Err(String::from("argument expected"))
+        };
+        //Grouped expression
+        if arg == "(" {
+            *index += 1;
+            let result: bool = self.test_or(core, args, index, extended)?;
+            if args.get(*index).map(|x| x.as_str()) != Some(")") {
+                return Err(String::from("`)' expected"))
+            }
+            *index += 1;
+            return Ok(result)
+        }
+        //Binary test
+        if let (Some(operator), Some(rhs)) = (args.get(*index + 1), args.get(*index + 2)) {
+            if self.is_test_binary_operator(operator.as_str(), extended) {
+                *index += 3;
+                return self.test_binary(core, arg, operator.as_str(), rhs.as_str(), extended)
+            }
+        }
+        //Unary test
+        if let Some(operand) = args.get(*index + 1) {
+            if self.is_test_unary_operator(arg) {
+                *index += 2;
+                return self.test_unary(core, arg, operand.as_str(), extended)
+            }
+        }
+        //Single string: true if not empty
+        *index += 1;
+        Ok(! self.test_operand(core, arg, extended).is_empty())
+    }
+
+    /// ### test_unary
+    /// 
+    /// Evaluate a unary test (string and file tests)
+    fn test_unary(&self, core: &mut ShellCore, operator: &str, operand: &str, extended: bool) -> Result<bool, String> {
+        let value: String = self.test_operand(core, operand, extended);
+        match operator {
+            "-z" => return Ok(value.is_empty()),
+            "-n" => return Ok(! value.is_empty()),
+            "-v" => return Ok(core.value_get(&value).is_some()),
+            _ => {}
+        }
+        //File tests
+        if value.is_empty() {
+            return Ok(false)
+        }
+        let path: PathBuf = core.get_wrkdir().join(value.as_str());
+        let metadata: Option<Metadata> = match operator {
+            "-L" | "-h" => fs::symlink_metadata(path.as_path()).ok(),
+            _ => fs::metadata(path.as_path()).ok()
+        };
+        let metadata: Metadata = match metadata {
+            Some(metadata) => metadata,
+            None => return Ok(false)
+        };
+        match operator {
+            "-e" => Ok(true),
+            "-f" => Ok(metadata.is_file()),
+            "-d" => Ok(metadata.is_dir()),
+            "-L" | "-h" => Ok(metadata.file_type().is_symlink()),
+            "-s" => Ok(metadata.len() > 0),
+            "-p" => Ok(metadata.file_type().is_fifo()),
+            "-S" => Ok(metadata.file_type().is_socket()),
+            "-b" => Ok(metadata.file_type().is_block_device()),
+            "-c" => Ok(metadata.file_type().is_char_device()),
+            "-r" => Ok(access(path.as_path(), AccessFlags::R_OK).is_ok()),
+            "-w" => Ok(access(path.as_path(), AccessFlags::W_OK).is_ok()),
+            "-x" => Ok(access(path.as_path(), AccessFlags::X_OK).is_ok()),
+            _ => Err(format!("{}: unary operator expected", operator))
+        }
+    }
+
+    /// ### test_binary
+    /// 
+    /// Evaluate a binary test (string, integer and file comparisons).
+    /// If extended, the right operand of `==` and `!=` is a pattern, while the one of `=~` is a regex
+    fn test_binary(&self, core: &mut ShellCore, lhs: &str, operator: &str, rhs: &str, extended: bool) -> Result<bool, String> {
+        let left: String = self.test_operand(core, lhs, extended);
+        match operator {
+            "=" | "==" | "!=" => {
+                let matched: bool = match extended {
+                    true => wildcard::matches(self.expand_pattern(core, rhs, wildcard::escape).as_str(), left.as_str()),
+                    false => left == rhs
+                };
+                Ok(matched == (operator != "!="))
+            },
+            "=~" => self.test_regex(core, left.as_str(), rhs),
+            _ => {
+                let right: String = self.test_operand(core, rhs, extended);
+                match operator {
+                    "<" => Ok(left < right),
+                    ">" => Ok(left > right),
+                    "-nt" | "-ot" | "-ef" => {
+                        let left: Option<Metadata> = fs::metadata(core.get_wrkdir().join(left.as_str())).ok();
+                        let right: Option<Metadata> = fs::metadata(core.get_wrkdir().join(right.as_str())).ok();
+                        Ok(match (operator, left, right) {
+                            ("-nt", Some(left), Some(right)) => left.modified().ok() > right.modified().ok(),
+                            ("-nt", Some(_), None) => true,
+                            ("-ot", Some(left), Some(right)) => left.modified().ok() < right.modified().ok(),
+                            ("-ot", None, Some(_)) => true,
+                            ("-ef", Some(left), Some(right)) => left.dev() == right.dev() && left.ino() == right.ino(),
+                            _ => false
+                        })
+                    },
+                    _ => {
+                        //Integer comparison
+                        let left: i64 = self.test_integer(left.as_str())?;
+                        let right: i64 = self.test_integer(right.as_str())?;
+                        match operator {
+                            "-eq" => Ok(left == right),
+                            "-ne" => Ok(left != right),
+                            "-lt" => Ok(left < right),
+                            "-le" => Ok(left <= right),
+                            "-gt" => Ok(left > right),
+                            "-ge" => Ok(left >= right),
+                            _ => Err(format!("{}: binary operator expected", operator))
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// ### test_regex
+    /// 
+    /// Match text against an extended regex; quoted characters of the regex are matched literally.
+    /// The matched string and the matched groups are stored into the BASH_REMATCH array
+    fn test_regex(&self, core: &mut ShellCore, text: &str, regex: &str) -> Result<bool, String> {
+        let pattern: String = self.expand_pattern(core, regex, regex::escape);
+        let regex: Regex = match Regex::new(pattern.as_str()) {
+            Ok(regex) => regex,
+            Err(_) => return Err(format!("{}: invalid regular expression", pattern))
+        };
+        let groups: Vec<String> = match regex.captures(text) {
+            Some(captures) => captures.iter().map(|group| group.map(|x| String::from(x.as_str())).unwrap_or_default()).collect(),
+            None => Vec::new()
+        };
+        let matched: bool = ! groups.is_empty();
+        let _ = core.storage_array_set(String::from("BASH_REMATCH"), groups);
+        Ok(matched)
+    }
+
+    /// ### test_operand
+    /// 
+    /// Get the value of an operand. With `[[ ]]` operands are expanded, otherwise they have been expanded already
+    fn test_operand(&self, core: &mut ShellCore, operand: &str, extended: bool) -> String {
+        match extended {
+            true => self.expand_string(core, operand),
+            false => String::from(operand)
+        }
+    }
+
+    /// ### test_integer
+    /// 
+    /// Parse an integer operand
+    fn test_integer(&self, value: &str) -> Result<i64, String> {
+        match value.trim().parse::<i64>() {
+            Ok(value) => Ok(value),
+            Err(_) => Err(format!("{}: integer expression expected", value))
+        }
+    }
+
+    /// ### is_test_unary_operator
+    /// 
+    /// Returns whether the argument is a unary operator of conditional expressions
+    fn is_test_unary_operator(&self, arg: &str) -> bool {
+        matches!(arg, "-z" | "-n" | "-v" | "-e" | "-f" | "-d" | "-L" | "-h" | "-s" | "-p" | "-S" | "-b" | "-c" | "-r" | "-w" | "-x")
+    }
+
+    /// ### is_test_binary_operator
+    /// 
+    /// Returns whether the argument is a binary operator of conditional expressions; `=~` is available only with `[[ ]]`
+    fn is_test_binary_operator(&self, arg: &str, extended: bool) -> bool {
+        match arg {
+            "=" | "==" | "!=" | "<" | ">" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" | "-nt" | "-ot" | "-ef" => true,
+            "=~" => extended,
+            _ => false
         }
-        //Once out of variable control, let's look for wildcards
-        if (outval.matches("*").count() > 0 && outval.matches("*").count() != outval.matches("\\*").count()) || (outval.matches("?").count() > 0 && outval.matches("?").count() != outval.matches("\\?").count()) {
-            //Resolve wildcards, we expect value to be a path. In case of wild cards, value is a string with matched files separated by whitespace
-            let path: &Path = Path::new(outval.as_str());
-            //If path is relative, get absolute path
-            let abs_path: PathBuf = match path.is_relative() {
-                true => {
-                    let mut abs_path: PathBuf = core.get_wrkdir();
-                    abs_path.push(path);
-                    abs_path
+    }
+
+    /// ### trap
+    /// 
+    /// Execute trap statement: list the signals, print the traps or set and reset them
+    fn trap(&mut self, core: &mut ShellCore, opt: TrapOptions) -> u8 {
+        match opt {
+            TrapOptions::List => {
+                let signals: Vec<String> = signals::SIGNALS.iter().map(|x| format!("{:2}) {}", *x as u8, x.name())).collect();
+                let lines: Vec<String> = signals.chunks(5).map(|x| x.join("\t")).collect();
+                core.sstream.send(ShellStreamMessage::Output((Some(lines.join("\n")), None)));
+                0
+            },
+            TrapOptions::Print(signals) => {
+                let mut traps: Vec<(TrapSignal, String)> = match signals.is_empty() {
+                    true => core.trap_get_all().into_iter().collect(),
+                    false => signals.into_iter().filter_map(|x| core.trap_get(&x).map(|action| (x, action))).collect()
+                };
+                traps.sort_by_key(|(signal, _)| self.trap_signal_order(signal));
+                let lines: Vec<String> = traps.iter().map(|(signal, action)| format!("trap -- '{}' {}", action.replace("'", "'\\''"), self.trap_signal_name(signal))).collect();
+                if ! lines.is_empty() {
+                    core.sstream.send(ShellStreamMessage::Output((Some(lines.join("\n")), None)));
+                }
+                0
+            },
+            TrapOptions::Reset(signals) => {
+                for signal in signals.iter() {
+                    core.trap_unset(signal);
+                }
+                0
+            },
+            TrapOptions::Set(action, signals) => {
+                let mut rc: u8 = 0;
+                for signal in signals.into_iter() {
+                    if ! core.trap_set(signal, action.clone()) {
+                        core.sstream.send(ShellStreamMessage::Error(ShellError::BadValue(format!("trap: {}: cannot trap signal", self.trap_signal_name(&signal)))));
+                        rc = 1;
+                    }
+                }
+                rc
+            }
+        }
+    }
+
+    /// ### run_trap
+    /// 
+    /// Run the action of the trap associated to the signal, if any. The exit code of the last statement is preserved
+    fn run_trap(&mut self, core: &mut ShellCore, signal: TrapSignal) {
+        if self.in_trap {
+            return
+        }
+        let action: String = match core.trap_get(&signal) {
+            Some(action) if ! action.is_empty() => action,
+            _ => return
+        };
+        match core.parse(&action) {
+            Ok(expression) => {
+                //Traps don't read the pipe
+                self.in_trap = true;
+                let piped: bool = std::mem::replace(&mut self.piped, false);
+                let _ = self.run_expression(core, expression);
+                self.piped = piped;
+                self.in_trap = false;
+            },
+            Err(err) => {
+                core.sstream.send(ShellStreamMessage::Error(ShellError::Parser(err)));
+            }
+        }
+    }
+
+    /// ### expand_word
+    /// 
+    /// Expand a word of a command into a list of words. The expansions are performed in this order:
+    /// tildes, variables, word splitting (only for the unquoted expansions, according to IFS), wildcards and finally quote removal.
+    /// If failglob is set and a pattern doesn't match any file, NoMatch is returned
+    fn expand_word(&self, core: &mut ShellCore, word: &str) -> Result<Vec<String>, ShellError> {
+        let (expanded, quoted): (Vec<(char, WordChar)>, bool) = self.expand_chars(core, word, self.is_assignment_word(word));
+        //Split fields; a quoted empty word is still a word
+        let mut fields: Vec<Vec<(char, WordChar)>> = self.split_fields(core, expanded);
+        if fields.is_empty() && quoted {
+            fields.push(Vec::new());
+        }
+        //Treat wildcards and remove quotes
+        let mut words: Vec<String> = Vec::with_capacity(fields.len());
+        for field in fields.into_iter() {
+            //Quoted characters must be matched literally
+            let pattern: String = self.quote_chars(&field, wildcard::escape);
+            let word: String = field.into_iter().map(|(c, _)| c).collect();
+            if ! wildcard::has_wildcards(pattern.as_str()) {
+                words.push(word);
+                continue;
+            }
+            let matches: Vec<String> = core.glob(pattern.as_str());
+            let options: GlobOptions = core.get_glob_options();
+            if ! matches.is_empty() {
+                words.extend(matches);
+            } else if options.failglob {
+                return Err(ShellError::NoMatch(word))
+            } else if ! options.nullglob {
+                //If nothing matches, the word is kept
+                words.push(word);
+            }
+        }
+        Ok(words)
+    }
+
+    /// ### expand_string
+    /// 
+    /// Expand a word into a single string: tildes and variables are expanded and quotes are removed, but neither word splitting nor wildcards are performed
+    fn expand_string(&self, core: &mut ShellCore, word: &str) -> String {
+        let (expanded, _): (Vec<(char, WordChar)>, bool) = self.expand_chars(core, word, self.is_assignment_word(word));
+        expanded.into_iter().map(|(c, _)| c).collect()
+    }
+
+    /// ### expand_pattern
+    /// 
+    /// Expand a word into a pattern, like expand_string, but quoted characters are escaped with the provided function,
+    /// so that they're matched literally
+    fn expand_pattern(&self, core: &mut ShellCore, word: &str, escape: fn(&str) -> String) -> String {
+        let (expanded, _): (Vec<(char, WordChar)>, bool) = self.expand_chars(core, word, self.is_assignment_word(word));
+        self.quote_chars(&expanded, escape)
+    }
+
+    /// ### quote_chars
+    /// 
+    /// Collect expanded characters into a string, escaping the quoted ones with the provided function
+    fn quote_chars(&self, chars: &[(char, WordChar)], escape: fn(&str) -> String) -> String {
+        let mut output: String = String::with_capacity(chars.len());
+        for (c, kind) in chars.iter() {
+            match kind {
+                WordChar::Quoted => output.push_str(escape(c.to_string().as_str()).as_str()),
+                _ => output.push(*c)
+            }
+        }
+        output
+    }
+
+    /// ### expand_chars
+    /// 
+    /// Expand tildes and variables of a word and remove its quotes. Each character is returned along with how it must be treated by the next expansions.
+    /// If assignment is true, tildes are expanded as in an assignment (after `=` and `:` too).
+    /// Returns also whether the word contained quotes
+    fn expand_chars(&self, core: &mut ShellCore, word: &str, assignment: bool) -> (Vec<(char, WordChar)>, bool) {
+        //Treat tildes
+        let word: String = core.expand_tilde(&String::from(word), assignment);
+        //Treat variables and quotes
+        let chars: Vec<char> = word.chars().collect();
+        let mut expanded: Vec<(char, WordChar)> = Vec::with_capacity(chars.len());
+        let mut quote: Option<char> = None;
+        let mut quoted: bool = false; //Whether the word contained quotes
+        let mut index: usize = 0;
+        while index < chars.len() {
+            let c: char = chars[index];
+            match (quote, c) {
+                (None, '\'') | (None, '"') => {
+                    quote = Some(c);
+                    quoted = true;
                 },
-                false => PathBuf::from(path)
-            };
-            //Get files in path
-            let mut result: String = String::new();
-            if let Ok(records) = glob(abs_path.to_str().unwrap()) {
-                for entry in records {
-                    if let Ok(path) = entry {
-                        result.push_str(format!("{} ", path.display()).as_str());
+                (Some(q), c) if q == c => quote = None,
+                (Some('\''), '\\') if chars.get(index + 1) == Some(&'\'') => {
+                    //Escaped quote inside single quotes is kept as it is
+                    index += 1;
+                    expanded.push(('\\', WordChar::Quoted));
+                    expanded.push(('\'', WordChar::Quoted));
+                },
+                (Some('\''), c) => expanded.push((c, WordChar::Quoted)),
+                (None, '\\') => {
+                    index += 1;
+                    if let Some(c) = chars.get(index) {
+                        expanded.push((*c, WordChar::Quoted));
                     }
+                },
+                (Some(_), '\\') => match chars.get(index + 1) {
+                    //Inside double quotes, backslash escapes only these characters
+                    Some('$') | Some('`') | Some('"') | Some('\\') => {
+                        index += 1;
+                        expanded.push((chars[index], WordChar::Quoted));
+                    },
+                    _ => expanded.push(('\\', WordChar::Quoted))
+                },
+                (_, '$') => match self.variable_name(&chars, index) {
+                    Some((name, end)) => {
+                        //Unquoted expansions are subject to word splitting
+                        let kind: WordChar = match quote {
+                            Some(_) => WordChar::Quoted,
+                            None => WordChar::Expanded
+                        };
+                        for c in core.value_get(&name).unwrap_or_default().chars() {
+                            expanded.push((c, kind));
+                        }
+                        index = end;
+                        continue;
+                    },
+                    None => expanded.push(('$', match quote {
+                        Some(_) => WordChar::Quoted,
+                        None => WordChar::Literal
+                    }))
+                },
+                (Some(_), c) => expanded.push((c, WordChar::Quoted)),
+                (None, c) => expanded.push((c, WordChar::Literal))
+            }
+            index += 1;
+        }
+        (expanded, quoted)
+    }
+
+    /// ### split_fields
+    /// 
+    /// Split the result of the expansions into fields. Only the characters which came from unquoted expansions are used as separators.
+    /// Sequences of IFS whitespaces are treated as a single separator
+    fn split_fields(&self, core: &ShellCore, chars: Vec<(char, WordChar)>) -> Vec<Vec<(char, WordChar)>> {
+        let ifs: String = match core.value_get(&String::from("IFS")) {
+            Some(ifs) => ifs,
+            None => String::from(" \t\n")
+        };
+        let mut fields: Vec<Vec<(char, WordChar)>> = Vec::new();
+        let mut field: Vec<(char, WordChar)> = Vec::new();
+        let mut whitespace_delimited: bool = false; //Whether the last field has been terminated by a whitespace
+        for (c, kind) in chars.into_iter() {
+            if kind != WordChar::Expanded || ! ifs.contains(c) {
+                field.push((c, kind));
+                whitespace_delimited = false;
+                continue;
+            }
+            if c.is_whitespace() {
+                if ! field.is_empty() {
+                    fields.push(std::mem::take(&mut field));
+                    whitespace_delimited = true;
                 }
+            } else if ! (whitespace_delimited && field.is_empty()) {
+                fields.push(std::mem::take(&mut field));
+            } else {
+                whitespace_delimited = false;
+            }
+        }
+        if ! field.is_empty() {
+            fields.push(field);
+        }
+        fields
+    }
+
+    /// ### split_words
+    /// 
+    /// Split a string into words according to IFS
+    fn split_words(&self, core: &ShellCore, value: &str) -> Vec<String> {
+        let chars: Vec<(char, WordChar)> = value.chars().map(|c| (c, WordChar::Expanded)).collect();
+        self.split_fields(core, chars).into_iter().map(|field| field.into_iter().map(|(c, _)| c).collect()).collect()
+    }
+
+    /// ### variable_name
+    /// 
+    /// Get the name of the variable referenced by the `$` at index (`$NAME`, `${NAME}` or special parameters such as `$?`).
+    /// Returns the name and the index of the first character after the reference
+    fn variable_name(&self, chars: &[char], index: usize) -> Option<(String, usize)> {
+        match chars.get(index + 1) {
+            Some('{') => {
+                let end: usize = index + 2 + chars[index + 2..].iter().position(|c| *c == '}')?;
+                Some((chars[index + 2..end].iter().collect(), end + 1))
+            },
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                let mut end: usize = index + 1;
+                while end < chars.len() && (chars[end].is_ascii_alphanumeric() || chars[end] == '_') {
+                    end += 1;
+                }
+                Some((chars[index + 1..end].iter().collect(), end))
+            },
+            Some(c) if c.is_ascii_digit() || "?#@*$!-".contains(*c) => Some((c.to_string(), index + 2)),
+            _ => None
+        }
+    }
+
+    /// ### is_assignment_word
+    /// 
+    /// Returns whether the word looks like an assignment (NAME=value)
+    fn is_assignment_word(&self, word: &str) -> bool {
+        match word.find('=') {
+            Some(index) => {
+                let name: &str = &word[..index];
+                match name.chars().next() {
+                    Some(c) if c.is_ascii_alphabetic() || c == '_' => name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                    _ => false
+                }
+            },
+            None => false
+        }
+    }
+
+    /// ### eval_assignment
+    /// 
+    /// Evaluate the value of an assignment: tildes (after `:` too) and variables are expanded and finally quotes are removed.
+    /// Neither word splitting nor wildcards are performed
+    fn eval_assignment(&self, core: &mut ShellCore, value: String) -> String {
+        let (expanded, _): (Vec<(char, WordChar)>, bool) = self.expand_chars(core, value.as_str(), true);
+        expanded.into_iter().map(|(c, _)| c).collect()
+    }
+
+    /// ### eval_assignment_expression
+    /// 
+    /// Evaluate the expression assigned to a variable; plain values are evaluated as assignments, any other expression is performed
+    fn eval_assignment_expression(&mut self, core: &mut ShellCore, value: ShellExpression) -> String {
+        match value.statements.as_slice() {
+            [(ShellStatement::Value(val), _)] => self.eval_assignment(core, val.clone()),
+            _ => {
+                let (_, output): (u8, String) = self.run_expression(core, value);
+                output
             }
-            result
-        } else {
-            //Else return value
-            outval
         }
     }
 
+    /// ### eval_value
+    /// 
+    /// Evaluate value into words, as the words of a command (see expand_word)
+    fn eval_value(&self, core: &mut ShellCore, value: String) -> Result<Vec<String>, ShellError> {
+        self.expand_word(core, value.as_str())
+    }
+
     /// ### while_loop
     /// 
     /// Perform While shell statement
     fn while_loop(&mut self, core: &mut ShellCore, condition: ShellExpression, expression: ShellExpression) -> Option<u8> {
         let mut exitcode: Option<u8> = None;
         loop {
+            self.conditions += 1;
             let (rc, _): (u8, String) = self.run_expression(core, condition.clone());
+            self.conditions -= 1;
             if rc != 0 { //If rc is NOT 0, break
                 break;
             }
@@ -977,6 +2345,22 @@ impl ShellRunner {
         exitcode
     }
 
+    /// ### with_env
+    /// 
+    /// Perform an expression with the provided variables set in the environment.
+    /// The previous environment is restored once the expression has terminated
+    fn with_env(&mut self, core: &mut ShellCore, env: HashMap<String, String>, expression: ShellExpression) -> (u8, String) {
+        //Evaluate values
+        let mut environ: HashMap<String, String> = HashMap::with_capacity(env.len());
+        for (key, value) in env.into_iter() {
+            environ.insert(key, self.eval_assignment(core, value));
+        }
+        core.environ_override(&environ);
+        let (rc, output): (u8, String) = self.run_expression(core, expression);
+        core.environ_restore();
+        (rc, output)
+    }
+
     /// ### write_file
     /// 
     /// Write file with a certain content
@@ -1000,14 +2384,26 @@ impl ShellRunner {
     /// Return the string output and the result of an expression.
     /// This function is very important since must be used by all the other statements which uses an expression (e.g. set, export, case, if...)
     fn run_expression(&mut self, core: &mut ShellCore, expression: ShellExpression) -> (u8, String) {
+        //Deep recursion continues on a new stack segment, on the same thread
+        stacker::maybe_grow(STACK_RED_ZONE, STACK_SIZE, || self.run_statements(core, expression))
+    }
+
+    /// ### run_statements
+    /// 
+    /// Run the statements of an expression; see run_expression
+    fn run_statements(&mut self, core: &mut ShellCore, expression: ShellExpression) -> (u8, String) {
         let mut rc: u8 = 0;
         let mut output: String = String::new();
         //Iterate over expression
         let mut relation_satisfied: bool = true;
+        let piped_input: bool = self.piped;
+        let mut piped: bool = piped_input; //Whether the previous statement piped its output to this one (the input of a group is piped to its first statement)
         //NOTE: the expression is executed as long as it's possible
         for statement in expression.statements.iter() {
             //Execute statement only if relation is satisfied
             if relation_satisfied {
+                self.run_trap(core, TrapSignal::Debug);
+                self.piped = piped;
                 //Match statement and execute it
                 match &statement.0 {
                     ShellStatement::Alias(name, cmd) => {
@@ -1022,24 +2418,42 @@ impl ShellRunner {
                             rc = exitcode;
                         }
                     },
-                    ShellStatement::Cd(path) => {
-                        rc = self.change_directory(core, path.clone());
+                    ShellStatement::Cd(path, physical) => {
+                        rc = self.cd(core, path.clone(), *physical);
+                    },
+                    ShellStatement::Command(task) => {
+                        let (exitcode, stdout): (u8, String) = self.exec_with_relation(core, task.clone(), statement.1, true);
+                        rc = exitcode;
+                        output.push_str(stdout.as_str());
                     },
                     ShellStatement::Continue => {
                         //Keep iterating
                         continue;
                     },
+                    ShellStatement::Describe(names, verbose) => {
+                        rc = self.describe(core, names.clone(), *verbose);
+                    },
                     ShellStatement::Dirs => {
                         rc = self.dirs(core);
                     },
+                    ShellStatement::Eval(args) => {
+                        let (exitcode, stdout): (u8, String) = self.eval(core, args.clone());
+                        rc = exitcode;
+                        output.push_str(stdout.as_str());
+                    },
                     ShellStatement::Exec(task) => {
-                        let (exitcode, stdout): (u8, String) = self.exec(core, task.clone());
+                        let (exitcode, stdout): (u8, String) = self.exec_with_relation(core, task.clone(), statement.1, false);
                         rc = exitcode;
                         output.push_str(stdout.as_str());
                     },
                     ShellStatement::ExecHistory(index)  => {
                         rc = self.exec_history(core, *index);
                     },
+                    ShellStatement::ExecReplace(task, redirections) => {
+                        let (exitcode, stdout): (u8, String) = self.exec_replace(core, task.clone(), redirections.clone());
+                        rc = exitcode;
+                        output.push_str(stdout.as_str());
+                    },
                     ShellStatement::Exit(exitcode) => {
                         self.exit(core, *exitcode);
                     },
@@ -1051,8 +2465,19 @@ impl ShellRunner {
                             rc = exitcode;
                         }
                     },
-                    ShellStatement::Function(name, expression) => {
-                        rc = self.function(core, name.clone(), expression.clone());
+                    ShellStatement::Function(name, expression, source) => {
+                        rc = self.function(core, name.clone(), expression.clone(), source.clone());
+                    },
+                    ShellStatement::Functions(opt) => {
+                        rc = self.functions(core, opt.clone());
+                    },
+                    ShellStatement::Group(expression, redirection) => {
+                        let (exitcode, stdout): (u8, String) = self.group(core, expression.clone(), redirection.clone(), statement.1);
+                        rc = exitcode;
+                        output.push_str(stdout.as_str());
+                    },
+                    ShellStatement::Hash(opt) => {
+                        rc = self.hash(core, opt.clone());
                     },
                     ShellStatement::History(opt) => {
                         rc = self.history(core, opt.clone());
@@ -1066,9 +2491,19 @@ impl ShellRunner {
                         rc = self.let_perform(core, dest.clone(), operator1.clone(), operation.clone(), operator2.clone());
                     },
                     ShellStatement::Output(stdout, stderr) => {
+                        //Stdout goes to the shell redirection file if set (`exec > file`)
+                        let stdout: Option<String> = match (stdout, core.get_redirection()) {
+                            (Some(out), Redirection::File(file, mode)) => {
+                                if let Err(err) = self.redirect_function_output(&core.sstream, Redirection::File(file, mode), out.clone()) {
+                                    core.sstream.send(ShellStreamMessage::Error(err));
+                                }
+                                None
+                            },
+                            (stdout, _) => stdout.clone()
+                        };
                         //Send output (if not both are none)
                         if stdout.is_some() || stderr.is_some() {
-                            core.sstream.send(ShellStreamMessage::Output((stdout.clone(), stderr.clone())));
+                            core.sstream.send(ShellStreamMessage::Output((stdout, stderr.clone())));
                         }
                     },
                     ShellStatement::PopdBack => {
@@ -1083,8 +2518,8 @@ impl ShellRunner {
                     ShellStatement::Rc(code) => {
                         rc = *code;
                     },
-                    ShellStatement::Read(prompt, length, result_key) => {
-                        rc = self.read(core, prompt.clone(), length.clone(), result_key.clone());
+                    ShellStatement::Read(options) => {
+                        rc = self.read(core, options.clone());
                     },
                     ShellStatement::Return(ret) => {
                         return (*ret, output);
@@ -1095,30 +2530,71 @@ impl ShellRunner {
                     ShellStatement::Source(file) => {
                         rc = self.source(core, file.clone());
                     },
+                    ShellStatement::Subshell(expression, redirection) => {
+                        let (exitcode, stdout): (u8, String) = self.subshell(core, expression.clone(), redirection.clone(), statement.1);
+                        rc = exitcode;
+                        output.push_str(stdout.as_str());
+                    },
+                    ShellStatement::Test(args, extended) => {
+                        rc = self.test(core, args.clone(), *extended);
+                    },
                     ShellStatement::Time(task) => {
                         let (exitcode, stdout): (u8, String) = self.exec_time(core, task.clone());
                         rc = exitcode;
                         output.push_str(stdout.as_str());
                     },
+                    ShellStatement::Trap(opt) => {
+                        rc = self.trap(core, opt.clone());
+                    },
+                    ShellStatement::Type(names, format, all) => {
+                        rc = self.describe_type(core, "type", names.clone(), *format, *all);
+                    },
                     ShellStatement::Unalias(alias) => {
                         rc = self.unalias(core, alias.clone());
                     },
                     ShellStatement::Unset(var) => {
-                        core.value_unset(var);
+                        //If there's no such variable, the function is removed
+                        match core.value_get(var) {
+                            None => {
+                                core.function_unset(var);
+                            },
+                            Some(_) => core.value_unset(var)
+                        }
                     },
                     ShellStatement::Value(val) => {
-                        output = self.eval_value(core, val.clone());
+                        match self.eval_value(core, val.clone()) {
+                            Ok(words) => output = words.join(" "),
+                            Err(err) => {
+                                core.sstream.send(ShellStreamMessage::Error(err));
+                                rc = 1;
+                            }
+                        }
                     },
                     ShellStatement::While(until, perform) => {
                         if let Some(exitcode) = self.while_loop(core, until.clone(), perform.clone()) {
                             rc = exitcode;
                         }
                     },
+                    ShellStatement::WithEnv(env, perform) => {
+                        let (exitcode, stdout): (u8, String) = self.with_env(core, env.clone(), perform.clone());
+                        rc = exitcode;
+                        output.push_str(stdout.as_str());
+                    },
                     ShellStatement::WriteFile(file, content, trunc) => {
                         rc = self.write_file(file.clone(), content.clone(), *trunc);
                     }
                 }
+                //Run ERR trap, unless the exit code is checked (condition or And/Or lists) or the statement is compound
+                let compound: bool = matches!(statement.0, ShellStatement::Case(..) | ShellStatement::For(..) | ShellStatement::Group(..) | ShellStatement::If(..) | ShellStatement::Subshell(..) | ShellStatement::While(..));
+                if rc != 0 && self.conditions == 0 && ! compound && statement.1 != TaskRelation::And && statement.1 != TaskRelation::Or {
+                    self.run_trap(core, TrapSignal::Err);
+                }
             }
+            //What hasn't been read from the pipe is discarded (unless the statement piped its own output)
+            if piped && statement.1 != TaskRelation::Pipe {
+                self.buffer = None;
+            }
+            piped = statement.1 == TaskRelation::Pipe;
             //Verify if relation is satisfied
             relation_satisfied = self.is_relation_satisfied(rc, statement.1);
             //look for inputs
@@ -1145,7 +2621,11 @@ impl ShellRunner {
                                 self.exit_flag = Some(9);
                             },
                             UserStreamMessage::Signal(sig) => {
-                                self.exit_flag = Some(*sig as u8);
+                                //Trapped signals don't terminate the expression
+                                match core.trap_get(&TrapSignal::Signal(*sig)) {
+                                    Some(_) => self.run_trap(core, TrapSignal::Signal(*sig)),
+                                    None => self.exit_flag = Some(*sig as u8)
+                                }
                             }
                         }
                     }
@@ -1155,6 +2635,10 @@ impl ShellRunner {
                     self.exit_flag = Some(255);
                 }
             }
+            //Run traps of the signals delivered to the shell process
+            for signal in core.trap_pending().into_iter() {
+                self.run_trap(core, signal);
+            }
             //check exit flag
             if let Some(exitcode) = self.exit_flag {
                 //If exit flag is set, terminate expression execution
@@ -1162,6 +2646,7 @@ impl ShellRunner {
                 break;
             }
         }
+        self.piped = piped_input;
         (rc, output)
     }
 
@@ -1195,6 +2680,141 @@ impl ShellRunner {
         Ok(())
     }
 
+    /// ### capture_start
+    /// 
+    /// Start capturing the output of a compound command if it's redirected or piped. Returns whether the output is being captured
+    fn capture_start(&self, core: &mut ShellCore, redirection: &Redirection, relation: TaskRelation) -> bool {
+        let capture: bool = *redirection != Redirection::Stdout || relation == TaskRelation::Pipe;
+        if capture {
+            core.sstream.capture_start();
+        }
+        capture
+    }
+
+    /// ### capture_stop
+    /// 
+    /// Stop capturing the output of a compound command and redirect it: files come first, then the pipe.
+    /// Returns the output which is left to the caller (i.e. when it's not redirected)
+    fn capture_stop(&mut self, core: &mut ShellCore, redirection: Redirection, relation: TaskRelation) -> String {
+        let output: String = core.sstream.capture_stop();
+        let result: Result<(), ShellError> = match redirection {
+            Redirection::Stdout if relation == TaskRelation::Pipe => {
+                self.buffer = Some(output);
+                Ok(())
+            },
+            Redirection::Stdout => return output,
+            Redirection::Stderr => {
+                core.sstream.send(ShellStreamMessage::Output((None, Some(output))));
+                Ok(())
+            },
+            redirection => self.redirect_function_output(&core.sstream, redirection, output)
+        };
+        if let Err(err) = result {
+            //Report error
+            if ! core.sstream.send(ShellStreamMessage::Error(err)) {
+                self.exit_flag = Some(255);
+            }
+        }
+        String::new()
+    }
+
+    /// ### process_substitution
+    /// 
+    /// If the word is a process substitution (`<(cmd)` or `>(cmd)`), start its commands and return the path of the fifo which replaces the word.
+    /// The commands are run as tasks, so functions, aliases and builtins are not resolved
+    fn process_substitution(&mut self, core: &mut ShellCore, word: &str) -> Result<Option<String>, ShellError> {
+        let input: bool = word.starts_with("<(");
+        if ! (input || word.starts_with(">(")) || ! word.ends_with(')') {
+            return Ok(None)
+        }
+        let expression: ShellExpression = core.parse(&String::from(&word[2..word.len() - 1])).map_err(ShellError::Parser)?;
+        let mut statements: Vec<(Task, TaskRelation)> = Vec::with_capacity(expression.statements.len());
+        for (statement, relation) in expression.statements.into_iter() {
+            let mut task: Task = match statement {
+                ShellStatement::Exec(task) => task,
+                _ => return Err(ShellError::BadValue(format!("{}: process substitutions support only commands", word)))
+            };
+            //Expand the words of each task in the pipeline
+            let mut block: Option<&mut Task> = Some(&mut task);
+            while let Some(current) = block {
+                let mut words: Vec<String> = Vec::with_capacity(current.command.len());
+                for arg in current.command.iter() {
+                    words.extend(self.expand_word(core, arg.as_str())?);
+                }
+                current.command = words;
+                for value in current.env.values_mut() {
+                    *value = self.eval_assignment(core, value.to_string());
+                }
+                for (key, value) in core.environ_overridden().into_iter() {
+                    current.env.entry(key).or_insert(value);
+                }
+                block = current.next.as_deref_mut();
+            }
+            statements.push((task, relation));
+        }
+        let substitution: Substitution = Substitution::start(statements, input, core.sstream.sender.clone())?;
+        let fifo: String = substitution.fifo.display().to_string();
+        self.substitutions.push(substitution);
+        Ok(Some(fifo))
+    }
+
+    /// ### close_substitutions
+    /// 
+    /// Close the process substitutions started after the first `first` ones, once the command which consumed them has terminated
+    fn close_substitutions(&mut self, first: usize) {
+        for substitution in self.substitutions.split_off(first).into_iter() {
+            substitution.close();
+        }
+    }
+
+    /// ### funcnest_limit
+    /// 
+    /// Returns the maximum function nesting level: FUNCNEST if it's set (0 means no limit), otherwise the default one
+    fn funcnest_limit(&self, core: &ShellCore) -> usize {
+        match core.value_get(&String::from("FUNCNEST")).map(|x| x.parse::<usize>()) {
+            Some(Ok(0)) => usize::MAX,
+            Some(Ok(limit)) => limit,
+            _ => FUNCNEST_DEFAULT
+        }
+    }
+
+    /// ### send_output
+    /// 
+    /// Send the stdout and stderr lines to the user, if any
+    fn send_output(&mut self, core: &mut ShellCore, stdout: Vec<String>, stderr: Vec<String>) {
+        let stdout: Option<String> = if stdout.is_empty() { None } else { Some(stdout.join("\n")) };
+        let stderr: Option<String> = if stderr.is_empty() { None } else { Some(stderr.join("\n")) };
+        if (stdout.is_some() || stderr.is_some()) && ! core.sstream.send(ShellStreamMessage::Output((stdout, stderr))) {
+            self.exit_flag = Some(255);
+        }
+    }
+
+    /// ### trap_signal_name
+    /// 
+    /// Returns the name of the trap signal, as displayed by `trap -p`
+    fn trap_signal_name(&self, signal: &TrapSignal) -> String {
+        match signal {
+            TrapSignal::Exit => String::from("EXIT"),
+            TrapSignal::Err => String::from("ERR"),
+            TrapSignal::Debug => String::from("DEBUG"),
+            TrapSignal::Return => String::from("RETURN"),
+            TrapSignal::Signal(sig) => sig.name()
+        }
+    }
+
+    /// ### trap_signal_order
+    /// 
+    /// Returns the position of the trap signal in `trap -p`: EXIT, the signals by number, then DEBUG, ERR and RETURN
+    fn trap_signal_order(&self, signal: &TrapSignal) -> u8 {
+        match signal {
+            TrapSignal::Exit => 0,
+            TrapSignal::Signal(sig) => *sig as u8,
+            TrapSignal::Debug => 32,
+            TrapSignal::Err => 33,
+            TrapSignal::Return => 34
+        }
+    }
+
     /// ### is_relation_satisfied
     /// 
     /// Checks whether a relation between two task is satisfied
@@ -1229,10 +2849,11 @@ impl TaskChain {
     /// ### new
     /// 
     /// Instantiates a new TaskChain. This must be called for the first element only
-    pub(self) fn new(task: Option<Task>, function: Option<Function>, prev_relation: TaskRelation) -> TaskChain {
+    pub(self) fn new(task: Option<Task>, function: Option<Function>, builtin: Option<Builtin>, prev_relation: TaskRelation) -> TaskChain {
         TaskChain {
             task: task,
             function: function,
+            builtin,
             prev_relation: prev_relation,
             next_relation: TaskRelation::Unrelated,
             next: None
@@ -1242,15 +2863,305 @@ impl TaskChain {
     /// ### chain
     /// 
     /// Chain a Task to the back current one
-    pub(self) fn chain(&mut self, next_task: Option<Task>, next_function: Option<Function>, relation: TaskRelation) {
+    pub(self) fn chain(&mut self, next_task: Option<Task>, next_function: Option<Function>, next_builtin: Option<Builtin>, relation: TaskRelation) {
         //If next is None, set Next as new Task, otherwise pass new task to the next of the next etc...
         match &mut self.next {
             None => self.next = {
                 //Set current relation to relation
                 self.next_relation = relation;
-                Some(Box::new(TaskChain::new(next_task, next_function, self.next_relation)))
+                Some(Box::new(TaskChain::new(next_task, next_function, next_builtin, self.next_relation)))
+            },
+            Some(next) => next.chain(next_task, next_function, next_builtin, relation)
+        }
+    }
+
+    /// ### set_last_relation
+    /// 
+    /// Set the relation of the last block of the chain with what comes after the chain
+    pub(self) fn set_last_relation(&mut self, relation: TaskRelation) {
+        match &mut self.next {
+            None => self.next_relation = relation,
+            Some(next) => next.set_last_relation(relation)
+        }
+    }
+}
+
+impl Substitution {
+
+    /// ### start
+    /// 
+    /// Create the fifo in the temporary directory and start the worker thread of the process substitution
+    pub(self) fn start(statements: Vec<(Task, TaskRelation)>, input: bool, sender: mpsc::Sender<ShellStreamMessage>) -> Result<Substitution, ShellError> {
+        let fifo: PathBuf = env::temp_dir().join(format!("shellcore-{}-{}", process::id(), FIFO_COUNT.fetch_add(1, Ordering::Relaxed)));
+        if mkfifo(&fifo, Mode::S_IRUSR | Mode::S_IWUSR).is_err() {
+            return Err(ShellError::PermissionDenied(fifo))
+        }
+        let opened: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+        let stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+        let processes: Arc<Mutex<Vec<u32>>> = Arc::new(Mutex::new(Vec::new()));
+        let worker: JoinHandle<()> = {
+            let (fifo, opened, stop, processes) = (fifo.clone(), opened.clone(), stop.clone(), processes.clone());
+            thread::spawn(move || Substitution::work(fifo, input, statements, opened, stop, processes, sender))
+        };
+        Ok(Substitution {
+            fifo,
+            input,
+            opened,
+            stop,
+            processes,
+            worker
+        })
+    }
+
+    /// ### close
+    /// 
+    /// Terminate the process substitution and remove its fifo.
+    /// The commands of `<(cmd)` are terminated, while the commands of `>(cmd)` get the end of their input and are waited for
+    pub(self) fn close(self) {
+        {
+            let processes = self.processes.lock().unwrap();
+            self.stop.store(true, Ordering::SeqCst);
+            if self.input {
+                for pid in processes.iter() {
+                    let _ = kill(Pid::from_raw(*pid as i32), Signal::SIGTERM);
+                }
+            }
+        }
+        //If the consumer has never opened the fifo, the worker is (or is about to be) waiting for it: open the other end without blocking to wake it up
+        while ! self.opened.load(Ordering::SeqCst) && ! self.worker.is_finished() {
+            let _ = match self.input {
+                true => OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(self.fifo.as_path()),
+                false => OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK).open(self.fifo.as_path())
+            };
+            sleep(Duration::from_millis(10));
+        }
+        let _ = self.worker.join();
+        let _ = fs::remove_file(self.fifo.as_path());
+    }
+
+    /// ### work
+    /// 
+    /// Worker thread of the process substitution: wait for the consumer to open the fifo, then run the statements in sequence with the fifo as stdout (`<(cmd)`) or stdin (`>(cmd)`).
+    /// The stderr of the commands, and the stdout of the `>(cmd)` ones, are sent to the shell stream
+    fn work(fifo: PathBuf, input: bool, statements: Vec<(Task, TaskRelation)>, opened: Arc<AtomicBool>, stop: Arc<AtomicBool>, processes: Arc<Mutex<Vec<u32>>>, sender: mpsc::Sender<ShellStreamMessage>) {
+        let pipe: std::io::Result<File> = match input {
+            true => OpenOptions::new().write(true).open(fifo.as_path()),
+            false => OpenOptions::new().read(true).open(fifo.as_path())
+        };
+        opened.store(true, Ordering::SeqCst);
+        let pipe: File = match pipe {
+            Ok(pipe) if ! stop.load(Ordering::SeqCst) => pipe,
+            _ => return
+        };
+        let (stdout, stdout_forward): (File, Option<JoinHandle<()>>) = match input {
+            true => match pipe.try_clone() {
+                Ok(stdout) => (stdout, None),
+                Err(_) => return
             },
-            Some(next) => next.chain(next_task, next_function, relation)
+            false => match Substitution::forward(sender.clone(), true) {
+                Some((stdout, forward)) => (stdout, Some(forward)),
+                None => return
+            }
+        };
+        let (stderr, stderr_forward): (File, JoinHandle<()>) = match Substitution::forward(sender, false) {
+            Some(forward) => forward,
+            None => return
+        };
+        let stdin: Option<&File> = match input {
+            true => None,
+            false => Some(&pipe)
+        };
+        let mut rc: u8 = 0;
+        let mut relation: TaskRelation = TaskRelation::Unrelated; //Relation with the previous statement
+        for (task, next_relation) in statements.into_iter() {
+            rc = match relation {
+                TaskRelation::And if rc != 0 => rc,
+                TaskRelation::Or if rc == 0 => rc,
+                _ => Substitution::run_task(task, stdin, &stdout, &stderr, &stop, &processes)
+            };
+            relation = next_relation;
+        }
+        drop(pipe);
+        drop(stdout);
+        drop(stderr);
+        if let Some(forward) = stdout_forward {
+            let _ = forward.join();
+        }
+        let _ = stderr_forward.join();
+    }
+
+    /// ### run_task
+    /// 
+    /// Run the tasks of a statement, honouring the relations between them (pipes, `&&`, `||`). Returns the exit code of the last task run
+    fn run_task(task: Task, stdin: Option<&File>, stdout: &File, stderr: &File, stop: &AtomicBool, processes: &Mutex<Vec<u32>>) -> u8 {
+        let mut rc: u8 = 0;
+        let mut relation: TaskRelation = TaskRelation::Unrelated; //Relation with the previous pipeline
+        let mut next: Option<Task> = Some(task);
+        while let Some(mut task) = next.take() {
+            //Collect the pipeline
+            let mut pipeline: Vec<Task> = Vec::new();
+            let next_relation: TaskRelation = loop {
+                let following: Option<Box<Task>> = task.next.take();
+                let task_relation: TaskRelation = task.relation;
+                pipeline.push(task);
+                match (task_relation, following) {
+                    (TaskRelation::Pipe, Some(following)) => task = *following,
+                    (task_relation, following) => {
+                        next = following.map(|x| *x);
+                        break task_relation
+                    }
+                }
+            };
+            rc = match relation {
+                TaskRelation::And if rc != 0 => rc,
+                TaskRelation::Or if rc == 0 => rc,
+                _ => Substitution::run_pipeline(pipeline, stdin, stdout, stderr, stop, processes)
+            };
+            relation = next_relation;
+        }
+        rc
+    }
+
+    /// ### run_pipeline
+    /// 
+    /// Run a pipeline and wait for it to terminate. Returns the exit code of the last command
+    fn run_pipeline(pipeline: Vec<Task>, stdin: Option<&File>, stdout: &File, stderr: &File, stop: &AtomicBool, processes: &Mutex<Vec<u32>>) -> u8 {
+        let mut commands: Vec<Exec> = Vec::with_capacity(pipeline.len());
+        for task in pipeline.iter() {
+            let env: Vec<(&String, &String)> = task.env.iter().collect();
+            let errors: std::io::Result<File> = match &task.stderr_redirection {
+                Redirection::File(file, mode) => Substitution::open_file(file.as_str(), mode),
+                _ => stderr.try_clone()
+            };
+            match errors {
+                Ok(errors) => commands.push(Exec::cmd(task.command[0].as_str()).args(&task.command[1..]).env_extend(&env).stderr(errors)),
+                Err(err) => {
+                    let _ = writeln!(&*stderr, "bash: {}", err);
+                    return 1
+                }
+            }
+        }
+        //The pipeline reads the fifo (`>(cmd)`) or nothing, and writes to the fifo (`<(cmd)`) or to the shell stream, unless redirected
+        let input: Option<File> = stdin.and_then(|x| x.try_clone().ok());
+        let output: std::io::Result<File> = match &pipeline[pipeline.len() - 1].stdout_redirection {
+            Redirection::File(file, mode) => Substitution::open_file(file.as_str(), mode),
+            _ => stdout.try_clone()
+        };
+        let output: File = match output {
+            Ok(output) => output,
+            Err(err) => {
+                let _ = writeln!(&*stderr, "bash: {}", err);
+                return 1
+            }
+        };
+        //Start the commands, unless the consumer has already terminated
+        let mut popens: Vec<Popen> = {
+            let mut processes = processes.lock().unwrap();
+            if stop.load(Ordering::SeqCst) {
+                return 1
+            }
+            let result: Result<Vec<Popen>, PopenError> = match commands.len() {
+                1 => {
+                    let command: Exec = commands.pop().unwrap().stdout(output);
+                    match input {
+                        Some(input) => command.stdin(input),
+                        None => command.stdin(NullFile)
+                    }.popen().map(|x| vec![x])
+                },
+                _ => {
+                    let pipeline: Pipeline = Pipeline::from_exec_iter(commands).stdout(output);
+                    match input {
+                        Some(input) => pipeline.stdin(input),
+                        None => pipeline.stdin(NullFile)
+                    }.popen()
+                }
+            };
+            match result {
+                Ok(popens) => {
+                    processes.extend(popens.iter().filter_map(|x| x.pid()));
+                    popens
+                },
+                Err(PopenError::IoError(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                    let _ = writeln!(&*stderr, "bash: {}: command not found", pipeline[0].command[0]);
+                    return 127
+                },
+                Err(err) => {
+                    let _ = writeln!(&*stderr, "bash: {}: {}", pipeline[0].command[0], err);
+                    return 126
+                }
+            }
+        };
+        let mut rc: u8 = 0;
+        for popen in popens.iter_mut() {
+            let pid: Option<u32> = popen.pid();
+            rc = match popen.wait() {
+                Ok(ExitStatus::Exited(code)) => code as u8,
+                Ok(ExitStatus::Signaled(signal)) => 128 + signal,
+                _ => 1
+            };
+            //Once waited for, the pid can be reused
+            if let Some(pid) = pid {
+                processes.lock().unwrap().retain(|x| *x != pid);
+            }
+        }
+        rc
+    }
+
+    /// ### open_file
+    /// 
+    /// Open the file of a redirection of the commands
+    fn open_file(file: &str, mode: &FileRedirectionType) -> std::io::Result<File> {
+        OpenOptions::new().create(true).write(true).append(*mode == FileRedirectionType::Append).truncate(*mode == FileRedirectionType::Truncate).open(file)
+    }
+
+    /// ### forward
+    /// 
+    /// Create a pipe whose content is sent to the shell stream as stdout or stderr by another thread.
+    /// Returns the write end of the pipe and the forwarding thread, which terminates once all the write ends have been closed
+    fn forward(sender: mpsc::Sender<ShellStreamMessage>, stdout: bool) -> Option<(File, JoinHandle<()>)> {
+        let (read_fd, write_fd) = pipe2(OFlag::O_CLOEXEC).ok()?;
+        //The descriptors have just been created and are owned by the files only
+        let (mut reader, writer): (File, File) = unsafe { (File::from_raw_fd(read_fd), File::from_raw_fd(write_fd)) };
+        let forward: JoinHandle<()> = thread::spawn(move || {
+            let mut buffer: [u8; 4096] = [0; 4096];
+            let mut pending: Vec<u8> = Vec::new();
+            loop {
+                let bytes: usize = match reader.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(bytes) => bytes
+                };
+                pending.extend_from_slice(&buffer[..bytes]);
+                //Keep a truncated UTF-8 sequence for the next read
+                let valid: usize = match std::str::from_utf8(pending.as_slice()) {
+                    Err(err) if err.error_len().is_none() => err.valid_up_to(),
+                    _ => pending.len()
+                };
+                let text: String = String::from_utf8_lossy(&pending[..valid]).to_string();
+                pending.drain(..valid);
+                let message: ShellStreamMessage = match stdout {
+                    true => ShellStreamMessage::Output((Some(text), None)),
+                    false => ShellStreamMessage::Output((None, Some(text)))
+                };
+                if valid > 0 && sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Some((writer, forward))
+    }
+}
+
+impl Builtin {
+
+    /// ### new
+    /// 
+    /// Instantiate a new Builtin
+    pub(self) fn new(args: Vec<String>, redirection: Redirection, stderr_redirection: Redirection, env: HashMap<String, String>) -> Builtin {
+        Builtin {
+            args,
+            redirection,
+            stderr_redirection,
+            env
         }
     }
 }
@@ -1260,11 +3171,12 @@ impl Function {
     /// ### new
     /// 
     /// Instantiate a new Function
-    pub(self) fn new(expression: ShellExpression, args: Vec<String>, redirection: Redirection) -> Function {
+    pub(self) fn new(expression: ShellExpression, args: Vec<String>, redirection: Redirection, env: HashMap<String, String>) -> Function {
         Function {
-            expression: expression,
-            args: args,
-            redirection: redirection
+            expression,
+            args,
+            redirection,
+            env
         }
     }
 }
@@ -1276,6 +3188,7 @@ mod tests {
 
     use super::*;
     use crate::parsers::bash::Bash;
+    use crate::ParseStatement;
     use crate::ShellStatement;
     use crate::UserStream;
     use crate::UnixSignal;
@@ -1411,10 +3324,10 @@ mod tests {
     fn test_runner_change_directory() {
         let mut runner: ShellRunner = ShellRunner::new();
         let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
-        assert_eq!(runner.change_directory(&mut core, PathBuf::from("/tmp/")), 0);
+        assert_eq!(runner.change_directory(&mut core, PathBuf::from("/tmp/"), false), 0);
         assert_eq!(core.get_wrkdir(), PathBuf::from("/tmp/"));
         //Try to change directory to not existing path
-        assert_eq!(runner.change_directory(&mut core, PathBuf::from("/onett/")), 1);
+        assert_eq!(runner.change_directory(&mut core, PathBuf::from("/onett/"), false), 1);
         //Directory shouldn't have changed
         assert_eq!(core.get_wrkdir(), PathBuf::from("/tmp/"));
         //Verify we received an error
@@ -1425,18 +3338,58 @@ mod tests {
         }
         //Drop ustream and change directory
         drop(ustream);
-        assert_eq!(runner.change_directory(&mut core, PathBuf::from("/onett/")), 1);
+        assert_eq!(runner.change_directory(&mut core, PathBuf::from("/onett/"), false), 1);
         assert!(runner.exit_flag.is_some());
     }
 
+    #[test]
+    fn test_runner_cd() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(Some(PathBuf::from("/")), 128, Box::new(Bash {}));
+        //Simple cd doesn't print anything
+        assert_eq!(runner.cd(&mut core, PathBuf::from("/tmp"), false), 0);
+        assert_eq!(core.get_wrkdir(), PathBuf::from("/tmp"));
+        assert_eq!(ustream.receive().unwrap().len(), 0);
+        //cd - goes to the previous directory and prints it
+        assert_eq!(runner.cd(&mut core, PathBuf::from("-"), false), 0);
+        assert_eq!(core.get_wrkdir(), PathBuf::from("/"));
+        assert_eq!(core.get_prev_dir(), PathBuf::from("/tmp"));
+        match &ustream.receive().unwrap()[0] {
+            ShellStreamMessage::Output((stdout, _)) => assert_eq!(*stdout.as_ref().unwrap(), String::from("/\n")),
+            _ => panic!("Not an output")
+        }
+        //cd with CDPATH
+        let tmpdir: tempfile::TempDir = create_tmp_dir();
+        let root: PathBuf = tmpdir.path().canonicalize().unwrap();
+        let mut subdir: PathBuf = root.clone();
+        subdir.push("cdpath_subdir");
+        assert!(std::fs::create_dir(subdir.as_path()).is_ok());
+        core.storage_set(String::from("CDPATH"), String::from(root.to_str().unwrap()));
+        assert_eq!(runner.cd(&mut core, PathBuf::from("cdpath_subdir"), false), 0);
+        assert_eq!(core.get_wrkdir(), subdir);
+        match &ustream.receive().unwrap()[0] {
+            ShellStreamMessage::Output((stdout, _)) => assert_eq!(*stdout.as_ref().unwrap(), format!("{}\n", subdir.display())),
+            _ => panic!("Not an output")
+        }
+        //Directories found through an empty entry (the current directory) are not printed
+        core.storage_set(String::from("CDPATH"), format!(":{}", root.display()));
+        assert_eq!(runner.cd(&mut core, root.clone(), false), 0);
+        assert_eq!(runner.cd(&mut core, PathBuf::from("cdpath_subdir"), false), 0);
+        assert_eq!(core.get_wrkdir(), subdir);
+        assert!(ustream.receive().unwrap().is_empty());
+        //Error
+        assert_eq!(runner.cd(&mut core, PathBuf::from("pippoland"), false), 1);
+        assert_eq!(core.get_wrkdir(), subdir);
+    }
+
     #[test]
     fn test_runner_chain_task() {
         let mut runner: ShellRunner = ShellRunner::new();
         let (mut core, _): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
         //Simple task
-        let command: Vec<String> = vec![String::from("echo"), String::from("foo")];
+        let command: Vec<String> = vec![String::from("/bin/echo"), String::from("foo")];
         let mut sample_task: Task = Task::new(command, Redirection::Stdout, Redirection::Stderr);
-        let command: Vec<String> = vec![String::from("echo"), String::from("bar")];
+        let command: Vec<String> = vec![String::from("/bin/echo"), String::from("bar")];
         sample_task.new_pipeline(
             command,
             Redirection::Stdout,
@@ -1444,7 +3397,7 @@ mod tests {
             TaskRelation::Unrelated,
         );
         //Chain task
-        let chain: TaskChain = runner.chain_task(&mut core, sample_task);
+        let chain: TaskChain = runner.chain_task(&mut core, sample_task, false).unwrap();
         assert!(chain.task.is_some());
         assert!(chain.function.is_none());
         assert_eq!(chain.next_relation, TaskRelation::Unrelated);
@@ -1455,10 +3408,10 @@ mod tests {
         let expression: ShellExpression = ShellExpression {
             statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]
         };
-        runner.function(&mut core, String::from("myfunc"), expression);
-        let command: Vec<String> = vec![String::from("echo"), String::from("foo")];
+        runner.function(&mut core, String::from("myfunc"), expression, String::from("{ return 0; }"));
+        let command: Vec<String> = vec![String::from("/bin/echo"), String::from("foo")];
         let mut sample_task: Task = Task::new(command, Redirection::Stdout, Redirection::Stderr);
-        let command: Vec<String> = vec![String::from("echo"), String::from("bar")];
+        let command: Vec<String> = vec![String::from("/bin/echo"), String::from("bar")];
         sample_task.new_pipeline(
             command,
             Redirection::Stdout,
@@ -1486,7 +3439,7 @@ mod tests {
             Redirection::Stderr,
             TaskRelation::Pipe,
         );
-        let chain: TaskChain = runner.chain_task(&mut core, sample_task);
+        let chain: TaskChain = runner.chain_task(&mut core, sample_task, false).unwrap();
         //Let's see if it's correct
         assert!(chain.task.is_some());
         assert!(chain.task.clone().unwrap().next.is_some());
@@ -1510,6 +3463,9 @@ mod tests {
         assert!(chain.task.is_some());
         assert_eq!(chain.task.as_ref().unwrap().command[0], String::from("cat"));
         assert!(chain.task.as_ref().unwrap().next.is_some());
+        //Executables are resolved for each task in the block
+        assert_eq!(chain.task.as_ref().unwrap().executable, core.path_lookup("cat"));
+        assert_eq!(chain.task.as_ref().unwrap().next.as_ref().unwrap().executable, core.path_lookup("head"));
         assert!(chain.function.is_none());
         assert_eq!(chain.next_relation, TaskRelation::Unrelated);
         assert_eq!(chain.prev_relation, TaskRelation::Or);
@@ -1519,13 +3475,13 @@ mod tests {
         let mut runner: ShellRunner = ShellRunner::new();
         let (mut core, _): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
         //Simple task
-        let command: Vec<String> = vec![String::from("echo"), String::from("foo")];
+        let command: Vec<String> = vec![String::from("/bin/echo"), String::from("foo")];
         let mut sample_task: Task = Task::new(command, Redirection::Stdout, Redirection::Stderr);
         //Add a function to runner
         let expression: ShellExpression = ShellExpression {
             statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]
         };
-        runner.function(&mut core, String::from("myfunc"), expression);
+        runner.function(&mut core, String::from("myfunc"), expression, String::from("{ return 0; }"));
         let command: Vec<String> = vec![String::from("myfunc"), String::from("bar")];
         sample_task.new_pipeline(
             command,
@@ -1533,7 +3489,7 @@ mod tests {
             Redirection::Stderr,
             TaskRelation::And, //and between echo2 and myfunc
         );
-        let chain: TaskChain = runner.chain_task(&mut core, sample_task);
+        let chain: TaskChain = runner.chain_task(&mut core, sample_task, false).unwrap();
         //Let's see if it's correct
         assert!(chain.task.is_some());
         assert!(chain.task.unwrap().next.is_none());
@@ -1577,6 +3533,62 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_runner_exec_builtin() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        let parser: Bash = Bash {};
+        //Echo
+        let expression: ShellExpression = parser.parse(&core, &String::from("echo -n \"foo  bar\"")).unwrap();
+        assert_eq!(runner.run(&mut core, expression), 0);
+        let inbox: Vec<ShellStreamMessage> = ustream.receive().unwrap();
+        assert_eq!(inbox.len(), 1);
+        if let ShellStreamMessage::Output((stdout, stderr)) = &inbox[0] {
+            assert_eq!(*stdout, Some(String::from("foo  bar")));
+            assert!(stderr.is_none());
+        } else {
+            panic!("Not an output message");
+        }
+        //Printf into variable
+        let expression: ShellExpression = parser.parse(&core, &String::from("printf -v NUM '%05.1f|%s' 3.14159 $HOSTNAME_NOT_SET")).unwrap();
+        assert_eq!(runner.run(&mut core, expression), 0);
+        assert_eq!(core.value_get(&String::from("NUM")).unwrap(), String::from("003.1|"));
+        assert!(ustream.receive().unwrap().is_empty());
+        //Redirection to file
+        let tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        let expression: ShellExpression = parser.parse(&core, &format!("printf '%s\\n' a b > {} && echo -e 'c\\td' >> {}", tmpfile.path().display(), tmpfile.path().display())).unwrap();
+        assert_eq!(runner.run(&mut core, expression), 0);
+        assert!(ustream.receive().unwrap().is_empty());
+        assert_eq!(std::fs::read_to_string(tmpfile.path()).unwrap(), String::from("a\nb\nc\td\n"));
+        //Pipeline
+        let (rc, out): (u8, String) = runner.exec(&mut core, Task::new(vec![String::from("printf"), String::from("'%s\\n'"), String::from("first"), String::from("second")], Redirection::Stdout, Redirection::Stderr));
+        assert_eq!(rc, 0);
+        assert_eq!(out, String::from("first\nsecond"));
+        let _ = ustream.receive();
+        let mut task: Task = Task::new(vec![String::from("printf"), String::from("'%s\\n'"), String::from("first"), String::from("second")], Redirection::Stdout, Redirection::Stderr);
+        task.new_pipeline(vec![String::from("head"), String::from("-n"), String::from("1")], Redirection::Stdout, Redirection::Stderr, TaskRelation::Pipe);
+        let (rc, out): (u8, String) = runner.exec(&mut core, task);
+        assert_eq!(rc, 0);
+        assert_eq!(out, String::from("first"));
+        let _ = ustream.receive();
+        //Errors
+        let (rc, out): (u8, String) = runner.exec(&mut core, Task::new(vec![String::from("printf"), String::from("%d")], Redirection::Stdout, Redirection::Stderr));
+        assert_eq!(rc, 0);
+        assert_eq!(out, String::from("0"));
+        let _ = ustream.receive();
+        let (rc, _): (u8, String) = runner.exec(&mut core, Task::new(vec![String::from("printf"), String::from("%d"), String::from("foo")], Redirection::Stdout, Redirection::Stderr));
+        assert_eq!(rc, 1);
+        let (rc, _): (u8, String) = runner.exec(&mut core, Task::new(vec![String::from("printf")], Redirection::Stdout, Redirection::Stderr));
+        assert_eq!(rc, 2);
+        //Functions come before builtins
+        let myfunc: ShellExpression = ShellExpression {
+            statements: vec![(ShellStatement::Return(7), TaskRelation::Unrelated)]
+        };
+        assert!(core.function_set(String::from("echo"), myfunc));
+        let (rc, _): (u8, String) = runner.exec(&mut core, Task::new(vec![String::from("echo"), String::from("foo")], Redirection::Stdout, Redirection::Stderr));
+        assert_eq!(rc, 7);
+    }
+
     #[test]
     fn test_runner_exec_task_and_func() {
         let mut runner: ShellRunner = ShellRunner::new();
@@ -1865,14 +3877,14 @@ mod tests {
         let mut runner: ShellRunner = ShellRunner::new();
         let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
         //Define a function which echoes the first provided argument
-        let echo_task: Task = Task::new(vec![String::from("echo"), String::from("$1")], Redirection::Stdout, Redirection::Stderr);
+        let echo_task: Task = Task::new(vec![String::from("/bin/echo"), String::from("$1")], Redirection::Stdout, Redirection::Stderr);
         let myfunc: ShellExpression = ShellExpression {
             statements: vec![(ShellStatement::Exec(echo_task), TaskRelation::Unrelated)]
         };
         //Save function
         assert_eq!(core.function_set(String::from("myecho"), myfunc), true);
         //Prepare task to exec
-        let mut task: Task = Task::new(vec![String::from("echo"), String::from("FOOBAR")], Redirection::Stdout, Redirection::Stderr);
+        let mut task: Task = Task::new(vec![String::from("/bin/echo"), String::from("FOOBAR")], Redirection::Stdout, Redirection::Stderr);
         //Chain myecho
         task.new_pipeline(vec![String::from("myecho"), String::from("HI")], Redirection::Stdout, Redirection::Stderr, TaskRelation::And);
         //Prepare Signal
@@ -1946,6 +3958,13 @@ mod tests {
         //Print
         assert_eq!(runner.history(&mut core, HistoryOptions::Print), 0);
         assert_eq!(ustream.receive().unwrap().len(), 1);
+        //Print with times
+        assert!(core.storage_set(String::from("HISTTIMEFORMAT"), String::from("[%s] ")));
+        assert_eq!(runner.history(&mut core, HistoryOptions::Print), 0);
+        match ustream.receive().unwrap().pop() {
+            Some(ShellStreamMessage::Output((Some(stdout), _))) => assert_eq!(stdout, format!("0 [{}] echo bar\n", core.history_entry_at(0).unwrap().timestamp)),
+            _ => panic!("Not an output")
+        }
         //Write
         core.history_push(String::from("echo cac"));
         let tmpfile = create_tmpfile();
@@ -1995,7 +4014,7 @@ mod tests {
         let mut runner: ShellRunner = ShellRunner::new();
         let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
         //Prepare task to exec
-        let task: Task = Task::new(vec![String::from("echo"), String::from("HELLO"), String::from("WORLD")], Redirection::Stdout, Redirection::Stderr);
+        let task: Task = Task::new(vec![String::from("/bin/echo"), String::from("HELLO"), String::from("WORLD")], Redirection::Stdout, Redirection::Stderr);
         //Exec task
         let (rc, out): (u8, String) = runner.exec_time(&mut core, task);
         assert_eq!(rc, 0);
@@ -2052,6 +4071,20 @@ mod tests {
         assert_eq!(runner.export(&mut core, String::from("RESULT"), expression), 0);
         //Verify value is exported
         assert_eq!(core.value_get(&String::from("RESULT")).unwrap(), String::from("5"));
+        //Quotes are removed after the expansions
+        assert!(core.storage_set(String::from("WORD"), String::from("foo  bar")));
+        let commands: Vec<(&str, &str)> = vec![
+            ("export QUOTED='$WORD'", "$WORD"),
+            ("export QUOTED=\"$WORD\"", "foo  bar"),
+            ("export QUOTED=$WORD'*'", "foo  bar*"),
+            ("declare QUOTED='$WORD'\\$", "$WORD$"),
+            ("QUOTED='$WORD' ; export QUOTED", "$WORD")
+        ];
+        for (command, value) in commands.into_iter() {
+            let expression: ShellExpression = core.parse(&String::from(command)).unwrap();
+            assert_eq!(runner.run_expression(&mut core, expression).0, 0);
+            assert_eq!(core.value_get(&String::from("QUOTED")).unwrap(), String::from(value));
+        }
     }
     
     #[test]
@@ -2101,8 +4134,37 @@ mod tests {
         let foreach_perform: ShellExpression = ShellExpression {
             statements: vec![(ShellStatement::Exec(foreach_task), TaskRelation::Unrelated)]
         };
-        //Must be None since there's no file in it
+        //The pattern is kept if nothing matches
+        assert_eq!(runner.foreach(&mut core, String::from("FILE"), iterator.clone(), foreach_perform.clone()).unwrap(), 0);
+        assert_eq!(core.value_get(&String::from("FILE")), None);
+        if let ShellStreamMessage::Output((stdout, _)) = &ustream.receive().unwrap()[0] {
+            assert_eq!(*stdout.as_ref().unwrap(), format!("{}\n", file_case));
+        } else {
+            panic!("Not an output message");
+        }
+        //Must be None with nullglob, since there's no file in it
+        let mut options: GlobOptions = core.get_glob_options();
+        options.nullglob = true;
+        core.set_glob_options(options);
         assert!(runner.foreach(&mut core, String::from("FILE"), iterator, foreach_perform).is_none());
+        //File names with blanks are single words
+        let tmpdir: tempfile::TempDir = create_tmp_dir();
+        assert!(std::fs::File::create(tmpdir.path().join("a b")).is_ok());
+        let iterator: ShellExpression = ShellExpression {
+            statements: vec![(ShellStatement::Value(format!("{}/*", tmpdir.path().display())), TaskRelation::Unrelated)]
+        };
+        let foreach_task: Task = Task::new(vec![String::from("echo"), String::from("\"$FILE\"")], Redirection::Stdout, Redirection::Stderr);
+        let foreach_perform: ShellExpression = ShellExpression {
+            statements: vec![(ShellStatement::Exec(foreach_task), TaskRelation::Unrelated)]
+        };
+        assert_eq!(runner.foreach(&mut core, String::from("FILE"), iterator, foreach_perform).unwrap(), 0);
+        let inbox: Vec<ShellStreamMessage> = ustream.receive().unwrap();
+        assert_eq!(inbox.len(), 1);
+        if let ShellStreamMessage::Output((stdout, _)) = &inbox[0] {
+            assert_eq!(*stdout.as_ref().unwrap(), format!("{}/a b\n", tmpdir.path().display()));
+        } else {
+            panic!("Not an output message");
+        }
         //Foreach in not existing directory
         let iterator: ShellExpression = ShellExpression {
             statements: vec![(ShellStatement::Value(String::from("/tmp/thisdirectorydoesnotexist/*")), TaskRelation::Unrelated)]
@@ -2428,7 +4490,8 @@ mod tests {
         //Send input before read, otherwise will block
         assert!(ustream.send(UserStreamMessage::Input(String::from("HI_THERE"))));
         //Read
-        assert_eq!(runner.read(&mut core, Some(String::from("type something")), Some(5), Some(String::from("OUTPUT"))), 0);
+        let options: ReadOptions = ReadOptions { prompt: Some(String::from("type something")), length: Some(5), names: vec![String::from("OUTPUT")], ..ReadOptions::new() };
+        assert_eq!(runner.read(&mut core, options), 0);
         //Prompt is shown
         if let ShellStreamMessage::Output((stdout, _)) = &ustream.receive().unwrap()[0] {
             //Must be prompt
@@ -2440,7 +4503,7 @@ mod tests {
         assert_eq!(core.value_get(&String::from("OUTPUT")).unwrap(), String::from("HI_TH")); //Max size is 5, do you remember?
         //Let's try without option now
         assert!(ustream.send(UserStreamMessage::Input(String::from("HI_THERE"))));
-        runner.read(&mut core, None, None, None);
+        runner.read(&mut core, ReadOptions::new());
         //Prompt is shown
         if let ShellStreamMessage::Output((stdout, _)) = &ustream.receive().unwrap()[0] {
             //Must be prompt
@@ -2449,18 +4512,124 @@ mod tests {
             panic!("Not an output");
         }
         assert_eq!(core.value_get(&String::from("REPLY")).unwrap(), String::from("HI_THERE")); //This time will be stored in reply
+        //Length is in characters
+        assert!(ustream.send(UserStreamMessage::Input(String::from("héllo"))));
+        assert_eq!(runner.read(&mut core, ReadOptions { length: Some(2), ..ReadOptions::new() }), 0);
+        assert_eq!(core.value_get(&String::from("REPLY")).unwrap(), String::from("hé"));
+        assert!(ustream.send(UserStreamMessage::Input(String::from("hi"))));
+        assert_eq!(runner.read(&mut core, ReadOptions { length: Some(16), ..ReadOptions::new() }), 0);
+        assert_eq!(core.value_get(&String::from("REPLY")).unwrap(), String::from("hi"));
+        let _ = ustream.receive();
+        //Silent
+        assert!(ustream.send(UserStreamMessage::Input(String::from("secret"))));
+        assert_eq!(runner.read(&mut core, ReadOptions { silent: true, ..ReadOptions::new() }), 0);
+        let inbox: Vec<ShellStreamMessage> = ustream.receive().unwrap();
+        assert!(matches!(inbox[1], ShellStreamMessage::InputEcho(false)));
+        assert!(matches!(inbox[2], ShellStreamMessage::InputEcho(true)));
+        assert_eq!(core.value_get(&String::from("REPLY")).unwrap(), String::from("secret"));
+        //Timeout
+        let t_start: Instant = Instant::now();
+        assert_eq!(runner.read(&mut core, ReadOptions { timeout: Some(Duration::from_millis(200)), ..ReadOptions::new() }), 142);
+        assert!(t_start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(runner.read(&mut core, ReadOptions { timeout: Some(Duration::from_secs(0)), ..ReadOptions::new() }), 1);
+        assert!(ustream.send(UserStreamMessage::Input(String::from("available\n"))));
+        assert_eq!(runner.read(&mut core, ReadOptions { timeout: Some(Duration::from_secs(0)), ..ReadOptions::new() }), 0);
+        //Input is kept for the next read
+        assert_eq!(runner.read(&mut core, ReadOptions::new()), 0);
+        assert_eq!(core.value_get(&String::from("REPLY")).unwrap(), String::from("available"));
+        assert!(runner.buffer.is_none());
+        //Input files are checked too
+        let mut tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        let input: Option<String> = Some(tmpfile.path().display().to_string());
+        assert_eq!(runner.read(&mut core, ReadOptions { timeout: Some(Duration::from_secs(0)), input: input.clone(), ..ReadOptions::new() }), 1);
+        writeln!(tmpfile, "line").unwrap();
+        assert_eq!(runner.read(&mut core, ReadOptions { timeout: Some(Duration::from_secs(0)), input: input.clone(), ..ReadOptions::new() }), 0);
+        assert_eq!(runner.read(&mut core, ReadOptions { timeout: Some(Duration::from_secs(0)), input: Some(String::from("/tmp/thisfiledoesnotexist")), ..ReadOptions::new() }), 1);
+        let _ = ustream.receive();
+        let snapshot: ShellCoreSnapshot = core.snapshot();
+        core.set_input_redirection(File::open(tmpfile.path()).unwrap());
+        assert_eq!(runner.read(&mut core, ReadOptions { timeout: Some(Duration::from_secs(0)), ..ReadOptions::new() }), 0);
+        assert_eq!(runner.read(&mut core, ReadOptions::new()), 0);
+        assert_eq!(runner.read(&mut core, ReadOptions { timeout: Some(Duration::from_secs(0)), ..ReadOptions::new() }), 1);
+        core.restore(snapshot);
+        //Multiple names (from buffer)
+        runner.buffer = Some(String::from("  one two   three four  \nnext line\n"));
+        let names: Vec<String> = vec![String::from("A"), String::from("B"), String::from("C")];
+        assert_eq!(runner.read(&mut core, ReadOptions { names: names.clone(), ..ReadOptions::new() }), 0);
+        assert_eq!(core.value_get(&String::from("A")).unwrap(), String::from("one"));
+        assert_eq!(core.value_get(&String::from("B")).unwrap(), String::from("two"));
+        assert_eq!(core.value_get(&String::from("C")).unwrap(), String::from("three four"));
+        assert_eq!(runner.buffer.as_ref().unwrap(), "next line\n");
+        //Less words than names
+        assert_eq!(runner.read(&mut core, ReadOptions { names: names.clone(), ..ReadOptions::new() }), 0);
+        assert_eq!(core.value_get(&String::from("A")).unwrap(), String::from("next"));
+        assert_eq!(core.value_get(&String::from("B")).unwrap(), String::from("line"));
+        assert_eq!(core.value_get(&String::from("C")).unwrap(), String::from(""));
+        //End of file
+        assert!(runner.buffer.is_none());
+        runner.buffer = Some(String::from("no newline"));
+        assert_eq!(runner.read(&mut core, ReadOptions::new()), 1);
+        assert_eq!(core.value_get(&String::from("REPLY")).unwrap(), String::from("no newline"));
+        //IFS
+        core.storage_set(String::from("IFS"), String::from(":"));
+        runner.buffer = Some(String::from("a::b c:d\n"));
+        assert_eq!(runner.read(&mut core, ReadOptions { names: names.clone(), ..ReadOptions::new() }), 0);
+        assert_eq!(core.value_get(&String::from("A")).unwrap(), String::from("a"));
+        assert_eq!(core.value_get(&String::from("B")).unwrap(), String::from(""));
+        assert_eq!(core.value_get(&String::from("C")).unwrap(), String::from("b c:d"));
+        core.value_unset(&String::from("IFS"));
+        //Array
+        runner.buffer = Some(String::from("x y z\n"));
+        assert_eq!(runner.read(&mut core, ReadOptions { array: Some(String::from("ARR")), ..ReadOptions::new() }), 0);
+        assert_eq!(core.value_get(&String::from("ARR[0]")).unwrap(), String::from("x"));
+        assert_eq!(core.value_get(&String::from("ARR[2]")).unwrap(), String::from("z"));
+        //Backslashes
+        runner.buffer = Some(String::from("a\\ b c\\\nd\n"));
+        assert_eq!(runner.read(&mut core, ReadOptions { names: vec![String::from("A"), String::from("B")], ..ReadOptions::new() }), 0);
+        assert_eq!(core.value_get(&String::from("A")).unwrap(), String::from("a b"));
+        assert_eq!(core.value_get(&String::from("B")).unwrap(), String::from("cd"));
+        runner.buffer = Some(String::from("a\\ b c\\\nd\n"));
+        assert_eq!(runner.read(&mut core, ReadOptions { raw: true, ..ReadOptions::new() }), 0);
+        assert_eq!(core.value_get(&String::from("REPLY")).unwrap(), String::from("a\\ b c\\"));
+        //Delimiter
+        runner.buffer = Some(String::from("one\ntwo:three"));
+        assert_eq!(runner.read(&mut core, ReadOptions { delimiter: ':', ..ReadOptions::new() }), 0);
+        assert_eq!(core.value_get(&String::from("REPLY")).unwrap(), String::from("one\ntwo"));
+        runner.buffer = None;
+        //From file
+        let mut tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        write!(tmpfile, "first line\nsecond line\n").unwrap();
+        let file: String = String::from(tmpfile.path().to_str().unwrap());
+        assert_eq!(runner.read(&mut core, ReadOptions { input: Some(file), ..ReadOptions::new() }), 0);
+        assert_eq!(core.value_get(&String::from("REPLY")).unwrap(), String::from("first line"));
+        assert!(runner.buffer.is_none());
+        assert_eq!(runner.read(&mut core, ReadOptions { input: Some(String::from("/this/file/does/not/exist")), ..ReadOptions::new() }), 1);
+        let _ = ustream.receive();
+        //From pipe
+        let expression: ShellExpression = ShellExpression {
+            statements: vec![
+                (ShellStatement::Exec(Task::new(vec![String::from("echo"), String::from("piped words")], Redirection::Stdout, Redirection::Stderr)), TaskRelation::Pipe),
+                (ShellStatement::Read(ReadOptions { names: vec![String::from("FIRST"), String::from("SECOND")], ..ReadOptions::new() }), TaskRelation::Unrelated)
+            ]
+        };
+        assert_eq!(runner.run_expression(&mut core, expression).0, 0);
+        assert_eq!(core.value_get(&String::from("FIRST")).unwrap(), String::from("piped"));
+        assert_eq!(core.value_get(&String::from("SECOND")).unwrap(), String::from("words"));
+        assert!(runner.buffer.is_none());
+        //Nothing has been printed
+        assert!(ustream.receive().unwrap().is_empty());
         //Let's try terminate, kill and other stuff
         core.value_unset(&String::from("REPLY"));
         assert!(ustream.send(UserStreamMessage::Kill));
-        assert_eq!(runner.read(&mut core, None, None, None), 1);
+        assert_eq!(runner.read(&mut core, ReadOptions::new()), 1);
         //Nothing to display
         assert!(core.value_get(&String::from("REPLY")).is_none());
         assert!(ustream.send(UserStreamMessage::Interrupt));
-        assert_eq!(runner.read(&mut core, None, None, None), 1);
+        assert_eq!(runner.read(&mut core, ReadOptions::new()), 1);
         //Nothing to display
         assert!(core.value_get(&String::from("REPLY")).is_none());
         assert!(ustream.send(UserStreamMessage::Signal(UnixSignal::Sigint)));
-        assert_eq!(runner.read(&mut core, None, None, None), 1);
+        assert_eq!(runner.read(&mut core, ReadOptions::new()), 1);
         //Nothing to display
         assert!(core.value_get(&String::from("REPLY")).is_none());
     }
@@ -2491,6 +4660,45 @@ mod tests {
 
     //TODO: source (requires readline)
 
+    #[test]
+    fn test_runner_with_env() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, _ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        core.storage_set(String::from("WITHENV_SOURCE"), String::from("BAR"));
+        //Builtin (export copies the overridden value into another variable)
+        let mut env: HashMap<String, String> = HashMap::new();
+        env.insert(String::from("WITHENV_TEST"), String::from("$WITHENV_SOURCE"));
+        let expression: ShellExpression = ShellExpression {
+            statements: vec![(ShellStatement::Export(String::from("WITHENV_RESULT"), ShellExpression {
+                statements: vec![(ShellStatement::Value(String::from("$WITHENV_TEST")), TaskRelation::Unrelated)]
+            }), TaskRelation::Unrelated)]
+        };
+        let (rc, _): (u8, String) = runner.with_env(&mut core, env, expression);
+        assert_eq!(rc, 0);
+        assert_eq!(core.value_get(&String::from("WITHENV_RESULT")).unwrap(), String::from("BAR"));
+        //Override is scoped to the expression
+        assert!(core.value_get(&String::from("WITHENV_TEST")).is_none());
+        //Function
+        let printenv_task: Task = Task::new(vec![String::from("printenv"), String::from("WITHENV_FUNC")], Redirection::Stdout, Redirection::Stderr);
+        let myfunc: ShellExpression = ShellExpression {
+            statements: vec![(ShellStatement::Exec(printenv_task), TaskRelation::Unrelated)]
+        };
+        assert!(core.function_set(String::from("myprintenv"), myfunc));
+        let mut task: Task = Task::new(vec![String::from("myprintenv")], Redirection::Stdout, Redirection::Stderr);
+        task.set_env(String::from("WITHENV_FUNC"), String::from("$WITHENV_SOURCE"));
+        let (rc, out): (u8, String) = runner.exec(&mut core, task);
+        assert_eq!(rc, 0);
+        assert_eq!(out, String::from("BAR"));
+        assert!(core.value_get(&String::from("WITHENV_FUNC")).is_none());
+        //Task
+        let mut task: Task = Task::new(vec![String::from("printenv"), String::from("WITHENV_TASK")], Redirection::Stdout, Redirection::Stderr);
+        task.set_env(String::from("WITHENV_TASK"), String::from("${WITHENV_SOURCE}"));
+        let (rc, out): (u8, String) = runner.exec(&mut core, task);
+        assert_eq!(rc, 0);
+        assert_eq!(out, String::from("BAR"));
+        assert!(core.value_get(&String::from("WITHENV_TASK")).is_none());
+    }
+
     #[test]
     fn test_runner_while() {
         let mut runner: ShellRunner = ShellRunner::new();
@@ -2590,17 +4798,17 @@ mod tests {
         //Prepare an expression with all the statements
         let expression: ShellExpression = ShellExpression {
             statements: vec![
-                (ShellStatement::Read(Some(String::from(">>")), None, None), TaskRelation::Unrelated), //Read as first to not interfere with exec
+                (ShellStatement::Read(ReadOptions { prompt: Some(String::from(">>")), ..ReadOptions::new() }), TaskRelation::Unrelated), //Read as first to not interfere with exec
                 (ShellStatement::Alias(Some(String::from("ll")), Some(String::from("ls -l"))), TaskRelation::Unrelated),
                 (ShellStatement::Case(case_match, cases), TaskRelation::Unrelated),
-                (ShellStatement::Cd(PathBuf::from("/tmp/")), TaskRelation::Unrelated),
+                (ShellStatement::Cd(PathBuf::from("/tmp/"), false), TaskRelation::Unrelated),
                 (ShellStatement::Continue, TaskRelation::Unrelated),
                 (ShellStatement::Dirs, TaskRelation::Unrelated),
                 (ShellStatement::Exec(Task::new(vec![String::from("echo"), String::from("HELLO")], Redirection::Stdout, Redirection::Stderr)), TaskRelation::Unrelated),
                 //ShellStatement::ExecHistory(0) TODO: requires readlin, TaskRelation::Unrelated)e
                 (ShellStatement::Export(String::from("MYKEY"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("MYVALUE")), TaskRelation::Unrelated)]}), TaskRelation::Unrelated),
                 (ShellStatement::For(String::from("FILE"), iterator, foreach_perform), TaskRelation::Unrelated),
-                (ShellStatement::Function(String::from("myecho"), ShellExpression { statements: vec![(ShellStatement::Exec(Task::new(vec![String::from("echo"), String::from("$1")], Redirection::Stdout, Redirection::Stderr)), TaskRelation::Unrelated)]}), TaskRelation::Unrelated),
+                (ShellStatement::Function(String::from("myecho"), ShellExpression { statements: vec![(ShellStatement::Exec(Task::new(vec![String::from("echo"), String::from("$1")], Redirection::Stdout, Redirection::Stderr)), TaskRelation::Unrelated)]}, String::from("{ echo $1; }")), TaskRelation::Unrelated),
                 (ShellStatement::History(HistoryOptions::Clear), TaskRelation::Unrelated),
                 (ShellStatement::If(ShellExpression {statements: vec![(ShellStatement::Value(String::from("1")), TaskRelation::Unrelated)]}, ShellExpression {statements: vec![(ShellStatement::Return(0), TaskRelation::Unrelated)]}, None), TaskRelation::Unrelated),
                 (ShellStatement::Let(String::from("RESULT"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("5")), TaskRelation::Unrelated)]}, MathOperator::Sum, ShellExpression {statements: vec![(ShellStatement::Value(String::from("2")), TaskRelation::Unrelated)]}), TaskRelation::Unrelated),
@@ -2613,7 +4821,7 @@ mod tests {
                 (ShellStatement::Rc(55), TaskRelation::Unrelated),
                 (ShellStatement::Set(String::from("YOURKEY"), ShellExpression {statements: vec![(ShellStatement::Value(String::from("YOURVALUE")), TaskRelation::Unrelated)]}), TaskRelation::Unrelated),
                 //ShellStatement::Source(PathBuf::from("/tmp/stuff.sh")), TODO: requires readlin, TaskRelation::Unrelated
-                (ShellStatement::Time(Task::new(vec![String::from("echo"), String::from("TIME")], Redirection::Stdout, Redirection::Stderr)), TaskRelation::Unrelated),
+                (ShellStatement::Time(Task::new(vec![String::from("/bin/echo"), String::from("TIME")], Redirection::Stdout, Redirection::Stderr)), TaskRelation::Unrelated),
                 (ShellStatement::Unalias(String::from("ll")), TaskRelation::Unrelated),
                 (ShellStatement::Unset(String::from("FOOBAR")), TaskRelation::Unrelated),
                 (ShellStatement::While(ShellExpression {statements: vec![(ShellStatement::Value(String::from("1")), TaskRelation::Unrelated)]}, ShellExpression {statements: vec![(ShellStatement::Break, TaskRelation::Unrelated)]}), TaskRelation::Unrelated),
@@ -2764,12 +4972,12 @@ mod tests {
         // Output: FOO
         let expression: ShellExpression = ShellExpression {
             statements: vec![
-                (ShellStatement::Cd(PathBuf::from("/tmp/")), TaskRelation::And),
-                (ShellStatement::Cd(PathBuf::from("/fjggtt/")), TaskRelation::And),
-                (ShellStatement::Cd(PathBuf::from("/bin/")), TaskRelation::Or),
+                (ShellStatement::Cd(PathBuf::from("/tmp/"), false), TaskRelation::And),
+                (ShellStatement::Cd(PathBuf::from("/fjggtt/"), false), TaskRelation::And),
+                (ShellStatement::Cd(PathBuf::from("/bin/"), false), TaskRelation::Or),
                 (ShellStatement::Exec(Task::new(vec![String::from("echo"), String::from("FOO")], Redirection::Stdout, Redirection::Stderr)), TaskRelation::Or),
                 (ShellStatement::Exec(Task::new(vec![String::from("echo"), String::from("BAR")], Redirection::Stdout, Redirection::Stderr)), TaskRelation::Unrelated),
-                (ShellStatement::Cd(PathBuf::from("/onett/")), TaskRelation::Unrelated),
+                (ShellStatement::Cd(PathBuf::from("/onett/"), false), TaskRelation::Unrelated),
             ]
         };
         let rc: u8 = runner.run(&mut core, expression);
@@ -2806,15 +5014,473 @@ mod tests {
         core.storage_set(String::from("KEYTEST2"), String::from("/*"));
         core.storage_set(String::from("KEYTEST3"), String::from("./*"));
         //Evaluate values
-        assert_eq!(runner.eval_value(&mut core, String::from("$NOKEY")), String::from(""));
-        assert_eq!(runner.eval_value(&mut core, String::from("${NOKEY}")), String::from(""));
-        assert_eq!(runner.eval_value(&mut core, String::from("$KEYTEST1")), String::from("BAR"));
-        assert_eq!(runner.eval_value(&mut core, String::from("${KEYTEST1}")), String::from("BAR"));
-        assert_ne!(runner.eval_value(&mut core, String::from("${KEYTEST2}")), String::from("/*"));
-        assert!(runner.eval_value(&mut core, String::from("${KEYTEST2}")).matches("/bin").count() > 0);
-        assert_ne!(runner.eval_value(&mut core, String::from("${KEYTEST3}")), String::from("./*"));
-        assert_ne!(runner.eval_value(&mut core, String::from("${KEYTEST3}")), String::from(""));
-        assert!(runner.eval_value(&mut core, String::from("${KEYTEST3}")).matches("cp").count() > 0);
+        assert!(runner.eval_value(&mut core, String::from("$NOKEY")).unwrap().is_empty());
+        assert!(runner.eval_value(&mut core, String::from("${NOKEY}")).unwrap().is_empty());
+        assert_eq!(runner.eval_value(&mut core, String::from("$KEYTEST1")).unwrap(), vec![String::from("BAR")]);
+        assert_eq!(runner.eval_value(&mut core, String::from("${KEYTEST1}")).unwrap(), vec![String::from("BAR")]);
+        //Wildcards expand to a word for each match
+        assert!(runner.eval_value(&mut core, String::from("${KEYTEST2}")).unwrap().contains(&String::from("/bin")));
+        assert!(runner.eval_value(&mut core, String::from("${KEYTEST3}")).unwrap().contains(&String::from("./cp")));
+        core.storage_set(String::from("KEYTEST5"), String::from("a  b"));
+        assert_eq!(runner.eval_value(&mut core, String::from("$KEYTEST5")).unwrap(), vec![String::from("a"), String::from("b")]);
+        assert_eq!(runner.eval_value(&mut core, String::from("\"$KEYTEST5\"")).unwrap(), vec![String::from("a  b")]);
+        //Tildes
+        let home: String = String::from(core.get_home().to_str().unwrap());
+        assert_eq!(runner.eval_value(&mut core, String::from("~/bin")).unwrap(), vec![format!("{}/bin", home)]);
+        assert_eq!(runner.eval_value(&mut core, String::from("\\~/bin")).unwrap(), vec![String::from("~/bin")]);
+        assert_eq!(runner.eval_value(&mut core, String::from("a:~/bin")).unwrap(), vec![String::from("a:~/bin")]);
+        assert_eq!(runner.eval_value(&mut core, String::from("PATH=~/bin:~")).unwrap(), vec![format!("PATH={}/bin:{}", home, home)]);
+        assert_eq!(runner.eval_assignment(&mut core, String::from("~/bin:~")), format!("{}/bin:{}", home, home));
+        //Values from variables are not tilde-expanded
+        core.storage_set(String::from("KEYTEST4"), String::from("~"));
+        assert_eq!(runner.eval_value(&mut core, String::from("$KEYTEST4")).unwrap(), vec![String::from("~")]);
+    }
+
+    #[test]
+    fn test_runner_test() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, _ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        let test = |runner: &ShellRunner, core: &mut ShellCore, args: &[&str], extended: bool| -> u8 {
+            runner.test(core, args.iter().map(|x| String::from(*x)).collect(), extended)
+        };
+        let tmpdir: tempfile::TempDir = create_tmp_dir();
+        let dir: String = String::from(tmpdir.path().to_str().unwrap());
+        let file: String = format!("{}/file.txt", dir);
+        let empty: String = format!("{}/empty.txt", dir);
+        let link: String = format!("{}/link", dir);
+        let mut f: File = File::create(file.as_str()).unwrap();
+        assert!(writeln!(f, "hello").is_ok());
+        drop(f);
+        File::create(empty.as_str()).unwrap();
+        std::os::unix::fs::symlink(file.as_str(), link.as_str()).unwrap();
+        core.storage_set(String::from("FOO"), String::from("foo bar"));
+        //POSIX argument rules
+        assert_eq!(test(&runner, &mut core, &[], false), 1);
+        assert_eq!(test(&runner, &mut core, &["foo"], false), 0);
+        assert_eq!(test(&runner, &mut core, &["''"], false), 1);
+        assert_eq!(test(&runner, &mut core, &["-n"], false), 0);
+        assert_eq!(test(&runner, &mut core, &["!", "''"], false), 0);
+        assert_eq!(test(&runner, &mut core, &["(", "foo", ")"], false), 0);
+        //File tests
+        assert_eq!(test(&runner, &mut core, &["-e", file.as_str()], false), 0);
+        assert_eq!(test(&runner, &mut core, &["-f", file.as_str()], false), 0);
+        assert_eq!(test(&runner, &mut core, &["-f", dir.as_str()], false), 1);
+        assert_eq!(test(&runner, &mut core, &["-d", dir.as_str()], false), 0);
+        assert_eq!(test(&runner, &mut core, &["-s", file.as_str()], false), 0);
+        assert_eq!(test(&runner, &mut core, &["-s", empty.as_str()], false), 1);
+        assert_eq!(test(&runner, &mut core, &["-L", link.as_str()], false), 0);
+        assert_eq!(test(&runner, &mut core, &["-L", file.as_str()], false), 1);
+        assert_eq!(test(&runner, &mut core, &["-r", file.as_str()], false), 0);
+        assert_eq!(test(&runner, &mut core, &["-w", file.as_str()], false), 0);
+        assert_eq!(test(&runner, &mut core, &["-x", dir.as_str()], false), 0);
+        assert_eq!(test(&runner, &mut core, &["-e", "/this/does/not/exist"], false), 1);
+        assert_eq!(test(&runner, &mut core, &["-f", "''"], false), 1);
+        assert_eq!(test(&runner, &mut core, &[file.as_str(), "-nt", "/this/does/not/exist"], false), 0);
+        assert_eq!(test(&runner, &mut core, &[file.as_str(), "-ot", "/this/does/not/exist"], false), 1);
+        assert_eq!(test(&runner, &mut core, &[file.as_str(), "-ef", link.as_str()], false), 0);
+        //Strings, with variables
+        assert_eq!(test(&runner, &mut core, &["\"$FOO\"", "=", "'foo bar'"], false), 0);
+        assert_eq!(test(&runner, &mut core, &["\"$FOO\"", "!=", "'foo bar'"], false), 1);
+        assert_eq!(test(&runner, &mut core, &["-z", "\"$NOTSET\""], false), 0);
+        assert_eq!(test(&runner, &mut core, &["abc", "\\<", "abd"], false), 0);
+        assert_eq!(test(&runner, &mut core, &["f*", "=", "foo"], false), 1);
+        //Unquoted variables are split
+        assert_eq!(test(&runner, &mut core, &["$FOO", "=", "'foo bar'"], false), 2);
+        //Integers
+        assert_eq!(test(&runner, &mut core, &["10", "-gt", "9"], false), 0);
+        assert_eq!(test(&runner, &mut core, &["-3", "-le", "-3"], false), 0);
+        assert_eq!(test(&runner, &mut core, &["4", "-eq", "5"], false), 1);
+        assert_eq!(test(&runner, &mut core, &["abc", "-eq", "5"], false), 2);
+        //Logical operators
+        assert_eq!(test(&runner, &mut core, &["-d", dir.as_str(), "-a", "-f", file.as_str()], false), 0);
+        assert_eq!(test(&runner, &mut core, &["-d", file.as_str(), "-a", "-f", file.as_str()], false), 1);
+        assert_eq!(test(&runner, &mut core, &["-d", file.as_str(), "-o", "-f", file.as_str()], false), 0);
+        assert_eq!(test(&runner, &mut core, &["!", "-d", file.as_str(), "-a", "(", "1", "-eq", "1", "-o", "''", ")"], false), 0);
+        assert_eq!(test(&runner, &mut core, &["(", "foo", "-a", "bar"], false), 2);
+        //Extended: no word splitting
+        assert_eq!(test(&runner, &mut core, &["$FOO", "==", "'foo bar'"], true), 0);
+        assert_eq!(test(&runner, &mut core, &["-n", "$FOO", "&&", "-z", "$NOTSET"], true), 0);
+        assert_eq!(test(&runner, &mut core, &["-z", "$FOO", "||", "!", "-f", file.as_str()], true), 1);
+        assert_eq!(test(&runner, &mut core, &["-f", file.as_str(), "-a", "-f", file.as_str()], true), 2);
+        //Extended: patterns
+        assert_eq!(test(&runner, &mut core, &["$FOO", "==", "foo*"], true), 0);
+        assert_eq!(test(&runner, &mut core, &["$FOO", "==", "\"foo*\""], true), 1);
+        assert_eq!(test(&runner, &mut core, &["$FOO", "!=", "*[0-9]"], true), 0);
+        assert_eq!(test(&runner, &mut core, &["file.txt", "=", "*.@(txt|md)"], true), 0);
+        //Extended: regex
+        assert_eq!(test(&runner, &mut core, &["abc-123", "=~", "^([a-z]+)-([0-9]+)$"], true), 0);
+        assert_eq!(core.value_get(&String::from("BASH_REMATCH")).unwrap(), String::from("abc-123"));
+        assert_eq!(core.value_get(&String::from("BASH_REMATCH[1]")).unwrap(), String::from("abc"));
+        assert_eq!(core.value_get(&String::from("BASH_REMATCH[2]")).unwrap(), String::from("123"));
+        assert_eq!(runner.expand_string(&mut core, "${BASH_REMATCH[2]}"), String::from("123"));
+        assert_eq!(test(&runner, &mut core, &["a.c", "=~", "'a.c'"], true), 0);
+        assert_eq!(test(&runner, &mut core, &["abc", "=~", "'a.c'"], true), 1);
+        assert!(core.value_get(&String::from("BASH_REMATCH[0]")).is_none());
+        assert_eq!(test(&runner, &mut core, &["abc", "=~", "("], true), 2);
+        //As conditions
+        let parser: Bash = Bash {};
+        let expression: ShellExpression = parser.parse(&core, &String::from("[[ $FOO =~ ^foo|^baz && $FOO == *bar ]] && return 5")).unwrap();
+        assert_eq!(runner.run(&mut core, expression), 5);
+        let if_expression: ShellExpression = parser.parse(&core, &format!("[ -f {} ]", file)).unwrap();
+        let if_perform: ShellExpression = ShellExpression {
+            statements: vec![(ShellStatement::Return(4), TaskRelation::Unrelated)]
+        };
+        assert_eq!(runner.ifcond(&mut core, if_expression, if_perform, None).unwrap(), 4);
+        core.storage_set(String::from("I"), String::from("0"));
+        let condition: ShellExpression = parser.parse(&core, &String::from("test $I -lt 3")).unwrap();
+        let perform: ShellExpression = ShellExpression {
+            statements: vec![(ShellStatement::Let(String::from("I"), ShellExpression { statements: vec![(ShellStatement::Value(String::from("$I")), TaskRelation::Unrelated)] }, MathOperator::Sum, ShellExpression { statements: vec![(ShellStatement::Value(String::from("1")), TaskRelation::Unrelated)] }), TaskRelation::Unrelated)]
+        };
+        runner.while_loop(&mut core, condition, perform);
+        assert_eq!(core.value_get(&String::from("I")).unwrap(), String::from("3"));
+    }
+
+    #[test]
+    fn test_runner_trap() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        //DEBUG is run before each statement
+        let tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        let log: String = String::from(tmpfile.path().to_str().unwrap());
+        let lines = || -> usize { std::fs::read_to_string(log.as_str()).unwrap().lines().count() };
+        assert_eq!(run(&mut runner, &mut core, format!("trap 'echo debug >> {}' DEBUG; dirs; dirs", log).as_str()).0, 0);
+        assert_eq!(lines(), 2);
+        //NOTE: DEBUG is run before the trap which resets it too
+        assert_eq!(run(&mut runner, &mut core, "trap - DEBUG; dirs").0, 0);
+        assert_eq!(lines(), 3);
+        //ERR is run on non-zero exit status, but not when the status is checked
+        assert_eq!(run(&mut runner, &mut core, format!("trap 'echo err >> {}' ERR; test 1 -eq 2", log).as_str()).0, 1);
+        assert_eq!(lines(), 4);
+        assert_eq!(run(&mut runner, &mut core, "test 1 -eq 2 || dirs; test 1 -eq 2 && dirs").0, 1);
+        assert_eq!(lines(), 4);
+        let condition: ShellExpression = core.parse(&String::from("test 1 -eq 2")).unwrap();
+        let perform: ShellExpression = core.parse(&String::from("dirs")).unwrap();
+        assert!(runner.ifcond(&mut core, condition, perform, None).is_none());
+        assert_eq!(lines(), 4);
+        //RETURN is run when a function returns
+        assert_eq!(run(&mut runner, &mut core, "trap - ERR; trap 'RETURNED=yes' RETURN").0, 0);
+        core.function_set(String::from("myfunc"), ShellExpression { statements: vec![(ShellStatement::Dirs, TaskRelation::Unrelated)] });
+        assert_eq!(run(&mut runner, &mut core, "myfunc").0, 0);
+        assert_eq!(core.value_get(&String::from("RETURNED")).unwrap(), String::from("yes"));
+        //Signal from the user stream: the expression is not terminated
+        assert_eq!(run(&mut runner, &mut core, "trap 'INTERRUPTED=yes' USR1").0, 0);
+        assert!(ustream.send(UserStreamMessage::Signal(UnixSignal::Sigusr1)));
+        assert_eq!(run(&mut runner, &mut core, "dirs; AFTER=yes").0, 0);
+        assert_eq!(core.value_get(&String::from("INTERRUPTED")).unwrap(), String::from("yes"));
+        assert_eq!(core.value_get(&String::from("AFTER")).unwrap(), String::from("yes"));
+        //Signal delivered to the process
+        assert_eq!(run(&mut runner, &mut core, "trap 'DELIVERED=yes' SIGUSR2").0, 0);
+        crate::signals::deliver(UnixSignal::Sigusr2);
+        assert_eq!(run(&mut runner, &mut core, "dirs").0, 0);
+        assert_eq!(core.value_get(&String::from("DELIVERED")).unwrap(), String::from("yes"));
+        //Print
+        let _ = ustream.receive();
+        assert_eq!(run(&mut runner, &mut core, "trap -p").0, 0);
+        if let ShellStreamMessage::Output((stdout, _)) = &ustream.receive().unwrap()[0] {
+            assert_eq!(*stdout.as_ref().unwrap(), String::from("trap -- 'INTERRUPTED=yes' SIGUSR1\ntrap -- 'DELIVERED=yes' SIGUSR2\ntrap -- 'RETURNED=yes' RETURN"));
+        } else {
+            panic!("Not an output");
+        }
+        assert_eq!(run(&mut runner, &mut core, "trap - USR1 USR2 RETURN; trap -l").0, 0);
+        if let ShellStreamMessage::Output((stdout, _)) = &ustream.receive().unwrap()[0] {
+            assert!(stdout.as_ref().unwrap().starts_with(" 1) SIGHUP\t 2) SIGINT\t 3) SIGQUIT\t 4) SIGILL\t 5) SIGTRAP\n 6) SIGABRT"));
+        } else {
+            panic!("Not an output");
+        }
+        assert!(core.trap_get_all().is_empty());
+        //EXIT is run on exit, only once
+        assert_eq!(run(&mut runner, &mut core, "trap 'EXITED=yes' EXIT; exit 3").0, 3);
+        assert_eq!(core.value_get(&String::from("EXITED")).unwrap(), String::from("yes"));
+        assert!(core.trap_get(&TrapSignal::Exit).is_none());
+    }
+
+    #[test]
+    fn test_runner_command() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        //Functions and aliases are bypassed
+        core.function_set(String::from("true"), core.parse(&String::from("FUNC=yes")).unwrap());
+        core.alias_set(String::from("false"), String::from("true"));
+        assert_eq!(run(&mut runner, &mut core, "command true").0, 0);
+        assert!(core.value_get(&String::from("FUNC")).is_none());
+        assert_eq!(run(&mut runner, &mut core, "command false").0, 1);
+        assert_eq!(run(&mut runner, &mut core, "true").0, 0);
+        assert_eq!(core.value_get(&String::from("FUNC")).unwrap(), String::from("yes"));
+        //Builtins
+        assert_eq!(run(&mut runner, &mut core, "command printf ''").0, 0);
+        //Describe
+        let _ = ustream.receive();
+        assert_eq!(run(&mut runner, &mut core, "command -v false true cd sh").0, 0);
+        if let ShellStreamMessage::Output((stdout, _)) = &ustream.receive().unwrap()[0] {
+            let stdout: &String = stdout.as_ref().unwrap();
+            assert!(stdout.starts_with("alias false='true'\ntrue\ncd\n/"));
+            assert!(stdout.ends_with("/sh"));
+        } else {
+            panic!("Not an output");
+        }
+        assert_eq!(run(&mut runner, &mut core, "command -V false true cd").0, 0);
+        if let ShellStreamMessage::Output((stdout, _)) = &ustream.receive().unwrap()[0] {
+            assert_eq!(*stdout.as_ref().unwrap(), String::from("false is aliased to `true'\ntrue is a function\ncd is a shell builtin"));
+        } else {
+            panic!("Not an output");
+        }
+        assert_eq!(run(&mut runner, &mut core, "command -V this-command-does-not-exist").0, 1);
+        if let ShellStreamMessage::Output((_, stderr)) = &ustream.receive().unwrap()[0] {
+            assert_eq!(*stderr.as_ref().unwrap(), String::from("bash: command: this-command-does-not-exist: not found"));
+        } else {
+            panic!("Not an output");
+        }
+    }
+
+    #[test]
+    fn test_runner_eval() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, _ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        //Arguments are expanded, joined and executed in the current shell
+        assert_eq!(run(&mut runner, &mut core, "NAME=RESULT; eval $NAME=ok").0, 0);
+        assert_eq!(core.value_get(&String::from("RESULT")).unwrap(), String::from("ok"));
+        assert_eq!(run(&mut runner, &mut core, "eval 'test 1 -eq' \"2\"").0, 1);
+        assert_eq!(run(&mut runner, &mut core, "eval \"cd /tmp; OTHER=yes\"").0, 0);
+        assert_eq!(core.get_wrkdir(), PathBuf::from("/tmp"));
+        assert_eq!(core.value_get(&String::from("OTHER")).unwrap(), String::from("yes"));
+        //Empty
+        assert_eq!(run(&mut runner, &mut core, "eval").0, 0);
+        //Syntax error
+        assert_eq!(run(&mut runner, &mut core, "eval 'exec >'").0, 2);
+    }
+
+    #[test]
+    fn test_runner_exec() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, _ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        //Redirect shell output
+        let tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        let log: String = String::from(tmpfile.path().to_str().unwrap());
+        let expression: ShellExpression = core.parse(&format!("exec > {}; echo foo; printf 'bar\\n'", log)).unwrap();
+        assert_eq!(runner.run_expression(&mut core, expression).0, 0);
+        assert_eq!(core.get_redirection(), Redirection::File(log.clone(), FileRedirectionType::Append));
+        assert_eq!(std::fs::read_to_string(log.as_str()).unwrap(), String::from("foo\nbar\n"));
+        core.set_redirection(Redirection::Stdout);
+        //Redirect shell stderr
+        let tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        let log: String = String::from(tmpfile.path().to_str().unwrap());
+        let expression: ShellExpression = core.parse(&format!("exec 2>{}; ls /exec_stderr_not_exists", log)).unwrap();
+        assert_ne!(runner.run_expression(&mut core, expression).0, 0);
+        assert_eq!(core.get_stderr_redirection(), Redirection::File(log.clone(), FileRedirectionType::Append));
+        assert!(std::fs::read_to_string(log.as_str()).unwrap().contains("/exec_stderr_not_exists"));
+        core.set_stderr_redirection(Redirection::Stderr);
+        //Redirect shell input: the commands share the file offset
+        let mut tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        write!(tmpfile, "one\ntwo\nthree\n").unwrap();
+        let expression: ShellExpression = core.parse(&format!("exec < {}; read FIRST; read SECOND; cat", tmpfile.path().display())).unwrap();
+        assert_eq!(runner.run_expression(&mut core, expression), (0, String::from("three")));
+        assert_eq!(core.value_get(&String::from("FIRST")).unwrap(), String::from("one"));
+        assert_eq!(core.value_get(&String::from("SECOND")).unwrap(), String::from("two"));
+        //Redirection errors
+        let expression: ShellExpression = core.parse(&String::from("exec < /exec_stdin_not_exists")).unwrap();
+        assert_eq!(runner.run_expression(&mut core, expression).0, 1);
+        //Replace shell: the EXIT trap is not run
+        let expression: ShellExpression = core.parse(&String::from("trap 'EXITED=yes' EXIT; exec false; AFTER=yes")).unwrap();
+        assert_eq!(runner.run_expression(&mut core, expression).0, 1);
+        assert!(core.value_get(&String::from("EXITED")).is_none());
+        assert!(core.value_get(&String::from("AFTER")).is_none());
+        assert!(core.trap_get(&TrapSignal::Exit).is_none());
+    }
+
+    #[test]
+    fn test_runner_command_not_found() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        let error = || -> TaskError {
+            match ustream.receive().unwrap().pop() {
+                Some(ShellStreamMessage::Error(ShellError::TaskError(err))) => err,
+                _ => panic!("Not a task error")
+            }
+        };
+        //Not found
+        assert_eq!(run(&mut runner, &mut core, "this-command-does-not-exist foo").0, 127);
+        let err: TaskError = error();
+        assert_eq!(err.code, TaskErrorCode::CommandNotFound);
+        assert_eq!(err.message, String::from("bash: this-command-does-not-exist: command not found"));
+        assert_eq!(run(&mut runner, &mut core, "this-command-does-not-exist || test 1 -eq 1").0, 0);
+        assert_eq!(error().code, TaskErrorCode::CommandNotFound);
+        assert_eq!(run(&mut runner, &mut core, "/this/command/does/not/exist").0, 127);
+        assert_eq!(error().code, TaskErrorCode::CommandNotFound);
+        //Not executable
+        let tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        assert_eq!(run(&mut runner, &mut core, tmpfile.path().to_str().unwrap()).0, 126);
+        assert_eq!(error().code, TaskErrorCode::NotExecutable);
+        //Handler
+        let handled: std::rc::Rc<std::cell::RefCell<Vec<String>>> = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let handled_ref: std::rc::Rc<std::cell::RefCell<Vec<String>>> = handled.clone();
+        core.set_command_not_found_handler(Some(Box::new(move |core: &mut ShellCore, argv: &[String]| -> Option<u8> {
+            handled_ref.borrow_mut().extend(argv.iter().cloned());
+            match argv[0].as_str() {
+                "fallback" => {
+                    core.storage_set(String::from("FALLBACK"), argv[1].clone());
+                    Some(3)
+                },
+                _ => None
+            }
+        })));
+        assert_eq!(run(&mut runner, &mut core, "fallback foo").0, 3);
+        assert_eq!(core.value_get(&String::from("FALLBACK")).unwrap(), String::from("foo"));
+        assert_eq!(*handled.borrow(), vec![String::from("fallback"), String::from("foo")]);
+        assert_eq!(run(&mut runner, &mut core, "this-command-does-not-exist").0, 127);
+        assert_eq!(error().code, TaskErrorCode::CommandNotFound);
+        assert_eq!(handled.borrow().len(), 3);
+        //Handler removed
+        core.set_command_not_found_handler(None);
+        assert_eq!(run(&mut runner, &mut core, "fallback").0, 127);
+        assert_eq!(handled.borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_runner_hash() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        let output = || -> (Option<String>, Option<String>) {
+            match ustream.receive().unwrap().pop() {
+                Some(ShellStreamMessage::Output(output)) => output,
+                _ => panic!("Not an output")
+            }
+        };
+        //Prepare a PATH with an executable
+        let tmpdir: tempfile::TempDir = tempfile::TempDir::new().unwrap();
+        let executable: PathBuf = tmpdir.path().join("mycommand");
+        let log: PathBuf = tmpdir.path().join("log");
+        std::fs::write(executable.as_path(), format!("#!/bin/sh\necho \"$1\" >> {}\n", log.display())).unwrap();
+        assert!(std::process::Command::new("chmod").args(["755", executable.to_str().unwrap()]).status().unwrap().success());
+        core.storage_set(String::from("PATH"), String::from(tmpdir.path().to_str().unwrap()));
+        let _ = ustream.receive();
+        assert_eq!(run(&mut runner, &mut core, "hash").0, 0);
+        assert_eq!(output(), (Some(String::from("hash: hash table empty")), None));
+        //Executed commands are hashed
+        assert_eq!(run(&mut runner, &mut core, "mycommand foo; mycommand bar").0, 0);
+        assert_eq!(std::fs::read_to_string(log.as_path()).unwrap(), String::from("foo\nbar\n"));
+        assert_eq!(run(&mut runner, &mut core, "hash").0, 0);
+        assert_eq!(output(), (Some(format!("hits\tcommand\n   2\t{}", executable.display())), None));
+        assert_eq!(run(&mut runner, &mut core, "hash -t mycommand").0, 0);
+        assert_eq!(output(), (Some(format!("{}", executable.display())), None));
+        assert_eq!(run(&mut runner, &mut core, "type mycommand").0, 0);
+        assert_eq!(output(), (Some(format!("mycommand is hashed ({})", executable.display())), None));
+        //Reset and add
+        assert_eq!(run(&mut runner, &mut core, "hash -r; hash -t mycommand").0, 1);
+        assert_eq!(output(), (None, Some(String::from("bash: hash: mycommand: not found"))));
+        assert_eq!(run(&mut runner, &mut core, "hash mycommand cd").0, 0);
+        assert_eq!(run(&mut runner, &mut core, "hash").0, 0);
+        assert_eq!(output(), (Some(format!("hits\tcommand\n   0\t{}", executable.display())), None));
+        assert_eq!(run(&mut runner, &mut core, "hash -d mycommand; hash -d mycommand").0, 1);
+        assert_eq!(output(), (None, Some(String::from("bash: hash: mycommand: not found"))));
+        assert_eq!(run(&mut runner, &mut core, "hash notacommand").0, 1);
+        assert_eq!(output(), (None, Some(String::from("bash: hash: notacommand: not found"))));
+        //Set path
+        assert_eq!(run(&mut runner, &mut core, format!("hash -p {} othercommand; othercommand baz", executable.display()).as_str()).0, 0);
+        assert_eq!(std::fs::read_to_string(log.as_path()).unwrap(), String::from("foo\nbar\nbaz\n"));
+    }
+
+    #[test]
+    fn test_runner_type() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        let stdout = || -> String {
+            match ustream.receive().unwrap().pop() {
+                Some(ShellStreamMessage::Output((Some(stdout), _))) => stdout,
+                _ => panic!("Not an output")
+            }
+        };
+        core.storage_set(String::from("PATH"), String::from("/usr/bin:/bin"));
+        let ls: PathBuf = core.path_lookup("ls").unwrap();
+        core.alias_set(String::from("ll"), String::from("ls -l"));
+        core.function_set(String::from("ls"), ShellExpression { statements: vec![] });
+        let _ = ustream.receive();
+        assert_eq!(run(&mut runner, &mut core, "type ll ls cd echo").0, 0);
+        assert_eq!(stdout(), String::from("ll is aliased to `ls -l'\nls is a function\ncd is a shell builtin\necho is a shell builtin"));
+        assert_eq!(run(&mut runner, &mut core, "type -t ll ls cd").0, 0);
+        assert_eq!(stdout(), String::from("alias\nfunction\nbuiltin"));
+        assert_eq!(run(&mut runner, &mut core, "type -t ls notacommand").0, 1);
+        assert_eq!(stdout(), String::from("function"));
+        assert_eq!(run(&mut runner, &mut core, "type -P ls").0, 0);
+        assert_eq!(stdout(), format!("{}", ls.display()));
+        assert_eq!(run(&mut runner, &mut core, "type -a ls").0, 0);
+        assert!(stdout().starts_with(format!("ls is a function\nls is {}", ls.display()).as_str()));
+        assert_eq!(run(&mut runner, &mut core, "type -p ls cat").0, 0);
+        assert_eq!(stdout(), format!("{}", core.path_lookup("cat").unwrap().display()));
+        assert_eq!(run(&mut runner, &mut core, "type notacommand").0, 1);
+        match ustream.receive().unwrap().pop() {
+            Some(ShellStreamMessage::Output((None, Some(stderr)))) => assert_eq!(stderr, String::from("bash: type: notacommand: not found")),
+            _ => panic!("Not an output")
+        }
+    }
+
+    #[test]
+    fn test_runner_expand_word() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, _ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        let words = |runner: &ShellRunner, core: &mut ShellCore, word: &str| -> Vec<String> {
+            runner.expand_word(core, word).unwrap()
+        };
+        core.storage_set(String::from("SPACED"), String::from("  foo   bar "));
+        core.storage_set(String::from("EMPTY"), String::from(""));
+        //Quote removal
+        assert_eq!(words(&runner, &mut core, "foo"), vec![String::from("foo")]);
+        assert_eq!(words(&runner, &mut core, "\"foo   bar\""), vec![String::from("foo   bar")]);
+        assert_eq!(words(&runner, &mut core, "'$SPACED'"), vec![String::from("$SPACED")]);
+        assert_eq!(words(&runner, &mut core, "foo\\ bar"), vec![String::from("foo bar")]);
+        assert_eq!(words(&runner, &mut core, "\"a\\\"b\\c\""), vec![String::from("a\"b\\c")]);
+        assert_eq!(words(&runner, &mut core, "x'y'\"z\""), vec![String::from("xyz")]);
+        //Word splitting only for unquoted expansions
+        assert_eq!(words(&runner, &mut core, "$SPACED"), vec![String::from("foo"), String::from("bar")]);
+        assert_eq!(words(&runner, &mut core, "\"$SPACED\""), vec![String::from("  foo   bar ")]);
+        assert_eq!(words(&runner, &mut core, "x${SPACED}y"), vec![String::from("x"), String::from("foo"), String::from("bar"), String::from("y")]);
+        //Empty words
+        assert!(words(&runner, &mut core, "$EMPTY").is_empty());
+        assert!(words(&runner, &mut core, "$NOTSET").is_empty());
+        assert_eq!(words(&runner, &mut core, "\"$EMPTY\""), vec![String::new()]);
+        assert_eq!(words(&runner, &mut core, "''"), vec![String::new()]);
+        //Custom IFS
+        core.storage_set(String::from("IFS"), String::from(":"));
+        core.storage_set(String::from("COLONS"), String::from("a:b::c d"));
+        assert_eq!(words(&runner, &mut core, "$COLONS"), vec![String::from("a"), String::from("b"), String::new(), String::from("c d")]);
+        assert_eq!(runner.split_words(&core, "a:b"), vec![String::from("a"), String::from("b")]);
+        core.storage_set(String::from("IFS"), String::from(""));
+        assert_eq!(words(&runner, &mut core, "$SPACED"), vec![String::from("  foo   bar ")]);
+        core.value_unset(&String::from("IFS"));
+        assert_eq!(runner.split_words(&core, " a \tb\n"), vec![String::from("a"), String::from("b")]);
+        //Filenames with spaces are a single word
+        let tmpdir: tempfile::TempDir = create_tmp_dir();
+        let _ = File::create(format!("{}/my file.txt", tmpdir.path().display())).unwrap();
+        let _ = File::create(format!("{}/other.txt", tmpdir.path().display())).unwrap();
+        let pattern: String = format!("{}/*.txt", tmpdir.path().display());
+        assert_eq!(words(&runner, &mut core, pattern.as_str()), vec![format!("{}/my file.txt", tmpdir.path().display()), format!("{}/other.txt", tmpdir.path().display())]);
+        //Quoted wildcards are not expanded
+        let pattern: String = format!("\"{}/*.txt\"", tmpdir.path().display());
+        assert_eq!(words(&runner, &mut core, pattern.as_str()), vec![format!("{}/*.txt", tmpdir.path().display())]);
+        //Patterns which don't match are kept
+        let pattern: String = format!("{}/*.md", tmpdir.path().display());
+        assert_eq!(words(&runner, &mut core, pattern.as_str()), vec![pattern.clone()]);
+        //Relative patterns and bracket expressions
+        assert!(core.change_directory(PathBuf::from(tmpdir.path()), false).is_ok());
+        assert_eq!(words(&runner, &mut core, "[mo]*.txt"), vec![String::from("my file.txt"), String::from("other.txt")]);
+        assert_eq!(words(&runner, &mut core, "!(other).txt"), vec![String::from("my file.txt")]);
+        //Glob options
+        let mut options: GlobOptions = GlobOptions::new();
+        options.nullglob = true;
+        core.set_glob_options(options);
+        assert!(words(&runner, &mut core, "*.md").is_empty());
+        options.failglob = true;
+        core.set_glob_options(options);
+        assert_eq!(runner.expand_word(&mut core, "*.md").err().unwrap(), ShellError::NoMatch(String::from("*.md")));
+        let task: Task = Task::new(vec![String::from("echo"), String::from("*.md")], Redirection::Stdout, Redirection::Stderr);
+        let (rc, out): (u8, String) = runner.exec(&mut core, task);
+        assert_eq!(rc, 1);
+        assert_eq!(out, String::new());
+        core.set_glob_options(GlobOptions::new());
+        //Through a task
+        let task: Task = Task::new(vec![String::from("printf"), String::from("'%s|\\n'"), format!("{}/*.txt", tmpdir.path().display()), String::from("\"a  b\"")], Redirection::Stdout, Redirection::Stderr);
+        let (rc, out): (u8, String) = runner.exec(&mut core, task);
+        assert_eq!(rc, 0);
+        assert_eq!(out, format!("{}/my file.txt|\n{}/other.txt|\na  b|", tmpdir.path().display(), tmpdir.path().display()));
     }
 
     #[test]
@@ -2825,7 +5491,7 @@ mod tests {
         };
         //Instantiate function
         let argv: Vec<String> = vec![String::from("hi")];
-        let function: Function = Function::new(expression, argv, Redirection::Stdout);
+        let function: Function = Function::new(expression, argv, Redirection::Stdout, HashMap::new());
         assert_eq!(function.redirection, Redirection::Stdout);
         assert_eq!(function.expression.statements.len(), 1);
         assert_eq!(function.args.len(), 1);
@@ -2840,8 +5506,8 @@ mod tests {
         };
         //Instantiate function
         let argv: Vec<String> = vec![String::from("hi")];
-        let function: Function = Function::new(expression, argv, Redirection::Stdout);
-        let mut chain: TaskChain = TaskChain::new(None, Some(function), TaskRelation::Unrelated);
+        let function: Function = Function::new(expression, argv, Redirection::Stdout, HashMap::new());
+        let mut chain: TaskChain = TaskChain::new(None, Some(function), None, TaskRelation::Unrelated);
         //Verify constructor
         assert_eq!(chain.prev_relation, TaskRelation::Unrelated);
         assert_eq!(chain.next_relation, TaskRelation::Unrelated);
@@ -2856,9 +5522,9 @@ mod tests {
             statements: vec![(ShellStatement::Set(String::from("FOO"), expression), TaskRelation::Unrelated)]
         };
         let argv: Vec<String> = vec![String::from("hi")];
-        let function: Function = Function::new(expression, argv, Redirection::Stdout);
+        let function: Function = Function::new(expression, argv, Redirection::Stdout, HashMap::new());
         //Chain a new function
-        chain.chain(None, Some(function), TaskRelation::And);
+        chain.chain(None, Some(function), None, TaskRelation::And);
         assert_eq!(chain.next_relation, TaskRelation::And);
         assert_eq!(chain.prev_relation, TaskRelation::Unrelated);
         assert!(chain.next.is_some());
@@ -2871,12 +5537,12 @@ mod tests {
         assert!(next.next.is_none());
         //Prepare to chain a 3rd element
         let expression: ShellExpression = ShellExpression {
-            statements: vec![(ShellStatement::Read(None, None, None), TaskRelation::Unrelated)]
+            statements: vec![(ShellStatement::Read(ReadOptions::new()), TaskRelation::Unrelated)]
         };
         let argv: Vec<String> = vec![String::from("hi")];
-        let function: Function = Function::new(expression, argv, Redirection::Stdout);
+        let function: Function = Function::new(expression, argv, Redirection::Stdout, HashMap::new());
         //Chain a 3rd element
-        chain.chain(None, Some(function), TaskRelation::Or);
+        chain.chain(None, Some(function), None, TaskRelation::Or);
         let next: &TaskChain = chain.next.as_ref().unwrap();
         //Check if the relation between the 1st and the 2nd has been preserved
         assert_eq!(chain.next_relation, TaskRelation::And);
@@ -2895,7 +5561,331 @@ mod tests {
         assert!(next.next.is_none());
     }
 
+    #[test]
+    fn test_runner_native_builtin() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        assert!(core.builtin_register(Box::new(Upper)));
+        assert!(! core.builtin_register(Box::new(Cd)));
+        assert_eq!(core.resolve_command("upper"), CommandType::Builtin);
+        //Arguments and input
+        assert_eq!(run(&mut runner, &mut core, "upper foo bar"), (0, String::from("FOO BAR")));
+        match ustream.receive().unwrap().pop() {
+            Some(ShellStreamMessage::Output((stdout, stderr))) => {
+                assert_eq!(stdout, Some(String::from("FOO BAR\n")));
+                assert!(stderr.is_none());
+            },
+            _ => panic!("Not an output message")
+        }
+        assert_eq!(run(&mut runner, &mut core, "echo foo | upper"), (0, String::from("FOO")));
+        let _ = ustream.receive();
+        assert_eq!(run(&mut runner, &mut core, "upper foo | head -n 1"), (0, String::from("FOO")));
+        let _ = ustream.receive();
+        //Errors
+        assert_eq!(run(&mut runner, &mut core, "upper"), (1, String::new()));
+        match ustream.receive().unwrap().pop() {
+            Some(ShellStreamMessage::Output((stdout, stderr))) => {
+                assert!(stdout.is_none());
+                assert_eq!(stderr, Some(String::from("upper: no input\n")));
+            },
+            _ => panic!("Not an output message")
+        }
+        //Redirections
+        let tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        assert_eq!(run(&mut runner, &mut core, &format!("upper foo > {}", tmpfile.path().display())).0, 0);
+        let task: Task = Task::new(vec![String::from("upper")], Redirection::Stdout, Redirection::File(tmpfile.path().display().to_string(), FileRedirectionType::Append));
+        assert_eq!(ShellRunner::new().exec(&mut core, task).0, 1);
+        assert!(ustream.receive().unwrap().is_empty());
+        assert_eq!(std::fs::read_to_string(tmpfile.path()).unwrap(), String::from("FOO\nupper: no input\n"));
+        //Chains
+        assert_eq!(run(&mut runner, &mut core, "upper || upper bar"), (0, String::from("BAR")));
+        let _ = ustream.receive();
+        assert_eq!(run(&mut runner, &mut core, "test 1 -eq 2 && upper bar").0, 1);
+        assert!(ustream.receive().unwrap().is_empty());
+        //Environment overrides apply to the builtin only
+        assert!(core.builtin_register(Box::new(Getenv)));
+        assert_eq!(run(&mut runner, &mut core, "BUILTIN_ENV_TEST=foo getenv BUILTIN_ENV_TEST"), (0, String::from("foo")));
+        let _ = ustream.receive();
+        assert_eq!(run(&mut runner, &mut core, "echo bar | BUILTIN_ENV_TEST=\"$USER\" getenv BUILTIN_ENV_TEST"), (0, core.value_get(&String::from("USER")).unwrap_or_default()));
+        let _ = ustream.receive();
+        assert_eq!(run(&mut runner, &mut core, "getenv BUILTIN_ENV_TEST").0, 1);
+        assert!(std::env::var("BUILTIN_ENV_TEST").is_err());
+        //Unregister
+        assert!(core.builtin_unregister("upper"));
+        assert!(! core.builtin_unregister("upper"));
+        assert_eq!(run(&mut runner, &mut core, "upper foo").0, 127);
+        let _ = ustream.receive();
+        //Default builtins can be replaced
+        assert!(core.builtin_unregister("echo"));
+        assert!(matches!(core.resolve_command("echo"), CommandType::File(_)));
+    }
+
+    #[test]
+    fn test_runner_group() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        //Redirection
+        let tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        assert_eq!(run(&mut runner, &mut core, &format!("{{ echo foo; printf 'bar\\n'; }} > {}", tmpfile.path().display())), (0, String::new()));
+        assert!(outputs(&ustream).is_empty());
+        assert_eq!(std::fs::read_to_string(tmpfile.path()).unwrap(), String::from("foo\nbar\n"));
+        //Pipelines
+        assert_eq!(run(&mut runner, &mut core, "{ echo foo; echo bar; } | head -n 1"), (0, String::from("foo")));
+        assert_eq!(outputs(&ustream), vec![String::from("foo\n")]);
+        assert_eq!(run(&mut runner, &mut core, "echo baz | { read GROUP; echo $GROUP; } | tr a o").0, 0);
+        assert_eq!(outputs(&ustream), vec![String::from("boz\n")]);
+        //State is shared
+        assert_eq!(core.value_get(&String::from("GROUP")).unwrap(), String::from("baz"));
+        //Relations
+        assert_eq!(run(&mut runner, &mut core, "{ test 1 -eq 2; } || echo ok"), (0, String::from("ok")));
+        assert_eq!(outputs(&ustream), vec![String::from("ok\n")]);
+        assert_eq!(run(&mut runner, &mut core, "{ test 1 -eq 2; } && echo ok").0, 1);
+        assert!(outputs(&ustream).is_empty());
+    }
+
+    #[test]
+    fn test_runner_subshell() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        //Changes don't leak to the current shell
+        let wrkdir: PathBuf = core.get_wrkdir();
+        assert!(core.storage_set(String::from("SUBSHELL"), String::from("outer")));
+        assert_eq!(run(&mut runner, &mut core, "(cd /tmp && SUBSHELL=inner; alias subshell=ls; export SUBSHELL_EXPORTED=1; echo $SUBSHELL)"), (0, String::from("inner")));
+        assert_eq!(outputs(&ustream), vec![String::from("inner\n")]);
+        assert_eq!(core.get_wrkdir(), wrkdir);
+        assert_eq!(core.value_get(&String::from("SUBSHELL")).unwrap(), String::from("outer"));
+        assert!(core.alias_get(&String::from("subshell")).is_none());
+        assert!(std::env::var("SUBSHELL_EXPORTED").is_err());
+        //Exit terminates the subshell only
+        assert_eq!(run(&mut runner, &mut core, "(exit 3) || echo ok"), (0, String::from("ok")));
+        assert_eq!(outputs(&ustream), vec![String::from("ok\n")]);
+        assert_eq!(run(&mut runner, &mut core, "(exit 3)").0, 3);
+        //Redirection is relative to the current shell directory
+        let tmpdir: tempfile::TempDir = create_tmp_dir();
+        let tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        assert_eq!(run(&mut runner, &mut core, &format!("(cd {}; echo foo) >> {}", tmpdir.path().display(), tmpfile.path().display())).0, 0);
+        assert!(outputs(&ustream).is_empty());
+        assert_eq!(std::fs::read_to_string(tmpfile.path()).unwrap(), String::from("foo\n"));
+        //Pipelines
+        assert_eq!(run(&mut runner, &mut core, "echo foo | (read SUBSHELL; echo $SUBSHELL) | (cat)"), (0, String::from("foo")));
+        assert_eq!(outputs(&ustream), vec![String::from("foo\n")]);
+        assert_eq!(core.value_get(&String::from("SUBSHELL")).unwrap(), String::from("outer"));
+    }
+
+    #[test]
+    fn test_runner_process_substitution() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        let messages = || -> (Vec<String>, usize) {
+            let inbox: Vec<ShellStreamMessage> = ustream.receive().unwrap();
+            let errors: usize = inbox.iter().filter(|message| matches!(message, ShellStreamMessage::Error(_))).count();
+            (inbox.into_iter().filter_map(|message| match message {
+                ShellStreamMessage::Output((stdout, _)) => stdout,
+                _ => None
+            }).collect(), errors)
+        };
+        //Input substitutions
+        assert!(core.storage_set(String::from("SUBSTITUTION"), String::from("foo")));
+        assert_eq!(run(&mut runner, &mut core, "cat <(echo $SUBSTITUTION)"), (0, String::from("foo")));
+        assert_eq!(messages(), (vec![String::from("foo\n")], 0));
+        assert_eq!(run(&mut runner, &mut core, "diff <(printf 'a\\nb\\n') <(printf 'a\\nb\\n')").0, 0);
+        assert_eq!(messages(), (vec![], 0));
+        assert_eq!(run(&mut runner, &mut core, "diff <(printf 'a\\n') <(printf 'b\\n') > /dev/null").0, 1);
+        let _ = messages();
+        //Lists and pipelines
+        assert_eq!(run(&mut runner, &mut core, "cat <(echo a; false || echo b | tr b c)"), (0, String::from("a\nc")));
+        let _ = messages();
+        //Builtins read the fifo too
+        assert!(core.builtin_register(Box::new(Firstline)));
+        assert_eq!(run(&mut runner, &mut core, "firstline <(printf 'first\\nsecond\\n')"), (0, String::from("first")));
+        let _ = messages();
+        //The data is copied as is, and the command is stopped once the consumer has terminated
+        let tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        assert_eq!(run(&mut runner, &mut core, &format!("cp <(printf '\\377\\376') {}", tmpfile.path().display())).0, 0);
+        assert_eq!(std::fs::read(tmpfile.path()).unwrap(), vec![0xff, 0xfe]);
+        assert_eq!(run(&mut runner, &mut core, "head -c 3 <(yes)"), (0, String::from("y\ny")));
+        let _ = messages();
+        //The word is replaced by the fifo, which is removed once the command has terminated
+        let (rc, fifo): (u8, String) = run(&mut runner, &mut core, "echo <(true)");
+        assert_eq!(rc, 0);
+        assert!(PathBuf::from(fifo.as_str()).starts_with(std::env::temp_dir()));
+        assert!(! PathBuf::from(fifo.as_str()).exists());
+        let _ = messages();
+        //Output substitutions
+        let tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        assert_eq!(run(&mut runner, &mut core, &format!("echo foo | tee >(cat > {})", tmpfile.path().display())), (0, String::from("foo")));
+        assert_eq!(std::fs::read_to_string(tmpfile.path()).unwrap(), String::from("foo\n"));
+        assert_eq!(messages(), (vec![String::from("foo\n")], 0));
+        //Only commands can be substituted
+        assert_eq!(run(&mut runner, &mut core, "cat <(cd /tmp)"), (1, String::new()));
+        assert_eq!(messages(), (vec![], 1));
+        assert!(runner.substitutions.is_empty());
+    }
+
+    #[test]
+    fn test_runner_functions() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        //Define and call
+        assert_eq!(run(&mut runner, &mut core, "greet() { echo \"hello $1\"; }; greet world"), (0, String::from("hello world")));
+        assert_eq!(outputs(&ustream), vec![String::from("hello world\n")]);
+        //Redefinition
+        assert_eq!(run(&mut runner, &mut core, "function greet { echo \"hi $1\"; }; greet world"), (0, String::from("hi world")));
+        assert_eq!(outputs(&ustream), vec![String::from("hi world\n")]);
+        //Redirection attached to the definition
+        let tmpfile: tempfile::NamedTempFile = create_tmpfile();
+        assert_eq!(run(&mut runner, &mut core, &format!("logged() {{ echo foo; }} >> {}; logged; logged", tmpfile.path().display())).0, 0);
+        assert!(outputs(&ustream).is_empty());
+        assert_eq!(std::fs::read_to_string(tmpfile.path()).unwrap(), String::from("foo\nfoo\n"));
+        //Functions are visible to subshells
+        assert_eq!(run(&mut runner, &mut core, "(greet sub)"), (0, String::from("hi sub")));
+        let _ = outputs(&ustream);
+        //declare -f and -F
+        assert_eq!(run(&mut runner, &mut core, "declare -f greet"), (0, String::new()));
+        assert_eq!(outputs(&ustream), vec![String::from("greet () \n{ echo \"hi $1\"; }")]);
+        assert_eq!(run(&mut runner, &mut core, "declare -F").0, 0);
+        assert_eq!(outputs(&ustream), vec![String::from("declare -f greet\ndeclare -f logged")]);
+        assert_eq!(run(&mut runner, &mut core, "declare -F missing").0, 1);
+        //The printed source can be evaluated back
+        let source: String = format!("greet () {}", core.function_source("greet").unwrap());
+        assert_eq!(run(&mut runner, &mut core, &format!("{}; greet again", source)), (0, String::from("hi again")));
+        let _ = outputs(&ustream);
+        //export -f passes the function to the child processes (the environment is shared by the tests: the name must be unique)
+        assert_eq!(run(&mut runner, &mut core, "runner_exported() { echo \"exported $1\"; }; export -f runner_exported").0, 0);
+        assert_eq!(std::env::var("BASH_FUNC_runner_exported%%").unwrap(), String::from("() { echo \"exported $1\"; }"));
+        assert_eq!(run(&mut runner, &mut core, "declare -F").0, 0);
+        assert_eq!(outputs(&ustream), vec![String::from("declare -f greet\ndeclare -f logged\ndeclare -fx runner_exported")]);
+        let (child, _): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        assert_eq!(child.function_source("runner_exported").unwrap(), String::from("{ echo \"exported $1\"; }"));
+        assert_eq!(run(&mut runner, &mut core, "export -f missing").0, 1);
+        let _ = outputs(&ustream);
+        assert_eq!(run(&mut runner, &mut core, "export -n -f runner_exported").0, 0);
+        assert!(std::env::var("BASH_FUNC_runner_exported%%").is_err());
+        let (child, _): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        assert!(child.function_source("runner_exported").is_none());
+        //unset -f; unset falls back to functions when there's no such variable
+        assert_eq!(run(&mut runner, &mut core, "unset -f greet").0, 0);
+        assert!(core.function_get(&String::from("greet")).is_none());
+        assert!(core.storage_set(String::from("logged"), String::from("var")));
+        assert_eq!(run(&mut runner, &mut core, "unset logged").0, 0);
+        assert!(core.function_get(&String::from("logged")).is_some());
+        assert_eq!(run(&mut runner, &mut core, "unset logged").0, 0);
+        assert!(core.function_get(&String::from("logged")).is_none());
+    }
+
+    #[test]
+    fn test_runner_function_nesting() {
+        let mut runner: ShellRunner = ShellRunner::new();
+        let (mut core, ustream): (ShellCore, UserStream) = ShellCore::new(None, 128, Box::new(Bash {}));
+        let mut run = |core: &mut ShellCore, command: &str| -> (u8, String) {
+            let result: (u8, String) = run(&mut runner, core, command);
+            assert_eq!(runner.funcnest, 0);
+            result
+        };
+        let errors = || -> Vec<ShellError> {
+            ustream.receive().unwrap().into_iter().filter_map(|message| match message {
+                ShellStreamMessage::Error(err) => Some(err),
+                _ => None
+            }).collect()
+        };
+        //Runaway recursion is stopped by FUNCNEST
+        assert_eq!(run(&mut core, "FUNCNEST=5; f() { f; }; f").0, 1);
+        assert_eq!(errors(), vec![ShellError::FunctionNesting(String::from("f"), 5)]);
+        //Deep but legal recursion runs on new stacks
+        let depth: String = "x".repeat(1280);
+        assert_eq!(run(&mut core, &format!("FUNCNEST=0; f() {{ [ \"$1\" = \"$2\" ] || f \"x$1\" \"$2\"; }}; f \"\" {}", depth)).0, 0);
+        assert!(errors().is_empty());
+        //Invalid values fall back to the default limit
+        assert!(core.storage_set(String::from("FUNCNEST"), String::from("foo")));
+        assert_eq!(runner.funcnest_limit(&core), FUNCNEST_DEFAULT);
+    }
+
     //@! Utils
+    struct Upper;
+
+    impl crate::NativeBuiltin for Upper {
+
+        fn name(&self) -> &str {
+            "upper"
+        }
+
+        fn run(&self, _core: &mut ShellCore, argv: &[String], stdin: &mut dyn std::io::Read, stdout: &mut dyn Write, stderr: &mut dyn Write) -> u8 {
+            let mut input: String = argv[1..].join(" ");
+            if input.is_empty() {
+                let _ = stdin.read_to_string(&mut input);
+            }
+            if input.is_empty() {
+                let _ = stderr.write_all(b"upper: no input\n");
+                return 1
+            }
+            let _ = writeln!(stdout, "{}", input.trim_end().to_uppercase());
+            0
+        }
+    }
+
+    struct Firstline;
+
+    impl crate::NativeBuiltin for Firstline {
+
+        fn name(&self) -> &str {
+            "firstline"
+        }
+
+        fn run(&self, _core: &mut ShellCore, argv: &[String], _stdin: &mut dyn std::io::Read, stdout: &mut dyn Write, _stderr: &mut dyn Write) -> u8 {
+            match std::fs::read_to_string(argv[1].as_str()) {
+                Ok(content) => {
+                    let _ = writeln!(stdout, "{}", content.lines().next().unwrap_or_default());
+                    0
+                },
+                Err(_) => 1
+            }
+        }
+    }
+
+    struct Getenv;
+
+    impl crate::NativeBuiltin for Getenv {
+
+        fn name(&self) -> &str {
+            "getenv"
+        }
+
+        fn run(&self, core: &mut ShellCore, argv: &[String], _stdin: &mut dyn std::io::Read, stdout: &mut dyn Write, _stderr: &mut dyn Write) -> u8 {
+            match core.environ_getall().get(argv[1].as_str()) {
+                Some(value) => {
+                    let _ = writeln!(stdout, "{}", value);
+                    0
+                },
+                None => 1
+            }
+        }
+    }
+
+    struct Cd;
+
+    impl crate::NativeBuiltin for Cd {
+
+        fn name(&self) -> &str {
+            "cd"
+        }
+
+        fn run(&self, _core: &mut ShellCore, _argv: &[String], _stdin: &mut dyn std::io::Read, _stdout: &mut dyn Write, _stderr: &mut dyn Write) -> u8 {
+            0
+        }
+    }
+
+    fn run(runner: &mut ShellRunner, core: &mut ShellCore, command: &str) -> (u8, String) {
+        let expression: ShellExpression = core.parse(&String::from(command)).unwrap();
+        runner.run_expression(core, expression)
+    }
+
+    fn outputs(ustream: &UserStream) -> Vec<String> {
+        ustream.receive().unwrap().into_iter().filter_map(|message| match message {
+            ShellStreamMessage::Output((stdout, _)) => stdout,
+            _ => None
+        }).collect()
+    }
+
     fn create_tmp_dir_with_files(amount: usize) -> (tempfile::TempDir, Vec<String>) {
         let tmpdir: tempfile::TempDir = tempfile::TempDir::new().unwrap();
         let mut files: Vec<String> = Vec::with_capacity(amount);
@@ -0,0 +1,254 @@
+//! # Signals
+//!
+//! `signals` provides the signal names and the handlers used by the traps to catch the signals delivered to the shell process
+
+//
+//   Shell-Core
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::UnixSignal;
+
+use nix::libc;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// ### SIGNALS
+///
+/// All the signals, ordered by number
+pub(crate) const SIGNALS: [UnixSignal; 31] = [
+    UnixSignal::Sighup, UnixSignal::Sigint, UnixSignal::Sigquit, UnixSignal::Sigill, UnixSignal::Sigtrap, UnixSignal::Sigabrt,
+    UnixSignal::Sigbus, UnixSignal::Sigfpe, UnixSignal::Sigkill, UnixSignal::Sigusr1, UnixSignal::Sigsegv, UnixSignal::Sigusr2,
+    UnixSignal::Sigpipe, UnixSignal::Sigalrm, UnixSignal::Sigterm, UnixSignal::Sigstkflt, UnixSignal::Sigchld, UnixSignal::Sigcont,
+    UnixSignal::Sigstop, UnixSignal::Sigtstp, UnixSignal::Sigttin, UnixSignal::Sigttou, UnixSignal::Sigurg, UnixSignal::Sigxcpu,
+    UnixSignal::Sigxfsz, UnixSignal::Sigvtalrm, UnixSignal::Sigprof, UnixSignal::Sigwinch, UnixSignal::Sigio, UnixSignal::Sigpwr,
+    UnixSignal::Sigsys
+];
+
+//Number of times each signal has been delivered to the process (index is the signal number)
+static DELIVERED: [AtomicU32; 32] = [const { AtomicU32::new(0) }; 32];
+
+impl UnixSignal {
+
+    /// ### from_number
+    ///
+    /// Get the signal with the provided number
+    pub(crate) fn from_number(number: u8) -> Option<UnixSignal> {
+        match number {
+            0 => None,
+            n => SIGNALS.get(n as usize - 1).copied()
+        }
+    }
+
+    /// ### from_name
+    ///
+    /// Get the signal from its name; the name is case insensitive and the `SIG` prefix is optional (e.g. `INT`, `SIGINT`)
+    pub(crate) fn from_name(name: &str) -> Option<UnixSignal> {
+        let name: String = name.to_uppercase();
+        let name: &str = name.strip_prefix("SIG").unwrap_or(name.as_str());
+        SIGNALS.iter().find(|x| x.name()[3..] == *name).copied()
+    }
+
+    /// ### name
+    ///
+    /// Returns the signal name (e.g. `SIGINT`)
+    pub(crate) fn name(&self) -> String {
+        format!("{:?}", self).to_uppercase()
+    }
+}
+
+/// ## Signals
+///
+/// Signals keeps, for a shell core, the dispositions the trapped signals had before being trapped
+/// and the deliveries which have already been handled
+pub(crate) struct Signals {
+    handled: [u32; 32],                             //Deliveries already taken for each signal
+    dispositions: HashMap<UnixSignal, SigAction>    //Dispositions to restore once the trap is removed
+}
+
+impl Signals {
+
+    /// ### new
+    ///
+    /// Instantiate a new Signals
+    pub(crate) fn new() -> Signals {
+        Signals {
+            handled: [0; 32],
+            dispositions: HashMap::new()
+        }
+    }
+
+    /// ### catch
+    ///
+    /// Install the handler which marks the signal as pending when it's delivered to the process.
+    /// Signals delivered before are not reported as pending
+    pub(crate) fn catch(&mut self, signal: UnixSignal) -> bool {
+        match self.set_handler(signal, SigHandler::Handler(handler)) {
+            true => {
+                self.handled[signal as usize] = DELIVERED[signal as usize].load(Ordering::SeqCst);
+                true
+            },
+            false => false
+        }
+    }
+
+    /// ### ignore
+    ///
+    /// Ignore the signal
+    pub(crate) fn ignore(&mut self, signal: UnixSignal) -> bool {
+        self.set_handler(signal, SigHandler::SigIgn)
+    }
+
+    /// ### restore
+    ///
+    /// Restore the disposition the signal had before being caught or ignored
+    pub(crate) fn restore(&mut self, signal: UnixSignal) -> bool {
+        match (signal::Signal::try_from(signal as libc::c_int), self.dispositions.remove(&signal)) {
+            (Ok(sig), Some(action)) => unsafe { signal::sigaction(sig, &action) }.is_ok(),
+            (Ok(_), None) => true,
+            (Err(_), _) => false
+        }
+    }
+
+    /// ### take_pending
+    ///
+    /// Returns the signals, among the provided ones, which have been delivered since the last call, and marks them as handled
+    pub(crate) fn take_pending(&mut self, signals: &[UnixSignal]) -> Vec<UnixSignal> {
+        let mut pending: Vec<UnixSignal> = Vec::new();
+        for signal in SIGNALS.iter().filter(|x| signals.contains(x)) {
+            let delivered: u32 = DELIVERED[*signal as usize].load(Ordering::SeqCst);
+            if delivered != self.handled[*signal as usize] {
+                self.handled[*signal as usize] = delivered;
+                pending.push(*signal);
+            }
+        }
+        pending
+    }
+
+    /// ### set_handler
+    ///
+    /// Set the handler for the signal; the disposition it replaces is saved, unless the signal was already trapped
+    fn set_handler(&mut self, signal: UnixSignal, handler: SigHandler) -> bool {
+        let sig: signal::Signal = match signal::Signal::try_from(signal as libc::c_int) {
+            Ok(sig) => sig,
+            Err(_) => return false
+        };
+        let action: SigAction = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
+        match unsafe { signal::sigaction(sig, &action) } {
+            Ok(previous) => {
+                self.dispositions.entry(signal).or_insert(previous);
+                true
+            },
+            Err(_) => false
+        }
+    }
+}
+
+/// ### handler
+///
+/// Signal handler: counts the delivery of the signal
+extern "C" fn handler(signal: libc::c_int) {
+    if let Some(delivered) = DELIVERED.get(signal as usize) {
+        delivered.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// ### deliver
+///
+/// Simulate the delivery of a signal to the process, without raising it
+#[cfg(test)]
+pub(crate) fn deliver(signal: UnixSignal) {
+    handler(signal as libc::c_int);
+}
+
+//@! Tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_signals_names() {
+        assert_eq!(UnixSignal::from_number(2).unwrap(), UnixSignal::Sigint);
+        assert_eq!(UnixSignal::from_number(31).unwrap(), UnixSignal::Sigsys);
+        assert!(UnixSignal::from_number(0).is_none());
+        assert!(UnixSignal::from_number(32).is_none());
+        assert_eq!(UnixSignal::from_name("INT").unwrap(), UnixSignal::Sigint);
+        assert_eq!(UnixSignal::from_name("sigterm").unwrap(), UnixSignal::Sigterm);
+        assert_eq!(UnixSignal::from_name("SIGVTALRM").unwrap(), UnixSignal::Sigvtalrm);
+        assert!(UnixSignal::from_name("FOO").is_none());
+        assert_eq!(UnixSignal::Sigusr1.name(), String::from("SIGUSR1"));
+        //Numbers match
+        for (index, signal) in SIGNALS.iter().enumerate() {
+            assert_eq!(*signal as usize, index + 1);
+        }
+    }
+
+    #[test]
+    fn test_signals_pending() {
+        let mut signals: Signals = Signals::new();
+        let mut other: Signals = Signals::new();
+        //Deliveries before the signal is caught are not pending
+        deliver(UnixSignal::Sigxcpu);
+        assert!(signals.catch(UnixSignal::Sigxcpu));
+        assert!(other.catch(UnixSignal::Sigxcpu));
+        assert_eq!(disposition(UnixSignal::Sigxcpu), handler as *const () as libc::sighandler_t);
+        assert!(signals.take_pending(&[UnixSignal::Sigxcpu]).is_empty());
+        deliver(UnixSignal::Sigxcpu);
+        //Only the requested signals are taken
+        assert!(signals.take_pending(&[UnixSignal::Sigusr1]).is_empty());
+        assert_eq!(signals.take_pending(&[UnixSignal::Sigxcpu]), vec![UnixSignal::Sigxcpu]);
+        assert!(signals.take_pending(&[UnixSignal::Sigxcpu]).is_empty());
+        //Each one takes its own deliveries
+        assert_eq!(other.take_pending(&[UnixSignal::Sigxcpu]), vec![UnixSignal::Sigxcpu]);
+        assert!(other.restore(UnixSignal::Sigxcpu));
+        assert!(signals.restore(UnixSignal::Sigxcpu));
+    }
+
+    #[test]
+    fn test_signals_restore() {
+        let mut signals: Signals = Signals::new();
+        //The disposition the signal had before being trapped is restored
+        let ignored: SigAction = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+        let previous: SigAction = unsafe { signal::sigaction(signal::Signal::SIGXFSZ, &ignored) }.unwrap();
+        assert!(signals.catch(UnixSignal::Sigxfsz));
+        assert!(signals.ignore(UnixSignal::Sigxfsz));
+        assert!(signals.catch(UnixSignal::Sigxfsz));
+        assert_eq!(disposition(UnixSignal::Sigxfsz), handler as *const () as libc::sighandler_t);
+        assert!(signals.restore(UnixSignal::Sigxfsz));
+        assert_eq!(disposition(UnixSignal::Sigxfsz), libc::SIG_IGN);
+        //Nothing to restore
+        assert!(signals.restore(UnixSignal::Sigxfsz));
+        assert_eq!(disposition(UnixSignal::Sigxfsz), libc::SIG_IGN);
+        unsafe { signal::sigaction(signal::Signal::SIGXFSZ, &previous) }.unwrap();
+        //SIGKILL can't be caught
+        assert!(! signals.catch(UnixSignal::Sigkill));
+    }
+
+    fn disposition(signal: UnixSignal) -> libc::sighandler_t {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        unsafe { libc::sigaction(signal as libc::c_int, std::ptr::null(), &mut action) };
+        action.sa_sigaction
+    }
+}
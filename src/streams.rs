@@ -27,6 +27,7 @@
 
 use crate::{ShellStream, ShellStreamMessage, UserStream, UserStreamMessage};
 
+use std::cell::RefCell;
 use std::sync::mpsc;
 
 /// ## new_streams
@@ -48,8 +49,9 @@ impl ShellStream {
     /// Instantiate a new ShellStream
     pub(crate) fn new(receiver: mpsc::Receiver<UserStreamMessage>, sender: mpsc::Sender<ShellStreamMessage>) -> ShellStream {
         ShellStream {
-            receiver: receiver,
-            sender: sender
+            receiver,
+            sender,
+            captures: RefCell::new(Vec::new())
         }
     }
 
@@ -72,14 +74,41 @@ impl ShellStream {
 
     /// ### send
     /// 
-    /// Send a message to the UserStream receiver
+    /// Send a message to the UserStream receiver. While capturing, stdout is appended to the capture instead
     pub fn send(&self, message: ShellStreamMessage) -> bool {
+        let message: ShellStreamMessage = match message {
+            ShellStreamMessage::Output((Some(stdout), stderr)) => match self.captures.borrow_mut().last_mut() {
+                Some(capture) => {
+                    capture.push_str(stdout.as_str());
+                    match stderr {
+                        Some(stderr) => ShellStreamMessage::Output((None, Some(stderr))),
+                        None => return true
+                    }
+                },
+                None => ShellStreamMessage::Output((Some(stdout), stderr))
+            },
+            message => message
+        };
         match self.sender.send(message) {
             Ok(()) => true,
             Err(_) => false
         }
     }
 
+    /// ### capture_start
+    /// 
+    /// Start capturing stdout; captures can be nested
+    pub(crate) fn capture_start(&self) {
+        self.captures.borrow_mut().push(String::new());
+    }
+
+    /// ### capture_stop
+    /// 
+    /// Stop the last capture and return the captured output
+    pub(crate) fn capture_stop(&self) -> String {
+        self.captures.borrow_mut().pop().unwrap_or_default()
+    }
+
 }
 
 impl UserStream {
@@ -161,6 +190,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_streams_capture() {
+        let (sstream, ustream): (ShellStream, UserStream) = new_streams();
+        sstream.capture_start();
+        assert!(sstream.send(ShellStreamMessage::Output((Some(String::from("foo\n")), None))));
+        //Nested capture
+        sstream.capture_start();
+        assert!(sstream.send(ShellStreamMessage::Output((Some(String::from("bar\n")), Some(String::from("error\n"))))));
+        assert_eq!(sstream.capture_stop(), String::from("bar\n"));
+        assert!(sstream.send(ShellStreamMessage::Output((Some(String::from("baz\n")), None))));
+        assert_eq!(sstream.capture_stop(), String::from("foo\nbaz\n"));
+        //Only stderr has been sent
+        let inbox: Vec<ShellStreamMessage> = ustream.receive().unwrap();
+        assert_eq!(inbox.len(), 1);
+        match &inbox[0] {
+            ShellStreamMessage::Output((stdout, stderr)) => {
+                assert!(stdout.is_none());
+                assert_eq!(*stderr, Some(String::from("error\n")));
+            },
+            _ => panic!("Unexpected stream message")
+        }
+        //Not capturing anymore
+        assert!(sstream.send(ShellStreamMessage::Output((Some(String::from("foo")), None))));
+        assert_eq!(ustream.receive().unwrap().len(), 1);
+    }
+
     #[test]
     fn test_streams_empty_inbox() {
         let (sstream, ustream): (ShellStream, UserStream) = new_streams();
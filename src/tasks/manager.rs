@@ -208,6 +208,16 @@ impl TaskManager {
                                         }
                                     }
                                 },
+                                TaskMessageTx::CloseInput => {
+                                    //Close process' stdin
+                                    if let Err(err) = task.close_stdin() {
+                                        if rx_sender.send(TaskMessageRx::Error(err)).is_err() {
+                                            //Set running to false
+                                            TaskManager::false_running(running);
+                                            return 255 //The other end hung up, so  terminate the thread
+                                        }
+                                    }
+                                },
                                 TaskMessageTx::Kill => {
                                     //Try Kill process
                                     if let Err(err) = task.kill() {
@@ -761,7 +771,7 @@ mod tests {
         //Verify exit code
         assert!(!manager.is_running());
         let rc: u8 = manager.join().unwrap();
-        assert_eq!(rc, 255);
+        assert_eq!(rc, 127); //Exit status of the command which couldn't be found
     }
 
     #[test]
@@ -802,7 +812,7 @@ mod tests {
                         println!("test_manager_error : Received message from task (stdout): '{}'", stdout.as_ref().unwrap());
                     },
                     TaskMessageRx::Error(err) => match output_messages {
-                        1 => assert_eq!(err.code, TaskErrorCode::CouldNotStartTask),
+                        1 => assert_eq!(err.code, TaskErrorCode::CommandNotFound),
                         _ => panic!("That was unexpected... only 1st message should be error")
                     }
                 }
@@ -35,6 +35,8 @@ use crate::Redirection;
 use crate::TaskRelation;
 use process::Process;
 
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::{Arc, mpsc, Mutex};
 use std::thread;
 
@@ -43,11 +45,15 @@ use std::thread;
 /// The task error code represents the of error generated by the execution of a Task
 ///
 /// - CoultNotStart: it was not possible to start the task. The command is invalid or you don't have enough permissions to run it
+/// - CommandNotFound: the command doesn't exist (exit status 127)
+/// - NotExecutable: the command exists, but it can't be executed (exit status 126)
 /// - IoError: there was an IO error due to the impossibility to redirect the IO
 /// - BrokenPipe: the pipe broke
 #[derive(Copy, Clone, PartialEq, std::fmt::Debug)]
 pub enum TaskErrorCode {
     CouldNotStartTask,
+    CommandNotFound,
+    NotExecutable,
     IoError,
     BrokenPipe,
     ProcessTerminated,
@@ -60,8 +66,8 @@ pub enum TaskErrorCode {
 /// The task error represents the error raised by a task. It is made up of the error code and of a certain message
 #[derive(PartialEq, std::fmt::Debug)]
 pub struct TaskError {
-    code: TaskErrorCode,
-    message: String,
+    pub code: TaskErrorCode,
+    pub message: String,
 }
 
 /// ## Task
@@ -70,6 +76,8 @@ pub struct TaskError {
 #[derive(std::fmt::Debug)]
 pub struct Task {
     pub(crate) command: Vec<String>,        //Command argv
+    pub(crate) env: HashMap<String, String>, //Environment overrides for this command only
+    pub(crate) executable: Option<PathBuf>, //Resolved executable path (from the command hash table)
     process: Option<Process>,               //Current process in task
     pub(crate) stdout_redirection: Redirection,        //Stdout Redirection type
     pub(crate) stderr_redirection: Redirection,        //Stderr Redirection type
@@ -96,6 +104,7 @@ pub(crate) struct TaskManager {
 #[derive(std::fmt::Debug)]
 pub(crate) enum TaskMessageTx {
     Input(String),              //Send Input
+    CloseInput,                 //Close stdin (end of input)
     Kill,                       //Kill process
     Signal(crate::UnixSignal),  //Send signal
     Terminate                   //Terminate task manager thread
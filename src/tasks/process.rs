@@ -32,8 +32,13 @@ use crate::UnixSignal;
 
 //Fmt
 use std::fmt;
+//Environment
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
 //I/O
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 //UNIX stuff
 use nix::sys::select;
 use nix::sys::signal;
@@ -43,7 +48,7 @@ use nix::unistd::Pid;
 use std::os::unix::io::IntoRawFd;
 use std::os::unix::io::RawFd;
 //Subprocess
-use subprocess::{ExitStatus, Popen, PopenConfig, Redirection};
+use subprocess::{ExitStatus, Popen, PopenConfig, PopenError, Redirection};
 
 /// ### Process
 ///
@@ -63,6 +68,8 @@ pub struct Process {
 pub enum ProcessError {
     NoArgs,
     CouldNotStartProcess,
+    NotFound,
+    NotExecutable,
 }
 
 impl fmt::Display for ProcessError {
@@ -70,6 +77,8 @@ impl fmt::Display for ProcessError {
         let code_str: &str = match self {
             ProcessError::NoArgs => "Process was not provided of enough process",
             ProcessError::CouldNotStartProcess => "Could not start process",
+            ProcessError::NotFound => "No such file or directory",
+            ProcessError::NotExecutable => "Permission denied",
         };
         write!(f, "{}", code_str)
     }
@@ -79,11 +88,24 @@ impl Process {
     /// ### exec
     ///
     /// Start a new process and returns a Process struct
+    /// The process inherits the shell environment, extended with the provided overrides
+    /// If the executable is provided, it is started in place of the one found searching argv[0] in PATH
     /// If process failed to start, returns a PopenError
-    pub fn exec(argv: &Vec<String>) -> Result<Process, ProcessError> {
+    pub fn exec(argv: &Vec<String>, executable: Option<&PathBuf>, env_overrides: &HashMap<String, String>) -> Result<Process, ProcessError> {
         if argv.len() == 0 {
             return Err(ProcessError::NoArgs);
         }
+        //Build environment only if there are overrides; otherwise inherit it
+        let environ: Option<Vec<(OsString, OsString)>> = match env_overrides.len() {
+            0 => None,
+            _ => {
+                let mut environ: Vec<(OsString, OsString)> = env::vars_os().filter(|(key, _)| !env_overrides.contains_key(key.to_string_lossy().as_ref())).collect();
+                for (key, value) in env_overrides.iter() {
+                    environ.push((OsString::from(key), OsString::from(value)));
+                }
+                Some(environ)
+            }
+        };
         let p = Popen::create(
             &argv,
             PopenConfig {
@@ -91,11 +113,15 @@ impl Process {
                 stdout: Redirection::Pipe,
                 stderr: Redirection::Pipe,
                 detached: false,
+                env: environ,
+                executable: executable.map(|x| OsString::from(x.as_os_str())),
                 ..Default::default()
             },
         );
         let process: Popen = match p {
             Ok(p) => p,
+            Err(PopenError::IoError(err)) if err.kind() == ErrorKind::NotFound => return Err(ProcessError::NotFound),
+            Err(PopenError::IoError(err)) if err.kind() == ErrorKind::PermissionDenied => return Err(ProcessError::NotExecutable),
             Err(_) => return Err(ProcessError::CouldNotStartProcess),
         };
         let command: String = String::from(&argv[0]);
@@ -231,6 +257,13 @@ impl Process {
         stdin.write_all(input.as_bytes())
     }
 
+    /// ### close_stdin
+    ///
+    /// Close stdin, so that the process reaches the end of its input
+    pub fn close_stdin(&mut self) {
+        self.process.stdin = None;
+    }
+
     /// ### is_running
     ///
     /// Returns whether the process is still running or not
@@ -411,7 +444,7 @@ mod tests {
             String::from("foo"),
             String::from("bar"),
         ];
-        let mut process: Process = match Process::exec(&argv) {
+        let mut process: Process = match Process::exec(&argv, None, &HashMap::new()) {
             Ok(p) => p,
             Err(error) => panic!("Could not start process 'echo foo bar': {}", error),
         };
@@ -449,11 +482,46 @@ mod tests {
         assert_eq!(process.exit_status.unwrap(), 0); //Should be 0
     }
 
+    #[test]
+    fn test_process_env_overrides() {
+        let argv: Vec<String> = vec![String::from("printenv"), String::from("SHELLCORE_PROCESS_TEST")];
+        let mut env_overrides: HashMap<String, String> = HashMap::new();
+        env_overrides.insert(String::from("SHELLCORE_PROCESS_TEST"), String::from("foobar"));
+        let mut process: Process = match Process::exec(&argv, None, &env_overrides) {
+            Ok(p) => p,
+            Err(error) => panic!("Could not start process 'printenv': {}", error),
+        };
+        sleep(Duration::from_millis(100));
+        //Read stdout
+        let (stdout, _) = process.read().unwrap();
+        assert_eq!(stdout.unwrap(), String::from("foobar\n"));
+        //Variable must not have been set in the shell environment
+        assert!(env::var("SHELLCORE_PROCESS_TEST").is_err());
+        assert!(!process.is_running());
+        assert_eq!(process.exit_status.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_process_executable() {
+        //argv[0] is not searched in PATH if the executable is provided
+        let argv: Vec<String> = vec![String::from("not-an-echo"), String::from("foobar")];
+        let executable: PathBuf = PathBuf::from("/bin/echo");
+        let mut process: Process = match Process::exec(&argv, Some(&executable), &HashMap::new()) {
+            Ok(p) => p,
+            Err(error) => panic!("Could not start process '/bin/echo': {}", error),
+        };
+        sleep(Duration::from_millis(100));
+        let (stdout, _) = process.read().unwrap();
+        assert_eq!(stdout.unwrap(), String::from("foobar\n"));
+        assert_eq!(process.command, String::from("not-an-echo"));
+        assert!(Process::exec(&argv, None, &HashMap::new()).is_err());
+    }
+
     #[test]
     fn test_process_subprocess_io() {
         //the best and simplest example with this is CAT command :D
         let argv: Vec<String> = vec![String::from("cat")]; //No extra arg
-        let mut process: Process = match Process::exec(&argv) {
+        let mut process: Process = match Process::exec(&argv, None, &HashMap::new()) {
             Ok(p) => p,
             Err(error) => panic!("Could not start process 'cat': {}", error),
         };
@@ -518,7 +586,7 @@ mod tests {
     #[test]
     fn test_process_kill() {
         let argv: Vec<String> = vec![String::from("yes")];
-        let mut process: Process = match Process::exec(&argv) {
+        let mut process: Process = match Process::exec(&argv, None, &HashMap::new()) {
             Ok(p) => p,
             Err(error) => panic!("Could not start process 'yes': {}", error),
         };
@@ -538,21 +606,21 @@ mod tests {
     #[should_panic]
     fn test_process_no_argv() {
         let argv: Vec<String> = vec![];
-        Process::exec(&argv).ok().unwrap();
+        Process::exec(&argv, None, &HashMap::new()).ok().unwrap();
     }
 
     #[test]
     #[should_panic]
     fn test_process_unknown_command() {
         let argv: Vec<String> = vec![String::from("piroporopero")];
-        Process::exec(&argv).ok().unwrap();
+        Process::exec(&argv, None, &HashMap::new()).ok().unwrap();
     }
 
     #[test]
     #[should_panic]
     fn test_process_terminated_write() {
         let argv: Vec<String> = vec![String::from("echo"), String::from("0")];
-        let mut process: Process = match Process::exec(&argv) {
+        let mut process: Process = match Process::exec(&argv, None, &HashMap::new()) {
             Ok(p) => p,
             Err(error) => panic!("Could not start process 'echo foo bar': {}", error),
         };
@@ -574,7 +642,7 @@ mod tests {
     #[should_panic]
     fn test_process_terminated_read() {
         let argv: Vec<String> = vec![String::from("echo"), String::from("0")];
-        let mut process: Process = match Process::exec(&argv) {
+        let mut process: Process = match Process::exec(&argv, None, &HashMap::new()) {
             Ok(p) => p,
             Err(error) => panic!("Could not start process 'echo foo bar': {}", error),
         };
@@ -596,7 +664,7 @@ mod tests {
     #[should_panic]
     fn test_process_stderr_broken_pipe() {
         let argv: Vec<String> = vec![String::from("echo"), String::from("0")];
-        let mut process: Process = match Process::exec(&argv) {
+        let mut process: Process = match Process::exec(&argv, None, &HashMap::new()) {
             Ok(p) => p,
             Err(error) => panic!("Could not start process 'echo foo bar': {}", error),
         };
@@ -607,7 +675,7 @@ mod tests {
     #[test]
     fn test_process_signaled() {
         let argv: Vec<String> = vec![String::from("cat")];
-        let mut process: Process = match Process::exec(&argv) {
+        let mut process: Process = match Process::exec(&argv, None, &HashMap::new()) {
             Ok(p) => p,
             Err(error) => panic!("Could not start process 'echo foo bar': {}", error),
         };
@@ -623,6 +691,8 @@ mod tests {
     #[test]
     fn test_process_display_error() {
         println!("{}; {}", ProcessError::CouldNotStartProcess, ProcessError::NoArgs);
+        assert_eq!(ProcessError::NotFound.to_string(), String::from("No such file or directory"));
+        assert_eq!(ProcessError::NotExecutable.to_string(), String::from("Permission denied"));
     }
 
     #[test]
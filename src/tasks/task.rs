@@ -25,10 +25,11 @@
 // SOFTWARE.
 //
 
-use super::process::Process;
+use super::process::{Process, ProcessError};
 use super::{Redirection, Task, TaskError, TaskErrorCode, TaskRelation};
 use crate::{FileRedirectionType, UnixSignal};
 
+use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::Write;
 
@@ -39,6 +40,8 @@ impl Task {
     pub fn new(command: Vec<String>, stdout_redir: Redirection, stderr_redir: Redirection) -> Task {
         Task {
             command: command,
+            env: HashMap::new(),
+            executable: None,
             stdout_redirection: stdout_redir,
             stderr_redirection: stderr_redir,
             process: None,
@@ -48,6 +51,30 @@ impl Task {
         }
     }
 
+    /// ## append
+    ///
+    /// Append a task at the end of the pipeline; the relation with it is the one of the current last task
+    pub(crate) fn append(&mut self, task: Task) {
+        match &mut self.next {
+            None => self.next = Some(Box::new(task)),
+            Some(next) => next.append(task)
+        }
+    }
+
+    /// ## take_last_relation
+    ///
+    /// Returns the relation of the last task in the pipeline, which is then set to Unrelated
+    pub(crate) fn take_last_relation(&mut self) -> TaskRelation {
+        match &mut self.next {
+            None => {
+                let relation: TaskRelation = self.relation;
+                self.relation = TaskRelation::Unrelated;
+                relation
+            },
+            Some(next) => next.take_last_relation()
+        }
+    }
+
     /// ## new_pipeline
     ///
     /// Add to Task a new task
@@ -69,34 +96,12 @@ impl Task {
         }
     }
 
-    /// ### truncate
-    /// 
-    /// Truncate Task pipeline at a certain index. The provided index will be of the last element kept in the pipeline
-    /// Returns the broken relation
-    pub(crate) fn truncate(&mut self, index: usize) -> TaskRelation {
-        let t: &mut Task = self;
-        let mut i: usize = 0;
-        loop {
-            if t.next.is_none() {
-                return TaskRelation::Unrelated;
-            }
-            //This is why rust is for certain aspects the most stupid language ever, you can't edit t after that, because it says it's a temp value. But it's not fuckin true
-            let t: &mut Task = match i {
-                0 => t,
-                _ => t.next.as_mut().unwrap()
-            };
-            if i == index {
-                t.next = None; //Set next to None
-                let last_relation: TaskRelation = t.relation.clone();
-                t.relation = TaskRelation::Unrelated; //Set relation to unrelated
-                return last_relation;
-            }
-            //NOTE: no, you can't do that; rust is so fuckin bad sometimes
-            /*
-            t = &mut t.next.as_mut().unwrap();
-            */
-            i = i + 1;
-        }
+    /// ### set_env
+    ///
+    /// Set an environment variable for this task only (e.g. `FOO=bar cmd`).
+    /// The override doesn't apply to the other tasks in the pipeline
+    pub fn set_env(&mut self, key: String, value: String) {
+        self.env.insert(key, value);
     }
 
     /// ## start
@@ -116,10 +121,24 @@ impl Task {
                 }
             }
         }
-        //After starting the pipe, execute this process
-        self.process = match Process::exec(&self.command) {
+        //Redirection files are created (and truncated) once, before the process is started
+        if let Err(err) = self.open_redirections() {
+            self.exit_code = Some(1);
+            return Err(err)
+        }
+        //After starting the pipe, execute this process; if it can't be started, the exit code is set as bash does
+        self.process = match Process::exec(&self.command, self.executable.as_ref(), &self.env) {
             Ok(p) => Some(p),
+            Err(ProcessError::NotFound) => {
+                self.exit_code = Some(127);
+                return Err(TaskError::new(TaskErrorCode::CommandNotFound, format!("bash: {}: command not found", self.command[0])))
+            },
+            Err(ProcessError::NotExecutable) => {
+                self.exit_code = Some(126);
+                return Err(TaskError::new(TaskErrorCode::NotExecutable, format!("bash: {}: Permission denied", self.command[0])))
+            },
             Err(_) => {
+                self.exit_code = Some(255);
                 return Err(TaskError::new(
                     TaskErrorCode::CouldNotStartTask,
                     format!("Could not start process {}", self.command[0].clone()),
@@ -190,6 +209,22 @@ impl Task {
         }
     }
 
+    /// ### close_stdin
+    ///
+    /// Close the process stdin (end of input)
+    pub fn close_stdin(&mut self) -> Result<(), TaskError> {
+        match &mut self.process {
+            None => Err(TaskError::new(
+                TaskErrorCode::ProcessTerminated,
+                String::from("Process is not running"),
+            )),
+            Some(p) => {
+                p.close_stdin();
+                Ok(())
+            }
+        }
+    }
+
     /// ### kill
     ///
     /// Kill running task
@@ -276,14 +311,30 @@ impl Task {
                 }
             }
             Redirection::File(file, file_mode) => {
-                if output.is_some() {
-                    return self.redirect_to_file(file, file_mode, output.unwrap());
+                if let Some(output) = output {
+                    return self.redirect_to_file(file, file_mode, output);
                 }
             }
         }
         Ok(())
     }
 
+    /// ### open_redirections
+    ///
+    /// Create the files the output is redirected to, truncating them if required.
+    /// The output is then appended to them, chunk by chunk
+    fn open_redirections(&mut self) -> Result<(), TaskError> {
+        for redirection in [&mut self.stdout_redirection, &mut self.stderr_redirection] {
+            if let Redirection::File(file, file_mode) = redirection {
+                if let Err(e) = OpenOptions::new().create(true).write(true).append(*file_mode == FileRedirectionType::Append).truncate(*file_mode == FileRedirectionType::Truncate).open(file.as_str()) {
+                    return Err(TaskError::new(TaskErrorCode::IoError, format!("bash: {}: {}", file, e)))
+                }
+                *file_mode = FileRedirectionType::Append;
+            }
+        }
+        Ok(())
+    }
+
     /// ### redirect_to_file
     ///
     /// Redirect a certain output to a certain file
@@ -312,6 +363,8 @@ impl Clone for Task {
     fn clone(&self) -> Task {
         Task {
             command: self.command.clone(),
+            env: self.env.clone(),
+            executable: self.executable.clone(),
             process: None,
             stdout_redirection: self.stdout_redirection.clone(),
             stderr_redirection: self.stderr_redirection.clone(),
@@ -350,41 +403,54 @@ mod tests {
         assert_eq!(task.stdout_redirection, Redirection::Stdout);
         assert_eq!(task.command[0], String::from("echo"));
         assert_eq!(task.command[1], String::from("foobar"));
+        assert_eq!(task.env.len(), 0);
     }
 
     #[test]
-    fn test_task_truncate() {
-        let command: Vec<String> = vec![String::from("echo"), String::from("foo")];
+    fn test_task_env() {
+        let command: Vec<String> = vec![String::from("printenv"), String::from("SHELLCORE_TASK_TEST")];
         let mut task: Task = Task::new(command, Redirection::Stdout, Redirection::Stderr);
-        //Add pipe
-        let command: Vec<String> = vec![String::from("echo"), String::from("bar")];
-        task.new_pipeline(
-            command,
-            Redirection::Stdout,
-            Redirection::Stderr,
-            TaskRelation::And,
-        );
-        //Add pipe
-        let command: Vec<String> = vec![String::from("echo"), String::from("pippo")];
-        task.new_pipeline(
-            command,
-            Redirection::Stdout,
-            Redirection::Stderr,
-            TaskRelation::Or,
-        );
-        assert_eq!(task.relation, TaskRelation::And);
-        //Verify next is something
-        assert!(task.next.is_some());
-        //Reset next
-        assert_eq!(task.truncate(1), TaskRelation::Or);
-        assert_eq!(task.relation, TaskRelation::And);
-        assert!(task.next.is_some());
-        //Let's try to truncate again
-        assert_eq!(task.truncate(2), TaskRelation::Unrelated);
-        //Verify truncation
-        let task: Task = *task.next.unwrap();
-        assert_eq!(task.relation, TaskRelation::Unrelated);
-        assert!(task.next.is_none());
+        task.set_env(String::from("SHELLCORE_TASK_TEST"), String::from("foobar"));
+        //Add a second task, which must not see the variable
+        let command: Vec<String> = vec![String::from("printenv"), String::from("SHELLCORE_TASK_TEST")];
+        task.new_pipeline(command, Redirection::Stdout, Redirection::Stderr, TaskRelation::Unrelated);
+        assert_eq!(task.env.get("SHELLCORE_TASK_TEST").unwrap(), "foobar");
+        assert_eq!(task.next.as_ref().unwrap().env.len(), 0);
+        //Start process
+        assert!(task.start().is_ok());
+        sleep(Duration::from_millis(100));
+        let (stdout, _) = task.read().unwrap();
+        assert_eq!(stdout.unwrap(), String::from("foobar\n"));
+        assert!(!task.is_running());
+        assert_eq!(task.get_exitcode().unwrap(), 0);
+        //Env must be cloned too
+        let clone: Task = task.clone();
+        assert_eq!(clone.env.get("SHELLCORE_TASK_TEST").unwrap(), "foobar");
+    }
+
+    #[test]
+    fn test_task_append() {
+        let mut task: Task = Task::new(vec![String::from("echo"), String::from("foo")], Redirection::Stdout, Redirection::Stderr);
+        task.relation = TaskRelation::Pipe;
+        let mut next: Task = Task::new(vec![String::from("cat")], Redirection::Stdout, Redirection::Stderr);
+        next.relation = TaskRelation::And;
+        task.append(next);
+        task.append(Task::new(vec![String::from("echo"), String::from("bar")], Redirection::Stdout, Redirection::Stderr));
+        let next: &Task = task.next.as_ref().unwrap();
+        assert_eq!(next.command, vec![String::from("cat")]);
+        assert_eq!(next.relation, TaskRelation::And);
+        let next: &Task = next.next.as_ref().unwrap();
+        assert_eq!(next.command, vec![String::from("echo"), String::from("bar")]);
+        assert_eq!(next.relation, TaskRelation::Unrelated);
+        assert!(next.next.is_none());
+        //Take last relation
+        task.append(Task::new(vec![String::from("ls")], Redirection::Stdout, Redirection::Stderr));
+        let mut last: Task = Task::new(vec![String::from("echo")], Redirection::Stdout, Redirection::Stderr);
+        last.relation = TaskRelation::Or;
+        task.append(last);
+        assert_eq!(task.take_last_relation(), TaskRelation::Or);
+        assert_eq!(task.take_last_relation(), TaskRelation::Unrelated);
+        assert_eq!(task.relation, TaskRelation::Pipe);
     }
 
     #[test]
@@ -414,8 +480,14 @@ mod tests {
         //Start process
         assert_eq!(
             task.start().err().unwrap().code,
-            TaskErrorCode::CouldNotStartTask
+            TaskErrorCode::CommandNotFound
         );
+        assert_eq!(task.get_exitcode(), Some(127));
+        //Not executable
+        let tmpdir: tempfile::TempDir = tempfile::TempDir::new().unwrap();
+        let mut task: Task = Task::new(vec![String::from(tmpdir.path().to_str().unwrap())], Redirection::Stdout, Redirection::Stderr);
+        assert_eq!(task.start().err().unwrap().code, TaskErrorCode::NotExecutable);
+        assert_eq!(task.get_exitcode(), Some(126));
     }
 
     #[test]
@@ -466,6 +538,21 @@ mod tests {
         assert_eq!(task.get_exitcode().unwrap(), 9);
     }
 
+    #[test]
+    fn test_task_close_stdin() {
+        let command: Vec<String> = vec![String::from("cat")];
+        let mut task: Task = Task::new(command, Redirection::Stdout, Redirection::Stderr);
+        assert!(task.start().is_ok());
+        assert!(task.write(String::from("hi there!\n")).is_ok());
+        //Cat terminates once the input has been closed
+        assert!(task.close_stdin().is_ok());
+        sleep(Duration::from_millis(100));
+        assert!(!task.is_running());
+        assert_eq!(task.get_exitcode().unwrap(), 0);
+        //Write fails after the input has been closed
+        assert!(task.write(String::from("hi there!\n")).is_err());
+    }
+
     #[test]
     fn test_task_kill() {
         let command: Vec<String> = vec![String::from("yes")];
@@ -724,6 +811,30 @@ mod tests {
         assert_eq!(output, String::from("foobar\n"));
     }
 
+    #[test]
+    fn test_task_redirect_truncate_without_output() {
+        let mut tmpfile = create_tmpfile();
+        write!(tmpfile, "previous content").unwrap();
+        let tmpfile_path: String = String::from(tmpfile.path().to_str().unwrap());
+        //The file is truncated even if the command has no output
+        let mut task: Task = Task::new(vec![String::from("true")], Redirection::File(tmpfile_path.clone(), FileRedirectionType::Truncate), Redirection::Stderr);
+        assert!(task.start().is_ok());
+        sleep(Duration::from_millis(100));
+        assert!(task.read().is_ok());
+        assert!(!task.is_running());
+        assert_eq!(std::fs::read_to_string(tmpfile_path.as_str()).unwrap(), String::new());
+        //The file is created
+        let tmpdir: tempfile::TempDir = tempfile::TempDir::new().unwrap();
+        let created: String = String::from(tmpdir.path().join("created").to_str().unwrap());
+        let mut task: Task = Task::new(vec![String::from("true")], Redirection::File(created.clone(), FileRedirectionType::Append), Redirection::Stderr);
+        assert!(task.start().is_ok());
+        assert!(std::path::Path::new(created.as_str()).exists());
+        //The file can't be opened
+        let mut task: Task = Task::new(vec![String::from("true")], Redirection::File(String::from("/task_redirect_not_exists/file"), FileRedirectionType::Truncate), Redirection::Stderr);
+        assert_eq!(task.start().err().unwrap().code, TaskErrorCode::IoError);
+        assert_eq!(task.get_exitcode(), Some(1));
+    }
+
     #[test]
     fn test_task_stderr() {
         let command: Vec<String> = vec![
@@ -0,0 +1,571 @@
+//! # Wildcard
+//!
+//! `wildcard` provides the pattern matching and the filename expansion engine used by the shell
+
+//
+//   Shell-Core
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::GlobOptions;
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// ## Token
+///
+/// Token describes an element of a pattern
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+enum Token {
+    Literal(char),
+    AnyChar,                        //?
+    AnyString,                      //*
+    Class(bool, Vec<ClassItem>),    //[...] (negated, items)
+    Extended(char, Vec<Vec<Token>>) //?(...), *(...), +(...), @(...), !(...) (kind, alternatives)
+}
+
+/// ## ClassItem
+///
+/// ClassItem describes an element of a bracket expression
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    Named(String)   //[:alpha:], [:digit:], ...
+}
+
+impl GlobOptions {
+
+    /// ### new
+    ///
+    /// Instantiates a new GlobOptions with the default bash behaviour (all options disabled)
+    pub fn new() -> GlobOptions {
+        GlobOptions {
+            nullglob: false,
+            failglob: false,
+            dotglob: false
+        }
+    }
+}
+
+impl Default for GlobOptions {
+    fn default() -> Self {
+        GlobOptions::new()
+    }
+}
+
+/// ### has_wildcards
+///
+/// Returns whether the pattern contains any wildcard (escaped characters are not wildcards)
+pub(crate) fn has_wildcards(pattern: &str) -> bool {
+    let chars: Vec<char> = pattern.chars().collect();
+    tokenize(&chars).iter().any(|x| ! matches!(x, Token::Literal(_)))
+}
+
+/// ### matches
+///
+/// Returns whether the whole text matches the pattern (e.g. for `[[ text == pattern ]]`)
+pub(crate) fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_tokens(&tokenize(&pattern), &text)
+}
+
+/// ### escape
+///
+/// Escape all the characters which have a special meaning in patterns
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped: String = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "*?[]()|@!+\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// ### glob
+///
+/// Expand the pattern into the paths which match it. Relative patterns are resolved from the working directory, but the paths are returned relative.
+/// `**` matches any file and zero or more directories. Hidden files are matched only if the component starts with a dot, unless dotglob is set.
+/// The returned paths are sorted
+pub(crate) fn glob(pattern: &str, wrkdir: &Path, options: &GlobOptions) -> Vec<String> {
+    let (root, prefix): (PathBuf, String) = match pattern.starts_with('/') {
+        true => (PathBuf::from("/"), String::from("/")),
+        false => (PathBuf::from(wrkdir), String::new())
+    };
+    let components: Vec<&str> = pattern.trim_start_matches('/').split('/').collect();
+    let mut paths: Vec<String> = Vec::new();
+    glob_dir(&root, prefix, &components, options, &mut paths);
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// ### glob_dir
+///
+/// Match the pattern components against the entries of dir; matching paths are pushed to paths, prefixed by prefix
+fn glob_dir(dir: &Path, prefix: String, components: &[&str], options: &GlobOptions, paths: &mut Vec<String>) {
+    let component: &str = match components.first() {
+        Some(component) => component,
+        None => {
+            paths.push(prefix);
+            return
+        }
+    };
+    let next: &[&str] = &components[1..];
+    //Trailing slash or repeated slashes
+    if component.is_empty() {
+        match next.is_empty() {
+            true => paths.push(prefix),
+            false => glob_dir(dir, prefix, next, options, paths)
+        }
+        return
+    }
+    //Globstar: zero or more directories
+    if component == "**" {
+        match next.is_empty() {
+            true => glob_tree(dir, prefix, options, paths),
+            false => {
+                glob_dir(dir, prefix.clone(), next, options, paths);
+                for (name, path) in read_dir_entries(dir, options.dotglob).into_iter() {
+                    if path.is_dir() && ! is_symlink(&path) {
+                        glob_dir(&path, format!("{}{}/", prefix, name), components, options, paths);
+                    }
+                }
+            }
+        }
+        return
+    }
+    //Components without wildcards are looked up directly
+    if ! has_wildcards(component) {
+        let name: String = unescape(component);
+        let path: PathBuf = dir.join(name.as_str());
+        match next.is_empty() {
+            true => if fs::symlink_metadata(path.as_path()).is_ok() {
+                paths.push(format!("{}{}", prefix, name));
+            },
+            false => if path.is_dir() {
+                glob_dir(&path, format!("{}{}/", prefix, name), next, options, paths);
+            }
+        }
+        return
+    }
+    //Hidden files are matched only by an explicit dot
+    let dotglob: bool = options.dotglob || component.starts_with('.');
+    let chars: Vec<char> = component.chars().collect();
+    let tokens: Vec<Token> = tokenize(&chars);
+    for (name, path) in read_dir_entries(dir, dotglob).into_iter() {
+        let name_chars: Vec<char> = name.chars().collect();
+        if ! match_tokens(&tokens, &name_chars) {
+            continue;
+        }
+        match next.is_empty() {
+            true => paths.push(format!("{}{}", prefix, name)),
+            false => if path.is_dir() {
+                glob_dir(&path, format!("{}{}/", prefix, name), next, options, paths);
+            }
+        }
+    }
+}
+
+/// ### glob_tree
+///
+/// Push all the entries of the tree starting from dir (`**` as last component)
+fn glob_tree(dir: &Path, prefix: String, options: &GlobOptions, paths: &mut Vec<String>) {
+    for (name, path) in read_dir_entries(dir, options.dotglob).into_iter() {
+        paths.push(format!("{}{}", prefix, name));
+        if path.is_dir() && ! is_symlink(&path) {
+            glob_tree(&path, format!("{}{}/", prefix, name), options, paths);
+        }
+    }
+}
+
+/// ### read_dir_entries
+///
+/// Returns the names and the paths of the entries in dir. Hidden entries are returned only if hidden is true
+fn read_dir_entries(dir: &Path, hidden: bool) -> Vec<(String, PathBuf)> {
+    let mut entries: Vec<(String, PathBuf)> = Vec::new();
+    if let Ok(records) = fs::read_dir(dir) {
+        for entry in records.flatten() {
+            let name: String = entry.file_name().to_string_lossy().to_string();
+            if hidden || ! name.starts_with('.') {
+                entries.push((name, entry.path()));
+            }
+        }
+    }
+    entries.sort();
+    entries
+}
+
+/// ### is_symlink
+///
+/// Returns whether path is a symbolic link
+fn is_symlink(path: &Path) -> bool {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata.file_type().is_symlink(),
+        Err(_) => false
+    }
+}
+
+/// ### unescape
+///
+/// Remove the escapes from a pattern without wildcards
+fn unescape(pattern: &str) -> String {
+    let mut output: String = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => if let Some(c) = chars.next() {
+                output.push(c);
+            },
+            _ => output.push(c)
+        }
+    }
+    output
+}
+
+//@! Tokenizer
+
+/// ### tokenize
+///
+/// Convert a pattern into tokens. Malformed bracket expressions and extglob operators are treated as literals
+fn tokenize(pattern: &[char]) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::with_capacity(pattern.len());
+    let mut index: usize = 0;
+    while index < pattern.len() {
+        let c: char = pattern[index];
+        //Extglob
+        if "?*+@!".contains(c) && pattern.get(index + 1) == Some(&'(') {
+            if let Some((alternatives, end)) = tokenize_extended(pattern, index + 2) {
+                tokens.push(Token::Extended(c, alternatives));
+                index = end + 1;
+                continue;
+            }
+        }
+        match c {
+            '\\' => {
+                index += 1;
+                tokens.push(Token::Literal(*pattern.get(index).unwrap_or(&'\\')));
+            },
+            '?' => tokens.push(Token::AnyChar),
+            '*' => {
+                //Consecutive stars are the same as one
+                if tokens.last() != Some(&Token::AnyString) {
+                    tokens.push(Token::AnyString);
+                }
+            },
+            '[' => match tokenize_class(pattern, index + 1) {
+                Some((token, end)) => {
+                    tokens.push(token);
+                    index = end;
+                },
+                None => tokens.push(Token::Literal('['))
+            },
+            _ => tokens.push(Token::Literal(c))
+        }
+        index += 1;
+    }
+    tokens
+}
+
+/// ### tokenize_extended
+///
+/// Tokenize the alternatives of an extglob operator, starting after the open parenthesis.
+/// Returns the alternatives and the index of the closing parenthesis
+fn tokenize_extended(pattern: &[char], start: usize) -> Option<(Vec<Vec<Token>>, usize)> {
+    let mut alternatives: Vec<Vec<Token>> = Vec::new();
+    let mut depth: usize = 0;
+    let mut alt_start: usize = start;
+    let mut index: usize = start;
+    while index < pattern.len() {
+        match pattern[index] {
+            '\\' => index += 1,
+            '(' => depth += 1,
+            ')' if depth == 0 => {
+                alternatives.push(tokenize(&pattern[alt_start..index]));
+                return Some((alternatives, index))
+            },
+            ')' => depth -= 1,
+            '|' if depth == 0 => {
+                alternatives.push(tokenize(&pattern[alt_start..index]));
+                alt_start = index + 1;
+            },
+            _ => {}
+        }
+        index += 1;
+    }
+    None
+}
+
+/// ### tokenize_class
+///
+/// Tokenize a bracket expression, starting after the open bracket.
+/// Returns the token and the index of the closing bracket
+fn tokenize_class(pattern: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut index: usize = start;
+    let negated: bool = match pattern.get(index) {
+        Some('!') | Some('^') => {
+            index += 1;
+            true
+        },
+        _ => false
+    };
+    let mut items: Vec<ClassItem> = Vec::new();
+    let first: usize = index;
+    while index < pattern.len() {
+        let c: char = pattern[index];
+        //A closing bracket in the first position is a literal
+        if c == ']' && index > first {
+            return Some((Token::Class(negated, items), index))
+        }
+        //Named class
+        if c == '[' && pattern.get(index + 1) == Some(&':') {
+            let name_start: usize = index + 2;
+            let mut end: usize = name_start;
+            while end + 1 < pattern.len() && ! (pattern[end] == ':' && pattern[end + 1] == ']') {
+                end += 1;
+            }
+            if end + 1 < pattern.len() {
+                items.push(ClassItem::Named(pattern[name_start..end].iter().collect()));
+                index = end + 2;
+                continue;
+            }
+        }
+        let c: char = match c {
+            '\\' if index + 1 < pattern.len() => {
+                index += 1;
+                pattern[index]
+            },
+            _ => c
+        };
+        //Range
+        if pattern.get(index + 1) == Some(&'-') && index + 2 < pattern.len() && pattern[index + 2] != ']' {
+            items.push(ClassItem::Range(c, pattern[index + 2]));
+            index += 3;
+        } else {
+            items.push(ClassItem::Char(c));
+            index += 1;
+        }
+    }
+    None
+}
+
+//@! Matcher
+
+/// ### match_tokens
+///
+/// Returns whether text matches all the tokens
+fn match_tokens(tokens: &[Token], text: &[char]) -> bool {
+    let mut failed: HashSet<(usize, usize)> = HashSet::new();
+    match_from(tokens, 0, text, 0, &mut failed)
+}
+
+/// ### match_from
+///
+/// Returns whether text, from position on, matches the tokens, from token on.
+/// The states which don't match are remembered, so that each state is evaluated only once
+/// (otherwise patterns such as `*a*a*a*b` would take exponential time)
+fn match_from(tokens: &[Token], token: usize, text: &[char], position: usize, failed: &mut HashSet<(usize, usize)>) -> bool {
+    if failed.contains(&(token, position)) {
+        return false
+    }
+    let matched: bool = match tokens.get(token) {
+        None => position == text.len(),
+        Some(Token::Literal(c)) => text.get(position) == Some(c) && match_from(tokens, token + 1, text, position + 1, failed),
+        Some(Token::AnyChar) => position < text.len() && match_from(tokens, token + 1, text, position + 1, failed),
+        Some(Token::AnyString) => (position..=text.len()).any(|split| match_from(tokens, token + 1, text, split, failed)),
+        Some(Token::Class(negated, items)) => match text.get(position) {
+            Some(c) => (match_class(items, *c) != *negated) && match_from(tokens, token + 1, text, position + 1, failed),
+            None => false
+        },
+        Some(Token::Extended(kind, alternatives)) => {
+            let ends: Vec<bool> = match_extended(*kind, alternatives, &text[position..]);
+            (position..=text.len()).any(|split| ends[split - position] && match_from(tokens, token + 1, text, split, failed))
+        }
+    };
+    if ! matched {
+        failed.insert((token, position));
+    }
+    matched
+}
+
+/// ### match_extended
+///
+/// Returns, for each length up to the text length, whether the text prefix with that length matches the extglob operator
+fn match_extended(kind: char, alternatives: &[Vec<Token>], text: &[char]) -> Vec<bool> {
+    let any_alternative = |text: &[char]| -> bool {
+        alternatives.iter().any(|x| match_tokens(x, text))
+    };
+    match kind {
+        '@' => (0..=text.len()).map(|end| any_alternative(&text[..end])).collect(),
+        '!' => (0..=text.len()).map(|end| ! any_alternative(&text[..end])).collect(),
+        '?' => (0..=text.len()).map(|end| end == 0 || any_alternative(&text[..end])).collect(),
+        '*' | '+' => {
+            //Whether the prefix is a sequence of alternatives
+            let mut sequence: Vec<bool> = vec![false; text.len() + 1];
+            sequence[0] = true;
+            for end in 1..=text.len() {
+                sequence[end] = (0..end).any(|start| sequence[start] && any_alternative(&text[start..end]));
+            }
+            //`+` requires at least one alternative
+            sequence[0] = kind == '*';
+            sequence
+        },
+        _ => vec![false; text.len() + 1]
+    }
+}
+
+/// ### match_class
+///
+/// Returns whether the character matches any of the bracket expression items
+fn match_class(items: &[ClassItem], c: char) -> bool {
+    items.iter().any(|item| match item {
+        ClassItem::Char(x) => *x == c,
+        ClassItem::Range(from, to) => *from <= c && c <= *to,
+        ClassItem::Named(name) => match name.as_str() {
+            "alnum" => c.is_alphanumeric(),
+            "alpha" => c.is_alphabetic(),
+            "blank" => c == ' ' || c == '\t',
+            "cntrl" => c.is_control(),
+            "digit" => c.is_ascii_digit(),
+            "graph" => c.is_ascii_graphic(),
+            "lower" => c.is_lowercase(),
+            "print" => c.is_ascii_graphic() || c == ' ',
+            "punct" => c.is_ascii_punctuation(),
+            "space" => c.is_whitespace(),
+            "upper" => c.is_uppercase(),
+            "xdigit" => c.is_ascii_hexdigit(),
+            _ => false
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use std::fs::File;
+
+    #[test]
+    fn test_wildcard_has_wildcards() {
+        assert!(has_wildcards("*.txt"));
+        assert!(has_wildcards("file?"));
+        assert!(has_wildcards("[ab]"));
+        assert!(has_wildcards("@(a|b)"));
+        assert!(! has_wildcards("file.txt"));
+        assert!(! has_wildcards("\\*.txt"));
+        assert!(! has_wildcards("[ab"));
+        assert!(! has_wildcards(&escape("*?[a](b)")));
+    }
+
+    #[test]
+    fn test_wildcard_matches() {
+        //Stars and question marks
+        assert!(matches("*.txt", "file.txt"));
+        assert!(matches("*", ""));
+        assert!(! matches("*.txt", "file.md"));
+        assert!(matches("f??e", "file"));
+        assert!(! matches("f?", "file"));
+        assert!(matches("a*b*c", "aXXbYYc"));
+        //Escapes
+        assert!(matches("\\*", "*"));
+        assert!(! matches("\\*", "a"));
+        //Bracket expressions
+        assert!(matches("[abc]", "b"));
+        assert!(! matches("[abc]", "d"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(matches("[!a-c]", "d"));
+        assert!(matches("[^a-c]", "d"));
+        assert!(! matches("[!a-c]", "a"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[[:alpha:]]*", "foo"));
+        assert!(! matches("[[:alpha:]]*", "1foo"));
+        assert!(matches("[[:digit:][:upper:]]", "7"));
+        assert!(matches("[[:digit:][:upper:]]", "Q"));
+        assert!(! matches("[[:digit:][:upper:]]", "q"));
+        assert!(matches("[ab", "[ab"));
+        //Extglob
+        assert!(matches("@(foo|bar).txt", "bar.txt"));
+        assert!(! matches("@(foo|bar).txt", "baz.txt"));
+        assert!(matches("!(foo|bar).txt", "baz.txt"));
+        assert!(! matches("!(foo|bar).txt", "foo.txt"));
+        assert!(matches("!(*.txt)", "file.md"));
+        assert!(! matches("!(*.txt)", "file.txt"));
+        assert!(matches("?(a)b", "b"));
+        assert!(matches("?(a)b", "ab"));
+        assert!(! matches("?(a)b", "aab"));
+        assert!(matches("*(ab)c", "ababc"));
+        assert!(matches("*(ab)c", "c"));
+        assert!(matches("+(ab)c", "abc"));
+        assert!(! matches("+(ab)c", "c"));
+        assert!(matches("@(a|b@(c|d))", "bd"));
+        assert!(matches("@(a", "@(a"));
+        //Patterns which backtrack a lot
+        let text: String = "a".repeat(64);
+        assert!(! matches("*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b", text.as_str()));
+        assert!(matches("*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a", text.as_str()));
+        assert!(! matches("+(a|aa)b", text.as_str()));
+        assert!(! matches("*(a|a)*(a|a)*(a|a)b", text.as_str()));
+        assert!(matches("+(a|aa)", text.as_str()));
+    }
+
+    #[test]
+    fn test_wildcard_glob() {
+        let tmpdir: tempfile::TempDir = tempfile::TempDir::new().unwrap();
+        let root: &Path = tmpdir.path();
+        for dir in ["src", "src/bin", "docs", ".hidden"].iter() {
+            fs::create_dir(root.join(dir)).unwrap();
+        }
+        for file in ["b.txt", "a.txt", "my file.txt", "c.md", ".env", "src/main.rs", "src/lib.rs", "src/bin/tool.rs", ".hidden/x.txt"].iter() {
+            File::create(root.join(file)).unwrap();
+        }
+        let options: GlobOptions = GlobOptions::new();
+        //Relative patterns stay relative and are sorted
+        assert_eq!(glob("*.txt", root, &options), vec![String::from("a.txt"), String::from("b.txt"), String::from("my file.txt")]);
+        assert_eq!(glob("src/*.rs", root, &options), vec![String::from("src/lib.rs"), String::from("src/main.rs")]);
+        assert_eq!(glob("*/", root, &options), vec![String::from("docs/"), String::from("src/")]);
+        assert_eq!(glob("[ab].txt", root, &options), vec![String::from("a.txt"), String::from("b.txt")]);
+        assert_eq!(glob("!(*.txt|*.md)", root, &options), vec![String::from("docs"), String::from("src")]);
+        assert!(glob("*.json", root, &options).is_empty());
+        //Absolute patterns
+        let pattern: String = format!("{}/*.md", root.display());
+        assert_eq!(glob(pattern.as_str(), Path::new("/"), &options), vec![format!("{}/c.md", root.display())]);
+        //Globstar
+        assert_eq!(glob("**/*.rs", root, &options), vec![String::from("src/bin/tool.rs"), String::from("src/lib.rs"), String::from("src/main.rs")]);
+        assert_eq!(glob("src/**", root, &options), vec![String::from("src/bin"), String::from("src/bin/tool.rs"), String::from("src/lib.rs"), String::from("src/main.rs")]);
+        //Hidden files
+        assert_eq!(glob(".*", root, &options), vec![String::from(".env"), String::from(".hidden")]);
+        assert!(glob("*/x.txt", root, &options).is_empty());
+        let options: GlobOptions = GlobOptions {
+            nullglob: false,
+            failglob: false,
+            dotglob: true
+        };
+        assert_eq!(glob("*/x.txt", root, &options), vec![String::from(".hidden/x.txt")]);
+        assert_eq!(glob("*.md", root, &options), vec![String::from("c.md")]);
+        assert_eq!(glob("*", root, &options).len(), 8);
+    }
+}